@@ -0,0 +1,31 @@
+use std::fs;
+use std::process::Command;
+
+/// Embed the commit the exporter was built from as `GIT_SHA`, for the version route.
+/// Builds outside a git checkout, eg. from a crate tarball, go without. The variable is
+/// kept out of the `SAMSYNK_` namespace, as those are read as config at runtime.
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    // HEAD only changes on switching branches, so watch the branch it points to as well,
+    // which moves with each commit. Refs can be packed, eg. after a `git gc`.
+    if let Ok(head) = fs::read_to_string(".git/HEAD") {
+        if let Some(branch) = head.trim().strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed=.git/{}", branch);
+            println!("cargo:rerun-if-changed=.git/packed-refs");
+        }
+    }
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    if std::env::var_os("GIT_SHA").is_some() {
+        return;
+    }
+
+    let sha = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(sha) = sha {
+        println!("cargo:rustc-env=GIT_SHA={}", sha.trim());
+    }
+}
@@ -0,0 +1,340 @@
+//! The bodies of the JSON API's responses. These are the API's contract with its clients,
+//! so renaming a field here is a breaking change.
+
+use crate::connection::ConnectionStatus;
+use crate::events::{fault_codes, FaultChange, FaultEvent};
+use crate::modbus_error::ModbusError;
+use crate::sensor::{EnergyPeriod, RegisterWrite, SensorValue};
+use crate::sink::Reading;
+use crate::window::WindowStats;
+use serde::Serialize;
+use std::error::Error;
+use std::time::UNIX_EPOCH;
+
+/// A sensor's value from `/api/v1/sensors`, or why it couldn't be read.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum SensorReading {
+    Value(SensorValue),
+    Error(ReadError),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReadError {
+    pub error: String,
+    /// Set when the inverter refused the read with a Modbus exception.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exception_code: Option<u8>,
+}
+
+impl SensorReading {
+    pub fn not_found() -> SensorReading {
+        SensorReading::Error(ReadError {
+            error: "not found".to_string(),
+            exception_code: None,
+        })
+    }
+
+    pub fn write_only() -> SensorReading {
+        SensorReading::Error(ReadError {
+            error: "write only".to_string(),
+            exception_code: None,
+        })
+    }
+
+    /// For a sensor that can only be served from the last known values, before it has
+    /// been read.
+    pub fn not_yet_read() -> SensorReading {
+        SensorReading::Error(ReadError {
+            error: "not yet read".to_string(),
+            exception_code: None,
+        })
+    }
+
+    pub fn from_error(e: &(dyn Error + 'static)) -> SensorReading {
+        SensorReading::Error(ReadError {
+            error: e.to_string(),
+            exception_code: ModbusError::classify(e).and_then(|e| e.exception_code()),
+        })
+    }
+}
+
+/// A sensor in the listing from `/api/v1/sensors`, with its last known value if it's
+/// been read.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SensorListing {
+    pub unit: Option<String>,
+    pub writable: bool,
+    pub value: Option<SensorValue>,
+}
+
+/// A past reading from `/api/v1/sensors/<slug>/history`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub value: SensorValue,
+}
+
+impl From<&Reading> for HistoryEntry {
+    fn from(reading: &Reading) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: reading.unix_timestamp(),
+            value: reading.value.clone(),
+        }
+    }
+}
+
+/// A fault being raised or cleared, from `/api/v1/events`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EventEntry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The slug of the fault sensor.
+    pub sensor: String,
+    /// The fault code, eg. `F8`.
+    pub code: String,
+    pub event: FaultChange,
+}
+
+impl From<&FaultEvent> for EventEntry {
+    fn from(event: &FaultEvent) -> EventEntry {
+        EventEntry {
+            timestamp: event
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            sensor: event.slug.clone(),
+            code: format!("F{}", event.code),
+            event: event.change,
+        }
+    }
+}
+
+/// A fault sensor's active faults, from `/api/v1/faults`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FaultReport {
+    /// The active fault codes in order, eg. `["F1", "F8"]`.
+    pub codes: Vec<String>,
+}
+
+impl FaultReport {
+    /// The report for a fault sensor's value, or `None` if it isn't one.
+    pub fn from_value(value: &SensorValue) -> Option<FaultReport> {
+        let mut codes: Vec<u16> = fault_codes(value)?.into_iter().collect();
+        codes.sort();
+        Some(FaultReport {
+            codes: codes.iter().map(|code| format!("F{}", code)).collect(),
+        })
+    }
+}
+
+/// The extremes of a sensor's readings over the current aggregation window, from
+/// `/api/v1/sensors/<slug>/history/window`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WindowSummary {
+    /// When the window started, in seconds since the Unix epoch.
+    pub window_start: u64,
+    pub min: f64,
+    pub max: f64,
+    pub last: f64,
+}
+
+impl From<&WindowStats> for WindowSummary {
+    fn from(stats: &WindowStats) -> WindowSummary {
+        WindowSummary {
+            window_start: stats
+                .start
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            min: stats.min,
+            max: stats.max,
+            last: stats.last,
+        }
+    }
+}
+
+/// The state of the exporter's link to the inverter, from `/api/v1/health`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HealthStatus {
+    /// Whether the inverter answered the data collector's last request.
+    pub connected: bool,
+    /// When the inverter last answered, in seconds since the Unix epoch.
+    pub last_success: Option<u64>,
+}
+
+impl From<&ConnectionStatus> for HealthStatus {
+    fn from(status: &ConnectionStatus) -> HealthStatus {
+        HealthStatus {
+            connected: status.is_healthy(),
+            last_success: status.last_success().map(|time| {
+                time.duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            }),
+        }
+    }
+}
+
+/// The counters zeroed by `/api/v1/energy/reset`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EnergyReset {
+    pub period: EnergyPeriod,
+    /// The slugs of the counters, in order.
+    pub reset: Vec<String>,
+    /// The slugs of the counters the inverter didn't reset, in order.
+    pub failed: Vec<String>,
+}
+
+/// The write a sensor would make, from a dry run of `/api/unstable/<slug>`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PlannedWrite {
+    pub function_code: u8,
+    pub register: u16,
+    pub value: u16,
+}
+
+impl From<&RegisterWrite> for PlannedWrite {
+    fn from(write: &RegisterWrite) -> PlannedWrite {
+        PlannedWrite {
+            function_code: write.function.code(),
+            register: write.register,
+            value: write.value,
+        }
+    }
+}
+
+/// Which build of the exporter is running, with which sensors, from `/api/v1/version`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VersionInfo {
+    pub crate_version: String,
+    pub git_sha: Option<String>,
+    pub sensor_map_source: String,
+    pub sensor_count: usize,
+    /// The inverter's model code, if it was read at startup.
+    pub model: Option<String>,
+    /// The inverter's firmware version, eg. `3.18`, if it was read at startup.
+    pub firmware: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::exception_response;
+    use crate::modbus_error::ExceptionCode;
+    use crate::sensor::{MetricUpdate, WriteFunction};
+    use serde_json::json;
+    use std::io;
+    use std::time::{Duration, SystemTime};
+
+    fn value(model: &impl Serialize) -> serde_json::Value {
+        serde_json::to_value(model).unwrap()
+    }
+
+    #[test]
+    fn models_serialize_to_documented_fields() {
+        assert_eq!(value(&SensorReading::Value(SensorValue::Int(3))), json!(3));
+        assert_eq!(
+            value(&SensorReading::not_found()),
+            json!({"error": "not found"})
+        );
+        let e = exception_response(3, ExceptionCode::IllegalDataAddress);
+        assert_eq!(
+            value(&SensorReading::from_error(&e)),
+            json!({"error": e.to_string(), "exception_code": 2})
+        );
+        let e = io::Error::new(io::ErrorKind::BrokenPipe, "port gone");
+        assert_eq!(
+            value(&SensorReading::from_error(&e)),
+            json!({"error": "port gone"})
+        );
+
+        assert_eq!(
+            value(&SensorListing {
+                unit: Some("W".to_string()),
+                writable: false,
+                value: None,
+            }),
+            json!({"unit": "W", "writable": false, "value": null})
+        );
+        assert_eq!(
+            value(&HistoryEntry::from(&Reading {
+                slug: "history_model".to_string(),
+                value: SensorValue::Int(5),
+                timestamp: UNIX_EPOCH + Duration::from_secs(60),
+                metrics: MetricUpdate::default(),
+            })),
+            json!({"timestamp": 60, "value": 5})
+        );
+        assert_eq!(
+            value(&EventEntry::from(&FaultEvent {
+                slug: "inverter_faults".to_string(),
+                code: 8,
+                change: FaultChange::Cleared,
+                timestamp: UNIX_EPOCH + Duration::from_secs(90),
+            })),
+            json!({"timestamp": 90, "sensor": "inverter_faults", "code": "F8", "event": "cleared"})
+        );
+        assert_eq!(
+            value(&FaultReport::from_value(&SensorValue::Text(
+                "F8, F1".to_string()
+            ))),
+            json!({"codes": ["F1", "F8"]})
+        );
+        assert_eq!(
+            value(&FaultReport::from_value(&SensorValue::Text(String::new()))),
+            json!({"codes": []})
+        );
+
+        assert_eq!(
+            value(&WindowSummary::from(&WindowStats {
+                start: UNIX_EPOCH + Duration::from_secs(86400),
+                min: -3.0,
+                max: 12.5,
+                last: 4.0,
+            })),
+            json!({"window_start": 86400, "min": -3.0, "max": 12.5, "last": 4.0})
+        );
+
+        assert_eq!(
+            value(&PlannedWrite::from(&RegisterWrite {
+                function: WriteFunction::SingleRegister,
+                register: 300,
+                value: 5,
+            })),
+            json!({"function_code": 6, "register": 300, "value": 5})
+        );
+
+        let status = ConnectionStatus::default();
+        assert_eq!(
+            value(&HealthStatus::from(&status)),
+            json!({"connected": false, "last_success": null})
+        );
+        status.mark_success();
+        let health = value(&HealthStatus::from(&status));
+        assert_eq!(health["connected"], true);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        assert!(health["last_success"].as_u64().unwrap() <= now.as_secs());
+
+        assert_eq!(
+            value(&VersionInfo {
+                crate_version: "1.2.3".to_string(),
+                git_sha: None,
+                sensor_map_source: "builtin".to_string(),
+                sensor_count: 2,
+                model: Some("5".to_string()),
+                firmware: None,
+            }),
+            json!({
+                "crate_version": "1.2.3",
+                "git_sha": null,
+                "sensor_map_source": "builtin",
+                "sensor_count": 2,
+                "model": "5",
+                "firmware": null,
+            })
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use crate::helpers::slug_name;
+use crate::sensor::{MetricUpdate, SensorRead, SensorValue, REGISTRY};
+use async_trait::async_trait;
+use prometheus::{IntGaugeVec, Opts, Registry};
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_modbus::prelude::Reader;
+
+pub const CELLS_PER_PACK: usize = 16;
+pub const TEMPERATURES_PER_PACK: usize = 4;
+/// Each pack's cell voltages in mV, followed by its temperature probes. Temperatures are
+/// encoded like the inverter's own, in 0.1°C offset by 100°C.
+pub const PACK_LEN: usize = CELLS_PER_PACK + TEMPERATURES_PER_PACK;
+
+/// The minimum, maximum and mean of a set of readings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellStats {
+    pub min: i64,
+    pub max: i64,
+    pub avg: i64,
+}
+
+impl CellStats {
+    fn from_values(values: impl IntoIterator<Item = i64>) -> Option<CellStats> {
+        let values: Vec<i64> = values.into_iter().collect();
+        Some(CellStats {
+            min: *values.iter().min()?,
+            max: *values.iter().max()?,
+            avg: values.iter().sum::<i64>() / values.len() as i64,
+        })
+    }
+
+    fn set_metric(&self, metric: &IntGaugeVec) {
+        metric.with_label_values(&["min"]).set(self.min);
+        metric.with_label_values(&["max"]).set(self.max);
+        metric.with_label_values(&["avg"]).set(self.avg);
+    }
+}
+
+/// Cell voltages in mV and temperatures in °C, across every pack.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BmsSummary {
+    pub voltage: CellStats,
+    /// `None` if no pack reported a temperature, eg. a BMS without probes.
+    pub temperature: Option<CellStats>,
+}
+
+/// Per-pack cell readings from the battery BMS. The block starts with the number of packs
+/// reporting, followed by a `PACK_LEN` run of registers for each. Packs with fewer cells
+/// or probes than there are registers leave the rest at zero.
+#[derive(Clone, Debug)]
+pub struct BmsSensor<'a> {
+    pub name: &'a str,
+    /// The pack count register, followed by the registers of up to `max_packs` packs.
+    pub registers: Vec<u16>,
+    voltage_metric: IntGaugeVec,
+    temperature_metric: IntGaugeVec,
+}
+
+impl BmsSensor<'_> {
+    pub fn new(name: &str, start_register: u16, max_packs: usize) -> BmsSensor<'_> {
+        BmsSensor::new_in(&REGISTRY, name, start_register, max_packs)
+    }
+
+    pub fn new_in<'a>(
+        registry: &Registry,
+        name: &'a str,
+        start_register: u16,
+        max_packs: usize,
+    ) -> BmsSensor<'a> {
+        let slug = slug_name(name);
+        let voltage_metric = IntGaugeVec::new(
+            Opts::new(
+                format!("{}_cell_voltage", slug),
+                format!("{} cell voltage in mV", name),
+            ),
+            &["cell"],
+        )
+        .unwrap();
+        registry.register(Box::new(voltage_metric.clone())).unwrap();
+        let temperature_metric = IntGaugeVec::new(
+            Opts::new(
+                format!("{}_cell_temperature", slug),
+                format!("{} cell temperature in °C", name),
+            ),
+            &["cell"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(temperature_metric.clone()))
+            .unwrap();
+
+        BmsSensor {
+            name,
+            registers: (start_register..=start_register + (max_packs * PACK_LEN) as u16).collect(),
+            voltage_metric,
+            temperature_metric,
+        }
+    }
+
+    pub(crate) fn max_packs(&self) -> usize {
+        (self.registers.len() - 1) / PACK_LEN
+    }
+
+    pub(crate) fn metrics(&self) -> [IntGaugeVec; 2] {
+        [self.voltage_metric.clone(), self.temperature_metric.clone()]
+    }
+
+    /// Summarise the registers of each pack, or `None` if no pack reported any cells.
+    pub fn decode(packs: &[Vec<u16>]) -> Option<BmsSummary> {
+        let cells = packs
+            .iter()
+            .flat_map(|pack| &pack[..CELLS_PER_PACK])
+            .filter(|&&mv| mv != 0)
+            .map(|&mv| mv as i64);
+        let temperatures = packs
+            .iter()
+            .flat_map(|pack| &pack[CELLS_PER_PACK..])
+            .filter(|&&raw| raw != 0)
+            .map(|&raw| raw as i64 / 10 - 100);
+
+        Some(BmsSummary {
+            voltage: CellStats::from_values(cells)?,
+            temperature: CellStats::from_values(temperatures),
+        })
+    }
+}
+
+#[async_trait]
+impl SensorRead for BmsSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let start = self.registers[0];
+        let mut ctx = ctx.lock().await;
+        let pack_count = ctx.read_holding_registers(start, 1).await?[0] as usize;
+        if pack_count > self.max_packs() {
+            eprintln!(
+                "{} reports {} packs, only reading the first {}",
+                self.name,
+                pack_count,
+                self.max_packs()
+            );
+        }
+
+        // One read per pack, so a large bank can't exceed the request size limit.
+        let mut packs = Vec::new();
+        for pack in 0..pack_count.min(self.max_packs()) {
+            let register = start + 1 + (pack * PACK_LEN) as u16;
+            packs.push(
+                ctx.read_holding_registers(register, PACK_LEN as u16)
+                    .await?,
+            );
+        }
+
+        let Some(summary) = BmsSensor::decode(&packs) else {
+            return Ok((SensorValue::Unavailable, MetricUpdate::default()));
+        };
+        let voltage = format!("{}-{}mV", summary.voltage.min, summary.voltage.max);
+        let value = SensorValue::Text(match &summary.temperature {
+            Some(temperature) => {
+                format!("{}, {}-{}°C", voltage, temperature.min, temperature.max)
+            }
+            None => voltage,
+        });
+        let voltage_metric = self.voltage_metric.clone();
+        let temperature_metric = self.temperature_metric.clone();
+        let metrics = MetricUpdate::new(move || {
+            summary.voltage.set_metric(&voltage_metric);
+            if let Some(temperature) = &summary.temperature {
+                temperature.set_metric(&temperature_metric);
+            }
+        });
+        Ok((value, metrics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{modbus_context, ClientMock};
+    use tokio_modbus::prelude::Response::ReadHoldingRegisters;
+
+    fn pack(cells: &[u16], temperatures: &[u16]) -> Vec<u16> {
+        let mut pack = vec![0; PACK_LEN];
+        pack[..cells.len()].copy_from_slice(cells);
+        pack[CELLS_PER_PACK..CELLS_PER_PACK + temperatures.len()].copy_from_slice(temperatures);
+        pack
+    }
+
+    #[test]
+    fn decode_bms_block() {
+        let packs = [
+            pack(&[3300, 3310, 3290, 3305], &[1250, 1260]),
+            // A smaller pack, with unpopulated cell and probe registers.
+            pack(&[3280, 3320], &[1180]),
+        ];
+
+        assert_eq!(
+            BmsSensor::decode(&packs),
+            Some(BmsSummary {
+                voltage: CellStats {
+                    min: 3280,
+                    max: 3320,
+                    avg: 3300,
+                },
+                temperature: Some(CellStats {
+                    min: 18,
+                    max: 26,
+                    avg: 23,
+                }),
+            })
+        );
+        assert_eq!(BmsSensor::decode(&[]), None);
+    }
+
+    #[test]
+    fn decode_bms_block_without_probes() {
+        let packs = [pack(&[3300, 3310], &[])];
+
+        assert_eq!(
+            BmsSensor::decode(&packs),
+            Some(BmsSummary {
+                voltage: CellStats {
+                    min: 3300,
+                    max: 3310,
+                    avg: 3305,
+                },
+                temperature: None,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn bms_sensor_sets_cell_metrics() {
+        let mut client = Box::<ClientMock>::default();
+        // Responses are served last-in first-out.
+        client.set_next_response(Ok(ReadHoldingRegisters(pack(&[3280, 3320], &[1180]))));
+        client.set_next_response(Ok(ReadHoldingRegisters(pack(&[3300], &[1250]))));
+        client.set_next_response(Ok(ReadHoldingRegisters(vec![2])));
+
+        let sensor = BmsSensor::new("Test BMS", 760, 4);
+        let value = sensor.read_value(modbus_context(client)).await.unwrap();
+
+        assert_eq!(value, SensorValue::Text("3280-3320mV, 18-25°C".to_string()));
+        assert_eq!(
+            sensor.voltage_metric.with_label_values(&["min"]).get(),
+            3280
+        );
+        assert_eq!(
+            sensor.temperature_metric.with_label_values(&["max"]).get(),
+            25
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use crate::sensor::SensorValue;
+use crate::sink::{OutputSink, Reading};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use tokio::time::{Duration, Instant};
+
+/// The most recent value of each sensor, whether it came from the data collector or a
+/// live read.
+#[derive(Clone, Default)]
+pub struct SensorCache {
+    readings: Arc<RwLock<HashMap<String, (SensorValue, Instant)>>>,
+}
+
+impl SensorCache {
+    pub fn insert(&self, slug: &str, value: SensorValue) {
+        self.readings
+            .write()
+            .unwrap()
+            .insert(slug.to_owned(), (value, Instant::now()));
+    }
+
+    pub fn remove(&self, slug: &str) {
+        self.readings.write().unwrap().remove(slug);
+    }
+
+    /// The last known value for `slug`, however old.
+    pub fn get(&self, slug: &str) -> Option<SensorValue> {
+        self.readings
+            .read()
+            .unwrap()
+            .get(slug)
+            .map(|(value, _)| value.clone())
+    }
+
+    /// The cached value for `slug`, as long as it was read within the last `max_age`.
+    pub fn get_fresh(&self, slug: &str, max_age: Duration) -> Option<SensorValue> {
+        match self.readings.read().unwrap().get(slug) {
+            Some((value, read_at)) if read_at.elapsed() < max_age => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl OutputSink for SensorCache {
+    async fn publish(&self, readings: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for reading in readings.iter() {
+            self.insert(&reading.slug, reading.value.clone());
+        }
+        Ok(())
+    }
+}
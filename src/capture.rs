@@ -0,0 +1,159 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Duration, Instant};
+
+/// Which way a frame went over the wire.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Raw bytes seen on the bus. Writes are whole request frames, but a response may be
+/// split over several records if it arrives in more than one read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameRecord {
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+    /// The time since the previous record, or zero for the first.
+    pub gap: Duration,
+}
+
+impl fmt::Display for FrameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Sent => "TX",
+            Direction::Received => "RX",
+        };
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(
+            f,
+            "{} +{:.1}ms {}",
+            direction,
+            self.gap.as_secs_f64() * 1000.0,
+            bytes.join(" ")
+        )
+    }
+}
+
+/// Wraps a serial stream, writing each frame that passes through it to `out`, one line
+/// per record. For diagnosing framing issues, eg. on a noisy RS-485 bus.
+pub struct FrameCapture<T> {
+    inner: T,
+    out: Arc<Mutex<dyn Write + Send>>,
+    last_frame: Option<Instant>,
+}
+
+impl<T> FrameCapture<T> {
+    pub fn new(inner: T, out: Arc<Mutex<dyn Write + Send>>) -> FrameCapture<T> {
+        FrameCapture {
+            inner,
+            out,
+            last_frame: None,
+        }
+    }
+
+    fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let gap = self
+            .last_frame
+            .map_or(Duration::ZERO, |last| now.duration_since(last));
+        self.last_frame = Some(now);
+
+        let record = FrameRecord {
+            direction,
+            bytes: bytes.to_vec(),
+            gap,
+        };
+        if let Err(e) = writeln!(self.out.lock().unwrap(), "{}", record) {
+            eprintln!("could not write frame capture: {}", e);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for FrameCapture<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FrameCapture")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for FrameCapture<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let bytes = buf.filled()[filled..].to_vec();
+            self.record(Direction::Received, &bytes);
+        }
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for FrameCapture<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.record(Direction::Sent, &buf[..written]);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test(start_paused = true)]
+    async fn captures_request_and_response_frames() {
+        let (client, mut device) = tokio::io::duplex(64);
+        let out = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut capture = FrameCapture::new(client, out.clone());
+
+        // Read holding register 183, and the device's reply.
+        capture
+            .write_all(&[0x01, 0x03, 0x00, 0xb7, 0x00, 0x01, 0x35, 0xed])
+            .await
+            .unwrap();
+        let mut request = [0; 8];
+        device.read_exact(&mut request).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        device
+            .write_all(&[0x01, 0x03, 0x02, 0x00, 0xf0, 0xb8, 0x00])
+            .await
+            .unwrap();
+        let mut response = [0; 7];
+        capture.read_exact(&mut response).await.unwrap();
+
+        let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            out,
+            "TX +0.0ms 01 03 00 b7 00 01 35 ed\nRX +20.0ms 01 03 02 00 f0 b8 00\n"
+        );
+    }
+}
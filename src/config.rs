@@ -0,0 +1,688 @@
+use crate::connection::DEGRADED_AFTER;
+use crate::helpers::invalid_input;
+use crate::scaling::ScalingTable;
+use crate::sensor_config::SensorDefinition;
+use crate::serial_format::SerialFormat;
+use crate::server::{
+    MetricsAuth, ServerOptions, BUILTIN_SENSOR_MAP, COLLECT_INTERVAL, DEFAULT_HISTORY_DEPTH,
+    HTTP_HEADER_TIMEOUT,
+};
+use crate::sink::{JsonLinesSink, OutputSink, PrometheusSink};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::time::Duration;
+use tokio_modbus::prelude::Slave;
+
+const ENV_PREFIX: &str = "SAMSYNK_";
+const CONFIG_PATH_VAR: &str = "SAMSYNK_CONFIG";
+
+/// Settings for the whole application. Every field has a default, so a config file only
+/// needs the settings that differ, eg.
+/// ```toml
+/// [serial]
+/// tty_path = "/dev/ttyUSB1"
+///
+/// [network]
+/// port = 9100
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub serial: SerialConfig,
+    pub tcp: TcpConfig,
+    pub network: NetworkConfig,
+    pub collection: CollectionConfig,
+    pub logging: LoggingConfig,
+    pub scaling: ScalingTable,
+    /// Replaces the built-in sensors when not empty. `--dump-config` prints the built-in
+    /// ones to start from.
+    pub sensors: Vec<SensorDefinition>,
+    /// The file the config was loaded from, if any.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialConfig {
+    pub tty_path: String,
+    pub baud_rate: u32,
+    /// The inverter's Modbus unit id, from 1 to 247.
+    pub slave: u8,
+    /// Allow a unit id of 0, the broadcast address. No inverter answers a broadcast, so
+    /// only writes can work.
+    pub broadcast: bool,
+    pub timeout_secs: u64,
+    /// Shorthand for the data bits, parity and stop bits, eg. "8N1". Takes precedence over
+    /// the separate settings.
+    pub format: Option<String>,
+    pub data_bits: u8,
+    /// "none", "even" or "odd".
+    pub parity: String,
+    pub stop_bits: u8,
+    /// Log the raw frames sent and received, for diagnosing bus problems.
+    pub capture_frames: bool,
+    /// Where to append captured frames. Defaults to stderr.
+    pub capture_file: Option<PathBuf>,
+    /// How many times to try opening the port at startup, for adapters that appear late.
+    pub open_attempts: u32,
+    /// How long to wait after the first failed attempt, doubling after each one after.
+    pub open_backoff_secs: u64,
+}
+
+impl Default for SerialConfig {
+    fn default() -> SerialConfig {
+        SerialConfig {
+            tty_path: "/dev/ttyUSB0".to_string(),
+            baud_rate: 9600,
+            slave: 1,
+            broadcast: false,
+            timeout_secs: 2,
+            format: None,
+            data_bits: 8,
+            parity: "none".to_string(),
+            stop_bits: 1,
+            capture_frames: false,
+            capture_file: None,
+            open_attempts: 5,
+            open_backoff_secs: 1,
+        }
+    }
+}
+
+impl SerialConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    pub fn open_backoff(&self) -> Duration {
+        Duration::from_secs(self.open_backoff_secs)
+    }
+
+    /// The unit id to address the inverter by. Ids above 247 are reserved, and 0 is the
+    /// broadcast address, only allowed if `broadcast` is set.
+    pub fn slave(&self) -> io::Result<Slave> {
+        match self.slave {
+            1..=247 => Ok(Slave(self.slave)),
+            0 if self.broadcast => Ok(Slave(0)),
+            0 => Err(invalid_input(
+                "slave 0 is the broadcast address, set broadcast = true to use it".to_string(),
+            )),
+            id => Err(invalid_input(format!(
+                "slave {} is reserved, unit ids run from 1 to 247",
+                id
+            ))),
+        }
+    }
+
+    pub fn format(&self) -> io::Result<SerialFormat> {
+        match &self.format {
+            Some(format) => format.parse(),
+            None => SerialFormat::from_fields(
+                &self.data_bits.to_string(),
+                &self.parity,
+                &self.stop_bits.to_string(),
+            ),
+        }
+    }
+
+    /// Where to write captured frames, or `None` if capture is off.
+    pub fn capture_output(&self) -> io::Result<Option<Arc<Mutex<dyn Write + Send>>>> {
+        if !self.capture_frames {
+            return Ok(None);
+        }
+        Ok(Some(match &self.capture_file {
+            Some(path) => Arc::new(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => Arc::new(Mutex::new(io::stderr())),
+        }))
+    }
+}
+
+/// Modbus TCP, eg. to a Waveshare or USR gateway in front of the inverter's RS-485 port.
+/// The serial section's `slave` is still the unit id, which the gateway routes by.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TcpConfig {
+    /// The gateway's address, eg. "192.168.1.20:502". Set, this is used rather than the
+    /// serial port.
+    pub address: Option<SocketAddr>,
+    /// How many connections to open, so a slow read doesn't hold up the other reads and
+    /// writes. The gateway still has one RS-485 bus behind it, so more than a few rarely
+    /// helps. At least one is needed.
+    pub connections: NonZeroUsize,
+    /// How long a request may go unanswered before the connection is given up on and
+    /// reopened.
+    pub timeout_secs: u64,
+    /// How many times to try each connection at startup, for gateways that are still
+    /// booting.
+    pub open_attempts: u32,
+    /// How long to wait after the first failed attempt to connect, or before reconnecting
+    /// to a gateway that has dropped the connection, doubling after each one after.
+    pub open_backoff_secs: u64,
+}
+
+impl Default for TcpConfig {
+    fn default() -> TcpConfig {
+        TcpConfig {
+            address: None,
+            connections: NonZeroUsize::MIN,
+            timeout_secs: 2,
+            open_attempts: 5,
+            open_backoff_secs: 1,
+        }
+    }
+}
+
+impl TcpConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    pub fn open_backoff(&self) -> Duration {
+        Duration::from_secs(self.open_backoff_secs)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub ip_addr: [u8; 4],
+    pub port: u16,
+    /// Required as a bearer token by routes that act on the inverter or collector.
+    pub api_token: Option<String>,
+    /// Label every metric with the inverter's serial number.
+    pub serial_label: bool,
+    /// Read the inverter's model and firmware version at startup, for the version route.
+    pub inverter_version: bool,
+    /// Label every metric with the inverter's model and firmware version.
+    pub version_labels: bool,
+    /// How long HTTP clients have to send a request's headers before being disconnected.
+    pub header_timeout_secs: u64,
+    /// Keep HTTP connections open between requests.
+    pub keep_alive: bool,
+    /// Serve only `/metrics` and the healthcheck, without the sensor API.
+    pub metrics_only: bool,
+    /// Basic auth or a bearer token required to scrape `/metrics`.
+    pub metrics_auth: Option<MetricsAuth>,
+    /// Labels added to every metric, eg. `metric_labels = { site = "home" }`.
+    pub metric_labels: BTreeMap<String, String>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> NetworkConfig {
+        NetworkConfig {
+            ip_addr: [127, 0, 0, 1],
+            port: 8080,
+            api_token: None,
+            serial_label: false,
+            inverter_version: false,
+            version_labels: false,
+            header_timeout_secs: HTTP_HEADER_TIMEOUT.as_secs(),
+            keep_alive: true,
+            metrics_only: false,
+            metrics_auth: None,
+            metric_labels: BTreeMap::new(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    pub fn address(&self) -> ([u8; 4], u16) {
+        (self.ip_addr, self.port)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectionConfig {
+    pub interval_secs: u64,
+    /// Defaults to the collection interval.
+    pub cycle_timeout_secs: Option<u64>,
+    pub read_throttle_secs: u64,
+    /// Serve API reads from the last collected values only, leaving the bus to the data
+    /// collector. Sensors are unavailable through the API until they've been collected.
+    pub cached_reads_only: bool,
+    /// Put each cycle back by a random wait of up to this many milliseconds, so exporters
+    /// sharing a gateway don't all poll it at once.
+    pub jitter_ms: u64,
+    /// How many reads in a row must go unanswered before the link is reported down, so a
+    /// single dropped frame doesn't flap the healthcheck.
+    pub degraded_after: usize,
+    pub history_depth: usize,
+    /// Track each sensor's minimum and maximum over windows this long, eg. 86400 for daily
+    /// peaks. Off by default, or if zero.
+    pub window_secs: Option<u64>,
+    pub state_file: Option<PathBuf>,
+    /// Slugs of sensors to leave out entirely, eg. ones the inverter model doesn't have.
+    pub disabled_sensors: Vec<String>,
+    /// Only read the sensors when `/metrics` is scraped, at most once per interval, rather
+    /// than polling them in the background.
+    pub on_scrape: bool,
+    /// Log collect requests that wait longer than this for room in the data collector's
+    /// queue.
+    pub backpressure_alert_secs: Option<u64>,
+}
+
+impl Default for CollectionConfig {
+    fn default() -> CollectionConfig {
+        CollectionConfig {
+            interval_secs: COLLECT_INTERVAL.as_secs(),
+            cycle_timeout_secs: None,
+            read_throttle_secs: 0,
+            cached_reads_only: false,
+            jitter_ms: 0,
+            degraded_after: DEGRADED_AFTER,
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            window_secs: None,
+            state_file: None,
+            disabled_sensors: Vec::new(),
+            on_scrape: false,
+            backpressure_alert_secs: None,
+        }
+    }
+}
+
+impl CollectionConfig {
+    /// Refuse a zero interval, which there's no ticking at, and a cycle timeout longer
+    /// than the interval, which would let cycles run into each other.
+    pub fn check(&self) -> io::Result<()> {
+        if self.interval_secs == 0 {
+            return Err(invalid_input(
+                "collection.interval_secs must be at least 1".to_string(),
+            ));
+        }
+        match self.cycle_timeout_secs {
+            Some(timeout) if timeout > self.interval_secs => Err(invalid_input(format!(
+                "collection.cycle_timeout_secs of {} is longer than the {}s interval",
+                timeout, self.interval_secs
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Write every reading to stdout as a line of JSON.
+    pub readings_to_stdout: bool,
+}
+
+/// Set `section.field` in a config table. The value is parsed as TOML where possible, so
+/// numbers, booleans and arrays come through typed, and is taken as a string otherwise.
+fn set_override(table: &mut toml::Table, key: &str, value: &str) -> io::Result<()> {
+    let (section, field) = key
+        .split_once('.')
+        .ok_or_else(|| invalid_input(format!("expected section.key, got {}", key)))?;
+    let value = format!("value = {}", value)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut t| t.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_owned()));
+
+    match table
+        .entry(section)
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        .as_table_mut()
+    {
+        Some(section) => {
+            section.insert(field.to_owned(), value);
+            Ok(())
+        }
+        None => Err(invalid_input(format!(
+            "{} is not a config section",
+            section
+        ))),
+    }
+}
+
+impl AppConfig {
+    pub fn from_toml(s: &str) -> Result<AppConfig, toml::de::Error> {
+        toml::from_str(s)
+    }
+
+    /// Build the config from, in increasing order of precedence:
+    /// * the defaults
+    /// * the file given by `--config <path>` or `SAMSYNK_CONFIG`
+    /// * `SAMSYNK_<SECTION>_<KEY>` environment variables, eg. `SAMSYNK_NETWORK_PORT=9100`
+    /// * `--set <section>.<key>=<value>` arguments, eg. `--set serial.baud_rate=19200`
+    pub fn load(
+        args: impl IntoIterator<Item = String>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<AppConfig, Box<dyn Error>> {
+        let mut config_path = None;
+        let mut overrides = Vec::new();
+
+        for (name, value) in vars.into_iter() {
+            if name == CONFIG_PATH_VAR {
+                config_path = Some(PathBuf::from(value));
+            } else if let Some((section, field)) = name
+                .strip_prefix(ENV_PREFIX)
+                .and_then(|key| key.split_once('_'))
+            {
+                overrides.push((
+                    format!("{}.{}", section.to_lowercase(), field.to_lowercase()),
+                    value,
+                ));
+            }
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| invalid_input("--config needs a path".to_string()))?;
+                    config_path = Some(PathBuf::from(path));
+                }
+                "--set" => {
+                    let setting = args.next().unwrap_or_default();
+                    let (key, value) = setting.split_once('=').ok_or_else(|| {
+                        invalid_input("--set needs a <section>.<key>=<value>".to_string())
+                    })?;
+                    overrides.push((key.to_owned(), value.to_owned()));
+                }
+                _ => return Err(invalid_input(format!("unknown argument {}", arg)).into()),
+            }
+        }
+
+        let mut table = match &config_path {
+            Some(path) => fs::read_to_string(path)?.parse::<toml::Table>()?,
+            None => toml::Table::new(),
+        };
+        for (key, value) in overrides.iter() {
+            set_override(&mut table, key, value)?;
+        }
+        let config: AppConfig = toml::Value::Table(table).try_into()?;
+        config.collection.check()?;
+        Ok(AppConfig {
+            path: config_path,
+            ..config
+        })
+    }
+
+    /// Where the sensor definitions came from: the config file, or the built-in set.
+    pub fn sensor_map_source(&self) -> String {
+        match (self.sensors.is_empty(), &self.path) {
+            (true, _) => BUILTIN_SENSOR_MAP.to_string(),
+            (false, Some(path)) => path.display().to_string(),
+            (false, None) => "config".to_string(),
+        }
+    }
+
+    /// One line summing up the settings that matter most at startup, so a misconfiguration
+    /// shows in the log, eg. the wrong port or slave id. Credentials are left out, only
+    /// whether they're set is given.
+    pub fn summary(&self, sensor_count: usize) -> String {
+        let serial = &self.serial;
+        let format = match serial.format() {
+            Ok(format) => format.to_string(),
+            Err(_) => "invalid".to_string(),
+        };
+        let [a, b, c, d] = self.network.ip_addr;
+        let metrics_auth = match &self.network.metrics_auth {
+            Some(MetricsAuth::Basic { .. }) => "basic",
+            Some(MetricsAuth::Bearer { .. }) => "bearer",
+            None => "none",
+        };
+        let transport = match self.tcp.address {
+            Some(gateway) => format!(
+                "transport=tcp gateway={} connections={}",
+                gateway, self.tcp.connections
+            ),
+            None => format!(
+                "transport=rtu tty={} baud={} format={}",
+                serial.tty_path, serial.baud_rate, format
+            ),
+        };
+        format!(
+            "{} slave={} address={}.{}.{}.{}:{} \
+             interval={}s sensors={} sensor_map={} api_token={} metrics_auth={}",
+            transport,
+            serial.slave,
+            a,
+            b,
+            c,
+            d,
+            self.network.port,
+            self.collection.interval_secs,
+            sensor_count,
+            self.sensor_map_source(),
+            match self.network.api_token {
+                Some(_) => "set",
+                None => "none",
+            },
+            metrics_auth,
+        )
+    }
+
+    pub fn server_options(&self) -> ServerOptions {
+        let mut sinks: Vec<Arc<dyn OutputSink>> = vec![Arc::new(PrometheusSink)];
+        if self.logging.readings_to_stdout {
+            sinks.push(Arc::new(JsonLinesSink::stdout()));
+        }
+
+        ServerOptions {
+            sinks,
+            read_throttle: Duration::from_secs(self.collection.read_throttle_secs),
+            cached_reads_only: self.collection.cached_reads_only,
+            state_file: self.collection.state_file.clone(),
+            history_depth: self.collection.history_depth,
+            aggregation_window: self
+                .collection
+                .window_secs
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            collect_interval: Duration::from_secs(self.collection.interval_secs),
+            cycle_timeout: self.collection.cycle_timeout_secs.map(Duration::from_secs),
+            collect_jitter: Duration::from_millis(self.collection.jitter_ms),
+            degraded_after: self.collection.degraded_after,
+            api_token: self.network.api_token.clone(),
+            serial_label: self.network.serial_label,
+            inverter_version: self.network.inverter_version,
+            version_labels: self.network.version_labels,
+            http_header_timeout: Duration::from_secs(self.network.header_timeout_secs),
+            http_keep_alive: self.network.keep_alive,
+            metrics_only: self.network.metrics_only,
+            metrics_auth: self.network.metrics_auth.clone(),
+            metric_labels: self.network.metric_labels.clone().into_iter().collect(),
+            collect_on_scrape: self.collection.on_scrape,
+            backpressure_alert: self
+                .collection
+                .backpressure_alert_secs
+                .map(Duration::from_secs),
+            sensor_map_source: self.sensor_map_source(),
+            ..ServerOptions::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_config() {
+        let config = AppConfig::from_toml(
+            r#"
+            [serial]
+            tty_path = "/dev/ttyAMA0"
+            baud_rate = 19200
+            slave = 2
+
+            [tcp]
+            address = "192.168.1.20:502"
+            timeout_secs = 5
+
+            [network]
+            ip_addr = [0, 0, 0, 0]
+            port = 9100
+            metric_labels = { site = "home" }
+
+            [collection]
+            interval_secs = 30
+            state_file = "/var/lib/samsynk/state.json"
+            disabled_sensors = ["grid_ct_power"]
+
+            [logging]
+            readings_to_stdout = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.serial,
+            SerialConfig {
+                tty_path: "/dev/ttyAMA0".to_string(),
+                baud_rate: 19200,
+                slave: 2,
+                ..SerialConfig::default()
+            }
+        );
+        assert_eq!(config.tcp.address, Some(([192, 168, 1, 20], 502).into()));
+        assert_eq!(config.tcp.timeout(), Duration::from_secs(5));
+        assert_eq!(config.network.address(), ([0, 0, 0, 0], 9100));
+        assert_eq!(
+            config.server_options().metric_labels,
+            [("site".to_string(), "home".to_string())]
+        );
+        assert_eq!(config.collection.interval_secs, 30);
+        assert_eq!(config.collection.read_throttle_secs, 0);
+        assert_eq!(
+            config.collection.state_file,
+            Some(PathBuf::from("/var/lib/samsynk/state.json"))
+        );
+        assert_eq!(config.collection.disabled_sensors, vec!["grid_ct_power"]);
+        assert!(config.logging.readings_to_stdout);
+    }
+
+    #[test]
+    fn summary_leaves_out_credentials() {
+        let config = AppConfig::from_toml(
+            r#"
+            [serial]
+            tty_path = "/dev/ttyAMA0"
+            slave = 3
+            format = "8E1"
+
+            [network]
+            port = 9100
+            api_token = "api-secret"
+            metrics_auth = { token = "metrics-secret" }
+            "#,
+        )
+        .unwrap();
+
+        let summary = config.summary(42);
+        assert_eq!(
+            summary,
+            "transport=rtu tty=/dev/ttyAMA0 baud=9600 format=8E1 slave=3 \
+             address=127.0.0.1:9100 interval=10s sensors=42 sensor_map=builtin api_token=set \
+             metrics_auth=bearer"
+        );
+        assert!(!summary.contains("secret"));
+
+        let config = AppConfig::from_toml(
+            r#"
+            [serial]
+            slave = 2
+
+            [tcp]
+            address = "192.168.1.20:502"
+            connections = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.summary(42),
+            "transport=tcp gateway=192.168.1.20:502 connections=2 slave=2 address=127.0.0.1:8080 \
+             interval=10s sensors=42 sensor_map=builtin api_token=none metrics_auth=none"
+        );
+    }
+
+    #[test]
+    fn slave_ids_must_be_unit_ids() {
+        let slave = |slave, broadcast| {
+            SerialConfig {
+                slave,
+                broadcast,
+                ..SerialConfig::default()
+            }
+            .slave()
+            .map(|Slave(id)| id)
+            .map_err(|e| e.kind())
+        };
+        assert_eq!(slave(1, false), Ok(1));
+        assert_eq!(slave(247, false), Ok(247));
+        assert_eq!(slave(0, false), Err(io::ErrorKind::InvalidInput));
+        assert_eq!(slave(248, false), Err(io::ErrorKind::InvalidInput));
+
+        // Broadcast only opens up 0, the rest stay reserved.
+        assert_eq!(slave(0, true), Ok(0));
+        assert_eq!(slave(248, true), Err(io::ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn tcp_needs_a_connection() {
+        let connections = |count| {
+            AppConfig::from_toml(&format!("[tcp]\nconnections = {}", count))
+                .map(|config| config.tcp.connections.get())
+        };
+        assert_eq!(connections(2), Ok(2));
+        assert!(connections(0).is_err());
+    }
+
+    #[test]
+    fn collection_needs_an_interval_to_time_out_within() {
+        let load = |settings: &[&str]| {
+            let args = settings.iter().flat_map(|setting| ["--set", setting]);
+            AppConfig::load(args.map(String::from), Vec::new())
+        };
+        assert!(load(&["collection.interval_secs=0"]).is_err());
+        assert!(load(&["collection.cycle_timeout_secs=11"]).is_err());
+        let config = load(&[
+            "collection.interval_secs=30",
+            "collection.cycle_timeout_secs=30",
+        ])
+        .unwrap();
+        assert_eq!(config.collection.cycle_timeout_secs, Some(30));
+    }
+
+    #[test]
+    fn env_and_args_override_defaults() {
+        let args = [
+            "--set",
+            "serial.baud_rate=19200",
+            "--set",
+            "network.port=9200",
+        ];
+        let vars = [
+            ("SAMSYNK_NETWORK_PORT", "9100"),
+            ("SAMSYNK_SERIAL_TTY_PATH", "/dev/ttyUSB1"),
+            ("PATH", "/usr/bin"),
+        ];
+
+        let config = AppConfig::load(
+            args.into_iter().map(String::from),
+            vars.into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+        )
+        .unwrap();
+
+        assert_eq!(config.serial.tty_path, "/dev/ttyUSB1");
+        assert_eq!(config.serial.baud_rate, 19200);
+        // Arguments take precedence over the environment.
+        assert_eq!(config.network.port, 9200);
+        assert_eq!(config.collection, CollectionConfig::default());
+    }
+}
@@ -0,0 +1,574 @@
+use crate::correlation::log;
+use async_trait::async_trait;
+use prometheus::{IntCounter, Registry};
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use tokio_modbus::client::{Client, Context};
+use tokio_modbus::prelude::{Request, Response, Slave, SlaveContext};
+
+/// How many requests in a row go unanswered before the link counts as down, by default.
+pub const DEGRADED_AFTER: usize = 1;
+
+/// The longest wait between attempts to open a device, or reopen one that has gone away.
+const MAX_OPEN_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Status {
+    answered: AtomicBool,
+    /// Unanswered requests since the last answered one.
+    failures: AtomicUsize,
+    degraded_after: usize,
+    last_success: RwLock<Option<SystemTime>>,
+}
+
+/// Whether the link to the inverter is up, shared between the data collector, which
+/// updates it, and anything that reports on it. Clones share the same status.
+///
+/// The link counts as down until the first successful request.
+#[derive(Clone, Debug)]
+pub struct ConnectionStatus(Arc<Status>);
+
+impl Default for ConnectionStatus {
+    fn default() -> ConnectionStatus {
+        ConnectionStatus::degraded_after(DEGRADED_AFTER)
+    }
+}
+
+impl ConnectionStatus {
+    /// A status that counts the link as down once `failures` requests in a row have gone
+    /// unanswered, so an occasional dropped frame doesn't flap the healthcheck.
+    pub fn degraded_after(failures: usize) -> ConnectionStatus {
+        assert!(failures > 0, "a link can only go down after a failure");
+        ConnectionStatus(Arc::new(Status {
+            answered: AtomicBool::new(false),
+            failures: AtomicUsize::new(0),
+            degraded_after: failures,
+            last_success: RwLock::default(),
+        }))
+    }
+
+    /// Record that the inverter answered a request. Exception responses count, as the
+    /// inverter still answered.
+    pub fn mark_success(&self) {
+        *self.0.last_success.write().unwrap() = Some(SystemTime::now());
+        self.0.failures.store(0, Ordering::Relaxed);
+        self.0.answered.store(true, Ordering::Relaxed);
+    }
+
+    /// Record that a request went unanswered, eg. it timed out or the port is gone.
+    pub fn mark_failure(&self) {
+        self.0.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether the inverter has answered, and fewer requests than the threshold have gone
+    /// unanswered since.
+    pub fn is_healthy(&self) -> bool {
+        self.0.answered.load(Ordering::Relaxed)
+            && self.0.failures.load(Ordering::Relaxed) < self.0.degraded_after
+    }
+
+    /// When the inverter last answered a request, if it ever has.
+    pub fn last_success(&self) -> Option<SystemTime> {
+        *self.0.last_success.read().unwrap()
+    }
+}
+
+/// Open a connection with `open`, trying up to `attempts` times, eg. for a USB adapter
+/// that only appears a moment after the exporter starts, or a gateway that's still booting.
+/// The wait between attempts starts at `backoff` and doubles after each failure, up to
+/// `MAX_OPEN_BACKOFF`. The last failure is returned if none work.
+pub async fn open_with_retry<T, E: Display, O: Future<Output = Result<T, E>>>(
+    attempts: u32,
+    backoff: Duration,
+    mut open: impl FnMut() -> O,
+) -> Result<T, E> {
+    let mut wait = backoff;
+    let mut attempt = 1;
+    loop {
+        match open().await {
+            Ok(connection) => return Ok(connection),
+            Err(e) if attempt < attempts => {
+                eprintln!(
+                    "could not connect (attempt {} of {}), retrying in {:?}: {}",
+                    attempt, attempts, wait, e
+                );
+            }
+            Err(e) => return Err(e),
+        }
+        tokio::time::sleep(wait).await;
+        wait = wait.saturating_mul(2).min(MAX_OPEN_BACKOFF);
+        attempt += 1;
+    }
+}
+
+/// Whether `e` means the device behind a transport has gone away, eg. a USB adapter was
+/// unplugged or a gateway closed the connection, rather than that a request went
+/// unanswered.
+pub fn is_device_gone(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotFound
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// A Modbus transport that's reopened with `open` when its device goes away, eg. a USB
+/// serial adapter that was unplugged and plugged back in, or a TCP gateway that dropped the
+/// connection. Requests while it's gone fail straight away rather than hammering the dead
+/// port. The first request after a backoff tries to reopen it, with the backoff doubling
+/// after each failed attempt, up to `MAX_OPEN_BACKOFF`.
+pub struct ReconnectingTransport<C, F> {
+    open: F,
+    connection: Option<C>,
+    /// Set on the connection again when it's reopened.
+    slave: Option<Slave>,
+    backoff: Duration,
+    wait: Duration,
+    next_attempt: Instant,
+    /// How long a request may go unanswered before it's given up on.
+    timeout: Option<Duration>,
+}
+
+impl<C, F, O> ReconnectingTransport<C, F>
+where
+    C: Client,
+    F: FnMut() -> O + Send,
+    O: Future<Output = io::Result<C>> + Send,
+{
+    /// Wrap a connection already opened with `open`, waiting `backoff` before the first
+    /// attempt to reopen it.
+    pub fn new(connection: C, backoff: Duration, open: F) -> ReconnectingTransport<C, F> {
+        ReconnectingTransport {
+            open,
+            connection: Some(connection),
+            slave: None,
+            backoff,
+            wait: backoff,
+            next_attempt: Instant::now(),
+            timeout: None,
+        }
+    }
+
+    /// Give up on requests that go unanswered for `timeout`, eg. to a gateway that has
+    /// stopped answering without closing the connection. The connection is reopened for the
+    /// next request, as the answer to the one given up on may still turn up on it.
+    pub fn with_timeout(mut self, timeout: Duration) -> ReconnectingTransport<C, F> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The open connection, reopening it if it's gone and the backoff has passed.
+    async fn connection(&mut self) -> io::Result<&mut C> {
+        if self.connection.is_none() {
+            if Instant::now() < self.next_attempt {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "device gone, waiting to reopen it",
+                ));
+            }
+            match (self.open)().await {
+                Ok(mut connection) => {
+                    eprintln!("device reopened");
+                    if let Some(slave) = self.slave {
+                        connection.set_slave(slave);
+                    }
+                    self.connection = Some(connection);
+                    self.wait = self.backoff;
+                }
+                Err(e) => {
+                    self.wait = self.wait.saturating_mul(2).min(MAX_OPEN_BACKOFF);
+                    self.next_attempt = Instant::now() + self.wait;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self
+            .connection
+            .as_mut()
+            .expect("the connection was just opened"))
+    }
+}
+
+impl<C, F> fmt::Debug for ReconnectingTransport<C, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReconnectingTransport")
+            .field("connected", &self.connection.is_some())
+            .field("next_attempt", &self.next_attempt)
+            .finish()
+    }
+}
+
+impl<C, F, O> SlaveContext for ReconnectingTransport<C, F>
+where
+    C: Client,
+    F: FnMut() -> O + Send,
+    O: Future<Output = io::Result<C>> + Send,
+{
+    fn set_slave(&mut self, slave: Slave) {
+        self.slave = Some(slave);
+        if let Some(connection) = &mut self.connection {
+            connection.set_slave(slave)
+        }
+    }
+}
+
+#[async_trait]
+impl<C, F, O> Client for ReconnectingTransport<C, F>
+where
+    C: Client,
+    F: FnMut() -> O + Send,
+    O: Future<Output = io::Result<C>> + Send,
+{
+    async fn call(&mut self, request: Request<'_>) -> io::Result<Response> {
+        let timeout = self.timeout;
+        let connection = self.connection().await?;
+        let result = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, connection.call(request)).await {
+                Ok(result) => result,
+                Err(_) => {
+                    log(format_args!("no answer in {:?}, reconnecting", timeout));
+                    self.connection = None;
+                    self.next_attempt = Instant::now();
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "no answer in time"));
+                }
+            },
+            None => connection.call(request).await,
+        };
+        if let Err(e) = &result {
+            if is_device_gone(e) {
+                log(format_args!(
+                    "device gone, reopening it in {:?}: {}",
+                    self.wait, e
+                ));
+                self.connection = None;
+                self.next_attempt = Instant::now() + self.wait;
+            }
+        }
+        result
+    }
+}
+
+/// A Modbus transport that counts the requests sent over it, eg. to see how busy an RTU bus
+/// is. Every read and write is one request, however many registers it covers.
+#[derive(Debug)]
+pub struct CountedTransport<C> {
+    inner: C,
+    transactions: IntCounter,
+}
+
+/// The counter for `CountedTransport`s, served from `registry` as
+/// `samsynk_modbus_transactions_total`. Transports to the same inverter share one.
+pub fn transaction_counter(registry: &Registry) -> prometheus::Result<IntCounter> {
+    let counter = IntCounter::new(
+        "samsynk_modbus_transactions_total",
+        "Modbus requests sent to the inverter, answered or not",
+    )?;
+    registry.register(Box::new(counter.clone()))?;
+    Ok(counter)
+}
+
+impl<C: Client> CountedTransport<C> {
+    /// Count requests on `transactions`, eg. from `transaction_counter`.
+    pub fn new(inner: C, transactions: IntCounter) -> CountedTransport<C> {
+        CountedTransport {
+            inner,
+            transactions,
+        }
+    }
+}
+
+impl<C: Client> SlaveContext for CountedTransport<C> {
+    fn set_slave(&mut self, slave: Slave) {
+        self.inner.set_slave(slave)
+    }
+}
+
+#[async_trait]
+impl<C: Client> Client for CountedTransport<C> {
+    async fn call(&mut self, request: Request<'_>) -> io::Result<Response> {
+        self.transactions.inc();
+        self.inner.call(request).await
+    }
+}
+
+/// One unit behind a Modbus TCP gateway that routes to several RS-485 units by unit id, eg.
+/// a Waveshare or USR gateway. Each request sets the unit id on the shared context while
+/// holding its lock, so requests for other units can't be sent with this one's id.
+#[derive(Debug)]
+pub struct UnitContext {
+    shared: Arc<Mutex<Context>>,
+    unit: Slave,
+}
+
+impl UnitContext {
+    pub fn new(shared: Arc<Mutex<Context>>, unit: Slave) -> UnitContext {
+        UnitContext { shared, unit }
+    }
+}
+
+impl SlaveContext for UnitContext {
+    fn set_slave(&mut self, slave: Slave) {
+        self.unit = slave
+    }
+}
+
+#[async_trait]
+impl Client for UnitContext {
+    async fn call(&mut self, request: Request<'_>) -> io::Result<Response> {
+        let mut shared = self.shared.lock().await;
+        shared.set_slave(self.unit);
+        shared.call(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::ClientMock;
+    use crate::sensor::{shared_context, BasicSensor, Sensor, SensorRead, SensorWrite};
+    use std::sync::atomic::AtomicU16;
+
+    /// A transport that fails to open `failures` times before opening.
+    fn late_device(
+        failures: u32,
+    ) -> impl FnMut() -> std::future::Ready<Result<&'static str, String>> {
+        let mut opens = 0;
+        move || {
+            opens += 1;
+            std::future::ready(match opens > failures {
+                true => Ok("connected"),
+                false => Err("no such device".to_string()),
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connections_are_retried_with_backoff() {
+        let start = Instant::now();
+        let backoff = Duration::from_secs(1);
+        assert_eq!(
+            open_with_retry(5, backoff, late_device(2)).await,
+            Ok("connected")
+        );
+        // 1s, then 2s.
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+
+        assert_eq!(
+            open_with_retry(2, backoff, late_device(2)).await,
+            Err("no such device".to_string())
+        );
+
+        // 40s, then the cap twice.
+        let start = Instant::now();
+        let backoff = Duration::from_secs(40);
+        assert_eq!(
+            open_with_retry(4, backoff, late_device(3)).await,
+            Ok("connected")
+        );
+        assert_eq!(
+            start.elapsed(),
+            Duration::from_secs(40) + MAX_OPEN_BACKOFF * 2
+        );
+    }
+
+    /// A port that answers every read with 1 while `plugged` is set, and is gone otherwise.
+    #[derive(Debug)]
+    struct UsbPort {
+        plugged: Arc<AtomicBool>,
+    }
+
+    impl SlaveContext for UsbPort {
+        fn set_slave(&mut self, _slave: Slave) {}
+    }
+
+    #[async_trait]
+    impl Client for UsbPort {
+        async fn call(&mut self, _request: Request<'_>) -> io::Result<Response> {
+            match self.plugged.load(Ordering::Relaxed) {
+                true => Ok(Response::ReadHoldingRegisters(vec![1])),
+                false => Err(io::Error::new(io::ErrorKind::BrokenPipe, "port gone")),
+            }
+        }
+    }
+
+    #[test]
+    fn status_degrades_after_failures_in_a_row() {
+        let status = ConnectionStatus::degraded_after(3);
+        status.mark_success();
+        status.mark_failure();
+        status.mark_failure();
+        assert!(status.is_healthy());
+        // An answer starts the count again.
+        status.mark_success();
+        status.mark_failure();
+        status.mark_failure();
+        assert!(status.is_healthy());
+        status.mark_failure();
+        assert!(!status.is_healthy());
+        status.mark_success();
+        assert!(status.is_healthy());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gone_devices_are_reopened_with_backoff() {
+        let plugged = Arc::new(AtomicBool::new(true));
+        let opens = Arc::new(AtomicU16::new(0));
+        let open = {
+            let (plugged, opens) = (plugged.clone(), opens.clone());
+            move || {
+                opens.fetch_add(1, Ordering::Relaxed);
+                std::future::ready(match plugged.load(Ordering::Relaxed) {
+                    true => Ok(UsbPort {
+                        plugged: plugged.clone(),
+                    }),
+                    false => Err(io::Error::new(io::ErrorKind::NotFound, "no such device")),
+                })
+            }
+        };
+        let port = UsbPort {
+            plugged: plugged.clone(),
+        };
+        let backoff = Duration::from_secs(1);
+        let ctx = shared_context(ReconnectingTransport::new(port, backoff, open));
+        let sensor = BasicSensor(Sensor::new("Unplugged Sensor", &[1190], 1, false));
+        let status = ConnectionStatus::default();
+        let read = || async {
+            let result = sensor.read_value(ctx.clone()).await;
+            match &result {
+                Ok(_) => status.mark_success(),
+                Err(_) => status.mark_failure(),
+            }
+            result.is_ok()
+        };
+
+        assert!(read().await);
+        plugged.store(false, Ordering::Relaxed);
+        assert!(!read().await);
+        assert!(!status.is_healthy());
+        // The port isn't touched again until the backoff has passed.
+        assert!(!read().await);
+        assert_eq!(opens.load(Ordering::Relaxed), 0);
+
+        tokio::time::advance(backoff).await;
+        assert!(!read().await);
+        assert_eq!(opens.load(Ordering::Relaxed), 1);
+        // The device comes back, but the next attempt waits out the doubled backoff.
+        plugged.store(true, Ordering::Relaxed);
+        tokio::time::advance(backoff).await;
+        assert!(!read().await);
+        tokio::time::advance(backoff).await;
+        assert!(read().await);
+        assert!(status.is_healthy());
+        assert_eq!(opens.load(Ordering::Relaxed), 2);
+    }
+
+    /// A connection to a gateway that stops answering, without closing the connection, once
+    /// `silent` is set.
+    #[derive(Debug)]
+    struct GatewayConnection {
+        silent: Arc<AtomicBool>,
+    }
+
+    impl SlaveContext for GatewayConnection {
+        fn set_slave(&mut self, _slave: Slave) {}
+    }
+
+    #[async_trait]
+    impl Client for GatewayConnection {
+        async fn call(&mut self, _request: Request<'_>) -> io::Result<Response> {
+            if self.silent.load(Ordering::Relaxed) {
+                std::future::pending::<()>().await;
+            }
+            Ok(Response::ReadHoldingRegisters(vec![1]))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unanswered_requests_time_out_and_reconnect() {
+        let silent = Arc::new(AtomicBool::new(false));
+        let opens = Arc::new(AtomicU16::new(0));
+        let open = {
+            let opens = opens.clone();
+            move || {
+                opens.fetch_add(1, Ordering::Relaxed);
+                std::future::ready(Ok(GatewayConnection {
+                    silent: Arc::default(),
+                }))
+            }
+        };
+        let connection = GatewayConnection {
+            silent: silent.clone(),
+        };
+        let timeout = Duration::from_secs(2);
+        let transport = ReconnectingTransport::new(connection, Duration::from_secs(1), open)
+            .with_timeout(timeout);
+        let ctx = shared_context(transport);
+        let sensor = BasicSensor(Sensor::new("Gateway Sensor", &[1207], 1, false));
+
+        assert!(sensor.read_value(ctx.clone()).await.is_ok());
+        silent.store(true, Ordering::Relaxed);
+        let start = Instant::now();
+        assert!(sensor.read_value(ctx.clone()).await.is_err());
+        assert_eq!(start.elapsed(), timeout);
+        // The next request goes over a new connection straight away.
+        assert!(sensor.read_value(ctx.clone()).await.is_ok());
+        assert_eq!(opens.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn each_request_is_counted() {
+        let mut client = ClientMock::default();
+        client.set_register(1110, 1);
+        client.set_register(1111, 0);
+        client.set_register(1113, 2);
+        client.set_next_request(Ok(Request::WriteSingleRegister(1114, 3)));
+        let transactions = IntCounter::new("transactions", "Modbus requests").unwrap();
+        let ctx = shared_context(CountedTransport::new(client, transactions.clone()));
+
+        // The consecutive registers are read together, the others one by one.
+        let sensors = [
+            BasicSensor(Sensor::new("Counted Energy", &[1110, 1111], 1, false)),
+            BasicSensor(Sensor::new("Counted Gaps", &[1111, 1113], 1, false)),
+        ];
+        for sensor in sensors.iter() {
+            sensor.read_value(ctx.clone()).await.unwrap();
+        }
+        assert_eq!(transactions.get(), 3);
+
+        Sensor::new_mut("Counted Setting", &[1114], 1, false)
+            .write(ctx, AtomicU16::new(3))
+            .await
+            .unwrap();
+        assert_eq!(transactions.get(), 4);
+    }
+
+    #[test]
+    fn status_follows_the_last_request() {
+        let status = ConnectionStatus::default();
+        assert!(!status.is_healthy());
+        assert_eq!(status.last_success(), None);
+
+        let shared = status.clone();
+        shared.mark_success();
+        assert!(status.is_healthy());
+        let last_success = status.last_success().unwrap();
+
+        shared.mark_failure();
+        assert!(!status.is_healthy());
+        // A failure doesn't forget when the link was last up.
+        assert_eq!(status.last_success(), Some(last_success));
+
+        shared.mark_success();
+        assert!(status.is_healthy());
+        assert!(status.last_success().unwrap() >= last_success);
+    }
+}
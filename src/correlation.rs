@@ -0,0 +1,85 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Response header with the correlation id of the request's reads.
+pub const CORRELATION_HEADER: &str = "x-samsynk-correlation-id";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    static CURRENT: CorrelationId;
+}
+
+/// Ties the log lines of a read, down to the Modbus queries it made, to the HTTP request or
+/// collection cycle that asked for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CorrelationId(u64);
+
+impl CorrelationId {
+    /// A new id, unique to this process.
+    pub fn next() -> CorrelationId {
+        CorrelationId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// The id of the request or cycle being run, if any.
+    pub fn current() -> Option<CorrelationId> {
+        CURRENT.try_with(|id| *id).ok()
+    }
+
+    /// Run `f` with this as the current id, so everything it logs is tagged with it.
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+/// Log `message`, tagged with the current correlation id if there is one.
+pub fn log(message: impl fmt::Display) {
+    let line = match CorrelationId::current() {
+        Some(id) => format!("[{}] {}", id, message),
+        None => message.to_string(),
+    };
+    #[cfg(test)]
+    captured::LINES.with(|lines| lines.borrow_mut().push(line.clone()));
+    eprintln!("{}", line);
+}
+
+/// The lines logged on this thread, for tests to check what was logged.
+#[cfg(test)]
+pub(crate) mod captured {
+    use std::cell::RefCell;
+
+    thread_local! {
+        pub(super) static LINES: RefCell<Vec<String>> = RefCell::default();
+    }
+
+    /// Everything logged on this thread since the last call.
+    pub(crate) fn take() -> Vec<String> {
+        LINES.with(|lines| lines.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lines_are_tagged_within_a_scope() {
+        captured::take();
+        let id = CorrelationId::next();
+        id.scope(async { log("inside") }).await;
+        log("outside");
+
+        assert_eq!(
+            captured::take(),
+            vec![format!("[{}] inside", id), "outside".to_string()]
+        );
+        assert_ne!(CorrelationId::next(), id);
+    }
+}
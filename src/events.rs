@@ -0,0 +1,124 @@
+use crate::sensor::SensorValue;
+use crate::sink::{OutputSink, Reading};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/// How many fault events to keep for the events route.
+pub const RECENT_EVENTS: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FaultChange {
+    Raised,
+    Cleared,
+}
+
+/// A fault code of one of the fault sensors becoming active, or clearing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FaultEvent {
+    pub slug: String,
+    pub code: u16,
+    pub change: FaultChange,
+    pub timestamp: SystemTime,
+}
+
+/// The fault codes in a fault sensor's value, eg. `F1, F8`.
+pub(crate) fn fault_codes(value: &SensorValue) -> Option<HashSet<u16>> {
+    match value {
+        SensorValue::Text(text) => Some(
+            text.split(", ")
+                .filter_map(|code| code.strip_prefix('F')?.parse().ok())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Watches the fault sensors' readings for codes being raised and cleared, comparing each
+/// cycle's active faults with the last. Each event is logged as it happens, and the most
+/// recent are kept for the events route. A sensor that couldn't be read is left as it
+/// was, so a failed read doesn't clear its faults.
+#[derive(Clone)]
+pub struct FaultEvents {
+    slugs: Arc<HashSet<String>>,
+    active: Arc<RwLock<HashMap<String, HashSet<u16>>>>,
+    events: Arc<RwLock<VecDeque<FaultEvent>>>,
+}
+
+impl FaultEvents {
+    /// Events for the fault sensors with the given slugs. Other readings are ignored.
+    pub fn new(slugs: impl IntoIterator<Item = String>) -> FaultEvents {
+        FaultEvents {
+            slugs: Arc::new(slugs.into_iter().collect()),
+            active: Arc::default(),
+            events: Arc::default(),
+        }
+    }
+
+    /// The recent events, oldest first.
+    pub fn recent(&self) -> Vec<FaultEvent> {
+        self.events.read().unwrap().iter().cloned().collect()
+    }
+
+    fn record(&self, event: FaultEvent) {
+        let verb = match event.change {
+            FaultChange::Raised => "raised",
+            FaultChange::Cleared => "cleared",
+        };
+        eprintln!("fault F{} {} on {}", event.code, verb, event.slug);
+
+        let mut events = self.events.write().unwrap();
+        if events.len() == RECENT_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+}
+
+impl Default for FaultEvents {
+    fn default() -> FaultEvents {
+        FaultEvents::new([])
+    }
+}
+
+#[async_trait]
+impl OutputSink for FaultEvents {
+    async fn publish(&self, readings: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for reading in readings.iter() {
+            if !self.slugs.contains(&reading.slug) {
+                continue;
+            }
+            let Some(codes) = fault_codes(&reading.value) else {
+                continue;
+            };
+            let previous = self
+                .active
+                .write()
+                .unwrap()
+                .insert(reading.slug.clone(), codes.clone())
+                .unwrap_or_default();
+
+            let event = |code: u16, change| FaultEvent {
+                slug: reading.slug.clone(),
+                code,
+                change,
+                timestamp: reading.timestamp,
+            };
+            let mut cleared: Vec<_> = previous.difference(&codes).copied().collect();
+            cleared.sort();
+            let mut raised: Vec<_> = codes.difference(&previous).copied().collect();
+            raised.sort();
+            for code in cleared {
+                self.record(event(code, FaultChange::Cleared));
+            }
+            for code in raised {
+                self.record(event(code, FaultChange::Raised));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,22 @@
 use std::cmp::{Ord, Ordering};
+use std::io;
 
 /// Some values can go negative. We need to convert the unsigned 16-bit
 /// value into a signed one. The indication you haven't done this is values
 /// close to 2^16 in metrics, representing negative values.
+/// eg 0x7FFF -> 32767, 0x8000 -> -32768, 0xFFFF -> -1
 pub fn signed(raw_value: i64) -> i64 {
-    match raw_value.cmp(&0x7FFF) {
+    signed_bits(raw_value, 16)
+}
+
+/// As `signed`, for a two's complement value `bits` wide, eg. a 12-bit field packed into a
+/// register, or a 32-bit value spread over two.
+/// eg for 12 bits 0x7FF -> 2047, 0x800 -> -2048, 0xFFF -> -1
+pub fn signed_bits(raw_value: i64, bits: u32) -> i64 {
+    let max = (1 << (bits - 1)) - 1;
+    match raw_value.cmp(&max) {
         Ordering::Less | Ordering::Equal => raw_value,
-        Ordering::Greater => raw_value - 0xFFFF,
+        Ordering::Greater => raw_value - (1 << bits),
     }
 }
 
@@ -14,9 +24,27 @@ pub fn slug_name(name: &str) -> String {
     name.trim().to_lowercase().replace([' ', '-'], "_")
 }
 
+/// Whether `name` can name a Prometheus label: letters, digits and underscores, not
+/// starting with a digit. Names starting with `__` are reserved for Prometheus itself.
+pub fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_well = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    starts_well && chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !name.starts_with("__")
+}
+
+pub(crate) fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// The most holding registers Modbus allows in a single read.
+pub const MAX_READ_REGISTERS: u16 = 125;
+
 /// Given a list of registers, return a list containing the starting registers in a consective row,
 /// and the number of consecutive registers.
 /// eg [1, 2, 3, 5, 6, 9] -> [(1, 3), (5, 2), (9, 1)]
+/// Rows longer than `MAX_READ_REGISTERS` are split, so each can be read in one request.
 pub fn group_consecutive(mut registers: Vec<u16>) -> Vec<(u16, u16)> {
     let mut out: Vec<(u16, u16)> = Vec::new();
     let mut consecutive_number: u16 = 0;
@@ -25,7 +53,7 @@ pub fn group_consecutive(mut registers: Vec<u16>) -> Vec<(u16, u16)> {
     registers.sort();
     for reg in registers.iter() {
         if let Some(p) = prev_reg {
-            if (p + 1) == *reg {
+            if (p + 1) == *reg && consecutive_number < MAX_READ_REGISTERS {
                 consecutive_number += 1;
             } else {
                 out.push((starting_reg, consecutive_number));
@@ -45,6 +73,37 @@ pub fn group_consecutive(mut registers: Vec<u16>) -> Vec<(u16, u16)> {
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn label_names_follow_prometheus_rules() {
+        for name in ["site", "_region", "installer_2", "Site"] {
+            assert!(is_label_name(name), "{}", name);
+        }
+        for name in ["", "2nd_site", "site-name", "site name", "__name__", "sité"] {
+            assert!(!is_label_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_signed_boundaries() {
+        assert_eq!(signed(0), 0);
+        assert_eq!(signed(0x7FFF), 32767);
+        assert_eq!(signed(0x8000), -32768);
+        assert_eq!(signed(0xFFFF), -1);
+    }
+
+    #[test]
+    fn test_signed_bits_boundaries() {
+        assert_eq!(signed_bits(0, 12), 0);
+        assert_eq!(signed_bits(0x7FF, 12), 2047);
+        assert_eq!(signed_bits(0x800, 12), -2048);
+        assert_eq!(signed_bits(0xFFF, 12), -1);
+
+        assert_eq!(signed_bits(0x7F_FFFF, 24), 8_388_607);
+        assert_eq!(signed_bits(0x80_0000, 24), -8_388_608);
+        assert_eq!(signed_bits(0xFF_FFFF, 24), -1);
+    }
+
     #[test]
     fn test_group_consecutive() {
         let input = vec![1, 2, 3, 5, 6, 9];
@@ -60,4 +119,12 @@ mod tests {
         let out = group_consecutive(input);
         assert_eq!(out, expected_out);
     }
+
+    #[test]
+    fn test_group_consecutive_splits_long_rows() {
+        let input = (1000..1200).collect();
+        let out = group_consecutive(input);
+        assert_eq!(out, [(1000, 125), (1125, 75)]);
+        assert!(out.iter().all(|(_, len)| *len <= MAX_READ_REGISTERS));
+    }
 }
@@ -0,0 +1,50 @@
+use crate::sink::{OutputSink, Reading};
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+
+/// The last `depth` readings of each sensor, kept in memory for a quick look at recent
+/// values without a full time series database.
+#[derive(Clone)]
+pub struct SensorHistory {
+    depth: usize,
+    readings: Arc<RwLock<HashMap<String, VecDeque<Reading>>>>,
+}
+
+impl SensorHistory {
+    pub fn new(depth: usize) -> SensorHistory {
+        SensorHistory {
+            depth,
+            readings: Arc::default(),
+        }
+    }
+
+    /// The recent readings of `slug`, oldest first.
+    pub fn get(&self, slug: &str) -> Vec<Reading> {
+        match self.readings.read().unwrap().get(slug) {
+            Some(readings) => readings.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl OutputSink for SensorHistory {
+    async fn publish(&self, readings: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.depth == 0 {
+            return Ok(());
+        }
+        let mut history = self.readings.write().unwrap();
+        for reading in readings.iter() {
+            let sensor_history = history
+                .entry(reading.slug.clone())
+                .or_insert_with(|| VecDeque::with_capacity(self.depth));
+            if sensor_history.len() == self.depth {
+                sensor_history.pop_front();
+            }
+            sensor_history.push_back(reading.clone());
+        }
+        Ok(())
+    }
+}
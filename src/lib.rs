@@ -1,4 +1,29 @@
+pub mod api;
+pub mod bms;
+pub mod cache;
+pub mod capture;
+pub mod config;
+pub mod connection;
+pub mod correlation;
+pub mod events;
 pub mod helpers;
+pub mod history;
+#[cfg(test)]
+mod mock;
+pub mod modbus_error;
+pub mod pool;
+pub mod scaling;
+pub mod schedule;
 pub mod sensor;
+pub mod sensor_config;
 pub mod sensor_definitions;
+pub mod serial_format;
 pub mod server;
+pub mod sink;
+pub mod snapshot;
+pub mod state;
+#[cfg(feature = "systemd")]
+pub mod systemd;
+pub mod validate;
+pub mod watch;
+pub mod window;
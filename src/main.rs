@@ -1,43 +1,197 @@
+pub mod api;
+pub mod bms;
+pub mod cache;
+pub mod capture;
+pub mod config;
+pub mod connection;
+pub mod correlation;
+pub mod events;
 pub mod helpers;
+pub mod history;
+#[cfg(test)]
+mod mock;
+pub mod modbus_error;
+pub mod pool;
+pub mod scaling;
+pub mod schedule;
 pub mod sensor;
+pub mod sensor_config;
 pub mod sensor_definitions;
+pub mod serial_format;
 pub mod server;
+pub mod sink;
+pub mod snapshot;
+pub mod state;
+#[cfg(feature = "systemd")]
+pub mod systemd;
+pub mod validate;
+pub mod watch;
+pub mod window;
 
-use sensor::{register_sensors, SensorTypes};
-use std::collections::HashMap;
+use capture::FrameCapture;
+use config::{AppConfig, SerialConfig, TcpConfig};
+use connection::UnitContext;
+use pool::ContextPool;
+use prometheus::{IntCounter, Registry};
+use sensor::register_sensors_except;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tokio::time::Duration;
+use tokio_modbus::client::Context;
 use tokio_modbus::prelude::*;
-use tokio_serial::{DataBits, SerialStream, StopBits};
-
-const IP_ADDR: [u8; 4] = [127, 0, 0, 1];
-pub const TTY_PATH: &str = "/dev/ttyUSB0";
-const PORT: u16 = 8080;
-const BAUD_RATE: u32 = 9600;
-
-const SLAVE: Slave = Slave(1);
-const TIMEOUT: Duration = Duration::from_secs(2);
-const DATA_BITS: DataBits = DataBits::Eight;
-const STOP_BITS: StopBits = StopBits::One;
+use tokio_serial::SerialStream;
+use watch::WatchArgs;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    let sensors: HashMap<String, SensorTypes> = register_sensors();
+    if std::env::args().any(|arg| arg == "--dump-config") {
+        let dump = sensor_config::dump_builtin_definitions()
+            .unwrap_or_else(|e| panic!("Could not dump the sensor definitions: {}", e));
+        print!("{}", dump);
+        return;
+    }
+
+    let (check_sensors, args): (Vec<String>, Vec<String>) = std::env::args()
+        .skip(1)
+        .partition(|arg| arg == "--check-sensors");
+    let (watch, args) =
+        WatchArgs::take(args).unwrap_or_else(|e| panic!("Invalid arguments: {}", e));
+    let config = AppConfig::load(args, std::env::vars())
+        .unwrap_or_else(|e| panic!("Could not load config: {}", e));
 
-    let builder = tokio_serial::new(TTY_PATH, BAUD_RATE)
-        .stop_bits(STOP_BITS)
-        .data_bits(DATA_BITS)
-        .timeout(TIMEOUT);
-    let client_serial = SerialStream::open(&builder)
-        .unwrap_or_else(|_| panic!("Could not open port {}.", TTY_PATH));
+    let definitions = match config.sensors.is_empty() {
+        true => sensor_config::builtin_definitions(),
+        false => config.sensors.clone(),
+    };
+    let warnings = validate::validate_sensor_set(&definitions);
+    for warning in warnings.iter() {
+        eprintln!("sensor definitions: {}", warning);
+    }
+    if !check_sensors.is_empty() {
+        // Definitions that can't be built at all, eg. with a zero factor, fail it too.
+        let built = sensor_config::build_sensors::<&str>(&config.sensors, &[], &Registry::new());
+        if let Err(e) = &built {
+            eprintln!("sensor definitions: {}", e);
+        }
+        std::process::exit(if warnings.is_empty() && built.is_ok() {
+            0
+        } else {
+            1
+        });
+    }
 
-    let addr = (IP_ADDR, PORT);
+    let disabled = &config.collection.disabled_sensors;
+    let mut sensors = match config.sensors.is_empty() {
+        true => register_sensors_except(&sensor::REGISTRY, disabled),
+        false => sensor_config::build_sensors(&config.sensors, disabled, &sensor::REGISTRY)
+            .unwrap_or_else(|e| panic!("Invalid sensor definitions: {}", e)),
+    };
+    config.scaling.apply(&mut sensors);
+    eprintln!("starting: {}", config.summary(sensors.len()));
 
-    let ctx = Arc::new(Mutex::new(rtu::attach_slave(client_serial, SLAVE)));
+    let slave = config
+        .serial
+        .slave()
+        .unwrap_or_else(|e| panic!("Invalid slave id: {}", e));
+    let mut options = config.server_options();
+    let transactions = connection::transaction_counter(&options.registry)
+        .unwrap_or_else(|e| panic!("Could not register the transaction counter: {}", e));
+    let ctx = match config.tcp.address {
+        Some(gateway) => {
+            let mut connections = Vec::new();
+            for _ in 0..config.tcp.connections.get() {
+                let transactions = transactions.clone();
+                connections.push(connect_tcp(&config.tcp, gateway, slave, transactions).await);
+            }
+            // The first connection also serves the reads made at startup, which are done
+            // before the pool is used.
+            let ctx = connections[0].clone();
+            if connections.len() > 1 {
+                options.read_pool = Some(ContextPool::new(connections));
+            }
+            ctx
+        }
+        None => open_serial(&config.serial, slave, transactions).await,
+    };
 
-    let server = server::Server::new(ctx.clone(), addr, sensors)
+    if let Some(watch) = watch {
+        let sensor = sensors
+            .get(&watch.slug)
+            .unwrap_or_else(|| panic!("No sensor {}", watch.slug));
+        let mut stdout = std::io::stdout();
+        if let Err(e) =
+            watch::watch_sensor(sensor, ctx, watch.interval, watch.count, &mut stdout).await
+        {
+            eprintln!("could not watch {}: {}", watch.slug, e);
+        }
+        return;
+    }
+
+    let mut server =
+        server::Server::new_with_options(ctx.clone(), config.network.address(), sensors, options)
+            .await
+            .unwrap();
+
+    tokio::select! {
+        res = &mut server._join_handle => res.unwrap(),
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    if let Err(e) = server.save_state() {
+        eprintln!("could not save state: {}", e);
+    }
+}
+
+/// A connection to the inverter through a Modbus TCP gateway, reopened if the gateway drops
+/// it or stops answering. Requests over it are counted on `transactions`.
+async fn connect_tcp(
+    config: &TcpConfig,
+    gateway: SocketAddr,
+    slave: Slave,
+    transactions: IntCounter,
+) -> Arc<Mutex<Context>> {
+    let open = move || tcp::connect_slave(gateway, slave);
+    let transport = connection::open_with_retry(config.open_attempts, config.open_backoff(), open)
         .await
-        .unwrap();
-    server._join_handle.await.unwrap();
+        .unwrap_or_else(|e| panic!("Could not connect to {}: {}", gateway, e));
+    let transport = connection::ReconnectingTransport::new(transport, config.open_backoff(), open)
+        .with_timeout(config.timeout());
+    let shared = sensor::shared_context(connection::CountedTransport::new(transport, transactions));
+    sensor::shared_context(UnitContext::new(shared, slave))
+}
+
+/// The inverter's RS-485 port, reopened if the adapter is unplugged and comes back.
+/// Requests over it are counted on `transactions`.
+async fn open_serial(
+    serial: &SerialConfig,
+    slave: Slave,
+    transactions: IntCounter,
+) -> Arc<Mutex<Context>> {
+    let format = serial
+        .format()
+        .unwrap_or_else(|e| panic!("Invalid serial format: {}", e));
+    let builder = format
+        .apply(tokio_serial::new(&serial.tty_path, serial.baud_rate))
+        .timeout(serial.timeout());
+    let capture = serial
+        .capture_output()
+        .unwrap_or_else(|e| panic!("Could not open frame capture file: {}", e));
+    let open = move || -> std::io::Result<_> {
+        let port = SerialStream::open(&builder)?;
+        Ok(match &capture {
+            Some(out) => rtu::attach_slave(FrameCapture::new(port, out.clone()), slave),
+            None => rtu::attach_slave(port, slave),
+        })
+    };
+    let transport =
+        connection::open_with_retry(serial.open_attempts, serial.open_backoff(), || {
+            std::future::ready(open())
+        })
+        .await
+        .unwrap_or_else(|e| panic!("Could not open port {}: {}", serial.tty_path, e));
+
+    let transport =
+        connection::ReconnectingTransport::new(transport, serial.open_backoff(), move || {
+            std::future::ready(open())
+        });
+    sensor::shared_context(connection::CountedTransport::new(transport, transactions))
 }
@@ -0,0 +1,234 @@
+//! A mock Modbus client, shared by the unit tests.
+
+use crate::modbus_error::ExceptionCode;
+use async_trait::async_trait;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+use tokio_modbus::prelude::*;
+
+/// Wrap a mock in a real `tokio_modbus` context, for code that doesn't take a trait object.
+pub(crate) fn modbus_context(client: Box<ClientMock>) -> Arc<Mutex<client::Context>> {
+    Arc::new(Mutex::new(client::Context::from(client as Box<dyn Client>)))
+}
+
+/// The error `tokio_modbus` gives for an exception response to a request with the given
+/// function code.
+pub(crate) fn exception_response(function: u8, exception: ExceptionCode) -> Error {
+    Error::other(format!(
+        "Modbus function {}: {}",
+        function,
+        exception.name()
+    ))
+}
+
+#[derive(Debug)]
+pub(crate) struct Context {
+    pub(crate) client: Box<dyn Client>,
+}
+
+#[async_trait]
+impl Client for Context {
+    async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
+        self.client.call(request).await
+    }
+}
+
+#[derive(Default, Debug)]
+pub(crate) struct ClientMock {
+    slave: Option<Slave>,
+    last_request: Mutex<Option<Request<'static>>>,
+    responses: Vec<Result<Response, Error>>,
+    requests: Vec<Result<Request<'static>, Error>>,
+    registers: HashMap<u16, u16>,
+    input_registers: HashMap<u16, u16>,
+    read_counts: Arc<std::sync::Mutex<HashMap<u16, usize>>>,
+    read_delay: Duration,
+}
+
+impl ClientMock {
+    pub(crate) fn set_next_response(&mut self, next_response: Result<Response, Error>) {
+        self.responses.push(next_response)
+    }
+
+    pub(crate) fn set_next_request(&mut self, next_request: Result<Request<'static>, Error>) {
+        self.requests.push(next_request)
+    }
+
+    /// Serve reads of `addr` from a fixed value once the queued responses run out, for
+    /// tests where the order of reads isn't known up front.
+    pub(crate) fn set_register(&mut self, addr: u16, val: u16) {
+        self.registers.insert(addr, val);
+    }
+
+    /// As `set_register`, for reads of input registers.
+    pub(crate) fn set_input_register(&mut self, addr: u16, val: u16) {
+        self.input_registers.insert(addr, val);
+    }
+
+    /// Take this long to answer each read, like a slow or unresponsive inverter.
+    pub(crate) fn set_read_delay(&mut self, delay: Duration) {
+        self.read_delay = delay;
+    }
+
+    /// The number of reads starting at each address, shared so it can still be checked
+    /// once the mock has been moved into a context.
+    pub(crate) fn read_counts(&self) -> Arc<std::sync::Mutex<HashMap<u16, usize>>> {
+        self.read_counts.clone()
+    }
+}
+
+#[async_trait]
+impl Client for ClientMock {
+    async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
+        match request {
+            Request::ReadHoldingRegisters(addr, cnt) => {
+                *self.last_request.lock().await = Some(request.into_owned());
+                *self.read_counts.lock().unwrap().entry(addr).or_default() += 1;
+                tokio::time::sleep(self.read_delay).await;
+                if let Some(response) = self.responses.pop() {
+                    return response;
+                }
+                match (addr..addr + cnt)
+                    .map(|reg| self.registers.get(&reg).copied())
+                    .collect()
+                {
+                    Some(values) => Ok(Response::ReadHoldingRegisters(values)),
+                    None => Err(exception_response(3, ExceptionCode::IllegalDataAddress)),
+                }
+            }
+            Request::ReadInputRegisters(addr, cnt) => (addr..addr + cnt)
+                .map(|reg| self.input_registers.get(&reg).copied())
+                .collect::<Option<Vec<u16>>>()
+                .map(Response::ReadInputRegisters)
+                .ok_or_else(|| exception_response(4, ExceptionCode::IllegalDataAddress)),
+            Request::ReadDiscreteInputs(_, _) => self
+                .responses
+                .pop()
+                .unwrap_or_else(|| Err(exception_response(2, ExceptionCode::IllegalDataAddress))),
+            Request::WriteSingleRegister(addr, val) => {
+                if let Ok(Request::WriteSingleRegister(exp_addr, exp_val)) =
+                    self.requests.pop().unwrap()
+                {
+                    if exp_addr == addr && exp_val == val {
+                        return Ok(Response::WriteSingleRegister(addr, val));
+                    }
+                };
+                Err(Error::new(ErrorKind::InvalidData, "invalid response"))
+            }
+            Request::WriteMultipleRegisters(addr, vals) => {
+                if let Ok(Request::WriteMultipleRegisters(exp_addr, exp_vals)) =
+                    self.requests.pop().unwrap()
+                {
+                    if exp_addr == addr && exp_vals == vals {
+                        return Ok(Response::WriteMultipleRegisters(addr, vals.len() as u16));
+                    }
+                };
+                Err(Error::new(ErrorKind::InvalidData, "invalid response"))
+            }
+            _ => todo!(),
+        }
+    }
+}
+
+impl SlaveContext for ClientMock {
+    fn set_slave(&mut self, slave: Slave) {
+        self.slave = Some(slave);
+    }
+}
+
+impl SlaveContext for Context {
+    fn set_slave(&mut self, slave: Slave) {
+        self.client.set_slave(slave);
+    }
+}
+
+#[async_trait]
+impl Reader for Context {
+    async fn read_holding_registers<'a>(
+        &'a mut self,
+        addr: u16,
+        cnt: u16,
+    ) -> Result<Vec<u16>, Error> {
+        let rsp = self
+            .client
+            .call(Request::ReadHoldingRegisters(addr, cnt))
+            .await?;
+        if let Response::ReadHoldingRegisters(rsp) = rsp {
+            if rsp.len() as u16 != cnt {
+                return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
+            }
+            Ok(rsp)
+        } else {
+            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+        }
+    }
+
+    async fn read_discrete_inputs(&mut self, addr: u16, cnt: u16) -> Result<Vec<bool>, Error> {
+        match self
+            .client
+            .call(Request::ReadDiscreteInputs(addr, cnt))
+            .await?
+        {
+            Response::ReadDiscreteInputs(rsp) => Ok(rsp),
+            _ => Err(Error::new(ErrorKind::InvalidData, "unexpected response")),
+        }
+    }
+
+    async fn read_coils(&mut self, _: u16, _: u16) -> Result<Vec<bool>, Error> {
+        todo!()
+    }
+
+    async fn read_input_registers(&mut self, addr: u16, cnt: u16) -> Result<Vec<u16>, Error> {
+        match self
+            .client
+            .call(Request::ReadInputRegisters(addr, cnt))
+            .await?
+        {
+            Response::ReadInputRegisters(rsp) => Ok(rsp),
+            _ => Err(Error::new(ErrorKind::InvalidData, "unexpected response")),
+        }
+    }
+
+    async fn read_write_multiple_registers(
+        &mut self,
+        _: u16,
+        _: u16,
+        _: u16,
+        _: &[u16],
+    ) -> Result<Vec<u16>, Error> {
+        todo!()
+    }
+}
+
+#[async_trait]
+impl Writer for Context {
+    async fn write_single_register<'a>(&'a mut self, addr: u16, val: u16) -> Result<(), Error> {
+        self.client
+            .call(Request::WriteSingleRegister(addr, val))
+            .await?;
+        Ok(())
+    }
+
+    async fn write_single_coil(&mut self, _: u16, _: bool) -> Result<(), Error> {
+        todo!()
+    }
+
+    async fn write_multiple_coils(&mut self, _: u16, _: &[bool]) -> Result<(), Error> {
+        todo!()
+    }
+
+    async fn write_multiple_registers(&mut self, addr: u16, vals: &[u16]) -> Result<(), Error> {
+        self.client
+            .call(Request::WriteMultipleRegisters(addr, Cow::Borrowed(vals)))
+            .await?;
+        Ok(())
+    }
+
+    async fn masked_write_register(&mut self, _: u16, _: u16, _: u16) -> Result<(), Error> {
+        todo!()
+    }
+}
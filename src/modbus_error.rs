@@ -0,0 +1,148 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// A Modbus exception, ie. the device answered but refused the request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExceptionCode {
+    IllegalFunction = 0x01,
+    IllegalDataAddress = 0x02,
+    IllegalDataValue = 0x03,
+    ServerDeviceFailure = 0x04,
+    Acknowledge = 0x05,
+    ServerDeviceBusy = 0x06,
+    MemoryParityError = 0x08,
+    GatewayPathUnavailable = 0x0A,
+    GatewayTargetDevice = 0x0B,
+}
+
+impl ExceptionCode {
+    const ALL: [ExceptionCode; 9] = [
+        ExceptionCode::IllegalFunction,
+        ExceptionCode::IllegalDataAddress,
+        ExceptionCode::IllegalDataValue,
+        ExceptionCode::ServerDeviceFailure,
+        ExceptionCode::Acknowledge,
+        ExceptionCode::ServerDeviceBusy,
+        ExceptionCode::MemoryParityError,
+        ExceptionCode::GatewayPathUnavailable,
+        ExceptionCode::GatewayTargetDevice,
+    ];
+
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// The description `tokio_modbus` gives the exception.
+    pub fn name(self) -> &'static str {
+        match self {
+            ExceptionCode::IllegalFunction => "Illegal function",
+            ExceptionCode::IllegalDataAddress => "Illegal data address",
+            ExceptionCode::IllegalDataValue => "Illegal data value",
+            ExceptionCode::ServerDeviceFailure => "Server device failure",
+            ExceptionCode::Acknowledge => "Acknowledge",
+            ExceptionCode::ServerDeviceBusy => "Server device busy",
+            ExceptionCode::MemoryParityError => "Memory parity error",
+            ExceptionCode::GatewayPathUnavailable => "Gateway path unavailable",
+            ExceptionCode::GatewayTargetDevice => "Gateway target device failed to respond",
+        }
+    }
+}
+
+impl fmt::Display for ExceptionCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:#04x})", self.name(), self.code())
+    }
+}
+
+/// Why a Modbus request failed, telling a device that refused it apart from a bus that
+/// didn't carry it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModbusError {
+    Exception(ExceptionCode),
+    Timeout,
+    Transport(String),
+}
+
+impl ModbusError {
+    /// Classify the error from a sensor read, or `None` if it didn't come from the bus.
+    pub fn classify(e: &(dyn Error + 'static)) -> Option<ModbusError> {
+        e.downcast_ref::<io::Error>().map(ModbusError::from)
+    }
+
+    pub fn exception_code(&self) -> Option<u8> {
+        match self {
+            ModbusError::Exception(exception) => Some(exception.code()),
+            _ => None,
+        }
+    }
+}
+
+impl From<&io::Error> for ModbusError {
+    /// `tokio_modbus` reports exception responses as an `io::Error` carrying only the
+    /// exception's description, eg. "Modbus function 3: Illegal data address", so that's
+    /// what exceptions are recognised by.
+    fn from(e: &io::Error) -> ModbusError {
+        if e.kind() == io::ErrorKind::TimedOut {
+            return ModbusError::Timeout;
+        }
+        let message = e.to_string().to_lowercase();
+        ExceptionCode::ALL
+            .into_iter()
+            .find(|exception| message.ends_with(&exception.name().to_lowercase()))
+            .map_or_else(
+                || ModbusError::Transport(e.to_string()),
+                ModbusError::Exception,
+            )
+    }
+}
+
+impl fmt::Display for ModbusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModbusError::Exception(exception) => write!(f, "Modbus exception: {}", exception),
+            ModbusError::Timeout => write!(f, "timed out waiting for a response"),
+            ModbusError::Transport(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ModbusError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{exception_response, ClientMock, Context};
+    use crate::sensor::{BasicSensor, Sensor, SensorRead};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn exception_responses_are_told_apart_from_transport_errors() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Err(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "serial port disconnected",
+        )));
+        client.set_next_response(Err(exception_response(
+            3,
+            ExceptionCode::IllegalDataAddress,
+        )));
+        let ctx = Arc::new(Mutex::new(Context { client }));
+        let sensor = BasicSensor(Sensor::new("Exception Sensor", &[810], 1, false));
+
+        let e = sensor.read_value(ctx.clone()).await.unwrap_err();
+        assert_eq!(
+            ModbusError::classify(&*e),
+            Some(ModbusError::Exception(ExceptionCode::IllegalDataAddress))
+        );
+
+        let e = sensor.read_value(ctx).await.unwrap_err();
+        assert_eq!(
+            ModbusError::classify(&*e),
+            Some(ModbusError::Transport(
+                "serial port disconnected".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use async_trait::async_trait;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+use tokio_modbus::client::{Client, Context, Reader};
+use tokio_modbus::prelude::{Request, Response, Slave, SlaveContext};
+use tokio_modbus::{Address, Quantity};
+
+/// Several connections to the same inverter, so a slow read doesn't hold up the others.
+/// Only transports that can carry concurrent requests, like Modbus TCP, benefit. An RTU
+/// bus can only carry one request at a time, so gets a pool of one.
+#[derive(Clone, Debug)]
+pub struct ContextPool {
+    contexts: Arc<[Arc<Mutex<Context>>]>,
+    /// One permit per context, so a holder of a permit is sure to find one free.
+    available: Arc<Semaphore>,
+}
+
+/// A context taken from a `ContextPool`, returned to it when dropped. It can be read from
+/// like the context itself, so it can be handed to a sensor.
+#[derive(Debug)]
+pub struct PooledContext {
+    ctx: OwnedMutexGuard<Context>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl ContextPool {
+    pub fn new(contexts: Vec<Arc<Mutex<Context>>>) -> ContextPool {
+        assert!(!contexts.is_empty(), "a context pool needs a context");
+        ContextPool {
+            available: Arc::new(Semaphore::new(contexts.len())),
+            contexts: contexts.into(),
+        }
+    }
+
+    /// A pool of the one context, eg. for an RTU bus.
+    pub fn single(ctx: Arc<Mutex<Context>>) -> ContextPool {
+        ContextPool::new(vec![ctx])
+    }
+
+    pub fn size(&self) -> usize {
+        self.contexts.len()
+    }
+
+    /// Wait for a free context.
+    pub async fn acquire(&self) -> PooledContext {
+        let permit = self
+            .available
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the pool's semaphore is never closed");
+        let ctx = self
+            .contexts
+            .iter()
+            .find_map(|ctx| ctx.clone().try_lock_owned().ok())
+            .expect("a permit is only handed out while a context is free");
+        PooledContext {
+            ctx,
+            _permit: permit,
+        }
+    }
+
+    /// A context of its own, eg. for an API request, that takes a connection from the pool
+    /// at its first request and keeps it until it's dropped. Until then it holds up no one,
+    /// so it can be handed out whether or not it's going to be used.
+    pub fn context(&self) -> Arc<Mutex<Context>> {
+        let lazy = LazyContext {
+            pool: self.clone(),
+            ctx: None,
+            slave: None,
+        };
+        Arc::new(Mutex::new(Context::from(Box::new(lazy) as Box<dyn Client>)))
+    }
+}
+
+#[derive(Debug)]
+struct LazyContext {
+    pool: ContextPool,
+    ctx: Option<PooledContext>,
+    /// Set on the connection once it's taken.
+    slave: Option<Slave>,
+}
+
+impl SlaveContext for LazyContext {
+    fn set_slave(&mut self, slave: Slave) {
+        self.slave = Some(slave);
+        if let Some(ctx) = &mut self.ctx {
+            ctx.set_slave(slave)
+        }
+    }
+}
+
+#[async_trait]
+impl Client for LazyContext {
+    async fn call(&mut self, request: Request<'_>) -> io::Result<Response> {
+        let ctx = match self.ctx.take() {
+            Some(ctx) => ctx,
+            None => {
+                let mut ctx = self.pool.acquire().await;
+                if let Some(slave) = self.slave {
+                    ctx.set_slave(slave);
+                }
+                ctx
+            }
+        };
+        self.ctx.insert(ctx).call(request).await
+    }
+}
+
+impl Deref for PooledContext {
+    type Target = Context;
+
+    fn deref(&self) -> &Context {
+        &self.ctx
+    }
+}
+
+impl DerefMut for PooledContext {
+    fn deref_mut(&mut self) -> &mut Context {
+        &mut self.ctx
+    }
+}
+
+impl SlaveContext for PooledContext {
+    fn set_slave(&mut self, slave: Slave) {
+        self.ctx.set_slave(slave)
+    }
+}
+
+#[async_trait]
+impl Client for PooledContext {
+    async fn call(&mut self, request: Request<'_>) -> io::Result<Response> {
+        self.ctx.call(request).await
+    }
+}
+
+#[async_trait]
+impl Reader for PooledContext {
+    async fn read_coils(&mut self, addr: Address, cnt: Quantity) -> io::Result<Vec<bool>> {
+        self.ctx.read_coils(addr, cnt).await
+    }
+
+    async fn read_discrete_inputs(
+        &mut self,
+        addr: Address,
+        cnt: Quantity,
+    ) -> io::Result<Vec<bool>> {
+        self.ctx.read_discrete_inputs(addr, cnt).await
+    }
+
+    async fn read_holding_registers(
+        &mut self,
+        addr: Address,
+        cnt: Quantity,
+    ) -> io::Result<Vec<u16>> {
+        self.ctx.read_holding_registers(addr, cnt).await
+    }
+
+    async fn read_input_registers(&mut self, addr: Address, cnt: Quantity) -> io::Result<Vec<u16>> {
+        self.ctx.read_input_registers(addr, cnt).await
+    }
+
+    async fn read_write_multiple_registers(
+        &mut self,
+        read_addr: Address,
+        read_cnt: Quantity,
+        write_addr: Address,
+        write_data: &[u16],
+    ) -> io::Result<Vec<u16>> {
+        self.ctx
+            .read_write_multiple_registers(read_addr, read_cnt, write_addr, write_data)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{modbus_context, ClientMock};
+    use tokio::time::{Duration, Instant};
+
+    /// A pool of contexts that each take `delay` to answer, like a slow TCP gateway.
+    fn slow_pool(size: usize, delay: Duration) -> ContextPool {
+        let contexts = (0..size)
+            .map(|_| {
+                let mut client = Box::<ClientMock>::default();
+                client.set_register(910, 1);
+                client.set_read_delay(delay);
+                modbus_context(client)
+            })
+            .collect();
+        ContextPool::new(contexts)
+    }
+
+    async fn read(pool: &ContextPool) {
+        pool.acquire()
+            .await
+            .read_holding_registers(910, 1)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reads_overlap_across_the_pool() {
+        let delay = Duration::from_millis(100);
+
+        let pool = slow_pool(2, delay);
+        let start = Instant::now();
+        tokio::join!(read(&pool), read(&pool));
+        assert_eq!(start.elapsed(), delay);
+
+        // A single connection can only carry one read at a time.
+        let pool = slow_pool(1, delay);
+        let start = Instant::now();
+        tokio::join!(read(&pool), read(&pool));
+        assert_eq!(start.elapsed(), delay * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn contexts_take_a_connection_once_used() {
+        let delay = Duration::from_millis(100);
+        let pool = slow_pool(1, delay);
+
+        // Handed out but unused, it doesn't hold up anyone else.
+        let idle = pool.context();
+        let start = Instant::now();
+        read(&pool).await;
+        assert_eq!(start.elapsed(), delay);
+
+        // Once used, it keeps the connection until it's dropped.
+        let used = pool.context();
+        used.lock()
+            .await
+            .read_holding_registers(910, 1)
+            .await
+            .unwrap();
+        assert!(tokio::time::timeout(delay * 10, read(&pool)).await.is_err());
+        drop(used);
+        read(&pool).await;
+        drop(idle);
+    }
+}
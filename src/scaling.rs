@@ -0,0 +1,108 @@
+use crate::helpers::slug_name;
+use crate::sensor::SensorTypes;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+fn default_factor() -> i64 {
+    1
+}
+
+/// How to turn a sensor's raw register value into a reading, as given alongside each
+/// register in the community register maps.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Scaling {
+    #[serde(default = "default_factor")]
+    pub factor: i64,
+    #[serde(default)]
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub signed: bool,
+    #[serde(default)]
+    pub offset: i64,
+    /// Report the sensor as unavailable when its registers read 0xFFFF.
+    #[serde(default)]
+    pub ffff_unavailable: bool,
+}
+
+/// Scaling for sensors by name, eg.
+/// ```toml
+/// ["Battery Temperature"]
+/// factor = 10
+/// offset = 100
+/// unit = "°C"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct ScalingTable(HashMap<String, Scaling>);
+
+impl ScalingTable {
+    pub fn from_toml(s: &str) -> Result<ScalingTable, toml::de::Error> {
+        toml::from_str(s)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Scaling> {
+        self.0.get(name)
+    }
+
+    /// Override the scaling of the given sensors with the table's. Names in the table are
+    /// matched against sensor slugs, so either "Battery Temperature" or
+    /// "battery_temperature" will do.
+    pub fn apply(&self, sensors: &mut HashMap<String, SensorTypes<'_>>) {
+        for (name, scaling) in self.0.iter() {
+            if let Some(sensor) = sensors
+                .get_mut(&slug_name(name))
+                .and_then(|s| s.sensor_mut())
+            {
+                *sensor = sensor.clone().with_scaling(scaling);
+            }
+            // Keep compounds of the sensor in step with it.
+            for sensor in sensors.values_mut() {
+                if let SensorTypes::Compound(compound) = sensor {
+                    for component in compound.components.iter_mut() {
+                        if component.slug == slug_name(name) {
+                            component.sensor = component.sensor.clone().with_scaling(scaling);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{ClientMock, Context};
+    use crate::sensor::{BasicSensor, Sensor, SensorRead};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn scaling_table_is_applied_to_sensors() {
+        let table = ScalingTable::from_toml(
+            r#"
+            ["Scaled Sensor"]
+            factor = 10
+            offset = 40
+            unit = "°C"
+            "#,
+        )
+        .unwrap();
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "scaled_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Scaled Sensor", &[660], 1, false))),
+        );
+
+        table.apply(&mut sensors);
+
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(660, 650);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+        let SensorTypes::Basic(sensor) = &sensors["scaled_sensor"] else {
+            panic!("sensor type changed");
+        };
+        assert_eq!(sensor.unit(), Some("°C"));
+        assert_eq!(sensor.read(ctx).await.unwrap(), "25");
+    }
+}
@@ -0,0 +1,263 @@
+use crate::helpers::invalid_input;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::{Reader, Writer};
+
+/// The number of time-of-use slots.
+pub const SLOTS: usize = 6;
+/// Each slot setting is a run of `SLOTS` registers, one per slot, in this order.
+const TIME_OFFSET: usize = 0;
+const POWER_OFFSET: usize = SLOTS;
+const SOC_OFFSET: usize = 3 * SLOTS;
+const CHARGE_OFFSET: usize = 4 * SLOTS;
+/// Times, power, voltage, SOC and charge flags. Voltage only matters for lead acid
+/// batteries, so it isn't decoded.
+const BLOCK_LEN: usize = 5 * SLOTS;
+
+/// One time-of-use slot, which lasts from its start time until the next slot's.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleSlot {
+    /// "HH:MM"
+    pub start: String,
+    /// The maximum battery discharge power, in W.
+    pub power: u16,
+    /// The battery SOC to hold, in %.
+    pub soc: u16,
+    /// Whether to charge the battery from the grid to reach `soc`.
+    pub grid_charge: bool,
+}
+
+/// Times are stored as HHMM in decimal, eg. 2230 for 22:30.
+fn decode_time(raw: u16) -> io::Result<String> {
+    let (hours, minutes) = (raw / 100, raw % 100);
+    if hours > 23 || minutes > 59 {
+        return Err(invalid_input(format!("{} is not a valid HHMM time", raw)));
+    }
+    Ok(format!("{:02}:{:02}", hours, minutes))
+}
+
+fn encode_time(time: &str) -> io::Result<u16> {
+    let invalid = || invalid_input(format!("{:?} is not a valid HH:MM time", time));
+    let (hours, minutes) = time.split_once(':').ok_or_else(invalid)?;
+    let hours: u16 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 100 + minutes)
+}
+
+/// The inverter's time-of-use schedule, kept in one contiguous block of registers.
+#[derive(Clone, Debug)]
+pub struct ScheduleSensor<'a> {
+    pub name: &'a str,
+    pub(crate) start_register: u16,
+}
+
+impl ScheduleSensor<'_> {
+    pub fn decode(block: &[u16]) -> io::Result<Vec<ScheduleSlot>> {
+        if block.len() != BLOCK_LEN {
+            return Err(invalid_input(format!(
+                "expected {} schedule registers, got {}",
+                BLOCK_LEN,
+                block.len()
+            )));
+        }
+
+        (0..SLOTS)
+            .map(|i| {
+                Ok(ScheduleSlot {
+                    start: decode_time(block[TIME_OFFSET + i])?,
+                    power: block[POWER_OFFSET + i],
+                    soc: block[SOC_OFFSET + i],
+                    grid_charge: block[CHARGE_OFFSET + i] & 1 == 1,
+                })
+            })
+            .collect()
+    }
+
+    /// Encode `slots` over the current register block, leaving the registers the slots
+    /// don't cover, like the voltages and generator charge flags, as they are.
+    pub fn encode(slots: &[ScheduleSlot], current: &[u16]) -> io::Result<Vec<u16>> {
+        if slots.len() != SLOTS {
+            return Err(invalid_input(format!(
+                "expected {} schedule slots, got {}",
+                SLOTS,
+                slots.len()
+            )));
+        }
+        if current.len() != BLOCK_LEN {
+            return Err(invalid_input(format!(
+                "expected {} schedule registers, got {}",
+                BLOCK_LEN,
+                current.len()
+            )));
+        }
+
+        let mut block = current.to_vec();
+        let mut previous_time = None;
+        for (i, slot) in slots.iter().enumerate() {
+            let time = encode_time(&slot.start)?;
+            if previous_time.is_some_and(|previous| time <= previous) {
+                return Err(invalid_input(format!(
+                    "slot {} starts at {}, which isn't after the slot before it",
+                    i + 1,
+                    slot.start
+                )));
+            }
+            previous_time = Some(time);
+            if slot.soc > 100 {
+                return Err(invalid_input(format!(
+                    "slot {} has an SOC of {}%, which is over 100%",
+                    i + 1,
+                    slot.soc
+                )));
+            }
+
+            block[TIME_OFFSET + i] = time;
+            block[POWER_OFFSET + i] = slot.power;
+            block[SOC_OFFSET + i] = slot.soc;
+            block[CHARGE_OFFSET + i] = (block[CHARGE_OFFSET + i] & !1) | slot.grid_charge as u16;
+        }
+        Ok(block)
+    }
+
+    /// Write a new schedule in a single multiple register write, then read it back to
+    /// check the inverter took it, returning the schedule as read back. The context stays
+    /// locked throughout.
+    pub async fn write_slots(
+        &self,
+        ctx: Arc<Mutex<Context>>,
+        slots: &[ScheduleSlot],
+    ) -> Result<Vec<ScheduleSlot>, Box<dyn Error>> {
+        let mut ctx = ctx.lock().await;
+        let current = ctx
+            .read_holding_registers(self.start_register, BLOCK_LEN as u16)
+            .await?;
+        let block = ScheduleSensor::encode(slots, &current)?;
+        ctx.write_multiple_registers(self.start_register, &block)
+            .await?;
+
+        let written = ctx
+            .read_holding_registers(self.start_register, BLOCK_LEN as u16)
+            .await?;
+        if written != block {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the schedule read back from the inverter doesn't match what was written",
+            )));
+        }
+        Ok(ScheduleSensor::decode(&written)?)
+    }
+
+    pub async fn read_slots(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<Vec<ScheduleSlot>, Box<dyn Error>> {
+        let block = ctx
+            .lock()
+            .await
+            .read_holding_registers(self.start_register, BLOCK_LEN as u16)
+            .await?;
+        Ok(ScheduleSensor::decode(&block)?)
+    }
+}
+
+/// The last schedule read from or written to the inverter, to serve without touching the
+/// bus.
+#[derive(Clone, Debug, Default)]
+pub struct ScheduleCache(Arc<RwLock<Option<Vec<ScheduleSlot>>>>);
+
+impl ScheduleCache {
+    pub fn get(&self) -> Option<Vec<ScheduleSlot>> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn insert(&self, slots: Vec<ScheduleSlot>) {
+        *self.0.write().unwrap() = Some(slots);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_schedule_block() {
+        #[rustfmt::skip]
+        let block = [
+            // Times
+            0, 530, 800, 1600, 2130, 2300,
+            // Power
+            5000, 5000, 4000, 5000, 5000, 5000,
+            // Voltage
+            49, 49, 49, 49, 49, 49,
+            // SOC
+            100, 30, 30, 20, 50, 100,
+            // Charge flags, grid in bit 0 and generator in bit 1.
+            1, 0, 2, 0, 0, 3,
+        ];
+
+        let slots = ScheduleSensor::decode(&block).unwrap();
+
+        assert_eq!(slots.len(), SLOTS);
+        assert_eq!(
+            slots[0],
+            ScheduleSlot {
+                start: "00:00".to_string(),
+                power: 5000,
+                soc: 100,
+                grid_charge: true,
+            }
+        );
+        assert_eq!(
+            slots[2],
+            ScheduleSlot {
+                start: "08:00".to_string(),
+                power: 4000,
+                soc: 30,
+                grid_charge: false,
+            }
+        );
+        assert_eq!(slots[4].start, "21:30");
+        assert!(slots[5].grid_charge);
+
+        let mut bad_time = block;
+        bad_time[1] = 575;
+        assert!(ScheduleSensor::decode(&bad_time).is_err());
+    }
+
+    #[test]
+    fn encode_rejects_invalid_schedules() {
+        let block = [0; BLOCK_LEN];
+        let slot = |start: &str, soc| ScheduleSlot {
+            start: start.to_string(),
+            power: 5000,
+            soc,
+            grid_charge: false,
+        };
+        let valid: Vec<ScheduleSlot> = ["00:00", "05:30", "08:00", "16:00", "21:30", "23:00"]
+            .iter()
+            .map(|start| slot(start, 50))
+            .collect();
+        assert!(ScheduleSensor::encode(&valid, &block).is_ok());
+
+        let mut out_of_order = valid.clone();
+        out_of_order[3].start = "07:00".to_string();
+        assert!(ScheduleSensor::encode(&out_of_order, &block).is_err());
+
+        let mut too_full = valid.clone();
+        too_full[1].soc = 101;
+        assert!(ScheduleSensor::encode(&too_full, &block).is_err());
+
+        let mut bad_time = valid.clone();
+        bad_time[2].start = "8am".to_string();
+        assert!(ScheduleSensor::encode(&bad_time, &block).is_err());
+
+        assert!(ScheduleSensor::encode(&valid[..5], &block).is_err());
+    }
+}
@@ -1,8 +1,13 @@
-use crate::helpers::{group_consecutive, signed, slug_name};
+use crate::bms::BmsSensor;
+use crate::helpers::{group_consecutive, signed, signed_bits, slug_name};
+use crate::scaling::Scaling;
 use crate::sensor_definitions::*;
 use async_trait::async_trait;
 use lazy_static::lazy_static;
-use prometheus::{IntGauge, IntGaugeVec, Opts, Registry};
+use prometheus::core::Collector;
+use prometheus::{Gauge, IntCounter, IntGauge, IntGaugeVec, Opts, Registry};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::error::Error;
 use std::io;
@@ -11,7 +16,9 @@ use std::ops::Deref;
 use std::sync::atomic::AtomicU16;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
 pub use tokio_modbus::client::Context;
 use tokio_modbus::prelude::*;
 
@@ -19,6 +26,14 @@ lazy_static! {
     pub static ref REGISTRY: Registry = Registry::new();
 }
 
+/// Share any Modbus transport, eg. a TCP gateway or an in-memory fake for tests, in the
+/// form the sensors and the server take, in place of a serial `rtu` context.
+pub fn shared_context(transport: impl Client + 'static) -> Arc<Mutex<Context>> {
+    Arc::new(Mutex::new(Context::from(
+        Box::new(transport) as Box<dyn Client>
+    )))
+}
+
 #[derive(Default, Clone)]
 pub enum PriorityLoad {
     #[default]
@@ -27,8 +42,13 @@ pub enum PriorityLoad {
 }
 
 #[derive(Debug)]
-enum SensorError {
+pub(crate) enum SensorError {
     IsNotMut,
+    OutOfRange,
+    /// The value isn't one of those the sensor allows to be written.
+    NotAllowed,
+    /// The sensor was written too recently, and can be written again after this long.
+    RateLimited(Duration),
 }
 
 impl std::fmt::Display for SensorError {
@@ -39,19 +59,228 @@ impl std::fmt::Display for SensorError {
 
 impl Error for SensorError {}
 
+/// A decoded sensor value, for consumers that want to do arithmetic on readings rather
+/// than parse them back out of a string.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum SensorValue {
+    Int(i64),
+    Float(f64),
+    Text(String),
+    /// The inverter doesn't populate the register, so there is no value to report. This is
+    /// `null` in JSON.
+    Unavailable,
+}
+
+impl std::fmt::Display for SensorValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SensorValue::Int(v) => write!(f, "{}", v),
+            SensorValue::Float(v) => write!(f, "{}", v),
+            SensorValue::Text(v) => write!(f, "{}", v),
+            SensorValue::Unavailable => write!(f, "unavailable"),
+        }
+    }
+}
+
+/// Sets a sensor's gauges to what one of its reads found. A collection cycle hands it on
+/// with each reading, so the gauges are only set when the cycle is published, by
+/// `PrometheusSink`, rather than as each sensor is read.
+#[derive(Clone, Default)]
+pub struct MetricUpdate(Option<Arc<dyn Fn() + Send + Sync>>);
+
+impl MetricUpdate {
+    pub(crate) fn new(set: impl Fn() + Send + Sync + 'static) -> MetricUpdate {
+        MetricUpdate(Some(Arc::new(set)))
+    }
+
+    /// Set the gauges. Applying the same update again leaves them as they are.
+    pub fn apply(&self) {
+        if let Some(set) = &self.0 {
+            set()
+        }
+    }
+}
+
+impl std::fmt::Debug for MetricUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("MetricUpdate")
+    }
+}
+
 #[async_trait]
 pub trait SensorRead {
-    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>>;
+    /// Read the sensor, leaving its gauges to be set with the update that comes back.
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>>;
+
+    /// Read the sensor and set its gauges.
+    async fn read_value(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<SensorValue, Box<dyn Error>> {
+        let (value, metrics) = self.measure(ctx).await?;
+        metrics.apply();
+        Ok(value)
+    }
+
+    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>> {
+        Ok(self.read_value(ctx).await?.to_string())
+    }
+}
+
+/// The periods the inverter counts energy over, each with its own counters, eg.
+/// `day_pv_energy`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnergyPeriod {
+    Day,
+    Month,
+    Year,
+}
+
+/// The Modbus function code used to write a sensor's register.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WriteFunction {
+    /// Write Single Register (FC6).
+    #[default]
+    SingleRegister,
+    /// Write Multiple Registers (FC16), which some firmware requires even for a single register.
+    MultipleRegisters,
+}
+
+impl WriteFunction {
+    pub fn code(&self) -> u8 {
+        match self {
+            WriteFunction::SingleRegister => 6,
+            WriteFunction::MultipleRegisters => 16,
+        }
+    }
+}
+
+/// A write to a sensor's register, as it would go out on the bus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterWrite {
+    pub function: WriteFunction,
+    pub register: u16,
+    pub value: u16,
+}
+
+/// The Modbus function code used to read a sensor's registers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReadFunction {
+    /// Read Holding Registers (FC3).
+    #[default]
+    HoldingRegisters,
+    /// Read Input Registers (FC4), where some firmware keeps its measurements.
+    InputRegisters,
 }
 
 #[derive(Clone, Debug)]
 pub struct Sensor<'a> {
     pub name: &'a str,
     pub registers: &'a [u16],
-    factor: i64,
-    is_signed: bool,
-    is_mut: bool,
+    pub(crate) factor: i64,
+    /// `(numerator, denominator)` to multiply the value by, used in place of `factor` when
+    /// set, for scales like 3/100 that a whole divisor can't express.
+    pub(crate) rational_scale: Option<(i64, i64)>,
+    pub(crate) is_signed: bool,
+    /// The width of a signed value, in bits. A register's 16 unless set otherwise.
+    pub(crate) sign_bits: u32,
+    /// Subtracted from the value after dividing by the factor, eg. temperatures are stored
+    /// with +100 so they can go below zero.
+    pub(crate) offset: i64,
+    /// Values this close to zero are reported as zero, to hide noise around it. Zero
+    /// disables the clamp.
+    pub(crate) zero_epsilon: i64,
+    pub(crate) unit: Option<String>,
+    /// The unit the API shows the value in, if not `unit`.
+    pub(crate) display_unit: Option<DisplayUnit>,
+    pub(crate) ffff_unavailable: bool,
+    pub(crate) is_mut: bool,
+    /// Set for registers the inverter takes writes to but refuses reads of.
+    pub(crate) write_only: bool,
+    pub(crate) read_once: bool,
+    /// Sensors with a higher priority are read earlier in each collection cycle.
+    pub(crate) priority: i32,
+    pub(crate) read_fn: ReadFunction,
+    write_fn: WriteFunction,
+    write_limit: Option<WriteLimit>,
+    /// The only raw values writes may set, eg. the modes of a control register.
+    allowed_values: Option<Arc<[u16]>>,
+    /// Set for the inverter's energy counters, which `reset` zeroes.
+    energy_period: Option<EnergyPeriod>,
+    transform: Option<Transform>,
     metric: IntGauge,
+    /// Set to the combined register value before any decoding, when enabled.
+    raw_metric: Option<IntGauge>,
+    /// Set to the time of the last write the inverter acknowledged, for writable sensors.
+    last_write_metric: Option<IntGauge>,
+}
+
+/// A unit to show a sensor's value in through the API, eg. kW for a sensor read in W, with
+/// a gauge of its own. The sensor's own metric stays in its base unit.
+#[derive(Clone, Debug)]
+pub(crate) struct DisplayUnit {
+    pub(crate) unit: String,
+    /// What a value in the base unit is multiplied by.
+    multiplier: f64,
+    metric: Gauge,
+}
+
+/// The SI prefixes a unit can be converted between, eg. W and kW.
+const UNIT_PREFIXES: [(&str, f64); 2] = [("k", 1e3), ("M", 1e6)];
+
+fn split_prefix(unit: &str) -> (f64, &str) {
+    UNIT_PREFIXES
+        .iter()
+        .find_map(|&(prefix, scale)| {
+            unit.strip_prefix(prefix)
+                .filter(|base| !base.is_empty())
+                .map(|base| (scale, base))
+        })
+        .unwrap_or((1.0, unit))
+}
+
+/// What to multiply a value in `from` by to get it in `to`, if they're the same unit with
+/// different prefixes, eg. 0.001 from W to kW, or 1000 from kWh to Wh.
+pub fn unit_conversion(from: &str, to: &str) -> Option<f64> {
+    let (from_scale, from_base) = split_prefix(from);
+    let (to_scale, to_base) = split_prefix(to);
+    (from_base == to_base).then_some(from_scale / to_scale)
+}
+
+/// The minimum time between accepted writes to a sensor. Clones of the sensor share the
+/// time of the last write, as the server hands each request its own copy.
+#[derive(Clone, Debug)]
+struct WriteLimit {
+    interval: Duration,
+    last_write: Arc<std::sync::Mutex<Option<Instant>>>,
+}
+
+impl WriteLimit {
+    /// How long until the next write is allowed, or `None` if it is now.
+    fn retry_after(&self) -> Option<Duration> {
+        let last_write = (*self.last_write.lock().unwrap())?;
+        let next_write = last_write + self.interval;
+        next_write
+            .checked_duration_since(Instant::now())
+            .filter(|wait| !wait.is_zero())
+    }
+
+    fn record(&self) {
+        *self.last_write.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+/// A function applied to a sensor's value after the usual decoding, for corrections that
+/// don't fit a factor and offset, eg. a calibration curve.
+#[derive(Clone)]
+struct Transform(Arc<dyn Fn(i64) -> i64 + Send + Sync>);
+
+impl std::fmt::Debug for Transform {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Transform")
+    }
 }
 
 impl<'a> Default for Sensor<'a> {
@@ -64,9 +293,27 @@ impl<'a> Default for Sensor<'a> {
             name: "",
             registers: &[],
             factor: 0,
+            rational_scale: None,
             is_signed: false,
+            sign_bits: 16,
+            offset: 0,
+            zero_epsilon: 0,
+            unit: None,
+            display_unit: None,
+            ffff_unavailable: false,
             is_mut: false,
+            write_only: false,
+            read_once: false,
+            priority: 0,
+            read_fn: ReadFunction::default(),
+            write_fn: WriteFunction::default(),
+            write_limit: None,
+            allowed_values: None,
+            energy_period: None,
+            transform: None,
             metric,
+            raw_metric: None,
+            last_write_metric: None,
         }
     }
 }
@@ -87,14 +334,22 @@ impl SensorWrite<AtomicU16> for Sensor<'_> {
         ctx: Arc<Mutex<dyn Writer>>,
         data: AtomicU16,
     ) -> Result<(), Box<dyn Error>> {
-        if self.is_mut {
-            ctx.lock()
-                .await
-                .write_single_register(self.registers[0], data.load(Ordering::Relaxed))
-                .await?;
-        } else {
+        if !self.is_mut {
             return Err(SensorError::IsNotMut.into());
         }
+        let value = data.load(Ordering::Relaxed);
+        self.check_allowed(value)?;
+        // Writes are serialised by the context lock, so two requests can't both get in
+        // under the limit.
+        let mut ctx = ctx.lock().await;
+        if let Some(retry_after) = self.write_limit.as_ref().and_then(WriteLimit::retry_after) {
+            return Err(SensorError::RateLimited(retry_after).into());
+        }
+        self.write_raw(&mut *ctx, value).await?;
+        if let Some(limit) = &self.write_limit {
+            limit.record();
+        }
+        self.record_write();
         Ok(())
     }
 }
@@ -106,17 +361,21 @@ impl Sensor<'_> {
         factor: i64,
         is_signed: bool,
     ) -> Sensor<'a> {
-        let metric = IntGauge::new(slug_name(name), name).unwrap();
-        REGISTRY.register(Box::new(metric.clone())).unwrap();
+        Sensor::new_in(&REGISTRY, name, registers, factor, is_signed)
+    }
 
-        Sensor {
-            name,
-            registers,
-            factor,
-            is_signed,
-            is_mut: false,
-            metric,
-        }
+    /// Like `new`, but registering the sensor's metric in `registry` rather than the
+    /// global `REGISTRY`.
+    pub fn new_in<'a>(
+        registry: &Registry,
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+        is_signed: bool,
+    ) -> Sensor<'a> {
+        let sensor = Sensor::unregistered(name, registers, factor, is_signed);
+        registry.register(Box::new(sensor.metric.clone())).unwrap();
+        sensor
     }
 
     pub fn new_mut<'a>(
@@ -125,36 +384,531 @@ impl Sensor<'_> {
         factor: i64,
         is_signed: bool,
     ) -> Sensor<'a> {
-        let metric = IntGauge::new(slug_name(name), name).unwrap();
-        REGISTRY.register(Box::new(metric.clone())).unwrap();
+        Sensor::new_mut_in(&REGISTRY, name, registers, factor, is_signed)
+    }
+
+    pub fn new_mut_in<'a>(
+        registry: &Registry,
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+        is_signed: bool,
+    ) -> Sensor<'a> {
+        let last_write_metric = IntGauge::new(
+            format!("{}_last_write_timestamp_seconds", slug_name(name)),
+            format!(
+                "When {} was last written, in seconds since the Unix epoch",
+                name
+            ),
+        )
+        .unwrap();
+        registry
+            .register(Box::new(last_write_metric.clone()))
+            .unwrap();
+        Sensor {
+            is_mut: true,
+            last_write_metric: Some(last_write_metric),
+            ..Sensor::new_in(registry, name, registers, factor, is_signed)
+        }
+    }
 
+    /// A sensor whose metric isn't registered, for reading the component parts of another
+    /// sensor that exports its own metric.
+    pub(crate) fn unregistered<'a>(
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+        is_signed: bool,
+    ) -> Sensor<'a> {
         Sensor {
             name,
             registers,
             factor,
+            rational_scale: None,
             is_signed,
-            is_mut: true,
+            sign_bits: 16,
+            offset: 0,
+            zero_epsilon: 0,
+            unit: None,
+            display_unit: None,
+            ffff_unavailable: false,
+            is_mut: false,
+            write_only: false,
+            read_once: false,
+            priority: 0,
+            read_fn: ReadFunction::default(),
+            write_fn: WriteFunction::default(),
+            write_limit: None,
+            allowed_values: None,
+            energy_period: None,
+            transform: None,
+            metric: IntGauge::new(slug_name(name), name).unwrap(),
+            raw_metric: None,
+            last_write_metric: None,
+        }
+    }
+
+    pub fn with_offset(mut self, offset: i64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Scale values by `numerator / denominator` rather than dividing by the factor, and
+    /// report them unrounded, eg. 3/100 turns a raw 1234 into 37.02. The metric, and
+    /// anything else that takes a whole number, gets the value rounded towards zero.
+    /// The transform isn't applied to the unrounded value.
+    pub fn with_rational_scale(mut self, numerator: i64, denominator: i64) -> Self {
+        assert!(
+            denominator != 0,
+            "a rational scale needs a non-zero denominator"
+        );
+        self.rational_scale = Some((numerator, denominator));
+        self
+    }
+
+    /// Treat signed values as `bits` wide rather than a register's 16, eg. 12 for a packed
+    /// field or 32 for a value spread over two registers.
+    pub fn with_sign_bits(mut self, bits: u32) -> Self {
+        assert!(
+            (2..64).contains(&bits),
+            "a signed value needs between 2 and 63 bits"
+        );
+        self.sign_bits = bits;
+        self
+    }
+
+    pub fn with_unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_owned());
+        self
+    }
+
+    /// Take the factor, signedness, offset and unit from a scaling table entry.
+    /// `ffff_unavailable` can only be turned on by the table, not off.
+    pub fn with_scaling(mut self, scaling: &Scaling) -> Self {
+        self.factor = scaling.factor;
+        self.is_signed = scaling.signed;
+        self.offset = scaling.offset;
+        self.unit = scaling.unit.clone();
+        self.ffff_unavailable |= scaling.ffff_unavailable;
+        self
+    }
+
+    pub fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+
+    /// Show the value in `unit` through the API, and export it in that unit as a
+    /// `<slug>_<unit>` gauge too, eg. `grid_power_kw`. The sensor needs a unit that
+    /// converts to it.
+    pub fn with_display_unit(self, unit: &str) -> Self {
+        self.with_display_unit_in(&REGISTRY, unit)
+    }
+
+    pub fn with_display_unit_in(mut self, registry: &Registry, unit: &str) -> Self {
+        let base = self.unit.as_deref().unwrap_or_default();
+        let multiplier = unit_conversion(base, unit)
+            .unwrap_or_else(|| panic!("{} can't be shown in {} from {:?}", self.name, unit, base));
+        let metric = Gauge::new(
+            format!("{}_{}", slug_name(self.name), slug_name(unit)),
+            format!("{} in {}", self.name, unit),
+        )
+        .unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+        self.display_unit = Some(DisplayUnit {
+            unit: unit.to_owned(),
+            multiplier,
             metric,
+        });
+        self
+    }
+
+    /// `value` in the display unit, if the sensor has one.
+    pub fn display_value(&self, value: SensorValue) -> SensorValue {
+        let Some(display) = &self.display_unit else {
+            return value;
+        };
+        match value {
+            SensorValue::Int(v) => SensorValue::Float(v as f64 * display.multiplier),
+            SensorValue::Float(v) => SensorValue::Float(v * display.multiplier),
+            other => other,
         }
     }
 
-    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<i64, Box<dyn Error>> {
+    /// Report the sensor as unavailable, rather than setting its metric, when its registers
+    /// all read 0xFFFF. Many registers read 0xFFFF when the inverter doesn't populate them,
+    /// which would otherwise come out as -1 or some other nonsense value.
+    pub fn ffff_unavailable(mut self) -> Self {
+        self.ffff_unavailable = true;
+        self
+    }
+
+    /// Report values within `epsilon` of zero as zero, eg. the few watts a power sensor
+    /// reads either side of zero when nothing is flowing. Unlike `no_negative`, larger
+    /// negative values are left alone.
+    pub fn with_zero_epsilon(mut self, epsilon: i64) -> Self {
+        self.zero_epsilon = epsilon;
+        self
+    }
+
+    /// Apply `transform` to every value read, after the factor and offset. Writes and
+    /// adjustments aren't transformed.
+    pub fn with_transform(
+        mut self,
+        transform: impl Fn(i64) -> i64 + Send + Sync + 'static,
+    ) -> Self {
+        self.transform = Some(Transform(Arc::new(transform)));
+        self
+    }
+
+    /// Also export the raw register value, before sign conversion and scaling, as a
+    /// `<slug>_raw` gauge. For checking scaling factors against what the inverter reports.
+    pub fn emit_raw(self) -> Self {
+        self.emit_raw_in(&REGISTRY)
+    }
+
+    pub fn emit_raw_in(mut self, registry: &Registry) -> Self {
+        let raw_metric = IntGauge::new(
+            format!("{}_raw", slug_name(self.name)),
+            format!("{} raw register value", self.name),
+        )
+        .unwrap();
+        registry.register(Box::new(raw_metric.clone())).unwrap();
+        self.raw_metric = Some(raw_metric);
+        self
+    }
+
+    pub(crate) fn emits_raw(&self) -> bool {
+        self.raw_metric.is_some()
+    }
+
+    fn collectors(&self) -> Vec<Box<dyn Collector>> {
+        let mut collectors: Vec<Box<dyn Collector>> = vec![Box::new(self.metric.clone())];
+        if let Some(raw_metric) = &self.raw_metric {
+            collectors.push(Box::new(raw_metric.clone()));
+        }
+        if let Some(last_write_metric) = &self.last_write_metric {
+            collectors.push(Box::new(last_write_metric.clone()));
+        }
+        if let Some(display) = &self.display_unit {
+            collectors.push(Box::new(display.metric.clone()));
+        }
+        collectors
+    }
+
+    /// Mark a writable sensor as one that can't be read back, eg. a command register that
+    /// the inverter answers reads of with an exception. It's left out of collection, and
+    /// reads of it through the API are refused without touching the bus.
+    pub fn write_only(mut self) -> Self {
+        assert!(self.is_mut, "only a writable sensor can be write-only");
+        self.write_only = true;
+        self
+    }
+
+    /// Mark the sensor as static, eg. nameplate values like rated power, so the data
+    /// collector reads it once at startup rather than every cycle.
+    pub fn read_once(mut self) -> Self {
+        self.read_once = true;
+        self
+    }
+
+    /// Read the sensor before those of a lower priority in each collection cycle, so the
+    /// values that matter most are the freshest, and aren't the ones skipped when a slow
+    /// bus runs a cycle out of time. Sensors default to priority 0.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Use a different Modbus function code when reading the sensor's registers.
+    pub fn with_read_fn(mut self, read_fn: ReadFunction) -> Self {
+        self.read_fn = read_fn;
+        self
+    }
+
+    /// Use a different Modbus function code when writing the sensor's register.
+    pub fn with_write_fn(mut self, write_fn: WriteFunction) -> Self {
+        self.write_fn = write_fn;
+        self
+    }
+
+    /// Refuse writes within `interval` of the last accepted one, for settings stored in
+    /// flash that would wear out if rewritten constantly.
+    pub fn with_write_interval(mut self, interval: Duration) -> Self {
+        self.write_limit = Some(WriteLimit {
+            interval,
+            last_write: Arc::default(),
+        });
+        self
+    }
+
+    /// Refuse writes of anything but `values`, in raw register values. Stricter than a
+    /// range, for registers that hold one of a few modes.
+    pub fn with_allowed_values(mut self, values: &[u16]) -> Self {
+        self.allowed_values = Some(values.into());
+        self
+    }
+
+    pub(crate) fn allowed_values(&self) -> Option<&[u16]> {
+        self.allowed_values.as_deref()
+    }
+
+    fn check_allowed(&self, value: u16) -> Result<(), SensorError> {
+        match &self.allowed_values {
+            Some(allowed) if !allowed.contains(&value) => Err(SensorError::NotAllowed),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn write_interval(&self) -> Option<Duration> {
+        self.write_limit.as_ref().map(|limit| limit.interval)
+    }
+
+    /// How long until the write limit allows another write, or `None` if it does now.
+    pub(crate) fn write_retry_after(&self) -> Option<Duration> {
+        self.write_limit.as_ref().and_then(WriteLimit::retry_after)
+    }
+
+    /// Mark the sensor as one of the inverter's energy counters for `period`, so it can be
+    /// zeroed with `reset`. Only writable sensors can be reset.
+    pub fn energy_counter(mut self, period: EnergyPeriod) -> Self {
+        self.energy_period = Some(period);
+        self
+    }
+
+    pub(crate) fn energy_period(&self) -> Option<EnergyPeriod> {
+        self.energy_period
+    }
+
+    /// Read the sensor's registers and combine them into a single value, before any
+    /// sign conversion or scaling is applied. The context is held for every read, so
+    /// another sensor's reads can't land between them.
+    async fn read_raw(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<i64, Box<dyn Error>> {
+        self.read_raw_from(&mut *ctx.lock().await).await
+    }
+
+    /// As `read_raw`, with a context the caller already holds.
+    async fn read_raw_from(&self, ctx: &mut dyn Reader) -> Result<i64, Box<dyn Error>> {
         let mut output: Vec<u16> = Vec::new();
         for (reg, len) in group_consecutive(self.registers.to_vec()) {
-            let raw_out = ctx.lock().await.read_holding_registers(reg, len).await?;
+            let raw_out = match self.read_fn {
+                ReadFunction::HoldingRegisters => ctx.read_holding_registers(reg, len).await?,
+                ReadFunction::InputRegisters => ctx.read_input_registers(reg, len).await?,
+            };
             output.extend(raw_out);
         }
 
         let mut value: i64 = 0;
         for (i, reg_val) in output.iter().enumerate() {
-            value += (reg_val << (16 * i)) as i64
+            value += (*reg_val as i64) << (16 * i)
+        }
+        Ok(value)
+    }
+
+    /// Zero an energy counter's registers. Each run of consecutive registers is zeroed in
+    /// one write, so a counter in a single run is never half reset. The reset is a write
+    /// like any other, so it's held to the sensor's write limit and allowed values.
+    pub async fn reset(&self, ctx: Arc<Mutex<Context>>) -> Result<(), Box<dyn Error>> {
+        if !self.is_mut || self.energy_period.is_none() {
+            return Err(SensorError::IsNotMut.into());
+        }
+        self.check_allowed(0)?;
+        let mut ctx = ctx.lock().await;
+        if let Some(retry_after) = self.write_retry_after() {
+            return Err(SensorError::RateLimited(retry_after).into());
+        }
+        match self.registers {
+            [_] => self.write_raw(&mut *ctx, 0).await?,
+            registers => {
+                for (reg, len) in group_consecutive(registers.to_vec()) {
+                    ctx.write_multiple_registers(reg, &vec![0; len as usize])
+                        .await?
+                }
+            }
+        }
+        if let Some(limit) = &self.write_limit {
+            limit.record();
+        }
+        self.record_write();
+        Ok(())
+    }
+
+    /// Note a write the inverter acknowledged on the last-write gauge.
+    fn record_write(&self) {
+        if let Some(last_write_metric) = &self.last_write_metric {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            last_write_metric.set(now.as_secs() as i64);
+        }
+    }
+
+    /// The write `write` would make for `value`, without making it.
+    pub(crate) fn planned_write(&self, value: u16) -> Result<RegisterWrite, SensorError> {
+        if !self.is_mut {
+            return Err(SensorError::IsNotMut);
+        }
+        self.check_allowed(value)?;
+        Ok(RegisterWrite {
+            function: self.write_fn,
+            register: self.registers[0],
+            value,
+        })
+    }
+
+    async fn write_raw<W: Writer + ?Sized>(&self, writer: &mut W, value: u16) -> io::Result<()> {
+        match self.write_fn {
+            WriteFunction::SingleRegister => {
+                writer.write_single_register(self.registers[0], value).await
+            }
+            WriteFunction::MultipleRegisters => {
+                writer
+                    .write_multiple_registers(self.registers[0], &[value])
+                    .await
+            }
+        }
+    }
+
+    /// Add `delta`, in the sensor's units, to its current value and return the new value.
+    /// The lock on the context is held from the read until the write, so nothing else can
+    /// change the register in between.
+    pub async fn adjust(
+        &self,
+        ctx: Arc<Mutex<Context>>,
+        delta: i64,
+    ) -> Result<i64, Box<dyn Error>> {
+        if !self.is_mut {
+            return Err(SensorError::IsNotMut.into());
+        }
+        if self.registers.len() != 1 {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Only single register sensors can be adjusted.",
+            )));
         }
 
+        let mut ctx = ctx.lock().await;
+        if let Some(retry_after) = self.write_limit.as_ref().and_then(WriteLimit::retry_after) {
+            return Err(SensorError::RateLimited(retry_after).into());
+        }
+        let mut current = ctx.read_holding_registers(self.registers[0], 1).await?[0] as i64;
         if self.is_signed {
-            value = signed(value)
+            current = signed_bits(current, self.sign_bits);
         }
-        value /= self.factor;
-        Ok(value)
+        let (min, max) = if self.is_signed {
+            let limit = 1 << (self.sign_bits.min(16) - 1);
+            (-limit, limit - 1)
+        } else {
+            (0, u16::MAX as i64)
+        };
+        // The delta comes straight from the request, so it may be large enough to overflow.
+        let Some(adjusted) = delta
+            .checked_mul(self.factor)
+            .and_then(|delta| current.checked_add(delta))
+        else {
+            return Err(SensorError::OutOfRange.into());
+        };
+        if adjusted < min || adjusted > max {
+            return Err(SensorError::OutOfRange.into());
+        }
+        self.check_allowed(adjusted as u16)?;
+
+        // Negative values wrap around to their two's complement, as the inverter expects.
+        self.write_raw(&mut *ctx, adjusted as u16).await?;
+        if let Some(limit) = &self.write_limit {
+            limit.record();
+        }
+        self.record_write();
+        Ok(adjusted / self.factor - self.offset)
+    }
+
+    fn scale(&self, raw: i64) -> i64 {
+        let mut value = raw;
+        if self.is_signed {
+            value = signed_bits(value, self.sign_bits)
+        }
+        value = match self.rational_scale {
+            Some((numerator, denominator)) => {
+                (value as i128 * numerator as i128 / denominator as i128) as i64
+            }
+            None => value / self.factor,
+        };
+        value -= self.offset;
+        if let Some(Transform(transform)) = &self.transform {
+            value = transform(value);
+        }
+        clamp_near_zero(value, self.zero_epsilon)
+    }
+
+    /// As `scale`, without rounding to a whole number.
+    fn scale_exact(&self, raw: i64) -> f64 {
+        let (numerator, denominator) = self.rational_scale.unwrap_or((1, self.factor));
+        self.scale_rational(raw, numerator, denominator)
+    }
+
+    /// The value under the sensor's rational scale, without rounding to a whole number.
+    /// Multiplying first keeps the result to a single rounding, so 1234 × 3/100 is
+    /// exactly the float closest to 37.02.
+    fn scale_rational(&self, raw: i64, numerator: i64, denominator: i64) -> f64 {
+        let mut value = raw;
+        if self.is_signed {
+            value = signed_bits(value, self.sign_bits)
+        }
+        let scaled = (value as i128 * numerator as i128) as f64 / denominator as f64;
+        let value = scaled - self.offset as f64;
+        match self.zero_epsilon > 0 && value.abs() <= self.zero_epsilon as f64 {
+            true => 0.0,
+            false => value,
+        }
+    }
+
+    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<i64, Box<dyn Error>> {
+        Ok(self.scale(self.read_raw(ctx).await?))
+    }
+
+    /// Read the sensor, with the update to its metrics. The metric is left alone if every
+    /// register is unpopulated and the sensor treats that as unavailable.
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let raw = self.read_raw(ctx).await?;
+        let raw_metric = self.raw_metric.clone();
+        if self.ffff_unavailable && !self.registers.is_empty() {
+            let all_ones = u64::MAX >> (64 - 16 * self.registers.len());
+            if raw as u64 == all_ones {
+                let metrics = MetricUpdate::new(move || {
+                    if let Some(raw_metric) = &raw_metric {
+                        raw_metric.set(raw);
+                    }
+                });
+                return Ok((SensorValue::Unavailable, metrics));
+            }
+        }
+
+        let value = self.scale(raw);
+        let metric = self.metric.clone();
+        let display = self.display_unit.as_ref().map(|display| {
+            (
+                display.metric.clone(),
+                self.scale_exact(raw) * display.multiplier,
+            )
+        });
+        let metrics = MetricUpdate::new(move || {
+            if let Some(raw_metric) = &raw_metric {
+                raw_metric.set(raw);
+            }
+            metric.set(value);
+            if let Some((display_metric, display_value)) = &display {
+                display_metric.set(*display_value);
+            }
+        });
+        let value = match self.rational_scale {
+            Some((numerator, denominator)) => {
+                SensorValue::Float(self.scale_rational(raw, numerator, denominator))
+            }
+            None => SensorValue::Int(value),
+        };
+        Ok((value, metrics))
     }
 }
 
@@ -171,10 +925,11 @@ impl<'a> Deref for BinarySensor<'a> {
 
 #[async_trait]
 impl SensorRead for BinarySensor<'_> {
-    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>> {
-        let output = self.0.read(ctx).await.unwrap();
-        self.0.metric.set(output);
-        Ok(format!("{}", output))
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        self.0.measure(ctx).await
     }
 }
 
@@ -185,14 +940,18 @@ impl SensorWrite<AtomicU16> for BinarySensor<'_> {
         ctx: Arc<Mutex<dyn Writer>>,
         data: AtomicU16,
     ) -> Result<(), Box<dyn Error>> {
-        if data.load(Ordering::Relaxed) > 1 {
-            return Err(Box::new(io::Error::new(
-                io::ErrorKind::Unsupported,
-                "Binary sensors must receive either a 1 or 0.",
-            )));
-        }
-        let res = self.0.write(ctx, data).await.unwrap();
-        Ok(res)
+        check_binary(data.load(Ordering::Relaxed))?;
+        self.0.write(ctx, data).await
+    }
+}
+
+fn check_binary(value: u16) -> io::Result<()> {
+    match value {
+        0 | 1 => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Binary sensors must receive either a 1 or 0.",
+        )),
     }
 }
 
@@ -230,10 +989,11 @@ impl<'a> Deref for BasicSensor<'a> {
 
 #[async_trait]
 impl SensorRead for BasicSensor<'_> {
-    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>> {
-        let output = self.deref().read(ctx).await.unwrap();
-        self.metric.set(output);
-        Ok(format!("{}", output))
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        self.0.measure(ctx).await
     }
 }
 
@@ -244,8 +1004,7 @@ impl SensorWrite<AtomicU16> for BasicSensor<'_> {
         ctx: Arc<Mutex<dyn Writer>>,
         data: AtomicU16,
     ) -> Result<(), Box<dyn Error>> {
-        let res = self.write(ctx, data).await.unwrap();
-        Ok(res)
+        self.0.write(ctx, data).await
     }
 }
 
@@ -260,24 +1019,130 @@ impl<'a> Deref for TemperatureSensor<'a> {
     }
 }
 
-#[async_trait]
-impl SensorRead for TemperatureSensor<'_> {
-    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>> {
-        let mut output = self.deref().read(ctx).await.unwrap();
-        output -= 100_i64;
-        self.metric.set(output);
-        Ok(format!("{}", output))
+impl TemperatureSensor<'_> {
+    /// A temperature read as `raw / factor - offset`. Sunsynk's own sensors use a factor of
+    /// 10 and an offset of 100, but not every firmware reports every sensor that way.
+    pub fn new<'a>(
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+        offset: i64,
+    ) -> TemperatureSensor<'a> {
+        TemperatureSensor::new_in(&REGISTRY, name, registers, factor, offset)
     }
-}
 
-#[derive(Clone, Debug)]
+    pub fn new_in<'a>(
+        registry: &Registry,
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+        offset: i64,
+    ) -> TemperatureSensor<'a> {
+        TemperatureSensor(
+            Sensor::new_in(registry, name, registers, factor, false).with_offset(offset),
+        )
+    }
+
+    /// Decode the register as two's complement before dividing by the factor and taking
+    /// off the offset, for firmware that reports sub-zero temperatures as negative numbers
+    /// rather than as small values under the offset.
+    pub fn signed(self) -> Self {
+        TemperatureSensor(Sensor {
+            is_signed: true,
+            ..self.0
+        })
+    }
+}
+
+#[async_trait]
+impl SensorRead for TemperatureSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        self.0.measure(ctx).await
+    }
+}
+
+/// Which byte of a 16-bit register a `ByteSliceSensor` decodes, or a `SerialSensor`
+/// decodes first.
+#[derive(Clone, Copy, Debug)]
+pub enum HighOrLow {
+    High,
+    Low,
+}
+
+/// Some registers pack two independent 8-bit values (e.g. two temperatures) into a single
+/// register. This decodes one of the two bytes of the sensor's first register, as an 8-bit
+/// two's complement value if the sensor is signed.
+#[derive(Clone, Debug)]
+pub struct ByteSliceSensor<'a>(pub Sensor<'a>, pub HighOrLow);
+
+impl<'a> Deref for ByteSliceSensor<'a> {
+    type Target = Sensor<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl SensorRead for ByteSliceSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let raw_value = self.read_raw(ctx).await?;
+        let byte = match self.1 {
+            HighOrLow::High => (raw_value >> 8) & 0xFF,
+            HighOrLow::Low => raw_value & 0xFF,
+        };
+        let byte = match self.is_signed {
+            true => signed_bits(byte, 8),
+            false => byte,
+        };
+        let output = byte / self.factor;
+        let metric = self.metric.clone();
+        Ok((
+            SensorValue::Int(output),
+            MetricUpdate::new(move || metric.set(output)),
+        ))
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct CompoundSensor<'a> {
     pub name: &'a str,
     pub registers: &'a [u16],
-    factors: &'a [i64],
-    no_negative: bool,
-    absolute: bool,
+    pub(crate) factors: &'a [i64],
+    pub(crate) no_negative: bool,
+    pub(crate) absolute: bool,
+    pub(crate) zero_epsilon: i64,
+    /// Sensors combined by their decoded values, in place of `registers` and `factors`.
+    pub(crate) components: Vec<CompoundComponent<'a>>,
     metric: IntGauge,
+    /// Values that couldn't be represented, and were clamped to the nearest that could.
+    out_of_range: IntCounter,
+}
+
+/// A sensor a `CompoundSensor` combines by its decoded value, ie. after its own sign
+/// conversion and scaling, rather than by its raw register.
+#[derive(Clone, Debug)]
+pub struct CompoundComponent<'a> {
+    pub slug: String,
+    pub(crate) sensor: Sensor<'a>,
+    /// The component's value is divided by this, so -1 subtracts it.
+    pub(crate) factor: i64,
+}
+
+impl<'a> CompoundComponent<'a> {
+    pub fn new(slug: &str, sensor: Sensor<'a>, factor: i64) -> CompoundComponent<'a> {
+        CompoundComponent {
+            slug: slug.to_owned(),
+            sensor,
+            factor,
+        }
+    }
 }
 
 impl CompoundSensor<'_> {
@@ -287,9 +1152,29 @@ impl CompoundSensor<'_> {
         factors: &'a [i64],
         no_negative: bool,
         absolute: bool,
+    ) -> CompoundSensor<'a> {
+        CompoundSensor::new_in(&REGISTRY, name, registers, factors, no_negative, absolute)
+    }
+
+    pub fn new_in<'a>(
+        registry: &Registry,
+        name: &'a str,
+        registers: &'a [u16],
+        factors: &'a [i64],
+        no_negative: bool,
+        absolute: bool,
     ) -> CompoundSensor<'a> {
         let metric = IntGauge::new(slug_name(name), name).unwrap();
-        REGISTRY.register(Box::new(metric.clone())).unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+        let out_of_range = IntCounter::with_opts(
+            Opts::new(
+                "sensor_values_out_of_range_total",
+                "Sensor values clamped because they overflowed while being decoded",
+            )
+            .const_label("sensor", slug_name(name)),
+        )
+        .unwrap();
+        registry.register(Box::new(out_of_range.clone())).unwrap();
 
         CompoundSensor {
             name,
@@ -297,413 +1182,1800 @@ impl CompoundSensor<'_> {
             factors,
             no_negative,
             absolute,
+            zero_epsilon: 0,
+            components: Vec::new(),
             metric,
+            out_of_range,
+        }
+    }
+
+    /// A compound of other sensors' values, eg. essential power as the inverter's output
+    /// plus the grid's import less the grid-side load, without repeating their registers
+    /// or scaling. It reads no registers of its own.
+    pub fn from_sensors_in<'a>(
+        registry: &Registry,
+        name: &'a str,
+        components: Vec<CompoundComponent<'a>>,
+        no_negative: bool,
+        absolute: bool,
+    ) -> CompoundSensor<'a> {
+        CompoundSensor {
+            components,
+            ..CompoundSensor::new_in(registry, name, &[], &[], no_negative, absolute)
+        }
+    }
+
+    /// As for `Sensor::with_zero_epsilon`, applied to the combined value.
+    pub fn with_zero_epsilon(mut self, epsilon: i64) -> Self {
+        self.zero_epsilon = epsilon;
+        self
+    }
+
+    /// Sum the scaled parts, saturating rather than wrapping if the total overflows, eg.
+    /// with a misconfigured factor. Overflows are counted in `out_of_range`.
+    fn accumulate(&self, parts: &[i64]) -> i64 {
+        let mut overflowed = false;
+        let mut output: i64 = 0;
+        for &part in parts {
+            output = output.checked_add(part).unwrap_or_else(|| {
+                overflowed = true;
+                output.saturating_add(part)
+            });
         }
+        if self.absolute && output < 0 {
+            output = output.checked_neg().unwrap_or_else(|| {
+                overflowed = true;
+                i64::MAX
+            });
+        }
+        if overflowed {
+            eprintln!("{} overflowed, clamped to {}", self.name, output);
+            self.out_of_range.inc();
+        }
+        output
     }
 }
 
 #[async_trait]
 impl SensorRead for CompoundSensor<'_> {
-    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>> {
-        let mut output: i64 = 0;
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        // Held throughout, so every part is read in the one go.
+        let mut ctx = ctx.lock().await;
+        let mut parts = Vec::new();
+        for component in self.components.iter() {
+            let raw = component.sensor.read_raw_from(&mut *ctx).await?;
+            parts.push(component.sensor.scale(raw) / component.factor);
+        }
         for (i, reg) in self.registers.iter().enumerate() {
-            let raw_output = ctx.lock().await.read_holding_registers(*reg, 1u16).await?;
+            let raw_output = ctx.read_holding_registers(*reg, 1u16).await?;
             let signed = match self.factors[i] < 0 {
                 true => signed(raw_output[0] as i64),
                 false => raw_output[0] as i64,
             };
-            output += signed / self.factors[i];
-        }
-        if self.absolute && output < 0 {
-            output = -output
+            parts.push(signed / self.factors[i]);
         }
+        let mut output = self.accumulate(&parts);
         if self.no_negative && output < 0 {
             output = 0;
         }
+        output = clamp_near_zero(output, self.zero_epsilon);
+
+        let metric = self.metric.clone();
+        Ok((
+            SensorValue::Int(output),
+            MetricUpdate::new(move || metric.set(output)),
+        ))
+    }
+}
 
-        self.metric.set(output);
-        Ok(format!("{}", output))
+/// Zero if `value` is within `epsilon` of it, otherwise `value`. An `epsilon` of zero or
+/// less leaves every value as it is, including `i64::MIN`, which has no absolute value.
+fn clamp_near_zero(value: i64, epsilon: i64) -> i64 {
+    match epsilon > 0 && value.unsigned_abs() <= epsilon as u64 {
+        true => 0,
+        false => value,
     }
 }
 
+/// The labels of each phase, in register order.
+pub const PHASES: [&str; 3] = ["L1", "L2", "L3"];
+
+/// One quantity, eg. voltage, measured on each phase of a three-phase inverter and
+/// exported as a single `phase` labelled gauge. Single and split-phase models have fewer
+/// phases, so take fewer registers.
 #[derive(Clone, Debug)]
-pub struct FaultSensor<'a> {
+pub struct PhaseSensor<'a> {
     pub name: &'a str,
-    pub(crate) registers: [u16; 4],
+    /// One register per phase, starting with L1.
+    pub registers: &'a [u16],
+    pub(crate) factor: i64,
+    pub(crate) is_signed: bool,
     pub(crate) metric: IntGaugeVec,
 }
 
-impl<'a> FaultSensor<'_> {
-    pub fn new(name: &'a str, registers: [u16; 4]) -> FaultSensor {
-        let metric = IntGaugeVec::new(Opts::new(slug_name(name), name), &["code"]).unwrap();
-        REGISTRY.register(Box::new(metric.clone())).unwrap();
+impl<'a> PhaseSensor<'a> {
+    pub fn new(
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+        is_signed: bool,
+    ) -> PhaseSensor<'a> {
+        PhaseSensor::new_in(&REGISTRY, name, registers, factor, is_signed)
+    }
 
-        FaultSensor {
+    pub fn new_in(
+        registry: &Registry,
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+        is_signed: bool,
+    ) -> PhaseSensor<'a> {
+        assert!(
+            (1..=PHASES.len()).contains(&registers.len()),
+            "{} needs one register per phase, between 1 and {}",
+            name,
+            PHASES.len()
+        );
+        let metric = IntGaugeVec::new(Opts::new(slug_name(name), name), &["phase"]).unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+
+        PhaseSensor {
             name,
             registers,
+            factor,
+            is_signed,
             metric,
         }
     }
+
+    /// Pair each phase's label with its scaled value.
+    pub fn decode(&self, raw: &[u16]) -> Vec<(&'static str, i64)> {
+        PHASES
+            .iter()
+            .zip(raw)
+            .map(|(&phase, &raw)| {
+                let value = match self.is_signed {
+                    true => signed(raw as i64),
+                    false => raw as i64,
+                };
+                (phase, value / self.factor)
+            })
+            .collect()
+    }
 }
 
 #[async_trait]
-impl SensorRead for FaultSensor<'_> {
-    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>> {
-        let mut output: Vec<u16> = Vec::new();
+impl SensorRead for PhaseSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let mut ctx = ctx.lock().await;
+        let mut raw = Vec::new();
         for (reg, len) in group_consecutive(self.registers.to_vec()) {
-            let raw_output = ctx.lock().await.read_holding_registers(reg, len).await?;
-            output.extend(raw_output);
+            raw.extend(ctx.read_holding_registers(reg, len).await?);
         }
-        let faults = faults_decode(output);
 
-        for fault in faults.iter() {
-            self.metric.with_label_values(&[&fault.to_string()]).set(1);
+        let phases = self.decode(&raw);
+        let text: Vec<String> = phases
+            .iter()
+            .map(|(phase, value)| format!("{}={}", phase, value))
+            .collect();
+        let metric = self.metric.clone();
+        let metrics = MetricUpdate::new(move || {
+            for (phase, value) in phases.iter() {
+                metric.with_label_values(&[phase]).set(*value);
+            }
+        });
+        Ok((SensorValue::Text(text.join(", ")), metrics))
+    }
+}
+
+#[derive(Debug, Default)]
+struct IntegratorState {
+    energy_wh: f64,
+    last_reading: Option<(i64, Instant)>,
+}
+
+/// Energy in Wh, derived by integrating a power sensor (in W) over the time between reads.
+/// For inverters that don't report a cumulative energy total for a power flow.
+#[derive(Clone, Debug)]
+pub struct IntegratedEnergySensor<'a> {
+    pub name: &'a str,
+    pub registers: &'a [u16],
+    power: Sensor<'a>,
+    state: Arc<std::sync::Mutex<IntegratorState>>,
+    metric: IntGauge,
+}
+
+impl IntegratedEnergySensor<'_> {
+    /// `registers`, `factor` and `is_signed` describe the power sensor being integrated.
+    pub fn new<'a>(
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+        is_signed: bool,
+    ) -> IntegratedEnergySensor<'a> {
+        IntegratedEnergySensor::new_in(&REGISTRY, name, registers, factor, is_signed)
+    }
+
+    pub fn new_in<'a>(
+        registry: &Registry,
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+        is_signed: bool,
+    ) -> IntegratedEnergySensor<'a> {
+        let metric = IntGauge::new(slug_name(name), name).unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+
+        IntegratedEnergySensor {
+            name,
+            registers,
+            power: Sensor::unregistered(name, registers, factor, is_signed),
+            state: Arc::new(std::sync::Mutex::new(IntegratorState::default())),
+            metric,
         }
+    }
 
-        Ok(faults
-            .iter()
-            .map(|f| format!("F{}", f))
-            .collect::<Vec<_>>()
-            .join(", "))
+    /// The energy accumulated so far, in Wh.
+    pub fn energy_wh(&self) -> f64 {
+        self.state.lock().unwrap().energy_wh
+    }
+
+    /// Carry on accumulating from a previously saved total, eg. after a restart.
+    pub fn set_energy_wh(&self, energy_wh: f64) {
+        self.state.lock().unwrap().energy_wh = energy_wh;
     }
 }
 
-fn faults_decode(reg_vals: Vec<u16>) -> Vec<u16> {
-    let mut faults: Vec<u16> = Vec::new();
-    let mut off = 0;
-    for val in reg_vals.iter() {
-        for bit in 0..16 {
-            let mask = 1 << bit;
-            if mask & val != 0 {
-                faults.push(bit + off + 1);
+#[async_trait]
+impl SensorRead for IntegratedEnergySensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let power = self.power.read(ctx).await?;
+        let now = Instant::now();
+
+        let energy_wh = {
+            let mut state = self.state.lock().unwrap();
+            // Trapezoidal rule, using the power at either end of the interval.
+            if let Some((last_power, last_read)) = state.last_reading {
+                let hours = (now - last_read).as_secs_f64() / 3600.0;
+                state.energy_wh += (last_power + power) as f64 / 2.0 * hours;
             }
-        }
-        off += 16;
+            state.last_reading = Some((power, now));
+            state.energy_wh
+        };
+
+        let output = energy_wh.round() as i64;
+        let metric = self.metric.clone();
+        Ok((
+            SensorValue::Int(output),
+            MetricUpdate::new(move || metric.set(output)),
+        ))
     }
-    faults
 }
 
+/// The change in a cumulative register, eg. a lifetime energy total, over the last
+/// collection cycle. A register that goes backwards, eg. a counter reset, gives 0 rather
+/// than a huge negative step. The first cycle also gives 0, as there's nothing to compare
+/// it with. Only the collector moves it on, with `advance`, so reading it from the API
+/// gives the last cycle's change rather than the change since some other request.
 #[derive(Clone, Debug)]
-pub struct SerialSensor<'a> {
+pub struct DeltaSensor<'a> {
     pub name: &'a str,
-    pub(crate) registers: [u16; 5],
+    pub registers: &'a [u16],
+    cumulative: Sensor<'a>,
+    state: Arc<std::sync::Mutex<DeltaState>>,
+    metric: IntGauge,
 }
 
-#[async_trait]
-impl SensorRead for SerialSensor<'_> {
-    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>> {
-        let raw_value = ctx
-            .lock()
-            .await
-            .read_holding_registers(self.registers[0], self.registers.len() as u16)
-            .await?;
-        let mut output = "".to_owned();
-        for b16 in raw_value {
-            let first_char = format!("{}", (b16 >> 8) as u8);
-            let second_char = format!("{}", (b16 & 0xFF) as u8);
-            output.push_str(&first_char);
-            output.push_str(&second_char);
+#[derive(Debug, Default)]
+struct DeltaState {
+    previous: Option<i64>,
+    delta: i64,
+}
+
+impl DeltaSensor<'_> {
+    pub fn new<'a>(name: &'a str, registers: &'a [u16], factor: i64) -> DeltaSensor<'a> {
+        DeltaSensor::new_in(&REGISTRY, name, registers, factor)
+    }
+
+    pub fn new_in<'a>(
+        registry: &Registry,
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+    ) -> DeltaSensor<'a> {
+        let metric = IntGauge::new(slug_name(name), name).unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+
+        DeltaSensor {
+            name,
+            registers,
+            cumulative: Sensor::unregistered(name, registers, factor, false),
+            state: Arc::default(),
+            metric,
         }
+    }
 
-        Ok(output)
+    /// Read the register for a new collection cycle, and return its change since the last.
+    pub async fn advance(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let current = self.cumulative.read(ctx).await?;
+        let output = {
+            let mut state = self.state.lock().unwrap();
+            state.delta = match state.previous.replace(current) {
+                Some(previous) => (current - previous).max(0),
+                None => 0,
+            };
+            state.delta
+        };
+        let metric = self.metric.clone();
+        Ok((
+            SensorValue::Int(output),
+            MetricUpdate::new(move || metric.set(output)),
+        ))
     }
 }
 
-#[derive(Clone, Debug)]
-pub enum SDStatus {
-    Fault,
-    Ok,
-    Unknown,
+#[async_trait]
+impl SensorRead for DeltaSensor<'_> {
+    /// The change over the last collection cycle. This doesn't touch the bus.
+    async fn measure(
+        &self,
+        _ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        Ok((
+            SensorValue::Int(self.state.lock().unwrap().delta),
+            MetricUpdate::default(),
+        ))
+    }
 }
 
+/// A value whose sign is kept in a register of its own rather than in the value, eg. a
+/// power flowing either way, with a flag for which way. The magnitude is read from all
+/// but the last register, and a non-zero last register makes it negative.
 #[derive(Clone, Debug)]
-pub struct SDStatusSensor<'a> {
+pub struct DirectionalSensor<'a> {
     pub name: &'a str,
-    pub(crate) registers: [u16; 1],
+    /// The magnitude's registers, followed by the direction register.
+    pub registers: &'a [u16],
+    magnitude: Sensor<'a>,
+    pub(crate) metric: IntGauge,
 }
 
-#[async_trait]
-impl SensorRead for SDStatusSensor<'_> {
-    async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>> {
-        let raw_value = ctx
-            .lock()
-            .await
-            .read_holding_registers(self.registers[0], 1u16)
-            .await?;
+impl DirectionalSensor<'_> {
+    pub fn new<'a>(name: &'a str, registers: &'a [u16], factor: i64) -> DirectionalSensor<'a> {
+        DirectionalSensor::new_in(&REGISTRY, name, registers, factor)
+    }
 
-        let status = match raw_value[0] {
-            1000 => SDStatus::Fault,
-            2000 => SDStatus::Ok,
-            _ => SDStatus::Unknown,
+    pub fn new_in<'a>(
+        registry: &Registry,
+        name: &'a str,
+        registers: &'a [u16],
+        factor: i64,
+    ) -> DirectionalSensor<'a> {
+        assert!(
+            registers.len() >= 2,
+            "a directional sensor needs a magnitude and a direction register"
+        );
+        let metric = IntGauge::new(slug_name(name), name).unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+
+        DirectionalSensor {
+            name,
+            registers,
+            magnitude: Sensor::unregistered(name, &registers[..registers.len() - 1], factor, false),
+            metric,
+        }
+    }
+
+    pub(crate) fn factor(&self) -> i64 {
+        self.magnitude.factor
+    }
+}
+
+#[async_trait]
+impl SensorRead for DirectionalSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let mut ctx = ctx.lock().await;
+        let magnitude = self
+            .magnitude
+            .scale(self.magnitude.read_raw_from(&mut *ctx).await?);
+        let direction = self.registers[self.registers.len() - 1];
+        let output = match ctx.read_holding_registers(direction, 1).await?[0] {
+            0 => magnitude,
+            _ => -magnitude,
         };
-        Ok(format!("{:?}", status))
+        let metric = self.metric.clone();
+        Ok((
+            SensorValue::Int(output),
+            MetricUpdate::new(move || metric.set(output)),
+        ))
     }
 }
 
+/// One register as a percentage of another, eg. PV power as a percentage of the rated
+/// power. The value is unavailable while the denominator is zero.
 #[derive(Clone, Debug)]
-pub enum SensorTypes<'a> {
-    Basic(BasicSensor<'a>),
-    Binary(BinarySensor<'a>),
-    Compound(CompoundSensor<'a>),
-    Fault(FaultSensor<'a>),
-    Serial(SerialSensor<'a>),
-    Temperature(TemperatureSensor<'a>),
+pub struct RatioSensor<'a> {
+    pub name: &'a str,
+    /// The numerator's register, then the denominator's.
+    pub registers: [u16; 2],
+    pub(crate) metric: Gauge,
 }
 
-impl SensorTypes<'_> {
-    pub async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>> {
-        match self {
-            SensorTypes::Basic(s) => s.read(ctx.clone()).await,
-            SensorTypes::Binary(s) => s.read(ctx.clone()).await,
-            SensorTypes::Temperature(s) => s.read(ctx.clone()).await,
-            SensorTypes::Compound(s) => s.read(ctx.clone()).await,
-            SensorTypes::Fault(s) => s.read(ctx.clone()).await,
-            SensorTypes::Serial(s) => s.read(ctx.clone()).await,
-        }
+impl RatioSensor<'_> {
+    pub fn new<'a>(name: &'a str, numerator: u16, denominator: u16) -> RatioSensor<'a> {
+        RatioSensor::new_in(&REGISTRY, name, numerator, denominator)
     }
 
-    pub async fn write(
-        &self,
-        ctx: Arc<Mutex<dyn Writer>>,
-        data: AtomicU16,
-    ) -> Result<(), Box<dyn Error>> {
-        match self {
-            SensorTypes::Basic(s) => s.write(ctx.clone(), data).await,
-            SensorTypes::Binary(s) => s.write(ctx.clone(), data).await,
-            _ => Err(Box::new(io::Error::new(
-                io::ErrorKind::PermissionDenied,
-                "Sensor not writeable.",
-            ))),
+    pub fn new_in<'a>(
+        registry: &Registry,
+        name: &'a str,
+        numerator: u16,
+        denominator: u16,
+    ) -> RatioSensor<'a> {
+        let metric = Gauge::new(slug_name(name), name).unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+
+        RatioSensor {
+            name,
+            registers: [numerator, denominator],
+            metric,
         }
     }
 }
 
-pub fn register_sensors() -> HashMap<String, SensorTypes<'static>> {
-    let mut all_sensors: HashMap<String, SensorTypes<'static>> = HashMap::new();
+#[async_trait]
+impl SensorRead for RatioSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        // Read separately, as the denominator can come before the numerator.
+        let mut ctx = ctx.lock().await;
+        let numerator = ctx.read_holding_registers(self.registers[0], 1).await?[0];
+        let denominator = ctx.read_holding_registers(self.registers[1], 1).await?[0];
+        if denominator == 0 {
+            return Ok((SensorValue::Unavailable, MetricUpdate::default()));
+        }
 
-    for sensor in SENSORS.clone().into_iter() {
-        all_sensors.insert(
-            slug_name(sensor.name).to_owned(),
-            SensorTypes::Basic(sensor.clone()),
-        );
+        let output = numerator as f64 / denominator as f64 * 100.0;
+        let metric = self.metric.clone();
+        Ok((
+            SensorValue::Float(output),
+            MetricUpdate::new(move || metric.set(output)),
+        ))
     }
-    for sensor in BINARY_SENSORS.clone().into_iter() {
-        all_sensors.insert(
-            slug_name(sensor.name).to_owned(),
-            SensorTypes::Binary(sensor.clone()),
+}
+
+/// The share of an energy counter that didn't cross the grid, as a percentage. With the
+/// PV energy and the grid export it's the self-consumption, and with the load energy and
+/// the grid import the self-sufficiency. The value is unavailable while the total is
+/// zero, eg. before sunrise.
+#[derive(Clone, Debug)]
+pub struct EnergyShareSensor<'a> {
+    pub name: &'a str,
+    // Boxed, as two whole sensors would make every `SensorTypes` twice the size.
+    pub(crate) total: Box<CompoundComponent<'a>>,
+    /// The part of the total that was exported or imported.
+    pub(crate) exchanged: Box<CompoundComponent<'a>>,
+    pub(crate) metric: Gauge,
+}
+
+impl<'a> EnergyShareSensor<'a> {
+    pub fn new(
+        name: &'a str,
+        total: CompoundComponent<'a>,
+        exchanged: CompoundComponent<'a>,
+    ) -> EnergyShareSensor<'a> {
+        EnergyShareSensor::new_in(&REGISTRY, name, total, exchanged)
+    }
+
+    pub fn new_in(
+        registry: &Registry,
+        name: &'a str,
+        total: CompoundComponent<'a>,
+        exchanged: CompoundComponent<'a>,
+    ) -> EnergyShareSensor<'a> {
+        let metric = Gauge::new(slug_name(name), name).unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+
+        EnergyShareSensor {
+            name,
+            total: Box::new(total),
+            exchanged: Box::new(exchanged),
+            metric,
+        }
+    }
+}
+
+#[async_trait]
+impl SensorRead for EnergyShareSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let mut ctx = ctx.lock().await;
+        let total = self.total.sensor.read_raw_from(&mut *ctx).await?;
+        let total = self.total.sensor.scale_exact(total);
+        let exchanged = self.exchanged.sensor.read_raw_from(&mut *ctx).await?;
+        let exchanged = self.exchanged.sensor.scale_exact(exchanged);
+        if total <= 0.0 {
+            return Ok((SensorValue::Unavailable, MetricUpdate::default()));
+        }
+
+        // The counters are read moments apart, so can briefly disagree.
+        let output = ((total - exchanged) / total * 100.0).clamp(0.0, 100.0);
+        let metric = self.metric.clone();
+        Ok((
+            SensorValue::Float(output),
+            MetricUpdate::new(move || metric.set(output)),
+        ))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FaultSensor<'a> {
+    pub name: &'a str,
+    pub(crate) registers: [u16; 4],
+    pub(crate) metric: IntGaugeVec,
+    /// The most fault codes to keep a label for. Inactive codes beyond it are dropped,
+    /// longest inactive first.
+    pub(crate) max_codes: Option<usize>,
+    /// The codes with a label, longest inactive first. Shared by clones, like the metric.
+    codes: Arc<std::sync::Mutex<Vec<u16>>>,
+}
+
+impl<'a> FaultSensor<'_> {
+    pub fn new(name: &'a str, registers: [u16; 4]) -> FaultSensor<'a> {
+        FaultSensor::new_in(&REGISTRY, name, registers)
+    }
+
+    pub fn new_in(registry: &Registry, name: &'a str, registers: [u16; 4]) -> FaultSensor<'a> {
+        let metric = IntGaugeVec::new(Opts::new(slug_name(name), name), &["code"]).unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+
+        FaultSensor {
+            name,
+            registers,
+            metric,
+            max_codes: None,
+            codes: Arc::default(),
+        }
+    }
+
+    /// Keep labels for at most `max_codes` fault codes, so an inverter cycling through
+    /// transient faults can't grow the metric without bound. Active faults always keep
+    /// their label.
+    pub fn with_max_codes(mut self, max_codes: usize) -> Self {
+        self.max_codes = Some(max_codes);
+        self
+    }
+
+    /// The update setting the active faults' labels to 1 and every other code's to 0,
+    /// dropping the labels of inactive codes over the limit.
+    fn metric_update(&self, active: Vec<u16>) -> MetricUpdate {
+        let metric = self.metric.clone();
+        let max_codes = self.max_codes;
+        let codes = self.codes.clone();
+        MetricUpdate::new(move || {
+            let mut codes = codes.lock().unwrap();
+            codes.retain(|code| !active.contains(code));
+            let inactive = codes.len();
+            codes.extend_from_slice(&active);
+
+            let excess = match max_codes {
+                Some(max_codes) => codes.len().saturating_sub(max_codes).min(inactive),
+                None => 0,
+            };
+            for code in codes.drain(..excess) {
+                let _ = metric.remove_label_values(&[&code.to_string()]);
+            }
+            for (i, code) in codes.iter().enumerate() {
+                let value = (i >= inactive - excess) as i64;
+                metric.with_label_values(&[&code.to_string()]).set(value);
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl SensorRead for FaultSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let mut ctx = ctx.lock().await;
+        let mut output: Vec<u16> = Vec::new();
+        for (reg, len) in group_consecutive(self.registers.to_vec()) {
+            let raw_output = ctx.read_holding_registers(reg, len).await?;
+            output.extend(raw_output);
+        }
+        let faults = faults_decode(output);
+        let text = faults
+            .iter()
+            .map(|f| format!("F{}", f))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Ok((SensorValue::Text(text), self.metric_update(faults)))
+    }
+}
+
+fn faults_decode(reg_vals: Vec<u16>) -> Vec<u16> {
+    let mut faults: Vec<u16> = Vec::new();
+    let mut off = 0;
+    for val in reg_vals.iter() {
+        for bit in 0..16 {
+            let mask = 1 << bit;
+            if mask & val != 0 {
+                faults.push(bit + off + 1);
+            }
+        }
+        off += 16;
+    }
+    faults
+}
+
+/// A run of discrete inputs, each a named status flag. Every flag gets its own `flag`
+/// labelled gauge, set to 1 or 0.
+#[derive(Clone, Debug)]
+pub struct StatusFlagsSensor<'a> {
+    pub name: &'a str,
+    /// The first discrete input, holding `flags[0]`.
+    pub(crate) start: u16,
+    pub(crate) flags: &'a [&'a str],
+    pub(crate) metric: IntGaugeVec,
+}
+
+impl<'a> StatusFlagsSensor<'a> {
+    pub fn new(name: &'a str, start: u16, flags: &'a [&'a str]) -> StatusFlagsSensor<'a> {
+        StatusFlagsSensor::new_in(&REGISTRY, name, start, flags)
+    }
+
+    pub fn new_in(
+        registry: &Registry,
+        name: &'a str,
+        start: u16,
+        flags: &'a [&'a str],
+    ) -> StatusFlagsSensor<'a> {
+        let metric = IntGaugeVec::new(Opts::new(slug_name(name), name), &["flag"]).unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+
+        StatusFlagsSensor {
+            name,
+            start,
+            flags,
+            metric,
+        }
+    }
+}
+
+#[async_trait]
+impl SensorRead for StatusFlagsSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let inputs = ctx
+            .lock()
+            .await
+            .read_discrete_inputs(self.start, self.flags.len() as u16)
+            .await?;
+
+        let flags: Vec<(String, bool)> = self
+            .flags
+            .iter()
+            .zip(inputs)
+            .map(|(flag, is_set)| (flag.to_string(), is_set))
+            .collect();
+        Ok(flag_reading(&self.metric, flags))
+    }
+}
+
+/// Named bits of a status register that also holds other flags, eg. whether the grid
+/// relay is closed. Every named bit gets its own `flag` labelled gauge, set to 1 or 0, and
+/// the other bits are ignored.
+#[derive(Clone, Debug)]
+pub struct BitfieldSensor<'a> {
+    pub name: &'a str,
+    pub(crate) register: u16,
+    /// Each bit's position, counted from the least significant, and its name.
+    pub(crate) bits: &'a [(u8, &'a str)],
+    pub(crate) metric: IntGaugeVec,
+}
+
+impl<'a> BitfieldSensor<'a> {
+    pub fn new(name: &'a str, register: u16, bits: &'a [(u8, &'a str)]) -> BitfieldSensor<'a> {
+        BitfieldSensor::new_in(&REGISTRY, name, register, bits)
+    }
+
+    pub fn new_in(
+        registry: &Registry,
+        name: &'a str,
+        register: u16,
+        bits: &'a [(u8, &'a str)],
+    ) -> BitfieldSensor<'a> {
+        assert!(
+            bits.iter().all(|&(bit, _)| bit < 16),
+            "a register only has 16 bits"
+        );
+        let metric = IntGaugeVec::new(Opts::new(slug_name(name), name), &["flag"]).unwrap();
+        registry.register(Box::new(metric.clone())).unwrap();
+
+        BitfieldSensor {
+            name,
+            register,
+            bits,
+            metric,
+        }
+    }
+}
+
+#[async_trait]
+impl SensorRead for BitfieldSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let raw = ctx
+            .lock()
+            .await
+            .read_holding_registers(self.register, 1)
+            .await?[0];
+
+        let flags = self
+            .bits
+            .iter()
+            .map(|&(bit, flag)| (flag.to_string(), raw & (1 << bit) != 0))
+            .collect();
+        Ok(flag_reading(&self.metric, flags))
+    }
+}
+
+/// The names of the set flags, and the update setting each flag's label to 1 or 0.
+fn flag_reading(metric: &IntGaugeVec, flags: Vec<(String, bool)>) -> (SensorValue, MetricUpdate) {
+    let set_flags: Vec<&str> = flags
+        .iter()
+        .filter(|(_, is_set)| *is_set)
+        .map(|(flag, _)| flag.as_str())
+        .collect();
+    let value = SensorValue::Text(set_flags.join(", "));
+    let metric = metric.clone();
+    let metrics = MetricUpdate::new(move || {
+        for (flag, is_set) in flags.iter() {
+            metric.with_label_values(&[flag]).set(*is_set as i64);
+        }
+    });
+    (value, metrics)
+}
+
+/// The inverter's serial number, read from a run of consecutive registers with each byte
+/// written out as a decimal number. Most firmware puts the high byte of each register
+/// first, but some put the low byte first.
+#[derive(Clone, Debug)]
+pub struct SerialSensor<'a> {
+    pub name: &'a str,
+    pub(crate) registers: &'a [u16],
+    pub(crate) first_byte: HighOrLow,
+}
+
+impl<'a> SerialSensor<'a> {
+    pub const fn new(name: &'a str, registers: &'a [u16], first_byte: HighOrLow) -> Self {
+        SerialSensor {
+            name,
+            registers,
+            first_byte,
+        }
+    }
+
+    fn decode(&self, raw: &[u16]) -> String {
+        let mut output = String::new();
+        for b16 in raw {
+            let [high, low] = b16.to_be_bytes();
+            let (first, second) = match self.first_byte {
+                HighOrLow::High => (high, low),
+                HighOrLow::Low => (low, high),
+            };
+            output.push_str(&first.to_string());
+            output.push_str(&second.to_string());
+        }
+        output
+    }
+}
+
+#[async_trait]
+impl SensorRead for SerialSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let raw_value = ctx
+            .lock()
+            .await
+            .read_holding_registers(self.registers[0], self.registers.len() as u16)
+            .await?;
+        Ok((
+            SensorValue::Text(self.decode(&raw_value)),
+            MetricUpdate::default(),
+        ))
+    }
+}
+
+/// A version number packed into one register, the major version in the high byte and the
+/// minor in the low, eg. 0x0312 for 3.18.
+#[derive(Clone, Debug)]
+pub struct VersionSensor<'a> {
+    pub name: &'a str,
+    pub(crate) register: u16,
+}
+
+impl<'a> VersionSensor<'a> {
+    pub const fn new(name: &'a str, register: u16) -> Self {
+        VersionSensor { name, register }
+    }
+
+    pub(crate) fn decode(raw: u16) -> String {
+        let [major, minor] = raw.to_be_bytes();
+        format!("{}.{}", major, minor)
+    }
+}
+
+#[async_trait]
+impl SensorRead for VersionSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let raw_value = ctx
+            .lock()
+            .await
+            .read_holding_registers(self.register, 1)
+            .await?[0];
+        Ok((
+            SensorValue::Text(VersionSensor::decode(raw_value)),
+            MetricUpdate::default(),
+        ))
+    }
+}
+
+/// Text packed into a block of registers, two ASCII characters to a register with the
+/// first in the high byte, eg. the running status some firmware reports alongside the
+/// numeric state. The text ends at the first NUL, and the spaces or 0xFF bytes the
+/// firmware pads the rest of the block with are dropped.
+#[derive(Clone, Debug)]
+pub struct TextSensor<'a> {
+    pub name: &'a str,
+    pub registers: &'a [u16],
+}
+
+impl TextSensor<'_> {
+    pub(crate) fn decode(raw: &[u16]) -> String {
+        let text: String = raw
+            .iter()
+            .flat_map(|register| register.to_be_bytes())
+            .take_while(|&byte| byte != 0)
+            .filter(|byte| byte.is_ascii_graphic() || *byte == b' ')
+            .map(char::from)
+            .collect();
+        text.trim().to_owned()
+    }
+}
+
+#[async_trait]
+impl SensorRead for TextSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let mut ctx = ctx.lock().await;
+        let mut raw = Vec::new();
+        for (reg, len) in group_consecutive(self.registers.to_vec()) {
+            raw.extend(ctx.read_holding_registers(reg, len).await?);
+        }
+        Ok((
+            SensorValue::Text(TextSensor::decode(&raw)),
+            MetricUpdate::default(),
+        ))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SDStatus {
+    Fault,
+    Ok,
+    Unknown,
+}
+
+#[derive(Clone, Debug)]
+pub struct SDStatusSensor<'a> {
+    pub name: &'a str,
+    pub(crate) registers: [u16; 1],
+}
+
+#[async_trait]
+impl SensorRead for SDStatusSensor<'_> {
+    async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        let raw_value = ctx
+            .lock()
+            .await
+            .read_holding_registers(self.registers[0], 1u16)
+            .await?;
+
+        let status = match raw_value[0] {
+            1000 => SDStatus::Fault,
+            2000 => SDStatus::Ok,
+            _ => SDStatus::Unknown,
+        };
+        Ok((
+            SensorValue::Text(format!("{:?}", status)),
+            MetricUpdate::default(),
+        ))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SensorTypes<'a> {
+    Basic(BasicSensor<'a>),
+    Binary(BinarySensor<'a>),
+    Bitfield(BitfieldSensor<'a>),
+    Bms(BmsSensor<'a>),
+    ByteSlice(ByteSliceSensor<'a>),
+    Compound(CompoundSensor<'a>),
+    Delta(DeltaSensor<'a>),
+    Directional(DirectionalSensor<'a>),
+    EnergyShare(EnergyShareSensor<'a>),
+    Fault(FaultSensor<'a>),
+    IntegratedEnergy(IntegratedEnergySensor<'a>),
+    Phase(PhaseSensor<'a>),
+    Ratio(RatioSensor<'a>),
+    Serial(SerialSensor<'a>),
+    StatusFlags(StatusFlagsSensor<'a>),
+    Temperature(TemperatureSensor<'a>),
+    Text(TextSensor<'a>),
+}
+
+impl SensorTypes<'_> {
+    pub async fn read(&self, ctx: Arc<Mutex<dyn Reader>>) -> Result<String, Box<dyn Error>> {
+        Ok(self.read_value(ctx).await?.to_string())
+    }
+
+    /// Read the sensor for a collection cycle. This is `measure`, except for sensors
+    /// whose value is a change between cycles, which only move on here. The gauges are
+    /// left for whoever publishes the cycle to set.
+    pub async fn collect_value(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        match self {
+            SensorTypes::Delta(s) => s.advance(ctx).await,
+            _ => self.measure(ctx).await,
+        }
+    }
+
+    pub async fn read_value(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<SensorValue, Box<dyn Error>> {
+        let (value, metrics) = self.measure(ctx).await?;
+        metrics.apply();
+        Ok(value)
+    }
+
+    /// Read the sensor, leaving its gauges to be set with the update that comes back.
+    pub async fn measure(
+        &self,
+        ctx: Arc<Mutex<dyn Reader>>,
+    ) -> Result<(SensorValue, MetricUpdate), Box<dyn Error>> {
+        match self {
+            SensorTypes::Basic(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Binary(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Bitfield(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Bms(s) => s.measure(ctx.clone()).await,
+            SensorTypes::ByteSlice(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Temperature(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Compound(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Delta(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Directional(s) => s.measure(ctx.clone()).await,
+            SensorTypes::EnergyShare(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Fault(s) => s.measure(ctx.clone()).await,
+            SensorTypes::IntegratedEnergy(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Phase(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Ratio(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Serial(s) => s.measure(ctx.clone()).await,
+            SensorTypes::StatusFlags(s) => s.measure(ctx.clone()).await,
+            SensorTypes::Text(s) => s.measure(ctx.clone()).await,
+        }
+    }
+
+    pub async fn write(
+        &self,
+        ctx: Arc<Mutex<dyn Writer>>,
+        data: AtomicU16,
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            SensorTypes::Basic(s) if s.is_mut => s.write(ctx.clone(), data).await,
+            SensorTypes::Binary(s) if s.is_mut => s.write(ctx.clone(), data).await,
+            _ => Err(Box::new(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Sensor not writeable.",
+            ))),
+        }
+    }
+
+    /// The write `write` would make for `data`, for checking a write without touching
+    /// the inverter.
+    pub fn planned_write(&self, data: u16) -> Result<RegisterWrite, Box<dyn Error>> {
+        match self {
+            SensorTypes::Basic(s) if s.is_mut => Ok(s.planned_write(data)?),
+            SensorTypes::Binary(s) if s.is_mut => {
+                check_binary(data)?;
+                Ok(s.planned_write(data)?)
+            }
+            _ => Err(Box::new(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Sensor not writeable.",
+            ))),
+        }
+    }
+
+    pub async fn adjust(
+        &self,
+        ctx: Arc<Mutex<Context>>,
+        delta: i64,
+    ) -> Result<i64, Box<dyn Error>> {
+        match self {
+            SensorTypes::Basic(s) => s.adjust(ctx, delta).await,
+            _ => Err(SensorError::IsNotMut.into()),
+        }
+    }
+
+    pub async fn reset(&self, ctx: Arc<Mutex<Context>>) -> Result<(), Box<dyn Error>> {
+        match self {
+            SensorTypes::Basic(s) => s.reset(ctx).await,
+            _ => Err(Box::new(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Sensor can't be reset.",
+            ))),
+        }
+    }
+
+    /// The period of the energy counter the sensor is, if it's one.
+    pub fn energy_period(&self) -> Option<EnergyPeriod> {
+        match self {
+            SensorTypes::Basic(s) => s.energy_period(),
+            _ => None,
+        }
+    }
+
+    /// How long until the sensor's write limit allows another write, if it doesn't now.
+    pub fn write_retry_after(&self) -> Option<Duration> {
+        match self {
+            SensorTypes::Basic(s) => s.write_retry_after(),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> SensorTypes<'a> {
+    pub fn name(&self) -> &'a str {
+        match self {
+            SensorTypes::Basic(s) => s.name,
+            SensorTypes::Binary(s) => s.name,
+            SensorTypes::Bitfield(s) => s.name,
+            SensorTypes::Bms(s) => s.name,
+            SensorTypes::ByteSlice(s) => s.name,
+            SensorTypes::Compound(s) => s.name,
+            SensorTypes::Delta(s) => s.name,
+            SensorTypes::Directional(s) => s.name,
+            SensorTypes::EnergyShare(s) => s.name,
+            SensorTypes::Fault(s) => s.name,
+            SensorTypes::IntegratedEnergy(s) => s.name,
+            SensorTypes::Phase(s) => s.name,
+            SensorTypes::Ratio(s) => s.name,
+            SensorTypes::Serial(s) => s.name,
+            SensorTypes::StatusFlags(s) => s.name,
+            SensorTypes::Temperature(s) => s.name,
+            SensorTypes::Text(s) => s.name,
+        }
+    }
+
+    /// The underlying `Sensor`, for sensor types that are a thin wrapper around one.
+    pub fn sensor(&self) -> Option<&Sensor<'a>> {
+        match self {
+            SensorTypes::Basic(s) => Some(&s.0),
+            SensorTypes::Binary(s) => Some(&s.0),
+            SensorTypes::ByteSlice(s) => Some(&s.0),
+            SensorTypes::Temperature(s) => Some(&s.0),
+            _ => None,
+        }
+    }
+
+    /// As `sensor`, but mutable.
+    pub fn sensor_mut(&mut self) -> Option<&mut Sensor<'a>> {
+        match self {
+            SensorTypes::Basic(s) => Some(&mut s.0),
+            SensorTypes::Binary(s) => Some(&mut s.0),
+            SensorTypes::ByteSlice(s) => Some(&mut s.0),
+            SensorTypes::Temperature(s) => Some(&mut s.0),
+            _ => None,
+        }
+    }
+
+    /// The holding registers read to decode this sensor. Sensors that read other kinds of
+    /// Modbus data, like discrete inputs or input registers, have none.
+    pub fn registers(&self) -> &[u16] {
+        if let Some(Sensor {
+            read_fn: ReadFunction::InputRegisters,
+            ..
+        }) = self.sensor()
+        {
+            return &[];
+        }
+        match self {
+            SensorTypes::Basic(s) => s.registers,
+            SensorTypes::Binary(s) => s.registers,
+            SensorTypes::Bitfield(s) => std::slice::from_ref(&s.register),
+            SensorTypes::Bms(s) => &s.registers,
+            SensorTypes::ByteSlice(s) => s.registers,
+            SensorTypes::Compound(s) => s.registers,
+            SensorTypes::Delta(s) => s.registers,
+            SensorTypes::Directional(s) => s.registers,
+            // Like a compound of other sensors, it reads no registers of its own.
+            SensorTypes::EnergyShare(_) => &[],
+            SensorTypes::Fault(s) => &s.registers,
+            SensorTypes::IntegratedEnergy(s) => s.registers,
+            SensorTypes::Phase(s) => s.registers,
+            SensorTypes::Ratio(s) => &s.registers,
+            SensorTypes::Serial(s) => s.registers,
+            SensorTypes::StatusFlags(_) => &[],
+            SensorTypes::Temperature(s) => s.registers,
+            SensorTypes::Text(s) => s.registers,
+        }
+    }
+
+    /// The unit the API shows the sensor's value in, which is its own unless it has a
+    /// display unit.
+    pub fn display_unit(&self) -> Option<&str> {
+        match self.sensor().and_then(|s| s.display_unit.as_ref()) {
+            Some(display) => Some(&display.unit),
+            None => self.unit(),
+        }
+    }
+
+    /// `value` as the API shows it, ie. in the display unit if the sensor has one.
+    pub fn display_value(&self, value: SensorValue) -> SensorValue {
+        match self.sensor() {
+            Some(sensor) => sensor.display_value(value),
+            None => value,
+        }
+    }
+
+    /// The unit of the sensor's value, eg. "W", if it has one.
+    pub fn unit(&self) -> Option<&str> {
+        match self {
+            SensorTypes::Basic(s) => s.unit(),
+            SensorTypes::Binary(s) => s.unit(),
+            SensorTypes::ByteSlice(s) => s.unit(),
+            SensorTypes::Temperature(s) => s.unit(),
+            _ => None,
+        }
+    }
+
+    /// Whether the sensor can be written, ie. it's a writable type constructed with
+    /// `Sensor::new_mut`.
+    pub fn is_writable(&self) -> bool {
+        match self {
+            SensorTypes::Basic(s) => s.is_mut,
+            SensorTypes::Binary(s) => s.is_mut,
+            _ => false,
+        }
+    }
+
+    /// Whether the sensor can be read, ie. it isn't write-only.
+    pub fn is_readable(&self) -> bool {
+        match self {
+            SensorTypes::Basic(s) => !s.write_only,
+            SensorTypes::Binary(s) => !s.write_only,
+            _ => true,
+        }
+    }
+
+    /// Whether the sensor's value never changes, so only needs reading once.
+    pub fn is_read_once(&self) -> bool {
+        match self {
+            SensorTypes::Basic(s) => s.read_once,
+            SensorTypes::Binary(s) => s.read_once,
+            SensorTypes::ByteSlice(s) => s.read_once,
+            SensorTypes::Temperature(s) => s.read_once,
+            SensorTypes::Bitfield(_)
+            | SensorTypes::Bms(_)
+            | SensorTypes::Compound(_)
+            | SensorTypes::Delta(_)
+            | SensorTypes::Directional(_)
+            | SensorTypes::EnergyShare(_)
+            | SensorTypes::Fault(_)
+            | SensorTypes::IntegratedEnergy(_)
+            | SensorTypes::Phase(_)
+            | SensorTypes::Ratio(_)
+            | SensorTypes::Serial(_)
+            | SensorTypes::StatusFlags(_)
+            | SensorTypes::Text(_) => false,
+        }
+    }
+
+    /// Where the sensor comes in the data collector's reads, highest first.
+    pub fn priority(&self) -> i32 {
+        match self {
+            SensorTypes::Basic(s) => s.priority,
+            SensorTypes::Binary(s) => s.priority,
+            SensorTypes::ByteSlice(s) => s.priority,
+            SensorTypes::Temperature(s) => s.priority,
+            SensorTypes::Bitfield(_)
+            | SensorTypes::Bms(_)
+            | SensorTypes::Compound(_)
+            | SensorTypes::Delta(_)
+            | SensorTypes::Directional(_)
+            | SensorTypes::EnergyShare(_)
+            | SensorTypes::Fault(_)
+            | SensorTypes::IntegratedEnergy(_)
+            | SensorTypes::Phase(_)
+            | SensorTypes::Ratio(_)
+            | SensorTypes::Serial(_)
+            | SensorTypes::StatusFlags(_)
+            | SensorTypes::Text(_) => 0,
+        }
+    }
+
+    /// The metrics this sensor sets on each read.
+    fn collectors(&self) -> Vec<Box<dyn Collector>> {
+        match self {
+            SensorTypes::Basic(s) => s.collectors(),
+            SensorTypes::Binary(s) => s.collectors(),
+            SensorTypes::Bitfield(s) => vec![Box::new(s.metric.clone())],
+            SensorTypes::Bms(s) => s
+                .metrics()
+                .into_iter()
+                .map(|m| Box::new(m) as Box<dyn Collector>)
+                .collect(),
+            SensorTypes::ByteSlice(s) => s.collectors(),
+            SensorTypes::Compound(s) => {
+                vec![Box::new(s.metric.clone()), Box::new(s.out_of_range.clone())]
+            }
+            SensorTypes::Delta(s) => vec![Box::new(s.metric.clone())],
+            SensorTypes::Directional(s) => vec![Box::new(s.metric.clone())],
+            SensorTypes::EnergyShare(s) => vec![Box::new(s.metric.clone())],
+            SensorTypes::Fault(s) => vec![Box::new(s.metric.clone())],
+            SensorTypes::IntegratedEnergy(s) => vec![Box::new(s.metric.clone())],
+            SensorTypes::Phase(s) => vec![Box::new(s.metric.clone())],
+            SensorTypes::Ratio(s) => vec![Box::new(s.metric.clone())],
+            SensorTypes::Serial(_) => vec![],
+            SensorTypes::StatusFlags(s) => vec![Box::new(s.metric.clone())],
+            SensorTypes::Temperature(s) => s.collectors(),
+            SensorTypes::Text(_) => vec![],
+        }
+    }
+}
+
+/// Like `register_sensors_in`, but leaving out the sensors with the given slugs, eg. for
+/// inverters without a second PV string. Disabled sensors are never polled and their
+/// metrics are removed from `registry`.
+pub fn register_sensors_except<S: AsRef<str>>(
+    registry: &Registry,
+    disabled: &[S],
+) -> HashMap<String, SensorTypes<'static>> {
+    let mut all_sensors = register_sensors_in(registry);
+    for slug in disabled.iter() {
+        if let Some(sensor) = all_sensors.remove(slug.as_ref()) {
+            for collector in sensor.collectors() {
+                let _ = registry.unregister(collector);
+            }
+        }
+    }
+    all_sensors
+}
+
+pub fn register_sensors() -> HashMap<String, SensorTypes<'static>> {
+    sensor_map(
+        &*SENSORS,
+        &*BINARY_SENSORS,
+        &*TEMP_SENSORS,
+        &*COMPOUND_SENSORS,
+        &*ENERGY_SHARE_SENSORS,
+        &[
+            SensorTypes::Fault(FAULTS.clone()),
+            SensorTypes::Bitfield(RELAY_STATUS.clone()),
+        ],
+    )
+}
+
+/// A new set of the built-in sensors, with their metrics registered in `registry` rather
+/// than the global `REGISTRY`, eg. for a second server polling another inverter.
+pub fn register_sensors_in(registry: &Registry) -> HashMap<String, SensorTypes<'static>> {
+    sensor_map(
+        &sensors(registry),
+        &binary_sensors(registry),
+        &temp_sensors(registry),
+        &compound_sensors(registry),
+        &energy_share_sensors(registry),
+        &[
+            SensorTypes::Fault(faults(registry)),
+            SensorTypes::Bitfield(relay_status(registry)),
+        ],
+    )
+}
+
+/// There's no sensor with the slug that was asked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SensorNotFound(pub String);
+
+impl std::fmt::Display for SensorNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "no sensor has the slug {}", self.0)
+    }
+}
+
+impl Error for SensorNotFound {}
+
+/// Read the sensor with the given slug, as `GET /api/unstable/<slug>` does but without
+/// the cache, for embedding the exporter as a library. A missing slug gives a
+/// `SensorNotFound` error, without touching the inverter.
+pub async fn read_sensor(
+    ctx: Arc<Mutex<dyn Reader>>,
+    sensors: &HashMap<String, SensorTypes<'_>>,
+    slug: &str,
+) -> Result<SensorValue, Box<dyn Error>> {
+    match sensors.get(slug) {
+        Some(sensor) => sensor.read_value(ctx).await,
+        None => Err(SensorNotFound(slug.to_owned()).into()),
+    }
+}
+
+/// Two sensors whose names have the same slug, so can't both be served.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlugCollision {
+    pub slug: String,
+    /// The sensor that was kept.
+    pub first: String,
+    /// The sensor that was left out.
+    pub second: String,
+}
+
+impl std::fmt::Display for SlugCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} and {} both have the slug {}, leaving out {}",
+            self.first, self.second, self.slug, self.second
+        )
+    }
+}
+
+/// Add `sensor` under its slug, unless another sensor already has it. The first sensor is
+/// kept, so the sensor a slug refers to doesn't depend on what comes after it.
+pub fn insert_sensor<'a>(
+    sensors: &mut HashMap<String, SensorTypes<'a>>,
+    sensor: SensorTypes<'a>,
+) -> Result<(), SlugCollision> {
+    match sensors.entry(slug_name(sensor.name())) {
+        Entry::Occupied(existing) => Err(SlugCollision {
+            slug: existing.key().clone(),
+            first: existing.get().name().to_owned(),
+            second: sensor.name().to_owned(),
+        }),
+        Entry::Vacant(entry) => {
+            entry.insert(sensor);
+            Ok(())
+        }
+    }
+}
+
+fn sensor_map(
+    basic: &[BasicSensor<'static>],
+    binary: &[BinarySensor<'static>],
+    temperature: &[TemperatureSensor<'static>],
+    compound: &[CompoundSensor<'static>],
+    energy_shares: &[EnergyShareSensor<'static>],
+    others: &[SensorTypes<'static>],
+) -> HashMap<String, SensorTypes<'static>> {
+    let sensors = basic
+        .iter()
+        .cloned()
+        .map(SensorTypes::Basic)
+        .chain(binary.iter().cloned().map(SensorTypes::Binary))
+        .chain(temperature.iter().cloned().map(SensorTypes::Temperature))
+        .chain(compound.iter().cloned().map(SensorTypes::Compound))
+        .chain(energy_shares.iter().cloned().map(SensorTypes::EnergyShare))
+        .chain(others.iter().cloned());
+
+    let mut all_sensors = HashMap::new();
+    for sensor in sensors {
+        if let Err(collision) = insert_sensor(&mut all_sensors, sensor) {
+            eprintln!("warning: {}", collision);
+        }
+    }
+    all_sensors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{modbus_context, ClientMock, Context};
+    use tokio::sync::Mutex;
+    use tokio::time::Duration;
+    use tokio_modbus::prelude::Response::ReadHoldingRegisters;
+
+    #[tokio::test]
+    async fn read_data_from_modbus_over_serial() {
+        let mock_out = vec![240];
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(ReadHoldingRegisters(mock_out)));
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let sensor = BasicSensor(Sensor::new("Battery Voltage", &[183], 1, false));
+
+        let value = sensor.read(ctx).await.unwrap();
+
+        assert_eq!("240", value);
+    }
+
+    /// Check that the Temperature read method works as expected.
+    #[tokio::test]
+    async fn temp_sensor_read() {
+        let mock_out = vec![1110];
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(ReadHoldingRegisters(mock_out)));
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let sensor = TemperatureSensor(
+            Sensor::new("Battery Temperature", &[182], 10, false).with_offset(100),
+        );
+
+        let value = sensor.read(ctx).await.unwrap();
+
+        assert_eq!("11", value);
+    }
+
+    #[tokio::test]
+    async fn temperature_sensors_have_their_own_scaling() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(1040, 1110);
+        client.set_register(1041, 65);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let radiator = TemperatureSensor::new("Scaled Radiator Temperature", &[1040], 10, 100);
+        let environment = TemperatureSensor::new("Scaled Environment Temperature", &[1041], 1, 40);
+
+        assert_eq!(
+            radiator.read_value(ctx.clone()).await.unwrap(),
+            SensorValue::Int(11)
         );
+        assert_eq!(
+            environment.read_value(ctx).await.unwrap(),
+            SensorValue::Int(25)
+        );
+        assert_eq!((radiator.metric.get(), environment.metric.get()), (11, 25));
     }
-    for sensor in TEMP_SENSORS.clone().into_iter() {
-        all_sensors.insert(
-            slug_name(sensor.0.name).to_owned(),
-            SensorTypes::Temperature(sensor.clone()),
+
+    #[tokio::test]
+    async fn signed_temperatures_go_below_the_offset() {
+        let mut client = Box::<ClientMock>::default();
+        // -12.0 degrees as a signed register, in tenths.
+        client.set_register(1188, (-120i16) as u16);
+        // -5 as a signed register, which is -45 degrees after the offset of 40.
+        client.set_register(1189, (-5i16) as u16);
+        let ctx = modbus_context(client);
+
+        let tenths = TemperatureSensor::new("Signed Tenths Temperature", &[1188], 10, 0).signed();
+        let offset = TemperatureSensor::new("Signed Offset Temperature", &[1189], 1, 40).signed();
+        assert_eq!(
+            tenths.read_value(ctx.clone()).await.unwrap(),
+            SensorValue::Int(-12)
+        );
+        assert_eq!(
+            offset.read_value(ctx.clone()).await.unwrap(),
+            SensorValue::Int(-45)
+        );
+        assert_eq!((tenths.metric.get(), offset.metric.get()), (-12, -45));
+
+        // Unsigned, the same register reads as a scorching temperature.
+        let unsigned = TemperatureSensor::new("Unsigned Tenths Temperature", &[1188], 10, 0);
+        assert_eq!(
+            unsigned.read_value(ctx).await.unwrap(),
+            SensorValue::Int(6541)
+        );
+    }
+
+    #[tokio::test]
+    async fn transform_is_applied_after_decoding() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(820, 120);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let sensor = BasicSensor(
+            Sensor::new("Transformed Sensor", &[820], 10, false).with_transform(|v| v * v),
         );
+
+        assert_eq!(sensor.read_value(ctx).await.unwrap(), SensorValue::Int(144));
+        assert_eq!(sensor.metric.get(), 144);
     }
-    for sensor in COMPOUND_SENSORS.clone().into_iter() {
-        all_sensors.insert(
-            slug_name(sensor.name).to_owned(),
-            SensorTypes::Compound(sensor.clone()),
+
+    #[tokio::test]
+    async fn raw_values_are_exported_alongside_scaled_ones() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(900, (-125i16) as u16);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+        let registry = Registry::new();
+
+        let sensor = BasicSensor(
+            Sensor::new_in(&registry, "Raw Current", &[900], 10, true).emit_raw_in(&registry),
         );
-    }
+        assert_eq!(sensor.read_value(ctx).await.unwrap(), SensorValue::Int(-12));
 
-    all_sensors.insert(
-        slug_name(FAULTS.name).to_owned(),
-        SensorTypes::Fault(FAULTS.clone()),
-    );
-    all_sensors
-}
+        let gauges: HashMap<String, f64> = registry
+            .gather()
+            .iter()
+            .map(|family| {
+                let value = family.get_metric()[0].get_gauge().get_value();
+                (family.get_name().to_owned(), value)
+            })
+            .collect();
+        assert_eq!(gauges["raw_current"], -12.0);
+        assert_eq!(gauges["raw_current_raw"], 65411.0);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tokio::sync::Mutex;
-    use tokio_modbus::prelude::Response::ReadHoldingRegisters;
+    #[tokio::test]
+    async fn readings_near_zero_are_clamped() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(ReadHoldingRegisters(vec![(-10i16) as u16])));
+        client.set_next_response(Ok(ReadHoldingRegisters(vec![(-2i16) as u16])));
+        let ctx = Arc::new(Mutex::new(Context { client }));
 
-    use std::{
-        fmt::Debug,
-        io::{Error, ErrorKind},
-    };
+        let sensor = BasicSensor(Sensor::new("Noisy Power", &[870], 1, true).with_zero_epsilon(5));
 
-    #[derive(Debug)]
-    struct Context {
-        client: Box<dyn Client>,
+        assert_eq!(
+            sensor.read_value(ctx.clone()).await.unwrap(),
+            SensorValue::Int(0)
+        );
+        assert_eq!(sensor.read_value(ctx).await.unwrap(), SensorValue::Int(-10));
     }
 
-    #[async_trait]
-    impl Client for Context {
-        async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
-            self.client.call(request).await
-        }
+    #[test]
+    fn clamping_leaves_values_alone_without_an_epsilon() {
+        assert_eq!(clamp_near_zero(i64::MIN, 0), i64::MIN);
+        assert_eq!(clamp_near_zero(i64::MIN, 5), i64::MIN);
+        assert_eq!(clamp_near_zero(-1, -1), -1);
+        assert_eq!(clamp_near_zero(-5, 5), 0);
     }
 
-    #[derive(Default, Debug)]
-    pub(crate) struct ClientMock {
-        slave: Option<Slave>,
-        last_request: Mutex<Option<Request<'static>>>,
-        responses: Vec<Result<Response, Error>>,
-        requests: Vec<Result<Request<'static>, Error>>,
-    }
+    #[tokio::test]
+    async fn rational_scale_gives_exact_fractions() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(ReadHoldingRegisters(vec![(-1234i16) as u16])));
+        client.set_next_response(Ok(ReadHoldingRegisters(vec![1234])));
+        let ctx = Arc::new(Mutex::new(Context { client }));
 
-    impl ClientMock {
-        pub(crate) fn set_next_response(&mut self, next_response: Result<Response, Error>) {
-            self.responses.push(next_response)
-        }
+        let sensor = BasicSensor(
+            Sensor::new("Rational Current", &[930], 10, true).with_rational_scale(3, 100),
+        );
 
-        pub(crate) fn set_next_request(&mut self, next_request: Result<Request<'static>, Error>) {
-            self.requests.push(next_request)
-        }
+        assert_eq!(
+            sensor.read_value(ctx.clone()).await.unwrap(),
+            SensorValue::Float(37.02)
+        );
+        // The metric can only hold whole numbers.
+        assert_eq!(sensor.0.metric.get(), 37);
+        assert_eq!(
+            sensor.read_value(ctx).await.unwrap(),
+            SensorValue::Float(-37.02)
+        );
+        assert_eq!(sensor.0.metric.get(), -37);
     }
 
-    #[async_trait]
-    impl Client for ClientMock {
-        async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
-            match request {
-                Request::ReadHoldingRegisters(_, _) => {
-                    *self.last_request.lock().await = Some(request.into_owned());
-                    self.responses.pop().unwrap()
-                }
-                Request::WriteSingleRegister(addr, val) => {
-                    if let Ok(Request::WriteSingleRegister(exp_addr, exp_val)) =
-                        self.requests.pop().unwrap()
-                    {
-                        if exp_addr == addr && exp_val == val {
-                            return Ok(Response::WriteSingleRegister(addr, val));
-                        }
-                    };
-                    Err(Error::new(ErrorKind::InvalidData, "invalid response"))
-                }
-                _ => todo!(),
-            }
-        }
-    }
+    /// Check that both bytes of a packed register can be decoded as separate sensors.
+    #[tokio::test]
+    async fn byte_slice_sensor_read() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(ReadHoldingRegisters(vec![0x1E3C])));
+        client.set_next_response(Ok(ReadHoldingRegisters(vec![0x1E3C])));
+        let ctx = Arc::new(Mutex::new(Context { client }));
 
-    impl SlaveContext for ClientMock {
-        fn set_slave(&mut self, slave: Slave) {
-            self.slave = Some(slave);
-        }
-    }
+        let high = ByteSliceSensor(
+            Sensor::new("Packed High Byte", &[90], 1, false),
+            HighOrLow::High,
+        );
+        let low = ByteSliceSensor(
+            Sensor::new("Packed Low Byte", &[90], 1, false),
+            HighOrLow::Low,
+        );
 
-    impl SlaveContext for Context {
-        fn set_slave(&mut self, slave: Slave) {
-            self.client.set_slave(slave);
-        }
+        assert_eq!("30", high.read(ctx.clone()).await.unwrap());
+        assert_eq!("60", low.read(ctx).await.unwrap());
     }
 
-    #[async_trait]
-    impl Reader for Context {
-        async fn read_holding_registers<'a>(
-            &'a mut self,
-            addr: u16,
-            cnt: u16,
-        ) -> Result<Vec<u16>, Error> {
-            let rsp = self
-                .client
-                .call(Request::ReadHoldingRegisters(addr, cnt))
-                .await?;
-            if let Response::ReadHoldingRegisters(rsp) = rsp {
-                if rsp.len() as u16 != cnt {
-                    return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
-                }
-                Ok(rsp)
-            } else {
-                Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
-            }
-        }
+    /// Check that signed values narrower than a register are sign extended from their width.
+    #[tokio::test]
+    async fn narrow_signed_values() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(997, 0xF7FF);
+        client.set_register(998, 0x0800);
+        let ctx = modbus_context(client);
 
-        async fn read_discrete_inputs(&mut self, _: u16, _: u16) -> Result<Vec<bool>, Error> {
-            todo!()
-        }
+        let high = ByteSliceSensor(
+            Sensor::new("Signed High Byte", &[997], 1, true),
+            HighOrLow::High,
+        );
+        assert_eq!(high.read(ctx.clone()).await.unwrap(), "-9");
+        let low = ByteSliceSensor(
+            Sensor::new("Signed Low Byte", &[997], 1, true),
+            HighOrLow::Low,
+        );
+        assert_eq!(low.read(ctx.clone()).await.unwrap(), "-1");
 
-        async fn read_coils(&mut self, _: u16, _: u16) -> Result<Vec<bool>, Error> {
-            todo!()
-        }
+        let packed = Sensor::new("Signed 12 Bit", &[998], 1, true).with_sign_bits(12);
+        assert_eq!(packed.read(ctx).await.unwrap(), -2048);
+    }
 
-        async fn read_input_registers(&mut self, _: u16, _: u16) -> Result<Vec<u16>, Error> {
-            todo!()
-        }
+    /// Check that the Serial Number read method works as expected.
+    #[tokio::test]
+    async fn serial_sensor_read() {
+        let mock_out = vec![513, 513, 513, 513, 513];
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(ReadHoldingRegisters(mock_out)));
+        let ctx = Arc::new(Mutex::new(Context { client }));
 
-        async fn read_write_multiple_registers(
-            &mut self,
-            _: u16,
-            _: u16,
-            _: u16,
-            _: &[u16],
-        ) -> Result<Vec<u16>, Error> {
-            todo!()
-        }
-    }
+        let serial = SerialSensor::new("Serial Number", &[3, 4, 5, 6, 7], HighOrLow::High);
 
-    #[async_trait]
-    impl Writer for Context {
-        async fn write_single_register<'a>(&'a mut self, addr: u16, val: u16) -> Result<(), Error> {
-            self.client
-                .call(Request::WriteSingleRegister(addr, val))
-                .await?;
-            Ok(())
-        }
+        let value = serial.read(ctx).await.unwrap();
 
-        async fn write_single_coil(&mut self, _: u16, _: bool) -> Result<(), Error> {
-            todo!()
-        }
+        assert_eq!("2121212121", value);
+    }
 
-        async fn write_multiple_coils(&mut self, _: u16, _: &[bool]) -> Result<(), Error> {
-            todo!()
-        }
+    /// Check that the byte order and register count are the sensor's to choose.
+    #[tokio::test]
+    async fn serial_sensor_byte_orders() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(994, 0x0102);
+        client.set_register(995, 0x0304);
+        client.set_register(996, 0x0506);
+        let ctx = modbus_context(client);
+
+        let registers = [994, 995, 996];
+        let high_first = SerialSensor::new("Serial High First", &registers, HighOrLow::High);
+        let low_first = SerialSensor::new("Serial Low First", &registers, HighOrLow::Low);
+        assert_eq!(high_first.read(ctx.clone()).await.unwrap(), "123456");
+        assert_eq!(low_first.read(ctx).await.unwrap(), "214365");
+    }
 
-        async fn write_multiple_registers(&mut self, _: u16, _: &[u16]) -> Result<(), Error> {
-            todo!()
-        }
+    #[tokio::test]
+    async fn version_sensor_read() {
+        assert_eq!(VersionSensor::decode(0x0000), "0.0");
+        assert_eq!(VersionSensor::decode(0x0A01), "10.1");
 
-        async fn masked_write_register(&mut self, _: u16, _: u16, _: u16) -> Result<(), Error> {
-            todo!()
-        }
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(1185, 0x0312);
+        let firmware = VersionSensor::new("Test Firmware", 1185);
+        assert_eq!(firmware.read(modbus_context(client)).await.unwrap(), "3.18");
     }
 
+    /// Check that a text block decodes to its trimmed text, whichever padding follows it.
     #[tokio::test]
-    async fn read_data_from_modbus_over_serial() {
-        let mock_out = vec![240];
+    async fn text_sensor_read() {
+        // "Grid Mode" with a space, then 0xFF padding.
+        let block = [0x4772, 0x6964, 0x204D, 0x6F64, 0x6520, 0xFFFF];
         let mut client = Box::<ClientMock>::default();
-        client.set_next_response(Ok(ReadHoldingRegisters(mock_out)));
+        for (reg, value) in (980..).zip(block) {
+            client.set_register(reg, value);
+        }
         let ctx = Arc::new(Mutex::new(Context { client }));
 
-        let sensor = BasicSensor(Sensor::new("Battery Voltage", &[183], 1, false));
-
-        let value = sensor.read(ctx).await.unwrap();
+        let status = TextSensor {
+            name: "Running Status",
+            registers: &[980, 981, 982, 983, 984, 985],
+        };
+        let value = status.read_value(ctx).await.unwrap();
+        assert_eq!(value, SensorValue::Text("Grid Mode".to_string()));
 
-        assert_eq!("240", value);
+        // Anything after a NUL is stale, and bytes that aren't printable are dropped.
+        let block = [0x0A4F, 0x6666, 0x0047, 0x7269];
+        assert_eq!(TextSensor::decode(&block), "Off");
     }
 
-    /// Check that the Temperature read method works as expected.
+    /// Check that each kind of sensor decodes to the expected type of value.
     #[tokio::test]
-    async fn temp_sensor_read() {
-        let mock_out = vec![1110];
+    async fn read_value_types() {
         let mut client = Box::<ClientMock>::default();
-        client.set_next_response(Ok(ReadHoldingRegisters(mock_out)));
+        for reg in 3..8 {
+            client.set_register(reg, 513);
+        }
+        client.set_register(182, 1110);
+        client.set_register(183, 5432);
+        client.set_register(184, 0x1E3C);
+        client.set_register(103, 0x1);
+        client.set_register(104, 0x0);
+        client.set_register(105, 0x0);
+        client.set_register(106, 0x0);
         let ctx = Arc::new(Mutex::new(Context { client }));
 
-        let sensor = TemperatureSensor(Sensor::new("Battery Temperature", &[182], 10, false));
+        let sensors = [
+            SensorTypes::Basic(BasicSensor(Sensor::new("Value Basic", &[183], 100, false))),
+            SensorTypes::Binary(BinarySensor(Sensor::new("Value Binary", &[183], 1, false))),
+            SensorTypes::ByteSlice(ByteSliceSensor(
+                Sensor::new("Value Byte Slice", &[184], 1, false),
+                HighOrLow::Low,
+            )),
+            SensorTypes::Temperature(TemperatureSensor(
+                Sensor::new("Value Temperature", &[182], 10, false).with_offset(100),
+            )),
+            SensorTypes::Compound(CompoundSensor::new(
+                "Value Compound",
+                &[182, 183],
+                &[1, 1],
+                false,
+                false,
+            )),
+        ];
+        let expected = [
+            SensorValue::Int(54),
+            SensorValue::Int(5432),
+            SensorValue::Int(60),
+            SensorValue::Int(11),
+            SensorValue::Int(6542),
+        ];
+        for (sensor, expected) in sensors.iter().zip(expected) {
+            assert_eq!(sensor.read_value(ctx.clone()).await.unwrap(), expected);
+        }
 
-        let value = sensor.read(ctx).await.unwrap();
+        let faults = SensorTypes::Fault(FaultSensor::new("Value Faults", [103, 104, 105, 106]));
+        assert_eq!(
+            faults.read_value(ctx.clone()).await.unwrap(),
+            SensorValue::Text("F1".to_string())
+        );
 
-        assert_eq!("11", value);
+        let serial = SensorTypes::Serial(SerialSensor::new(
+            "Value Serial",
+            &[3, 4, 5, 6, 7],
+            HighOrLow::High,
+        ));
+        assert_eq!(
+            serial.read_value(ctx).await.unwrap(),
+            SensorValue::Text("2121212121".to_string())
+        );
     }
 
-    /// Check that the Serial Number read method works as expected.
-    #[tokio::test]
-    async fn serial_sensor_read() {
-        let mock_out = vec![513, 513, 513, 513, 513];
+    /// A constant 3600W over 30 seconds should accumulate 30Wh.
+    #[tokio::test(start_paused = true)]
+    async fn integrated_energy_sensor_read() {
         let mut client = Box::<ClientMock>::default();
-        client.set_next_response(Ok(ReadHoldingRegisters(mock_out)));
+        client.set_register(186, 3600);
         let ctx = Arc::new(Mutex::new(Context { client }));
 
-        let serial = SerialSensor {
-            name: "Serial Number",
-            registers: [3, 4, 5, 6, 7],
-        };
+        let sensor = IntegratedEnergySensor::new("Integrated PV Energy", &[186], 1, true);
 
-        let value = serial.read(ctx).await.unwrap();
+        let mut value = sensor.read_value(ctx.clone()).await.unwrap();
+        assert_eq!(value, SensorValue::Int(0));
+        for _ in 0..3 {
+            tokio::time::advance(Duration::from_secs(10)).await;
+            value = sensor.read_value(ctx.clone()).await.unwrap();
+        }
 
-        assert_eq!("2121212121", value);
+        assert_eq!(value, SensorValue::Int(30));
+        assert_eq!(sensor.energy_wh(), 30.0);
     }
 
     #[tokio::test]
@@ -736,6 +3008,118 @@ mod tests {
         assert_eq!("F1, F8, F32", value);
     }
 
+    /// Check that faults that have cleared are set back to 0, and the oldest dropped when
+    /// there are too many codes.
+    #[tokio::test]
+    async fn cleared_faults_are_zeroed() {
+        let registry = Registry::new();
+        let fault_sensor =
+            FaultSensor::new_in(&registry, "Clearing Faults", [1004, 1005, 1006, 1007])
+                .with_max_codes(2);
+        let codes = || -> Vec<(String, f64)> {
+            registry.gather()[0]
+                .get_metric()
+                .iter()
+                .map(|m| {
+                    (
+                        m.get_label()[0].get_value().to_string(),
+                        m.get_gauge().get_value(),
+                    )
+                })
+                .collect()
+        };
+        let read = |bits: u16| {
+            let mut client = Box::<ClientMock>::default();
+            client.set_next_response(Ok(ReadHoldingRegisters(vec![bits, 0, 0, 0])));
+            fault_sensor.read_value(modbus_context(client))
+        };
+
+        read(0b1).await.unwrap();
+        assert_eq!(codes(), [("1".to_string(), 1.0)]);
+        read(0b10).await.unwrap();
+        assert_eq!(codes(), [("1".to_string(), 0.0), ("2".to_string(), 1.0)]);
+        // F1 has been inactive longest, so makes way for F3.
+        read(0b100).await.unwrap();
+        assert_eq!(codes(), [("2".to_string(), 0.0), ("3".to_string(), 1.0)]);
+        // Active faults are never dropped, even over the limit.
+        read(0b111).await.unwrap();
+        assert_eq!(codes().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn status_flags_sensor_read() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(Response::ReadDiscreteInputs(vec![true, false, true])));
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let sensor = StatusFlagsSensor::new(
+            "Test Status Flags",
+            10,
+            &["grid_connected", "generator_running", "battery_charging"],
+        );
+        let value = sensor.read_value(ctx).await.unwrap();
+
+        assert_eq!(
+            value,
+            SensorValue::Text("grid_connected, battery_charging".to_string())
+        );
+        let flag = |name| sensor.metric.with_label_values(&[name]).get();
+        assert_eq!(flag("grid_connected"), 1);
+        assert_eq!(flag("generator_running"), 0);
+        assert_eq!(flag("battery_charging"), 1);
+    }
+
+    #[tokio::test]
+    async fn bitfield_sensor_read() {
+        let mut client = Box::<ClientMock>::default();
+        // The relays' bits, among others that aren't named.
+        client.set_register(1150, 0b1000_0000_0010_0101);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let sensor = BitfieldSensor::new(
+            "Test Relay Status",
+            1150,
+            &[
+                (2, "grid_relay"),
+                (3, "inverter_relay"),
+                (5, "generator_relay"),
+            ],
+        );
+        let value = sensor.read_value(ctx).await.unwrap();
+
+        assert_eq!(
+            value,
+            SensorValue::Text("grid_relay, generator_relay".to_string())
+        );
+        let flag = |name| sensor.metric.with_label_values(&[name]).get();
+        assert_eq!(flag("grid_relay"), 1);
+        assert_eq!(flag("inverter_relay"), 0);
+        assert_eq!(flag("generator_relay"), 1);
+    }
+
+    #[tokio::test]
+    async fn phase_sensor_sets_a_sample_per_phase() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(ReadHoldingRegisters(vec![2301, 2315, 2288])));
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let sensor = PhaseSensor::new("Test Phase Voltage", &[840, 841, 842], 10, false);
+        let value = sensor.read_value(ctx).await.unwrap();
+
+        assert_eq!(
+            value,
+            SensorValue::Text("L1=230, L2=231, L3=228".to_string())
+        );
+        let phase = |name| sensor.metric.with_label_values(&[name]).get();
+        assert_eq!(phase("L1"), 230);
+        assert_eq!(phase("L2"), 231);
+        assert_eq!(phase("L3"), 228);
+
+        // A single-phase model only has L1.
+        let single = PhaseSensor::new("Test Single Phase Voltage", &[843], 10, false);
+        assert_eq!(single.decode(&[2301]), vec![("L1", 230)]);
+    }
+
     #[tokio::test]
     async fn compound_sensor_read() {
         let mock_out: Vec<u16> = vec![1000, 800];
@@ -754,6 +3138,62 @@ mod tests {
         assert_eq!("200", value);
     }
 
+    #[tokio::test]
+    async fn sensors_are_read_by_slug() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(1097, 230);
+        let ctx: Arc<Mutex<dyn Reader>> = Arc::new(Mutex::new(Context { client }));
+        let mut sensors = HashMap::new();
+        let sensor = Sensor::new("Library Voltage", &[1097], 1, false);
+        insert_sensor(&mut sensors, SensorTypes::Basic(BasicSensor(sensor))).unwrap();
+
+        assert_eq!(
+            read_sensor(ctx.clone(), &sensors, "library_voltage")
+                .await
+                .unwrap(),
+            SensorValue::Int(230)
+        );
+        let e = read_sensor(ctx, &sensors, "library_current")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<SensorNotFound>(),
+            Some(&SensorNotFound("library_current".to_string()))
+        );
+    }
+
+    #[test]
+    fn slug_collisions_are_reported() {
+        let mut sensors = HashMap::new();
+        let first = Sensor::new_in(&Registry::new(), "Grid Power", &[1095], 1, false);
+        let second = Sensor::new_in(&Registry::new(), "grid power", &[1096], 1, false);
+
+        insert_sensor(&mut sensors, SensorTypes::Basic(BasicSensor(first))).unwrap();
+        assert_eq!(
+            insert_sensor(&mut sensors, SensorTypes::Basic(BasicSensor(second))),
+            Err(SlugCollision {
+                slug: "grid_power".to_string(),
+                first: "Grid Power".to_string(),
+                second: "grid power".to_string(),
+            })
+        );
+        assert_eq!(sensors["grid_power"].registers(), [1095]);
+    }
+
+    #[test]
+    fn compound_sensor_saturates_on_overflow() {
+        let sensor = CompoundSensor::new("Overflowing Sum", &[890, 891], &[1, 1], false, true);
+        let overflows = || sensor.out_of_range.get();
+
+        assert_eq!(sensor.accumulate(&[i64::MAX, 1]), i64::MAX);
+        assert_eq!(overflows(), 1);
+        // The total saturates at i64::MIN, which has no positive to take the absolute of.
+        assert_eq!(sensor.accumulate(&[i64::MIN, -1]), i64::MAX);
+        assert_eq!(overflows(), 2);
+        assert_eq!(sensor.accumulate(&[i64::MAX, -1]), i64::MAX - 1);
+        assert_eq!(overflows(), 2);
+    }
+
     #[tokio::test]
     async fn compound_sensor_read2() {
         let mock_out: Vec<u16> = vec![200, 800];
@@ -834,6 +3274,65 @@ mod tests {
         sensor.write(ctx, mock_val).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn write_multiple_registers_function() {
+        let mock_val = AtomicU16::new(45);
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_request(Ok(tokio_modbus::Request::WriteMultipleRegisters(
+            221,
+            vec![45].into(),
+        )));
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let sensor = Sensor::new_mut("Battery Low Voltage", &[221], 100, false)
+            .with_write_fn(WriteFunction::MultipleRegisters);
+
+        sensor.write(ctx, mock_val).await.unwrap();
+    }
+
+    /// Check that a sensor's reads aren't split up by another's on a shared context, which
+    /// on an RTU bus would leave each taking the other's responses.
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_reads_do_not_interleave() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_read_delay(Duration::from_millis(10));
+        // Answered in the order the requests go out, as on the bus.
+        for response in [[1, 2], [3, 4]].concat().into_iter().rev() {
+            client.set_next_response(Ok(ReadHoldingRegisters(vec![response])));
+        }
+        let ctx: Arc<Mutex<dyn Reader>> = Arc::new(Mutex::new(Context { client }));
+
+        let first = BasicSensor(Sensor::new("Interleaved First", &[1050, 1052], 1, false));
+        let second = BasicSensor(Sensor::new("Interleaved Second", &[1060, 1062], 1, false));
+        let (first, second) = tokio::join!(
+            first.read_value(ctx.clone()),
+            second.read_value(ctx.clone())
+        );
+
+        assert_eq!(first.unwrap(), SensorValue::Int(1 + (2 << 16)));
+        assert_eq!(second.unwrap(), SensorValue::Int(3 + (4 << 16)));
+    }
+
+    /// Check that each sensor is read with its own function code, whatever the others use.
+    #[tokio::test]
+    async fn read_function_codes() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(1011, 120);
+        client.set_input_register(1011, 340);
+        let ctx = modbus_context(client);
+
+        let holding = Sensor::new("Holding Read", &[1011], 1, false);
+        let input =
+            Sensor::new("Input Read", &[1011], 1, false).with_read_fn(ReadFunction::InputRegisters);
+        assert_eq!(holding.read(ctx.clone()).await.unwrap(), 120);
+        assert_eq!(input.read(ctx).await.unwrap(), 340);
+
+        // Input registers can't be served from a snapshot of holding registers.
+        assert!(SensorTypes::Basic(BasicSensor(input))
+            .registers()
+            .is_empty());
+    }
+
     #[tokio::test]
     async fn write_data_to_modbus_over_serial_err() {
         let mock_val = AtomicU16::new(45);
@@ -844,4 +3343,218 @@ mod tests {
 
         assert!(sensor.write(ctx, mock_val).await.is_err());
     }
+
+    /// Check that the last-write gauge is only set once the inverter acknowledges a write.
+    #[tokio::test]
+    async fn writes_set_the_last_write_gauge() {
+        let registry = Registry::new();
+        let sensor = Sensor::new_mut_in(&registry, "Last Write Limit", &[990], 1, false);
+        let last_write = || {
+            registry
+                .gather()
+                .iter()
+                .find(|family| family.get_name() == "last_write_limit_last_write_timestamp_seconds")
+                .map(|family| family.get_metric()[0].get_gauge().get_value())
+                .unwrap()
+        };
+        assert_eq!(last_write(), 0.0);
+
+        // The inverter doesn't acknowledge the write.
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_request(Ok(tokio_modbus::Request::WriteSingleRegister(990, 8)));
+        let ctx = modbus_context(client);
+        assert!(sensor.write(ctx, AtomicU16::new(7)).await.is_err());
+        assert_eq!(last_write(), 0.0);
+
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_request(Ok(tokio_modbus::Request::WriteSingleRegister(990, 7)));
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        sensor
+            .write(modbus_context(client), AtomicU16::new(7))
+            .await
+            .unwrap();
+        assert!(last_write() >= before.as_secs() as f64);
+    }
+
+    /// Check that the delta is the step between cycles, and that a counter reset gives 0.
+    #[tokio::test]
+    async fn delta_sensor_read() {
+        let mut client = Box::<ClientMock>::default();
+        // Responses are served last-in first-out.
+        for val in [5, 125, 110, 100] {
+            client.set_next_response(Ok(ReadHoldingRegisters(vec![val])));
+        }
+        let ctx = Arc::new(Mutex::new(Context { client }));
+        let sensor = DeltaSensor::new("Delta Energy", &[690], 1);
+
+        let mut deltas = Vec::new();
+        for _ in 0..4 {
+            deltas.push(sensor.advance(ctx.clone()).await.unwrap().0);
+            // Reads in between give the last cycle's delta, without moving it on.
+            assert_eq!(
+                sensor.read_value(ctx.clone()).await.unwrap(),
+                *deltas.last().unwrap()
+            );
+        }
+
+        assert_eq!(
+            deltas,
+            vec![
+                SensorValue::Int(0),
+                SensorValue::Int(10),
+                SensorValue::Int(15),
+                SensorValue::Int(0),
+            ]
+        );
+    }
+
+    /// Check that the direction register decides the sign of the magnitude.
+    #[tokio::test]
+    async fn directional_sensor_read() {
+        let mut client = Box::<ClientMock>::default();
+        // Responses are served last-in first-out: magnitude then direction, twice.
+        for val in [1, 4520, 0, 4520] {
+            client.set_next_response(Ok(ReadHoldingRegisters(vec![val])));
+        }
+        let ctx = Arc::new(Mutex::new(Context { client }));
+        let sensor = DirectionalSensor::new("Directional Power", &[1070, 1071], 10);
+
+        assert_eq!(
+            sensor.read_value(ctx.clone()).await.unwrap(),
+            SensorValue::Int(452)
+        );
+        assert_eq!(
+            sensor.read_value(ctx).await.unwrap(),
+            SensorValue::Int(-452)
+        );
+        assert_eq!(sensor.metric.get(), -452);
+    }
+
+    /// Check that a ratio is a percentage, and unavailable rather than infinite when the
+    /// denominator is zero.
+    #[tokio::test]
+    async fn ratio_sensor_read() {
+        let sensor = RatioSensor::new("PV Utilisation", 1101, 1100);
+        let context = |numerator, denominator| {
+            let mut client = Box::<ClientMock>::default();
+            client.set_register(1101, numerator);
+            client.set_register(1100, denominator);
+            Arc::new(Mutex::new(Context { client }))
+        };
+
+        assert_eq!(
+            sensor.read_value(context(2500, 8000)).await.unwrap(),
+            SensorValue::Float(31.25)
+        );
+        assert_eq!(
+            sensor.read_value(context(2500, 0)).await.unwrap(),
+            SensorValue::Unavailable
+        );
+        assert_eq!(sensor.metric.get(), 31.25);
+    }
+
+    /// Check that unpopulated registers are reported as unavailable, and don't set the metric.
+    #[tokio::test]
+    async fn ffff_unavailable_sensor_read() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(720, 0xFFFF);
+        client.set_register(721, 0xFFFF);
+        client.set_register(722, 0xFFFF);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let single = BasicSensor(Sensor::new("Unset Single", &[720], 1, true).ffff_unavailable());
+        let pair = BasicSensor(Sensor::new("Unset Pair", &[721, 722], 1, false).ffff_unavailable());
+        single.metric.set(7);
+        pair.metric.set(7);
+
+        assert_eq!(
+            single.read_value(ctx.clone()).await.unwrap(),
+            SensorValue::Unavailable
+        );
+        assert_eq!(
+            pair.read_value(ctx.clone()).await.unwrap(),
+            SensorValue::Unavailable
+        );
+        assert_eq!(single.metric.get(), 7);
+        assert_eq!(pair.metric.get(), 7);
+        assert_eq!(
+            serde_json::to_value(SensorValue::Unavailable).unwrap(),
+            serde_json::Value::Null
+        );
+
+        // Without the flag, 0xFFFF is read as usual.
+        let flagless = BasicSensor(Sensor::new("Unset Flagless", &[720], 1, true));
+        assert_eq!(
+            flagless.read_value(ctx).await.unwrap(),
+            SensorValue::Int(-1)
+        );
+    }
+
+    #[test]
+    fn sensor_sets_in_separate_registries_dont_collide() {
+        let first = Registry::new();
+        let second = Registry::new();
+
+        let first_sensors = register_sensors_in(&first);
+        let second_sensors = register_sensors_in(&second);
+
+        assert_eq!(first_sensors.len(), second_sensors.len());
+        assert!(!first.gather().is_empty());
+        assert_eq!(first.gather().len(), second.gather().len());
+    }
+
+    #[tokio::test]
+    async fn relay_status_names_the_closed_relays() {
+        let mut client = Box::<ClientMock>::default();
+        // The inverter and grid relays, plus an unrelated flag above them.
+        client.set_register(552, 0b1000_0101);
+        let sensor = relay_status(&Registry::new());
+
+        let value = sensor.read_value(modbus_context(client)).await.unwrap();
+        assert_eq!(value, SensorValue::Text("inverter, grid".to_string()));
+        assert_eq!(sensor.metric.with_label_values(&["grid"]).get(), 1);
+        assert_eq!(sensor.metric.with_label_values(&["generator"]).get(), 0);
+    }
+
+    #[tokio::test]
+    async fn builtin_energy_shares_read_the_day_counters() {
+        let sensors = register_sensors_in(&Registry::new());
+        let mut client = Box::<ClientMock>::default();
+        // PV, export, load and import, in tenths of a kWh.
+        for (register, value) in [(108, 200), (77, 50), (84, 400), (76, 300)] {
+            client.set_register(register, value);
+        }
+        let ctx = modbus_context(client);
+
+        for (slug, total, exchanged, share) in [
+            (
+                "day_self_consumption",
+                "day_pv_energy",
+                "day_grid_export",
+                75.0,
+            ),
+            (
+                "day_self_sufficiency",
+                "day_load_energy",
+                "day_grid_import",
+                25.0,
+            ),
+        ] {
+            let SensorTypes::EnergyShare(sensor) = &sensors[slug] else {
+                panic!("{} isn't an energy share", slug);
+            };
+            // The components are the builtin counters, read again.
+            assert_eq!(sensor.total.slug, total);
+            assert_eq!(sensor.total.sensor.registers, sensors[total].registers());
+            assert_eq!(sensor.exchanged.slug, exchanged);
+            assert_eq!(
+                sensor.exchanged.sensor.registers,
+                sensors[exchanged].registers()
+            );
+            assert_eq!(
+                sensor.read_value(ctx.clone()).await.unwrap(),
+                SensorValue::Float(share)
+            );
+        }
+    }
 }
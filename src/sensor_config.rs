@@ -0,0 +1,1149 @@
+use crate::bms::BmsSensor;
+use crate::helpers::slug_name;
+use crate::sensor::{
+    unit_conversion, BasicSensor, BinarySensor, BitfieldSensor, CompoundComponent, CompoundSensor,
+    DirectionalSensor, EnergyPeriod, EnergyShareSensor, FaultSensor, PhaseSensor, RatioSensor,
+    Sensor, SensorTypes, SlugCollision, TemperatureSensor, TextSensor,
+};
+use crate::sensor_definitions::{
+    binary_sensors, compound_sensors, energy_share_sensors, faults, relay_status, sensors,
+    temp_sensors,
+};
+use prometheus::Registry;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use tokio::time::Duration;
+
+/// A sensor backed by one value spread over `registers`, as in `Sensor`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterSensorDefinition {
+    pub name: String,
+    pub registers: Vec<u16>,
+    pub factor: i64,
+    /// `[numerator, denominator]` to scale by in place of `factor`, eg. `[3, 100]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<(i64, i64)>,
+    #[serde(default)]
+    pub signed: bool,
+    /// The width of signed values in bits, when it isn't the register's 16.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sign_bits: Option<u32>,
+    #[serde(default)]
+    pub offset: i64,
+    /// Values within this of zero are reported as zero.
+    #[serde(default)]
+    pub zero_epsilon: i64,
+    pub unit: Option<String>,
+    /// The unit the API shows the value in, eg. "kW" for a sensor in W. It's exported in
+    /// that unit too, as `<slug>_<display_unit>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_unit: Option<String>,
+    #[serde(default)]
+    pub ffff_unavailable: bool,
+    #[serde(default)]
+    pub writable: bool,
+    /// Writable, but never read, for registers the inverter refuses reads of.
+    #[serde(default)]
+    pub write_only: bool,
+    #[serde(default)]
+    pub read_once: bool,
+    /// Sensors with a higher priority are read earlier in each collection cycle.
+    #[serde(default)]
+    pub priority: i32,
+    /// Also export the raw register value as `<slug>_raw`.
+    #[serde(default)]
+    pub emit_raw: bool,
+    /// The minimum time between writes, for settings stored in flash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_interval_secs: Option<u64>,
+    /// The only raw values writes may set, eg. `[0, 1, 2]` for a mode register.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_values: Option<Vec<u16>>,
+    /// Marks one of the inverter's energy counters, eg. "day", for `/api/v1/energy/reset`.
+    /// The sensor must be writable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy_counter: Option<EnergyPeriod>,
+}
+
+/// A sensor as written in the `[[sensors]]` tables of a config file, eg.
+/// ```toml
+/// [[sensors]]
+/// kind = "basic"
+/// name = "Battery Voltage"
+/// registers = [183]
+/// factor = 100
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SensorDefinition {
+    Basic(RegisterSensorDefinition),
+    Binary(RegisterSensorDefinition),
+    Temperature(RegisterSensorDefinition),
+    /// Either `registers` to combine, or the slugs of basic, binary or temperature
+    /// `sensors` to combine the decoded values of. A sensor's factors default to 1.
+    Compound {
+        name: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        registers: Vec<u16>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        sensors: Vec<String>,
+        #[serde(default)]
+        factors: Vec<i64>,
+        #[serde(default)]
+        no_negative: bool,
+        #[serde(default)]
+        absolute: bool,
+        #[serde(default)]
+        zero_epsilon: i64,
+    },
+    Fault {
+        name: String,
+        registers: [u16; 4],
+        /// The most fault codes to keep a label for.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_codes: Option<usize>,
+    },
+    /// One register per phase, L1 first.
+    Phase {
+        name: String,
+        registers: Vec<u16>,
+        factor: i64,
+        #[serde(default)]
+        signed: bool,
+    },
+    Bms {
+        name: String,
+        start_register: u16,
+        max_packs: usize,
+    },
+    /// ASCII text, two characters to a register, eg. a running status.
+    Text {
+        name: String,
+        registers: Vec<u16>,
+    },
+    /// The `numerator` register as a percentage of the `denominator` register.
+    Ratio {
+        name: String,
+        numerator: u16,
+        denominator: u16,
+    },
+    /// A magnitude with its sign in a register of its own: the magnitude's registers,
+    /// then the direction register, which is non-zero when the value is negative.
+    Directional {
+        name: String,
+        registers: Vec<u16>,
+        factor: i64,
+    },
+    /// Named bits of a status register, eg. `bits = { 2 = "grid_relay" }`: each bit's
+    /// index, counted from the least significant, and its flag's name. TOML keys are
+    /// strings, so the indexes are parsed when the sensor is built.
+    Bitfield {
+        name: String,
+        register: u16,
+        bits: BTreeMap<String, String>,
+    },
+    /// The percentage of the `total` sensor's energy that isn't in the `exchanged`
+    /// sensor's, eg. the self-consumption from `day_pv_energy` and `day_grid_export`, or
+    /// the self-sufficiency from `day_load_energy` and `day_grid_import`. Both are slugs of
+    /// basic, binary or temperature sensors.
+    EnergyShare {
+        name: String,
+        total: String,
+        exchanged: String,
+    },
+}
+
+/// Sensors live for the rest of the program, so the strings and register lists they
+/// borrow are leaked rather than kept alongside them.
+fn leak_str(s: &str) -> &'static str {
+    Box::leak(s.to_owned().into_boxed_str())
+}
+
+fn leak_slice<T: Clone>(s: &[T]) -> &'static [T] {
+    Box::leak(s.to_vec().into_boxed_slice())
+}
+
+impl RegisterSensorDefinition {
+    fn from_sensor(sensor: &Sensor<'_>) -> RegisterSensorDefinition {
+        RegisterSensorDefinition {
+            name: sensor.name.to_owned(),
+            registers: sensor.registers.to_vec(),
+            factor: sensor.factor,
+            scale: sensor.rational_scale,
+            signed: sensor.is_signed,
+            sign_bits: Some(sensor.sign_bits).filter(|&bits| bits != 16),
+            offset: sensor.offset,
+            zero_epsilon: sensor.zero_epsilon,
+            unit: sensor.unit.clone(),
+            display_unit: sensor.display_unit.as_ref().map(|d| d.unit.clone()),
+            ffff_unavailable: sensor.ffff_unavailable,
+            writable: sensor.is_mut,
+            write_only: sensor.write_only,
+            read_once: sensor.read_once,
+            priority: sensor.priority,
+            emit_raw: sensor.emits_raw(),
+            write_interval_secs: sensor.write_interval().map(|interval| interval.as_secs()),
+            allowed_values: sensor.allowed_values().map(<[u16]>::to_vec),
+            energy_counter: sensor.energy_period(),
+        }
+    }
+
+    fn build(&self, registry: &Registry) -> Result<Sensor<'static>, SensorConfigError> {
+        if self.factor == 0 {
+            return Err(SensorConfigError::ZeroFactor(self.name.clone()));
+        }
+        if self.energy_counter.is_some() && !(self.writable || self.write_only) {
+            return Err(SensorConfigError::ReadOnlyCounter(self.name.clone()));
+        }
+        if matches!(self.scale, Some((_, 0))) {
+            return Err(SensorConfigError::ZeroDenominator(self.name.clone()));
+        }
+        if self.sign_bits.is_some_and(|bits| !(2..64).contains(&bits)) {
+            return Err(SensorConfigError::SignBitsOutOfRange(self.name.clone()));
+        }
+        if let Some(display_unit) = &self.display_unit {
+            let unit = self.unit.as_deref().unwrap_or_default();
+            if unit_conversion(unit, display_unit).is_none() {
+                return Err(SensorConfigError::UnitConversion {
+                    sensor: self.name.clone(),
+                    from: self.unit.clone(),
+                    to: display_unit.clone(),
+                });
+            }
+        }
+        let new = match self.writable || self.write_only {
+            true => Sensor::new_mut_in,
+            false => Sensor::new_in,
+        };
+        let mut sensor = new(
+            registry,
+            leak_str(&self.name),
+            leak_slice(&self.registers),
+            self.factor,
+            self.signed,
+        )
+        .with_offset(self.offset)
+        .with_zero_epsilon(self.zero_epsilon)
+        .with_priority(self.priority);
+        if let Some((numerator, denominator)) = self.scale {
+            sensor = sensor.with_rational_scale(numerator, denominator);
+        }
+        if let Some(bits) = self.sign_bits {
+            sensor = sensor.with_sign_bits(bits);
+        }
+        if let Some(unit) = &self.unit {
+            sensor = sensor.with_unit(unit);
+        }
+        if let Some(unit) = &self.display_unit {
+            sensor = sensor.with_display_unit_in(registry, unit);
+        }
+        if self.ffff_unavailable {
+            sensor = sensor.ffff_unavailable();
+        }
+        if self.write_only {
+            sensor = sensor.write_only();
+        }
+        if self.read_once {
+            sensor = sensor.read_once();
+        }
+        if self.emit_raw {
+            sensor = sensor.emit_raw_in(registry);
+        }
+        if let Some(secs) = self.write_interval_secs {
+            sensor = sensor.with_write_interval(Duration::from_secs(secs));
+        }
+        if let Some(values) = &self.allowed_values {
+            sensor = sensor.with_allowed_values(values);
+        }
+        if let Some(period) = self.energy_counter {
+            sensor = sensor.energy_counter(period);
+        }
+        Ok(sensor)
+    }
+}
+
+impl SensorDefinition {
+    /// The definition of an existing sensor, or `None` for sensor types that can't be
+    /// configured.
+    pub fn from_sensor(sensor: &SensorTypes<'_>) -> Option<SensorDefinition> {
+        Some(match sensor {
+            SensorTypes::Basic(s) => {
+                SensorDefinition::Basic(RegisterSensorDefinition::from_sensor(s))
+            }
+            SensorTypes::Binary(s) => {
+                SensorDefinition::Binary(RegisterSensorDefinition::from_sensor(s))
+            }
+            SensorTypes::Temperature(s) => {
+                SensorDefinition::Temperature(RegisterSensorDefinition::from_sensor(s))
+            }
+            SensorTypes::Compound(s) if !s.components.is_empty() => SensorDefinition::Compound {
+                name: s.name.to_owned(),
+                registers: Vec::new(),
+                sensors: s.components.iter().map(|c| c.slug.clone()).collect(),
+                factors: s.components.iter().map(|c| c.factor).collect(),
+                no_negative: s.no_negative,
+                absolute: s.absolute,
+                zero_epsilon: s.zero_epsilon,
+            },
+            SensorTypes::Compound(s) => SensorDefinition::Compound {
+                name: s.name.to_owned(),
+                registers: s.registers.to_vec(),
+                sensors: Vec::new(),
+                factors: s.factors.to_vec(),
+                no_negative: s.no_negative,
+                absolute: s.absolute,
+                zero_epsilon: s.zero_epsilon,
+            },
+            SensorTypes::Fault(s) => SensorDefinition::Fault {
+                name: s.name.to_owned(),
+                registers: s.registers,
+                max_codes: s.max_codes,
+            },
+            SensorTypes::Phase(s) => SensorDefinition::Phase {
+                name: s.name.to_owned(),
+                registers: s.registers.to_vec(),
+                factor: s.factor,
+                signed: s.is_signed,
+            },
+            SensorTypes::Bms(s) => SensorDefinition::Bms {
+                name: s.name.to_owned(),
+                start_register: s.registers[0],
+                max_packs: s.max_packs(),
+            },
+            SensorTypes::Text(s) => SensorDefinition::Text {
+                name: s.name.to_owned(),
+                registers: s.registers.to_vec(),
+            },
+            SensorTypes::Ratio(s) => SensorDefinition::Ratio {
+                name: s.name.to_owned(),
+                numerator: s.registers[0],
+                denominator: s.registers[1],
+            },
+            SensorTypes::Directional(s) => SensorDefinition::Directional {
+                name: s.name.to_owned(),
+                registers: s.registers.to_vec(),
+                factor: s.factor(),
+            },
+            SensorTypes::Bitfield(s) => SensorDefinition::Bitfield {
+                name: s.name.to_owned(),
+                register: s.register,
+                bits: s
+                    .bits
+                    .iter()
+                    .map(|&(bit, flag)| (bit.to_string(), flag.to_owned()))
+                    .collect(),
+            },
+            SensorTypes::EnergyShare(s) => SensorDefinition::EnergyShare {
+                name: s.name.to_owned(),
+                total: s.total.slug.clone(),
+                exchanged: s.exchanged.slug.clone(),
+            },
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            SensorDefinition::Basic(d)
+            | SensorDefinition::Binary(d)
+            | SensorDefinition::Temperature(d) => &d.name,
+            SensorDefinition::Compound { name, .. }
+            | SensorDefinition::Fault { name, .. }
+            | SensorDefinition::Phase { name, .. }
+            | SensorDefinition::Bms { name, .. }
+            | SensorDefinition::Text { name, .. }
+            | SensorDefinition::Ratio { name, .. }
+            | SensorDefinition::Directional { name, .. }
+            | SensorDefinition::Bitfield { name, .. }
+            | SensorDefinition::EnergyShare { name, .. } => name,
+        }
+    }
+
+    /// Construct the sensor, registering its metrics in `registry`. Compounds of other
+    /// sensors find them by slug among `definitions`.
+    pub fn build(
+        &self,
+        registry: &Registry,
+        definitions: &[SensorDefinition],
+    ) -> Result<SensorTypes<'static>, SensorConfigError> {
+        // Register sensors check their own factor, as they're also built as components.
+        let zero_factor = match self {
+            SensorDefinition::Compound { factors, .. } => factors.contains(&0),
+            SensorDefinition::Phase { factor, .. }
+            | SensorDefinition::Directional { factor, .. } => *factor == 0,
+            _ => false,
+        };
+        if zero_factor {
+            return Err(SensorConfigError::ZeroFactor(self.name().to_owned()));
+        }
+        Ok(match self {
+            SensorDefinition::Basic(d) => SensorTypes::Basic(BasicSensor(d.build(registry)?)),
+            SensorDefinition::Binary(d) => SensorTypes::Binary(BinarySensor(d.build(registry)?)),
+            SensorDefinition::Temperature(d) => {
+                SensorTypes::Temperature(TemperatureSensor(d.build(registry)?))
+            }
+            SensorDefinition::Compound {
+                name,
+                registers,
+                sensors,
+                factors,
+                no_negative,
+                absolute,
+                zero_epsilon,
+            } if !sensors.is_empty() => {
+                if !registers.is_empty() {
+                    return Err(SensorConfigError::MixedCompound(name.clone()));
+                }
+                if !factors.is_empty() && factors.len() != sensors.len() {
+                    return Err(SensorConfigError::ComponentFactors(name.clone()));
+                }
+                let components = sensors
+                    .iter()
+                    .enumerate()
+                    .map(|(i, slug)| {
+                        let factor = factors.get(i).copied().unwrap_or(1);
+                        component(name, slug, factor, definitions)
+                    })
+                    .collect::<Result<_, _>>()?;
+                SensorTypes::Compound(
+                    CompoundSensor::from_sensors_in(
+                        registry,
+                        leak_str(name),
+                        components,
+                        *no_negative,
+                        *absolute,
+                    )
+                    .with_zero_epsilon(*zero_epsilon),
+                )
+            }
+            SensorDefinition::Compound {
+                name,
+                registers,
+                factors,
+                no_negative,
+                absolute,
+                zero_epsilon,
+                ..
+            } => {
+                if !factors.is_empty() && factors.len() != registers.len() {
+                    return Err(SensorConfigError::ComponentFactors(name.clone()));
+                }
+                let factors = match factors.is_empty() {
+                    true => vec![1; registers.len()],
+                    false => factors.clone(),
+                };
+                SensorTypes::Compound(
+                    CompoundSensor::new_in(
+                        registry,
+                        leak_str(name),
+                        leak_slice(registers),
+                        leak_slice(&factors),
+                        *no_negative,
+                        *absolute,
+                    )
+                    .with_zero_epsilon(*zero_epsilon),
+                )
+            }
+            SensorDefinition::Fault {
+                name,
+                registers,
+                max_codes,
+            } => {
+                let mut sensor = FaultSensor::new_in(registry, leak_str(name), *registers);
+                if let Some(max_codes) = max_codes {
+                    sensor = sensor.with_max_codes(*max_codes);
+                }
+                SensorTypes::Fault(sensor)
+            }
+            SensorDefinition::Phase {
+                name,
+                registers,
+                factor,
+                signed,
+            } => SensorTypes::Phase(PhaseSensor::new_in(
+                registry,
+                leak_str(name),
+                leak_slice(registers),
+                *factor,
+                *signed,
+            )),
+            SensorDefinition::Bms {
+                name,
+                start_register,
+                max_packs,
+            } => SensorTypes::Bms(BmsSensor::new_in(
+                registry,
+                leak_str(name),
+                *start_register,
+                *max_packs,
+            )),
+            SensorDefinition::Text { name, registers } => SensorTypes::Text(TextSensor {
+                name: leak_str(name),
+                registers: leak_slice(registers),
+            }),
+            SensorDefinition::Ratio {
+                name,
+                numerator,
+                denominator,
+            } => SensorTypes::Ratio(RatioSensor::new_in(
+                registry,
+                leak_str(name),
+                *numerator,
+                *denominator,
+            )),
+            SensorDefinition::Bitfield {
+                name,
+                register,
+                bits,
+            } => {
+                let mut parsed = Vec::with_capacity(bits.len());
+                for (bit, flag) in bits {
+                    match bit.parse::<u8>() {
+                        Ok(bit) if bit < 16 => parsed.push((bit, leak_str(flag))),
+                        _ => return Err(SensorConfigError::BitOutOfRange(name.clone())),
+                    }
+                }
+                // In bit order rather than the keys' string order, so the set flags are
+                // listed like fault codes are.
+                parsed.sort();
+                SensorTypes::Bitfield(BitfieldSensor::new_in(
+                    registry,
+                    leak_str(name),
+                    *register,
+                    leak_slice(&parsed),
+                ))
+            }
+            SensorDefinition::EnergyShare {
+                name,
+                total,
+                exchanged,
+            } => SensorTypes::EnergyShare(EnergyShareSensor::new_in(
+                registry,
+                leak_str(name),
+                component(name, total, 1, definitions)?,
+                component(name, exchanged, 1, definitions)?,
+            )),
+            SensorDefinition::Directional {
+                name, registers, ..
+            } if registers.len() < 2 => {
+                return Err(SensorConfigError::MissingDirection(name.clone()))
+            }
+            SensorDefinition::Directional {
+                name,
+                registers,
+                factor,
+            } => SensorTypes::Directional(DirectionalSensor::new_in(
+                registry,
+                leak_str(name),
+                leak_slice(registers),
+                *factor,
+            )),
+        })
+    }
+}
+
+/// A sensor definition that can't be built.
+#[derive(Debug, PartialEq)]
+pub enum SensorConfigError {
+    /// A compound names a sensor that isn't a basic, binary or temperature sensor.
+    UnknownComponent { compound: String, slug: String },
+    /// A compound has both registers and sensors to combine.
+    MixedCompound(String),
+    /// A compound has a different number of factors to sensors or registers.
+    ComponentFactors(String),
+    /// A directional sensor doesn't have both a magnitude and a direction register.
+    MissingDirection(String),
+    /// A sensor's display unit isn't its unit with a different prefix, eg. kW for W.
+    UnitConversion {
+        sensor: String,
+        from: Option<String>,
+        to: String,
+    },
+    /// A bitfield sensor's bit isn't an index into its register.
+    BitOutOfRange(String),
+    /// Two sensors have names with the same slug.
+    DuplicateSlug(SlugCollision),
+    /// An energy counter isn't writable, so it can't be reset.
+    ReadOnlyCounter(String),
+    /// A sensor has a factor of zero, which its values would be divided by.
+    ZeroFactor(String),
+    /// A sensor's rational scale has a denominator of zero.
+    ZeroDenominator(String),
+    /// A sensor's sign bits aren't between 2 and 63.
+    SignBitsOutOfRange(String),
+}
+
+impl fmt::Display for SensorConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SensorConfigError::UnknownComponent { compound, slug } => write!(
+                f,
+                "{} combines {}, which isn't a basic, binary or temperature sensor",
+                compound, slug
+            ),
+            SensorConfigError::MixedCompound(name) => {
+                write!(f, "{} has both registers and sensors to combine", name)
+            }
+            SensorConfigError::ComponentFactors(name) => {
+                write!(
+                    f,
+                    "{} needs a factor for each of its sensors or registers",
+                    name
+                )
+            }
+            SensorConfigError::MissingDirection(name) => {
+                write!(f, "{} needs a magnitude and a direction register", name)
+            }
+            SensorConfigError::UnitConversion { sensor, from, to } => write!(
+                f,
+                "{} can't be shown in {} from {}",
+                sensor,
+                to,
+                from.as_deref().unwrap_or("no unit")
+            ),
+            SensorConfigError::BitOutOfRange(name) => {
+                write!(
+                    f,
+                    "{} names a bit that isn't one of the 16 in its register",
+                    name
+                )
+            }
+            SensorConfigError::DuplicateSlug(collision) => write!(
+                f,
+                "{} and {} both have the slug {}",
+                collision.first, collision.second, collision.slug
+            ),
+            SensorConfigError::ReadOnlyCounter(name) => {
+                write!(f, "{} is an energy counter, so must be writable", name)
+            }
+            SensorConfigError::ZeroFactor(name) => write!(f, "{} has a factor of zero", name),
+            SensorConfigError::ZeroDenominator(name) => {
+                write!(f, "{} has a scale with a denominator of zero", name)
+            }
+            SensorConfigError::SignBitsOutOfRange(name) => {
+                write!(f, "{} needs between 2 and 63 sign bits", name)
+            }
+        }
+    }
+}
+
+impl Error for SensorConfigError {}
+
+/// The sensor `slug` names among `definitions`, for `compound` to combine. It's built in
+/// a registry of its own, as its metrics are exported by the sensor itself, if at all.
+fn component(
+    compound: &str,
+    slug: &str,
+    factor: i64,
+    definitions: &[SensorDefinition],
+) -> Result<CompoundComponent<'static>, SensorConfigError> {
+    let sensor = definitions
+        .iter()
+        .find_map(|definition| match definition {
+            SensorDefinition::Basic(d)
+            | SensorDefinition::Binary(d)
+            | SensorDefinition::Temperature(d)
+                if slug_name(&d.name) == slug =>
+            {
+                Some(d.build(&Registry::new()))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| SensorConfigError::UnknownComponent {
+            compound: compound.to_owned(),
+            slug: slug.to_owned(),
+        })??;
+    Ok(CompoundComponent::new(slug, sensor, factor))
+}
+
+/// The definitions of the built-in sensors, in the order they're defined.
+pub fn builtin_definitions() -> Vec<SensorDefinition> {
+    // The sensors are only built to be described, so keep their metrics out of the way.
+    let registry = Registry::new();
+    let mut builtins: Vec<SensorTypes<'static>> = Vec::new();
+    builtins.extend(sensors(&registry).into_iter().map(SensorTypes::Basic));
+    builtins.extend(
+        binary_sensors(&registry)
+            .into_iter()
+            .map(SensorTypes::Binary),
+    );
+    builtins.extend(
+        temp_sensors(&registry)
+            .into_iter()
+            .map(SensorTypes::Temperature),
+    );
+    builtins.extend(
+        compound_sensors(&registry)
+            .into_iter()
+            .map(SensorTypes::Compound),
+    );
+    builtins.extend(
+        energy_share_sensors(&registry)
+            .into_iter()
+            .map(SensorTypes::EnergyShare),
+    );
+    builtins.push(SensorTypes::Fault(faults(&registry)));
+    builtins.push(SensorTypes::Bitfield(relay_status(&registry)));
+
+    builtins
+        .iter()
+        .filter_map(SensorDefinition::from_sensor)
+        .collect()
+}
+
+/// The built-in sensors as `[[sensors]]` config tables, to start a config file from.
+pub fn dump_builtin_definitions() -> Result<String, toml::ser::Error> {
+    #[derive(Serialize)]
+    struct SensorsConfig {
+        sensors: Vec<SensorDefinition>,
+    }
+
+    toml::to_string(&SensorsConfig {
+        sensors: builtin_definitions(),
+    })
+}
+
+/// Build the configured sensors, keyed by slug, leaving out any with a slug in `disabled`.
+/// A compound can still combine a disabled sensor.
+pub fn build_sensors<S: AsRef<str>>(
+    definitions: &[SensorDefinition],
+    disabled: &[S],
+    registry: &Registry,
+) -> Result<HashMap<String, SensorTypes<'static>>, SensorConfigError> {
+    // Checked before anything is built, as the second sensor's metric would clash with
+    // the first's.
+    let mut names: HashMap<String, &str> = HashMap::new();
+    for definition in definitions.iter() {
+        let slug = slug_name(definition.name());
+        if let Some(first) = names.insert(slug.clone(), definition.name()) {
+            return Err(SensorConfigError::DuplicateSlug(SlugCollision {
+                slug,
+                first: first.to_owned(),
+                second: definition.name().to_owned(),
+            }));
+        }
+    }
+
+    definitions
+        .iter()
+        .map(|definition| (slug_name(definition.name()), definition))
+        .filter(|(slug, _)| !disabled.iter().any(|d| d.as_ref() == slug))
+        .map(|(slug, definition)| Ok((slug, definition.build(registry, definitions)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bms::PACK_LEN;
+    use crate::config::AppConfig;
+    use crate::mock::{modbus_context, ClientMock};
+    use crate::sensor::{register_sensors_in, SensorValue};
+
+    #[test]
+    fn dumped_definitions_load_back_into_the_builtin_sensors() {
+        let dump = dump_builtin_definitions().unwrap();
+        let config = AppConfig::from_toml(&dump).unwrap();
+        assert_eq!(config.sensors, builtin_definitions());
+
+        let loaded = build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap();
+        let builtin = register_sensors_in(&Registry::new());
+        assert_eq!(loaded.len(), builtin.len());
+        for (slug, sensor) in builtin.iter() {
+            let loaded = &loaded[slug];
+            assert_eq!(loaded.registers(), sensor.registers(), "{}", slug);
+            assert_eq!(
+                SensorDefinition::from_sensor(loaded),
+                SensorDefinition::from_sensor(sensor)
+            );
+        }
+    }
+
+    #[test]
+    fn bms_blocks_are_configured() {
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "bms"
+            name = "Battery BMS"
+            start_register = 400
+            max_packs = 4
+            "#,
+        )
+        .unwrap();
+        let sensors = build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap();
+        assert_eq!(sensors["battery_bms"].registers().len(), 1 + 4 * PACK_LEN);
+        assert_eq!(
+            SensorDefinition::from_sensor(&sensors["battery_bms"]).as_ref(),
+            config.sensors.last()
+        );
+    }
+
+    #[tokio::test]
+    async fn compounds_combine_other_sensors_by_slug() {
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "basic"
+            name = "Compound Part Output"
+            registers = [991]
+            factor = 10
+
+            [[sensors]]
+            kind = "basic"
+            name = "Compound Part Load"
+            registers = [992]
+            factor = 1
+            signed = true
+
+            [[sensors]]
+            kind = "compound"
+            name = "Compound Of Parts"
+            sensors = ["compound_part_output", "compound_part_load"]
+            factors = [1, -1]
+            "#,
+        )
+        .unwrap();
+        // The parts are still combined when they aren't exported themselves.
+        let disabled = ["compound_part_load"];
+        let sensors = build_sensors(&config.sensors, &disabled, &Registry::new()).unwrap();
+        assert!(!sensors.contains_key("compound_part_load"));
+
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(991, 2500);
+        // -50 as a signed register.
+        client.set_register(992, 0xFFCE);
+        let value = sensors["compound_of_parts"]
+            .read_value(modbus_context(client))
+            .await
+            .unwrap();
+        assert_eq!(value, SensorValue::Int(300));
+        assert_eq!(
+            SensorDefinition::from_sensor(&sensors["compound_of_parts"]).as_ref(),
+            config.sensors.last()
+        );
+
+        let definitions = &config.sensors[2..];
+        assert_eq!(
+            build_sensors::<&str>(definitions, &[], &Registry::new()).unwrap_err(),
+            SensorConfigError::UnknownComponent {
+                compound: "Compound Of Parts".to_string(),
+                slug: "compound_part_output".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn compound_registers_need_a_factor_each() {
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "compound"
+            name = "Compound Of Registers"
+            registers = [993, 994]
+            "#,
+        )
+        .unwrap();
+        // Missing factors are taken as 1.
+        let sensors = build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap();
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(993, 20);
+        client.set_register(994, 30);
+        let value = sensors["compound_of_registers"]
+            .read_value(modbus_context(client))
+            .await
+            .unwrap();
+        assert_eq!(value, SensorValue::Int(50));
+
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "compound"
+            name = "Compound Of Registers"
+            registers = [993, 994]
+            factors = [1]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap_err(),
+            SensorConfigError::ComponentFactors("Compound Of Registers".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn energy_shares_divide_other_sensors() {
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "basic"
+            name = "Share PV Energy"
+            registers = [1120]
+            factor = 10
+
+            [[sensors]]
+            kind = "basic"
+            name = "Share Grid Export"
+            registers = [1121]
+            factor = 10
+
+            [[sensors]]
+            kind = "basic"
+            name = "Share Load Energy"
+            registers = [1122]
+            factor = 10
+
+            [[sensors]]
+            kind = "basic"
+            name = "Share Grid Import"
+            registers = [1123]
+            factor = 10
+
+            [[sensors]]
+            kind = "energy_share"
+            name = "Share Self Consumption"
+            total = "share_pv_energy"
+            exchanged = "share_grid_export"
+
+            [[sensors]]
+            kind = "energy_share"
+            name = "Share Self Sufficiency"
+            total = "share_load_energy"
+            exchanged = "share_grid_import"
+            "#,
+        )
+        .unwrap();
+        let sensors = build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap();
+
+        let mut client = Box::<ClientMock>::default();
+        // 20.0kWh of PV with 5.0kWh exported, and 16.0kWh of load with 4.5kWh imported.
+        client.set_register(1120, 200);
+        client.set_register(1121, 50);
+        client.set_register(1122, 160);
+        client.set_register(1123, 45);
+        let ctx = modbus_context(client);
+        let value = sensors["share_self_consumption"]
+            .read_value(ctx.clone())
+            .await
+            .unwrap();
+        assert_eq!(value, SensorValue::Float(75.0));
+        let value = sensors["share_self_sufficiency"]
+            .read_value(ctx)
+            .await
+            .unwrap();
+        assert_eq!(value, SensorValue::Float(71.875));
+        assert_eq!(
+            SensorDefinition::from_sensor(&sensors["share_self_sufficiency"]).as_ref(),
+            config.sensors.last()
+        );
+
+        // Nothing has been used yet today.
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(1122, 0);
+        client.set_register(1123, 0);
+        let value = sensors["share_self_sufficiency"]
+            .read_value(modbus_context(client))
+            .await
+            .unwrap();
+        assert_eq!(value, SensorValue::Unavailable);
+    }
+
+    #[test]
+    fn display_units_must_convert_from_the_unit() {
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "basic"
+            name = "Display Energy"
+            registers = [1171]
+            factor = 1
+            unit = "Wh"
+            display_unit = "kWh"
+
+            [[sensors]]
+            kind = "basic"
+            name = "Display Current"
+            registers = [1172]
+            factor = 1
+            unit = "A"
+            display_unit = "kW"
+            "#,
+        )
+        .unwrap();
+        let sensors = build_sensors::<&str>(&config.sensors[..1], &[], &Registry::new()).unwrap();
+        assert_eq!(
+            SensorDefinition::from_sensor(&sensors["display_energy"]).as_ref(),
+            config.sensors.first()
+        );
+        assert_eq!(
+            build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap_err(),
+            SensorConfigError::UnitConversion {
+                sensor: "Display Current".to_string(),
+                from: Some("A".to_string()),
+                to: "kW".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn bitfield_bits_must_be_in_the_register() {
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "bitfield"
+            name = "Relay Status"
+            register = 1151
+            bits = { 2 = "grid_relay", 15 = "generator_relay" }
+            "#,
+        )
+        .unwrap();
+        let sensors = build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap();
+        assert_eq!(
+            SensorDefinition::from_sensor(&sensors["relay_status"]).as_ref(),
+            config.sensors.last()
+        );
+
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "bitfield"
+            name = "Relay Status"
+            register = 1151
+            bits = { 16 = "grid_relay" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap_err(),
+            SensorConfigError::BitOutOfRange("Relay Status".to_string())
+        );
+
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "bitfield"
+            name = "Relay Status"
+            register = 1151
+            bits = { grid_relay = "2" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap_err(),
+            SensorConfigError::BitOutOfRange("Relay Status".to_string())
+        );
+    }
+
+    #[test]
+    fn zero_factors_are_refused() {
+        for sensor in [
+            "kind = \"basic\"\nregisters = [502]\nfactor = 0",
+            "kind = \"compound\"\nregisters = [502, 503]\nfactors = [1, 0]",
+            "kind = \"phase\"\nregisters = [502, 503, 504]\nfactor = 0",
+            "kind = \"directional\"\nregisters = [502, 503]\nfactor = 0",
+        ] {
+            let config =
+                AppConfig::from_toml(&format!("[[sensors]]\nname = \"Zero\"\n{}", sensor)).unwrap();
+            assert_eq!(
+                build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap_err(),
+                SensorConfigError::ZeroFactor("Zero".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn bad_scales_and_sign_bits_are_refused() {
+        for (sensor, error) in [
+            (
+                "scale = [3, 0]",
+                SensorConfigError::ZeroDenominator("Odd".to_string()),
+            ),
+            (
+                "sign_bits = 1",
+                SensorConfigError::SignBitsOutOfRange("Odd".to_string()),
+            ),
+            (
+                "sign_bits = 64",
+                SensorConfigError::SignBitsOutOfRange("Odd".to_string()),
+            ),
+        ] {
+            let config = AppConfig::from_toml(&format!(
+                "[[sensors]]\nkind = \"basic\"\nname = \"Odd\"\nregisters = [502]\nfactor = 1\n{}",
+                sensor
+            ))
+            .unwrap();
+            assert_eq!(
+                build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap_err(),
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn energy_counters_must_be_writable() {
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "basic"
+            name = "Day Energy"
+            registers = [501]
+            factor = 10
+            energy_counter = "day"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap_err(),
+            SensorConfigError::ReadOnlyCounter("Day Energy".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn configured_bitfields_name_the_set_bits() {
+        let config = AppConfig::from_toml(
+            r#"
+            [[sensors]]
+            kind = "bitfield"
+            name = "Configured Status"
+            register = 1184
+            bits = { 0 = "grid_connected", 4 = "battery_charging", 9 = "fan_on", 12 = "alarm" }
+            "#,
+        )
+        .unwrap();
+        let sensors = build_sensors::<&str>(&config.sensors, &[], &Registry::new()).unwrap();
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(1184, 0b0001_0010_0000_0001);
+        let value = sensors["configured_status"]
+            .read_value(modbus_context(client))
+            .await
+            .unwrap();
+        assert_eq!(
+            value,
+            SensorValue::Text("grid_connected, fan_on, alarm".to_string())
+        );
+    }
+
+    #[test]
+    fn colliding_slugs_are_refused() {
+        let mut definitions = builtin_definitions();
+        let mut duplicate = definitions[0].clone();
+        let SensorDefinition::Basic(d) = &mut duplicate else {
+            panic!("the first built-in sensor isn't basic");
+        };
+        d.name = d.name.to_uppercase();
+        let name = d.name.clone();
+        definitions.push(duplicate);
+
+        assert_eq!(
+            build_sensors::<&str>(&definitions, &[], &Registry::new()).unwrap_err(),
+            SensorConfigError::DuplicateSlug(SlugCollision {
+                slug: slug_name(&name),
+                first: definitions[0].name().to_string(),
+                second: name,
+            })
+        );
+    }
+}
@@ -1,12 +1,24 @@
+use crate::helpers::slug_name;
+use crate::schedule::ScheduleSensor;
 use crate::sensor::{
-    BasicSensor, BinarySensor, CompoundSensor, FaultSensor, Sensor, SensorTypes, SerialSensor,
-    TemperatureSensor,
+    BasicSensor, BinarySensor, BitfieldSensor, CompoundComponent, CompoundSensor,
+    EnergyShareSensor, FaultSensor, HighOrLow, Sensor, SensorTypes, SerialSensor,
+    TemperatureSensor, VersionSensor, REGISTRY,
 };
 use lazy_static::lazy_static;
+use prometheus::Registry;
 
-pub const SERIAL: SerialSensor<'static> = SerialSensor {
-    name: "Serial Sensor",
-    registers: [3, 4, 5, 6, 7],
+pub const SERIAL: SerialSensor<'static> =
+    SerialSensor::new("Serial Sensor", &[3, 4, 5, 6, 7], HighOrLow::High);
+
+/// Holds a code for the inverter's model, eg. 5 for a single phase hybrid.
+pub const MODEL_REGISTER: u16 = 0;
+
+pub const FIRMWARE: VersionSensor<'static> = VersionSensor::new("Control Board Firmware", 13);
+
+pub const SCHEDULE: ScheduleSensor<'static> = ScheduleSensor {
+    name: "Time of use schedule",
+    start_register: 250,
 };
 
 //pub const FAULTS: FaultSensor<'static> = FaultSensor {
@@ -15,97 +27,153 @@ pub const SERIAL: SerialSensor<'static> = SerialSensor {
 //};
 
 lazy_static! {
+    pub static ref FAULTS: FaultSensor<'static> = faults(&REGISTRY);
+    pub static ref RELAY_STATUS: BitfieldSensor<'static> = relay_status(&REGISTRY);
+    pub static ref TEMP_SENSORS: [TemperatureSensor<'static>; 4] = temp_sensors(&REGISTRY);
+    pub static ref COMPOUND_SENSORS: [CompoundSensor<'static>; 3] = compound_sensors(&REGISTRY);
+    pub static ref ENERGY_SHARE_SENSORS: [EnergyShareSensor<'static>; 2] =
+        energy_share_sensors(&REGISTRY);
+    pub static ref SENSORS: [BasicSensor<'static>; 50] = sensors(&REGISTRY);
+    pub static ref BINARY_SENSORS: [BinarySensor<'static>; 5] = binary_sensors(&REGISTRY);
+    pub static ref ALL_SENSORS: Vec<SensorTypes<'static>> = vec![];
+}
+
+pub fn faults(registry: &Registry) -> FaultSensor<'static> {
+    FaultSensor::new_in(registry, "Sunsynk Fault Codes", [103, 104, 105, 106])
+}
 
-    pub static ref FAULTS: FaultSensor<'static> = FaultSensor::new("Sunsynk Fault Codes", [103, 104, 105, 106]);
+/// Which of the inverter's relays are closed. Bit 1 isn't used, and the bits above 6 hold
+/// other flags.
+pub fn relay_status(registry: &Registry) -> BitfieldSensor<'static> {
+    BitfieldSensor::new_in(
+        registry,
+        "AC Relay Status",
+        552,
+        &[
+            (0, "inverter"),
+            (2, "grid"),
+            (3, "generator"),
+            (4, "grid_feed"),
+            (5, "dry_contact_1"),
+            (6, "dry_contact_2"),
+        ],
+    )
+}
 
-    pub static ref TEMP_SENSORS: [TemperatureSensor<'static>; 4] = [
-        TemperatureSensor(Sensor::new("Battery Temperature", &[182], 10, false)),
-        TemperatureSensor(Sensor::new("DC transformer temperature", &[90], 10, false)),
-        TemperatureSensor(Sensor::new("Environment temperature", &[95], 10, false)),
-        TemperatureSensor(Sensor::new("Radiator temperature", &[91], 10, false)),
-    ];
+/// Each can be rescaled by name in the config's `[scaling]` table, for firmware that
+/// reports some of them differently.
+#[rustfmt::skip]
+pub fn temp_sensors(registry: &Registry) -> [TemperatureSensor<'static>; 4] {
+    [
+        TemperatureSensor::new_in(registry, "Battery Temperature", &[182], 10, 100),
+        TemperatureSensor::new_in(registry, "DC transformer temperature", &[90], 10, 100),
+        TemperatureSensor::new_in(registry, "Environment temperature", &[95], 10, 100),
+        TemperatureSensor::new_in(registry, "Radiator temperature", &[91], 10, 100),
+    ]
+}
 
-    pub static ref COMPOUND_SENSORS: [CompoundSensor<'static>; 3] = [
-        CompoundSensor::new("Essential Power", &[175, 167, 166], &[1, 1, -1], false, false),
-        CompoundSensor::new("Non-Essential Power", &[172, 176], &[1, -1], true, false),
-        CompoundSensor::new("Grid current", &[160, 161], &[100, 100], false, false),
-    ];
+#[rustfmt::skip]
+pub fn compound_sensors(registry: &Registry) -> [CompoundSensor<'static>; 3] {
+    [
+        CompoundSensor::new_in(registry, "Essential Power", &[175, 167, 166], &[1, 1, -1], false, false),
+        CompoundSensor::new_in(registry, "Non-Essential Power", &[172, 176], &[1, -1], true, false),
+        CompoundSensor::new_in(registry, "Grid current", &[160, 161], &[100, 100], false, false),
+    ]
+}
 
-    pub static ref SENSORS: [BasicSensor<'static>; 49] = [
+/// The share of the day's PV energy used on site rather than exported, and the share of
+/// the day's load met without importing.
+#[rustfmt::skip]
+pub fn energy_share_sensors(registry: &Registry) -> [EnergyShareSensor<'static>; 2] {
+    // The day counters from `sensors`, read again as components.
+    let day_counter = |name: &'static str, registers: &'static [u16]| {
+        CompoundComponent::new(&slug_name(name), Sensor::unregistered(name, registers, 10, false), 1)
+    };
+    [
+        EnergyShareSensor::new_in(registry, "Day Self Consumption", day_counter("Day PV Energy", &[108]), day_counter("Day Grid Export", &[77])),
+        EnergyShareSensor::new_in(registry, "Day Self Sufficiency", day_counter("Day Load Energy", &[84]), day_counter("Day Grid Import", &[76])),
+    ]
+}
+
+#[rustfmt::skip]
+pub fn sensors(registry: &Registry) -> [BasicSensor<'static>; 50] {
+    [
         // Battery
-        BasicSensor(Sensor::new("Battery Voltage", &[183], 100, false)),
-        BasicSensor(Sensor::new("Battery SOC", &[184], 1, false)),
-        BasicSensor(Sensor::new("Battery Power", &[190], 1, true)),
-        BasicSensor(Sensor::new("Battery Current", &[191], 100, true)),
-        BasicSensor(Sensor::new("Battery Charging Voltage", &[312], 100, false)),
-        BasicSensor(Sensor::new("Battery 1 SOC", &[603], 1, false)),
-        BasicSensor(Sensor::new("Battery 1 Cycle", &[611], 1, false)),
+        BasicSensor(Sensor::new_in(registry, "Battery Voltage", &[183], 100, false)),
+        BasicSensor(Sensor::new_in(registry, "Battery SOC", &[184], 1, false)),
+        BasicSensor(Sensor::new_in(registry, "Battery Power", &[190], 1, true)),
+        BasicSensor(Sensor::new_in(registry, "Battery Current", &[191], 100, true)),
+        BasicSensor(Sensor::new_in(registry, "Battery Charging Voltage", &[312], 100, false)),
+        BasicSensor(Sensor::new_in(registry, "Battery 1 SOC", &[603], 1, false)),
+        BasicSensor(Sensor::new_in(registry, "Battery 1 Cycle", &[611], 1, false)),
 
         // Inverter
-        BasicSensor(Sensor::new("Inverter Power", &[175], 1, true)),
-        BasicSensor(Sensor::new("Inverter Voltage", &[154], 10, false)),
-        BasicSensor(Sensor::new("Inverter Frequency", &[195], 100, false)),
+        BasicSensor(Sensor::new_in(registry, "Rated power", &[16, 17], 10, false).read_once()),
+        BasicSensor(Sensor::new_in(registry, "Inverter Power", &[175], 1, true)),
+        BasicSensor(Sensor::new_in(registry, "Inverter Voltage", &[154], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Inverter Frequency", &[195], 100, false)),
 
         // Grid
-        BasicSensor(Sensor::new("Grid frequency", &[79], 100, false)),
-        BasicSensor(Sensor::new("Grid power", &[169], 1, true)),  // L1(167) + L2(168)
-        BasicSensor(Sensor::new("Grid LD power", &[167], 1, true)),  // L1 seems to be LD
-        BasicSensor(Sensor::new("Grid L2 power", &[168], 1, true)),
-        BasicSensor(Sensor::new("Grid voltage", &[150], 10, false)),
-        BasicSensor(Sensor::new("Grid CT power", &[172], 1, true)),
+        BasicSensor(Sensor::new_in(registry, "Grid frequency", &[79], 100, false)),
+        BasicSensor(Sensor::new_in(registry, "Grid power", &[169], 1, true)),  // L1(167) + L2(168)
+        BasicSensor(Sensor::new_in(registry, "Grid LD power", &[167], 1, true)),  // L1 seems to be LD
+        BasicSensor(Sensor::new_in(registry, "Grid L2 power", &[168], 1, true)),
+        BasicSensor(Sensor::new_in(registry, "Grid voltage", &[150], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Grid CT power", &[172], 1, true)),
 
         // Load
-        BasicSensor(Sensor::new("Load power", &[178], 1, true)),  // L1(176) + L2(177)
-        BasicSensor(Sensor::new("Load L1 power", &[176], 1, true)),
-        BasicSensor(Sensor::new("Load L2 power", &[177], 1, true)),
+        BasicSensor(Sensor::new_in(registry, "Load power", &[178], 1, true)),  // L1(176) + L2(177)
+        BasicSensor(Sensor::new_in(registry, "Load L1 power", &[176], 1, true)),
+        BasicSensor(Sensor::new_in(registry, "Load L2 power", &[177], 1, true)),
 
         // Solar
-        BasicSensor(Sensor::new("PV1 power", &[186], 1, true)),
-        BasicSensor(Sensor::new("PV1 voltage", &[109], 10, false)),
-        BasicSensor(Sensor::new("PV1 current", &[110], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "PV1 power", &[186], 1, true)),
+        BasicSensor(Sensor::new_in(registry, "PV1 voltage", &[109], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "PV1 current", &[110], 10, false)),
 
-        BasicSensor(Sensor::new("PV2 power", &[187], 1, true)),
-        BasicSensor(Sensor::new("PV2 voltage", &[111], 10, false)),
-        BasicSensor(Sensor::new("PV2 current", &[112], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "PV2 power", &[187], 1, true)),
+        BasicSensor(Sensor::new_in(registry, "PV2 voltage", &[111], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "PV2 current", &[112], 10, false)),
 
         // Power on Outputs
-        BasicSensor(Sensor::new("AUX power", &[166], 1, true)),
+        BasicSensor(Sensor::new_in(registry, "AUX power", &[166], 1, true)),
 
         // Energy
-        BasicSensor(Sensor::new("Day Active Energy", &[60], 10, true)),
-        BasicSensor(Sensor::new("Day Battery Charge", &[70], 10, false)),
-        BasicSensor(Sensor::new("Day Battery discharge", &[71], 10, false)),
-        BasicSensor(Sensor::new("Day Grid Export", &[77], 10, false)),
-        BasicSensor(Sensor::new("Day Grid Import", &[76], 10, false)),
-        BasicSensor(Sensor::new("Day Load Energy", &[84], 10, false)),
-        BasicSensor(Sensor::new("Day PV Energy", &[108], 10, false)),
-        BasicSensor(Sensor::new("Day Reactive Energy", &[61], 10, true)),
-        BasicSensor(Sensor::new("Month Grid Energy", &[67], 10, false)),
-        BasicSensor(Sensor::new("Month Load Energy", &[66], 10, false)),
-        BasicSensor(Sensor::new("Month PV Energy", &[65], 10, false)),
-        BasicSensor(Sensor::new("Total Active Energy", &[63, 64], 10, false)),  // signed?
-        BasicSensor(Sensor::new("Total Battery Charge", &[72, 73], 10, false)),
-        BasicSensor(Sensor::new("Total Battery Discharge", &[74, 75], 10, false)),
-        BasicSensor(Sensor::new("Total Grid Export", &[81, 82], 10, false)),
-        BasicSensor(Sensor::new("Total Grid Import", &[78, 80], 10, false)),
-        BasicSensor(Sensor::new("Total Load Energy", &[85, 86], 10, false)),
-        BasicSensor(Sensor::new("Total PV Energy", &[96, 97], 10, false)),
-        BasicSensor(Sensor::new("Year Grid Export", &[98, 99], 10, false)),
-        BasicSensor(Sensor::new("Year Load Energy", &[87, 88], 10, false)),
-        BasicSensor(Sensor::new("Year PV Energy", &[68, 69], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Day Active Energy", &[60], 10, true)),
+        BasicSensor(Sensor::new_in(registry, "Day Battery Charge", &[70], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Day Battery discharge", &[71], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Day Grid Export", &[77], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Day Grid Import", &[76], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Day Load Energy", &[84], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Day PV Energy", &[108], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Day Reactive Energy", &[61], 10, true)),
+        BasicSensor(Sensor::new_in(registry, "Month Grid Energy", &[67], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Month Load Energy", &[66], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Month PV Energy", &[65], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Total Active Energy", &[63, 64], 10, false)),  // signed?
+        BasicSensor(Sensor::new_in(registry, "Total Battery Charge", &[72, 73], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Total Battery Discharge", &[74, 75], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Total Grid Export", &[81, 82], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Total Grid Import", &[78, 80], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Total Load Energy", &[85, 86], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Total PV Energy", &[96, 97], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Year Grid Export", &[98, 99], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Year Load Energy", &[87, 88], 10, false)),
+        BasicSensor(Sensor::new_in(registry, "Year PV Energy", &[68, 69], 10, false)),
 
         // Settings
-        BasicSensor(Sensor::new("Control Mode", &[200], 1, false)),
-        BasicSensor(Sensor::new("Grid Charge Battery current", &[230], 1, false)),
-    ];
-
-    pub static ref BINARY_SENSORS: [BinarySensor<'static>; 5] = [
-        BinarySensor(Sensor::new_mut("Grid Charge Enabled", &[232], 1, false)),
-        BinarySensor(Sensor::new_mut("Priority Load", &[243], 1, false)),
-        BinarySensor(Sensor::new_mut("Solar Export", &[247], 1, false)),
-        BinarySensor(Sensor::new_mut("Use Timer", &[248], 1, false)),
-        BinarySensor(Sensor::new("Grid Connected", &[194], 1, false)),
-    ];
+        BasicSensor(Sensor::new_in(registry, "Control Mode", &[200], 1, false)),
+        BasicSensor(Sensor::new_in(registry, "Grid Charge Battery current", &[230], 1, false)),
+    ]
+}
 
-    pub static ref ALL_SENSORS: Vec<SensorTypes<'static>> = vec![];
+#[rustfmt::skip]
+pub fn binary_sensors(registry: &Registry) -> [BinarySensor<'static>; 5] {
+    [
+        BinarySensor(Sensor::new_mut_in(registry, "Grid Charge Enabled", &[232], 1, false)),
+        BinarySensor(Sensor::new_mut_in(registry, "Priority Load", &[243], 1, false)),
+        BinarySensor(Sensor::new_mut_in(registry, "Solar Export", &[247], 1, false)),
+        BinarySensor(Sensor::new_mut_in(registry, "Use Timer", &[248], 1, false)),
+        BinarySensor(Sensor::new_in(registry, "Grid Connected", &[194], 1, false)),
+    ]
 }
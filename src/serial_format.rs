@@ -0,0 +1,166 @@
+use crate::helpers::invalid_input;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use tokio_serial::{DataBits, Parity, SerialPortBuilder, StopBits};
+
+/// The data bits, parity and stop bits of a serial port, eg. "8N1".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SerialFormat {
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for SerialFormat {
+    fn default() -> SerialFormat {
+        SerialFormat {
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+pub fn parse_data_bits(s: &str) -> io::Result<DataBits> {
+    match s.trim() {
+        "5" => Ok(DataBits::Five),
+        "6" => Ok(DataBits::Six),
+        "7" => Ok(DataBits::Seven),
+        "8" => Ok(DataBits::Eight),
+        _ => Err(invalid_input(format!(
+            "{:?} is not a valid number of data bits, expected 5 to 8",
+            s
+        ))),
+    }
+}
+
+/// Accepts the full name or the first letter, in any case, eg. "none", "N", "even", "e".
+pub fn parse_parity(s: &str) -> io::Result<Parity> {
+    match s.trim().to_lowercase().as_str() {
+        "n" | "none" => Ok(Parity::None),
+        "e" | "even" => Ok(Parity::Even),
+        "o" | "odd" => Ok(Parity::Odd),
+        _ => Err(invalid_input(format!(
+            "{:?} is not a valid parity, expected none, even or odd",
+            s
+        ))),
+    }
+}
+
+pub fn parse_stop_bits(s: &str) -> io::Result<StopBits> {
+    match s.trim() {
+        "1" => Ok(StopBits::One),
+        "2" => Ok(StopBits::Two),
+        _ => Err(invalid_input(format!(
+            "{:?} is not a valid number of stop bits, expected 1 or 2",
+            s
+        ))),
+    }
+}
+
+impl SerialFormat {
+    /// Parse each setting separately, eg. "8", "none", "1".
+    pub fn from_fields(data_bits: &str, parity: &str, stop_bits: &str) -> io::Result<SerialFormat> {
+        Ok(SerialFormat {
+            data_bits: parse_data_bits(data_bits)?,
+            parity: parse_parity(parity)?,
+            stop_bits: parse_stop_bits(stop_bits)?,
+        })
+    }
+
+    pub fn apply(&self, builder: SerialPortBuilder) -> SerialPortBuilder {
+        builder
+            .data_bits(self.data_bits)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+    }
+}
+
+impl FromStr for SerialFormat {
+    type Err = io::Error;
+
+    /// Parse the usual shorthand, eg. "8N1" or "7E1".
+    fn from_str(s: &str) -> io::Result<SerialFormat> {
+        let chars: Vec<char> = s.trim().chars().collect();
+        if chars.len() != 3 {
+            return Err(invalid_input(format!(
+                "{:?} is not a valid serial format, expected something like 8N1",
+                s
+            )));
+        }
+        SerialFormat::from_fields(
+            &chars[0].to_string(),
+            &chars[1].to_string(),
+            &chars[2].to_string(),
+        )
+    }
+}
+
+impl fmt::Display for SerialFormat {
+    /// The usual shorthand, eg. "8N1".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        write!(f, "{}{}{}", data_bits, parity, stop_bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_8n1() {
+        assert_eq!(
+            "8N1".parse::<SerialFormat>().unwrap(),
+            SerialFormat {
+                data_bits: DataBits::Eight,
+                parity: Parity::None,
+                stop_bits: StopBits::One,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_7e1() {
+        assert_eq!(
+            "7e1".parse::<SerialFormat>().unwrap(),
+            SerialFormat {
+                data_bits: DataBits::Seven,
+                parity: Parity::Even,
+                stop_bits: StopBits::One,
+            }
+        );
+        assert_eq!(
+            SerialFormat::from_fields("7", "even", "1").unwrap(),
+            "7E1".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for spec in ["9N1", "8X1", "8N3", "8N", "8N1X", ""] {
+            let err = spec.parse::<SerialFormat>().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", spec);
+        }
+        let err = SerialFormat::from_fields("8", "mark", "1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "\"mark\" is not a valid parity, expected none, even or odd"
+        );
+    }
+}
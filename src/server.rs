@@ -1,72 +1,794 @@
-use crate::sensor::{SensorTypes, REGISTRY};
+use crate::api::{
+    EnergyReset, EventEntry, FaultReport, HealthStatus, HistoryEntry, PlannedWrite, SensorListing,
+    SensorReading, VersionInfo, WindowSummary,
+};
+use crate::cache::SensorCache;
+use crate::connection::{ConnectionStatus, DEGRADED_AFTER};
+use crate::correlation::{log, CorrelationId, CORRELATION_HEADER};
+use crate::events::FaultEvents;
+use crate::helpers::{invalid_input, is_label_name};
+use crate::history::SensorHistory;
+use crate::modbus_error::ModbusError;
+use crate::pool::ContextPool;
+use crate::schedule::{ScheduleCache, ScheduleSlot};
+use crate::sensor::{
+    EnergyPeriod, MetricUpdate, SensorError, SensorRead, SensorTypes, SensorValue, REGISTRY,
+};
+use crate::sensor_definitions::{FIRMWARE, MODEL_REGISTER, SCHEDULE, SERIAL};
+use crate::sink::{OutputSink, PrometheusSink, Reading};
+use crate::snapshot::RegisterSnapshot;
+use crate::state::{State, StateFile, StateSink};
+use crate::window::SensorWindows;
+use base64::prelude::{Engine, BASE64_STANDARD};
 use bytes::Bytes;
-use prometheus::Encoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::stream::{self, StreamExt};
+use hyper::service::make_service_fn;
+use prometheus::core::Collector;
+use prometheus::proto::LabelPair;
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge,
+    Opts, Registry,
+};
 use reqwest::StatusCode;
-use std::collections::HashMap;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
 use std::error::Error;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicU16;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio::time::{interval, Duration, Instant};
+use std::time::SystemTime;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::time::{interval, timeout_at, Duration, Instant, MissedTickBehavior};
 use tokio_modbus::client::Context;
+use tokio_modbus::prelude::Reader;
+use warp::http::HeaderValue;
 use warp::{Filter, Rejection, Reply};
 
 const START_TIMEOUT: Duration = Duration::from_secs(5);
-const COLLECT_INTERVAL: Duration = Duration::from_secs(10);
+pub const COLLECT_INTERVAL: Duration = Duration::from_secs(10);
+/// Set on sensor reads that were served from the cache rather than the inverter.
+pub const CACHED_HEADER: &str = "x-samsynk-cached";
+/// Stands in for the serial number, model or firmware version when it can't be read.
+const UNKNOWN_IDENTITY: &str = "unknown";
+/// How long to wait before restarting the data collector after it panics, doubling with
+/// each restart in a row up to `MAX_COLLECTOR_BACKOFF`.
+const COLLECTOR_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_COLLECTOR_BACKOFF: Duration = Duration::from_secs(60);
+/// Ten minutes of readings at the default collection interval.
+pub const DEFAULT_HISTORY_DEPTH: usize = 60;
+/// The largest body accepted by the routes that write a value to a sensor. Plenty for any
+/// number, small enough that an oversized body is refused before it's read.
+const MAX_WRITE_BODY: u64 = 32;
+/// How long a client has to send a request's headers before the connection is dropped, so
+/// slow clients can't hold connections open indefinitely.
+pub const HTTP_HEADER_TIMEOUT: Duration = Duration::from_secs(30);
+/// The sensor map source reported when the built-in sensors are in use.
+pub const BUILTIN_SENSOR_MAP: &str = "builtin";
 
 type Address = ([u8; 4], u16);
 
+/// Where the data collector reads the sensors from.
+#[derive(Clone)]
+enum Readers {
+    /// A single connection, read one sensor at a time, eg. an RTU bus.
+    Shared(Arc<Mutex<dyn Reader>>),
+    /// Several connections, each reading a sensor at the same time as the others.
+    Pool(ContextPool),
+}
+
+impl Readers {
+    /// How many sensors can be read at once.
+    fn concurrency(&self) -> usize {
+        match self {
+            Readers::Shared(_) => 1,
+            Readers::Pool(pool) => pool.size(),
+        }
+    }
+
+    /// A connection to read one sensor over, waiting for one to come free if need be.
+    async fn get(&self) -> Arc<Mutex<dyn Reader>> {
+        match self {
+            Readers::Shared(ctx) => ctx.clone(),
+            Readers::Pool(pool) => Arc::new(Mutex::new(pool.acquire().await)),
+        }
+    }
+}
+
+/// What came of reading a sensor in a collection cycle.
+enum ReadOutcome {
+    Read(SensorValue, MetricUpdate, SystemTime),
+    Failed,
+    /// The cycle ran out of time before the sensor was read.
+    Skipped,
+}
+
+/// The data collector's own metrics. Each server has its own, registered alongside its
+/// sensors, so servers in the same process don't count each other's reads.
+#[derive(Clone)]
+struct CollectorMetrics {
+    read_failures: IntCounterVec,
+    skipped_reads: IntCounterVec,
+    restarts: IntCounter,
+    queue_full: IntCounter,
+    cycle_duration: Histogram,
+    sensors: IntGauge,
+}
+
+impl Default for CollectorMetrics {
+    /// Metrics that aren't registered anywhere.
+    fn default() -> CollectorMetrics {
+        CollectorMetrics {
+            read_failures: IntCounterVec::new(
+                Opts::new(
+                    "sensor_read_failures_total",
+                    "Failed reads of each sensor by the data collector",
+                ),
+                &["sensor"],
+            )
+            .unwrap(),
+            skipped_reads: IntCounterVec::new(
+                Opts::new(
+                    "sensor_reads_skipped_total",
+                    "Reads of each sensor skipped because the collection cycle ran out of time",
+                ),
+                &["sensor"],
+            )
+            .unwrap(),
+            restarts: IntCounter::new(
+                "collector_restarts_total",
+                "Times the data collector has been restarted after panicking",
+            )
+            .unwrap(),
+            queue_full: IntCounter::new(
+                "collect_queue_full_total",
+                "Requests to the data collector that waited because its queue was full",
+            )
+            .unwrap(),
+            cycle_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "samsynk_collection_duration_seconds",
+                    "How long each collection cycle took, including publishing its readings",
+                )
+                .buckets(exponential_buckets(0.1, 2.0, 10).unwrap()),
+            )
+            .unwrap(),
+            sensors: IntGauge::new("samsynk_sensors_total", "Sensors read by the collector")
+                .unwrap(),
+        }
+    }
+}
+
+impl CollectorMetrics {
+    /// New metrics, served from `registry`.
+    fn new_in(registry: &Registry) -> CollectorMetrics {
+        let metrics = CollectorMetrics::default();
+        let collectors: [Box<dyn Collector>; 6] = [
+            Box::new(metrics.read_failures.clone()),
+            Box::new(metrics.skipped_reads.clone()),
+            Box::new(metrics.restarts.clone()),
+            Box::new(metrics.queue_full.clone()),
+            Box::new(metrics.cycle_duration.clone()),
+            Box::new(metrics.sensors.clone()),
+        ];
+        for collector in collectors {
+            // Already registered if another server shares the registry, in which case
+            // that server's metrics are the ones served.
+            let _ = registry.register(collector);
+        }
+        metrics
+    }
+
+    /// Start every sensor's failure count at zero, so a sensor that has never failed is
+    /// distinguishable from one that doesn't exist, and count the sensors.
+    fn init(&self, sensors: &HashMap<String, SensorTypes<'static>>) {
+        self.sensors.set(sensors.len() as i64);
+        for slug in sensors.keys() {
+            self.read_failures.with_label_values(&[slug]);
+            self.skipped_reads.with_label_values(&[slug]);
+        }
+    }
+}
+
+async fn read_sensor(
+    slug: &str,
+    sensor: &SensorTypes<'static>,
+    readers: &Readers,
+    deadline: Instant,
+    status: &ConnectionStatus,
+    metrics: &CollectorMetrics,
+) -> ReadOutcome {
+    if Instant::now() >= deadline {
+        return ReadOutcome::Skipped;
+    }
+    let reader = match timeout_at(deadline, readers.get()).await {
+        Ok(reader) => reader,
+        Err(_) => return ReadOutcome::Skipped,
+    };
+    // A read that has started is finished, however late, as dropping it mid-frame would
+    // leave the rest of the answer to be taken for the next request's. The transport's own
+    // timeout bounds how long it can take.
+    match sensor.collect_value(reader).await {
+        Ok((value, metrics)) => {
+            status.mark_success();
+            ReadOutcome::Read(value, metrics, SystemTime::now())
+        }
+        Err(e) => {
+            match ModbusError::classify(&*e) {
+                Some(e @ ModbusError::Exception(_)) => {
+                    status.mark_success();
+                    log(format_args!("inverter refused read of {}: {}", slug, e))
+                }
+                Some(_) => {
+                    status.mark_failure();
+                    log(format_args!("could not read {}: {}", slug, e))
+                }
+                None => log(format_args!("could not read {}: {}", slug, e)),
+            }
+            metrics.read_failures.with_label_values(&[slug]).inc();
+            ReadOutcome::Failed
+        }
+    }
+}
+
+/// Read every sensor once, highest priority first, then hand the cycle's readings to each
+/// of the output sinks. Sensors not yet being read by `deadline` are skipped until the
+/// next cycle, so one slow sensor can't hold up the ones after it indefinitely. With a
+/// pool of connections, as many sensors are read at once as there are connections. The
+/// cycle's log lines share a correlation id. Returns the readings of the sensors that
+/// were read.
+async fn collect(
+    all_sensors: &HashMap<String, SensorTypes<'static>>,
+    readers: &Readers,
+    sinks: &[Arc<dyn OutputSink>],
+    deadline: Instant,
+    status: &ConnectionStatus,
+    metrics: &CollectorMetrics,
+) -> Vec<Reading> {
+    CorrelationId::next()
+        .scope(collect_cycle(
+            all_sensors,
+            readers,
+            sinks,
+            deadline,
+            status,
+            metrics,
+        ))
+        .await
+}
+
+async fn collect_cycle(
+    all_sensors: &HashMap<String, SensorTypes<'static>>,
+    readers: &Readers,
+    sinks: &[Arc<dyn OutputSink>],
+    deadline: Instant,
+    status: &ConnectionStatus,
+    metrics: &CollectorMetrics,
+) -> Vec<Reading> {
+    let _timer = metrics.cycle_duration.start_timer();
+    // Sensors of equal priority are read in slug order, so the order is the same each cycle.
+    let mut sensors: Vec<_> = all_sensors
+        .iter()
+        .filter(|(_, sensor)| sensor.is_readable())
+        .collect();
+    sensors.sort_by_key(|(slug, sensor)| (Reverse(sensor.priority()), *slug));
+    let mut reads = Vec::new();
+    for (slug, sensor) in sensors {
+        reads.push(async move {
+            let outcome = read_sensor(slug, sensor, readers, deadline, status, metrics).await;
+            (slug, outcome)
+        });
+    }
+    let outcomes: Vec<_> = stream::iter(reads)
+        .buffer_unordered(readers.concurrency())
+        .collect()
+        .await;
+
+    let mut readings = Vec::new();
+    let mut skipped = 0;
+    for (slug, outcome) in outcomes {
+        match outcome {
+            ReadOutcome::Read(value, metrics, timestamp) => readings.push(Reading {
+                slug: slug.clone(),
+                value,
+                timestamp,
+                metrics,
+            }),
+            ReadOutcome::Failed => {}
+            ReadOutcome::Skipped => {
+                metrics.skipped_reads.with_label_values(&[slug]).inc();
+                skipped += 1;
+            }
+        }
+    }
+
+    if skipped > 0 {
+        log(format_args!(
+            "collection cycle ran out of time, skipped {} sensors",
+            skipped
+        ));
+    }
+
+    for sink in sinks.iter() {
+        if let Err(e) = sink.publish(&readings).await {
+            log(format_args!("could not publish readings: {}", e));
+        }
+    }
+    readings
+}
+
+/// Asks the data collector for a cycle now rather than at its next tick. The sender is
+/// told once the cycle has finished.
+type CollectRequest = oneshot::Sender<()>;
+
+/// Shared so a restarted data collector can take over the requests from the one before it.
+type CollectRequests = Arc<Mutex<mpsc::Receiver<CollectRequest>>>;
+
+/// Queue a request for the data collector. A full queue means the collector can't keep
+/// up, eg. with a slow bus, so each send that has to wait for room is counted in
+/// `queue_full`, and logged if it waits longer than `alert_after`.
+async fn queue_collect_request(
+    collect_requests: &mpsc::Sender<CollectRequest>,
+    request: CollectRequest,
+    alert_after: Option<Duration>,
+    queue_full: &IntCounter,
+) -> Result<(), mpsc::error::SendError<CollectRequest>> {
+    let request = match collect_requests.try_send(request) {
+        Ok(()) => return Ok(()),
+        Err(mpsc::error::TrySendError::Closed(request)) => {
+            return Err(mpsc::error::SendError(request))
+        }
+        Err(mpsc::error::TrySendError::Full(request)) => request,
+    };
+    queue_full.inc();
+
+    let send = collect_requests.send(request);
+    tokio::pin!(send);
+    if let Some(alert_after) = alert_after {
+        tokio::select! {
+            result = &mut send => return result,
+            _ = tokio::time::sleep(alert_after) => eprintln!(
+                "collect request has waited over {:?} for the data collector",
+                alert_after
+            ),
+        }
+    }
+    send.await
+}
+
+/// When the data collector runs its cycles.
+#[derive(Clone, Copy, Debug)]
+struct CollectSchedule {
+    interval: Duration,
+    /// How long a cycle may take before the sensors not yet read are skipped.
+    cycle_timeout: Duration,
+    /// The most each polled cycle is put back by, chosen at random for every cycle.
+    jitter: Duration,
+}
+
+impl CollectSchedule {
+    /// Cycles every `interval`, each with the whole interval to finish, and no jitter.
+    fn new(interval: Duration) -> CollectSchedule {
+        CollectSchedule {
+            interval,
+            cycle_timeout: interval,
+            jitter: Duration::ZERO,
+        }
+    }
+}
+
+/// A random duration less than `max`, or zero if `max` is.
+fn random_jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    // Every `RandomState` is keyed differently, which is random enough to spread polls.
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_nanos(random % max.as_nanos().min(u64::MAX as u128) as u64)
+}
+
+/// Stop polling the static sensors among `readings`, now they've been read. Those whose
+/// read failed, eg. with the gateway still coming up, are kept until a read succeeds.
+fn drop_read_once(sensors: &mut HashMap<String, SensorTypes<'static>>, readings: &[Reading]) {
+    for reading in readings.iter() {
+        if sensors
+            .get(&reading.slug)
+            .is_some_and(|sensor| sensor.is_read_once())
+        {
+            sensors.remove(&reading.slug);
+        }
+    }
+}
+
 async fn data_collector(
     all_sensors: HashMap<String, SensorTypes<'static>>,
-    ctx: Arc<Mutex<Context>>,
+    readers: Readers,
+    sinks: Vec<Arc<dyn OutputSink>>,
+    schedule: CollectSchedule,
+    collect_requests: CollectRequests,
+    status: ConnectionStatus,
+    metrics: CollectorMetrics,
 ) {
-    let mut collect_interval = interval(COLLECT_INTERVAL);
+    // Released if the collector panics, as the task is dropped.
+    let mut collect_requests = collect_requests.lock_owned().await;
+    metrics.init(&all_sensors);
+
+    let mut collect_interval = interval(schedule.interval);
+    // A cycle that overran its interval, eg. a slow request, shouldn't be followed by a
+    // burst of cycles to catch up, so the next waits a full interval after it.
+    collect_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let start = collect_interval.tick().await;
+    let mut polled_sensors = all_sensors;
+    let readings = collect(
+        &polled_sensors,
+        &readers,
+        &sinks,
+        start + schedule.cycle_timeout,
+        &status,
+        &metrics,
+    )
+    .await;
+    drop_read_once(&mut polled_sensors, &readings);
+
+    loop {
+        let mut requests = Vec::new();
+        let start = tokio::select! {
+            start = collect_interval.tick() => {
+                // Exporters polling a shared gateway drift apart rather than staying in
+                // step. The wait comes out of the cycle's time, so cycles don't overlap.
+                tokio::time::sleep(random_jitter(schedule.jitter)).await;
+                start
+            }
+            Some(request) = collect_requests.recv() => {
+                // Serve every request made so far with the one cycle, and push back the
+                // next tick so it doesn't run another cycle straight after this one.
+                requests.push(request);
+                while let Ok(request) = collect_requests.try_recv() {
+                    requests.push(request);
+                }
+                collect_interval.reset();
+                Instant::now()
+            }
+        };
+
+        let readings = collect(
+            &polled_sensors,
+            &readers,
+            &sinks,
+            start + schedule.cycle_timeout,
+            &status,
+            &metrics,
+        )
+        .await;
+        drop_read_once(&mut polled_sensors, &readings);
+        for request in requests {
+            let _ = request.send(());
+        }
+    }
+}
+
+/// Aborts the task when dropped, so aborting the supervisor stops the task it supervises.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Run the task made by `start`, and start another in its place whenever it panics, so a
+/// bug hit by one collection cycle doesn't leave the metrics stale until the exporter is
+/// restarted. Restarts back off while the task keeps panicking, and are counted in
+/// `restarts`.
+async fn supervise<F, Fut>(restarts: IntCounter, mut start: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut backoff = COLLECTOR_BACKOFF;
     loop {
-        collect_interval.tick().await;
-        let ctx = ctx.clone();
+        let started = Instant::now();
+        let mut task = AbortOnDrop(tokio::spawn(start()));
+        match (&mut task.0).await {
+            Err(e) if e.is_panic() => {}
+            _ => return,
+        }
+
+        // A task that ran for a while before panicking isn't stuck panicking at startup.
+        if started.elapsed() > MAX_COLLECTOR_BACKOFF {
+            backoff = COLLECTOR_BACKOFF;
+        }
+        restarts.inc();
+        eprintln!("data collector panicked, restarting it in {:?}", backoff);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_COLLECTOR_BACKOFF);
+    }
+}
+
+/// Runs collection cycles when `/metrics` is scraped, in place of the data collector, so
+/// the bus is only polled when something actually wants the values.
+#[derive(Clone)]
+struct ScrapeCollector {
+    readers: Readers,
+    sinks: Vec<Arc<dyn OutputSink>>,
+    /// Scrapes within this long of the last cycle are served the metrics from that cycle.
+    min_interval: Duration,
+    cycle_timeout: Duration,
+    cycles: Arc<Mutex<ScrapeCycles>>,
+    status: ConnectionStatus,
+    metrics: CollectorMetrics,
+}
+
+/// What the scrape collector carries from one cycle to the next, shared by its clones.
+struct ScrapeCycles {
+    /// The sensors still polled, which loses read-once sensors once they've been read.
+    sensors: HashMap<String, SensorTypes<'static>>,
+    last: Option<Instant>,
+}
+
+impl ScrapeCollector {
+    fn new(
+        sensors: HashMap<String, SensorTypes<'static>>,
+        readers: Readers,
+        sinks: Vec<Arc<dyn OutputSink>>,
+        min_interval: Duration,
+        cycle_timeout: Duration,
+        status: ConnectionStatus,
+        metrics: CollectorMetrics,
+    ) -> ScrapeCollector {
+        metrics.init(&sensors);
+        ScrapeCollector {
+            readers,
+            sinks,
+            min_interval,
+            cycle_timeout,
+            cycles: Arc::new(Mutex::new(ScrapeCycles {
+                sensors,
+                last: None,
+            })),
+            status,
+            metrics,
+        }
+    }
+
+    /// Run a cycle, unless `force` isn't set and there was one recently. Scrapes arriving
+    /// during a cycle wait for it rather than starting another.
+    async fn collect(&self, force: bool) {
+        let mut cycles = self.cycles.lock().await;
+        if !force
+            && cycles
+                .last
+                .is_some_and(|last| last.elapsed() < self.min_interval)
+        {
+            return;
+        }
+
+        let start = Instant::now();
+        let readings = collect(
+            &cycles.sensors,
+            &self.readers,
+            &self.sinks,
+            start + self.cycle_timeout,
+            &self.status,
+            &self.metrics,
+        )
+        .await;
+        drop_read_once(&mut cycles.sensors, &readings);
+        cycles.last = Some(start);
+    }
+}
+
+/// Serves collect requests with the scrape collector, for when there is no data collector.
+async fn scrape_collect_requests(
+    collector: ScrapeCollector,
+    mut collect_requests: mpsc::Receiver<CollectRequest>,
+) {
+    while let Some(request) = collect_requests.recv().await {
+        collector.collect(true).await;
+        let _ = request.send(());
+    }
+}
 
-        for (_, sensor) in all_sensors.clone().iter() {
-            sensor.read(ctx.clone()).await.unwrap();
+/// The inverter's serial number, or a placeholder if it can't be read so that the
+/// exporter still starts.
+async fn read_serial(ctx: Arc<Mutex<dyn Reader>>) -> String {
+    match SERIAL.read(ctx).await {
+        Ok(serial) => serial,
+        Err(e) => {
+            eprintln!("could not read the inverter serial number: {}", e);
+            UNKNOWN_IDENTITY.to_string()
         }
     }
 }
 
+/// The inverter's model code and firmware version, read once at startup. Either is left
+/// out if it can't be read, so that the exporter still starts.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct InverterVersion {
+    model: Option<String>,
+    firmware: Option<String>,
+}
+
+impl InverterVersion {
+    async fn read(ctx: Arc<Mutex<dyn Reader>>) -> InverterVersion {
+        let model = ctx
+            .lock()
+            .await
+            .read_holding_registers(MODEL_REGISTER, 1)
+            .await;
+        let model = match model {
+            Ok(raw) => Some(raw[0].to_string()),
+            Err(e) => {
+                eprintln!("could not read the inverter model: {}", e);
+                None
+            }
+        };
+        let firmware = match FIRMWARE.read(ctx).await {
+            Ok(firmware) => Some(firmware),
+            Err(e) => {
+                eprintln!("could not read the inverter firmware version: {}", e);
+                None
+            }
+        };
+        InverterVersion { model, firmware }
+    }
+
+    /// `model` and `firmware` labels for every metric, with placeholders for either that
+    /// couldn't be read.
+    fn labels(&self) -> [(String, String); 2] {
+        let label =
+            |value: &Option<String>| value.as_deref().unwrap_or(UNKNOWN_IDENTITY).to_string();
+        [
+            ("model".to_string(), label(&self.model)),
+            ("firmware".to_string(), label(&self.firmware)),
+        ]
+    }
+}
+
+/// Label names the exporter's own metrics already have, which a configured label can't
+/// reuse. `le` and `quantile` are added by Prometheus to histograms and summaries.
+const RESERVED_LABELS: [&str; 7] = ["sensor", "phase", "code", "flag", "cell", "le", "quantile"];
+
 pub fn origin_url(addr: ([u8; 4], u16)) -> String {
     let host = addr.0.map(|i| i.to_string()).join(".");
     format!("http://{}:{}", host, addr.1)
 }
 
-async fn metrics_handler() -> Result<impl Reply, Rejection> {
-    let encoder = prometheus::TextEncoder::new();
+/// Whether an `Accept-Encoding` header allows a gzip response, ie. lists gzip without
+/// ruling it out with `q=0`.
+fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    accept_encoding.is_some_and(|header| {
+        header.split(',').any(|encoding| {
+            let mut params = encoding.split(';').map(str::trim);
+            params
+                .next()
+                .is_some_and(|name| name.eq_ignore_ascii_case("gzip"))
+                && !params.any(|param| {
+                    param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+                })
+        })
+    })
+}
 
-    let mut buffer = Vec::new();
-    if let Err(e) = encoder.encode(&REGISTRY.gather(), &mut buffer) {
-        eprintln!("could not encode custom metrics: {}", e);
-    };
-    let mut res = match String::from_utf8(buffer.clone()) {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("custom metrics could not be from_utf8'd: {}", e);
-            String::default()
+fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+async fn metrics_handler(
+    registry: Registry,
+    labels: Vec<(String, String)>,
+    scrape_collector: Option<ScrapeCollector>,
+    accept_encoding: Option<String>,
+    authorization: Option<String>,
+    metrics_auth: Option<MetricsAuth>,
+) -> Result<warp::reply::Response, Rejection> {
+    if let Some(auth) = metrics_auth {
+        if !auth.allows(authorization.as_deref()) {
+            let mut response = unauthorized();
+            if let MetricsAuth::Basic { .. } = auth {
+                response.headers_mut().insert(
+                    warp::http::header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static("Basic realm=\"metrics\""),
+                );
+            }
+            return Ok(response);
         }
-    };
-    buffer.clear();
+    }
+    if let Some(collector) = scrape_collector {
+        collector.collect(false).await;
+    }
+
+    // A family registered in both registries would otherwise be emitted twice, which some
+    // parsers reject. The custom registry takes precedence.
+    let mut families = registry.gather();
+    let custom_names: HashSet<String> = families.iter().map(|f| f.get_name().to_owned()).collect();
+    families.extend(
+        prometheus::gather()
+            .into_iter()
+            .filter(|f| !custom_names.contains(f.get_name())),
+    );
+
+    for family in families.iter_mut() {
+        for metric in family.mut_metric().iter_mut() {
+            for (name, value) in labels.iter() {
+                let mut label = LabelPair::new();
+                label.set_name(name.clone());
+                label.set_value(value.clone());
+                metric.mut_label().push(label);
+            }
+        }
+    }
 
+    let encoder = prometheus::TextEncoder::new();
     let mut buffer = Vec::new();
-    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
-        eprintln!("could not encode prometheus metrics: {}", e);
+    if let Err(e) = encoder.encode(&families, &mut buffer) {
+        // Partial output would look to Prometheus like the missing series had vanished.
+        eprintln!("could not encode metrics: {}", e);
+        return Ok(warp::reply::with_status(
+            "INTERNAL_SERVER_ERROR".to_string(),
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response());
     };
-    let res_custom = match String::from_utf8(buffer.clone()) {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("prometheus metrics could not be from_utf8'd: {}", e);
-            String::default()
+
+    // The metrics compress well, which matters to scrapes over slow links.
+    if accepts_gzip(accept_encoding.as_deref()) {
+        match gzip(&buffer) {
+            Ok(compressed) => {
+                let mut response = warp::reply::Response::new(compressed.into());
+                let headers = response.headers_mut();
+                headers.insert(
+                    warp::http::header::CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; charset=utf-8"),
+                );
+                headers.insert(
+                    warp::http::header::CONTENT_ENCODING,
+                    HeaderValue::from_static("gzip"),
+                );
+                return Ok(response);
+            }
+            Err(e) => eprintln!("could not compress metrics: {}", e),
         }
-    };
-    buffer.clear();
+    }
+    Ok(warp::reply::with_header(
+        buffer,
+        warp::http::header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    )
+    .into_response())
+}
 
-    res.push_str(&res_custom);
-    Ok(res)
+/// Which build of the exporter is running, and with which sensors, for telling the
+/// devices in a fleet apart.
+async fn version_handler(
+    sensors: HashMap<String, SensorTypes<'_>>,
+    sensor_map_source: String,
+    inverter_version: InverterVersion,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&VersionInfo {
+        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: option_env!("GIT_SHA").map(str::to_string),
+        sensor_map_source,
+        sensor_count: sensors.len(),
+        model: inverter_version.model,
+        firmware: inverter_version.firmware,
+    }))
+}
+
+async fn health_handler(
+    connection_status: ConnectionStatus,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&HealthStatus::from(&connection_status)))
 }
 
 async fn healthcheck_handler() -> Result<impl warp::Reply, warp::Rejection> {
@@ -77,104 +799,3942 @@ pub async fn sensor_get_handler(
     sensor_name: String,
     ctx: Arc<Mutex<Context>>,
     sensors: HashMap<String, SensorTypes<'_>>,
-) -> Result<impl warp::Reply, warp::Rejection> {
+    cache: SensorCache,
+    read_throttle: Duration,
+    cached_reads_only: bool,
+) -> Result<warp::reply::Response, warp::Rejection> {
     if let Some(sensor) = sensors.get(&sensor_name) {
-        let result = sensor.read(ctx).await;
-        match result {
-            Ok(res) => Ok(warp::reply::with_status(res, warp::http::StatusCode::OK)),
-            Err(_) => Ok(warp::reply::with_status(
-                "INTERNAL_SERVER_ERROR".to_string(),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-            )),
+        // The inverter would only answer with an exception.
+        if !sensor.is_readable() {
+            return Ok(warp::reply::with_header(
+                warp::reply::with_status(
+                    "METHOD_NOT_ALLOWED".to_string(),
+                    warp::http::StatusCode::METHOD_NOT_ALLOWED,
+                ),
+                "allow",
+                "POST",
+            )
+            .into_response());
+        }
+        // Don't hit the bus again if the sensor was read recently.
+        if let Some(value) = cache.get_fresh(&sensor_name, read_throttle) {
+            let value = sensor.display_value(value);
+            return Ok(
+                warp::reply::with_header(value.to_string(), CACHED_HEADER, "true").into_response(),
+            );
+        }
+        if cached_reads_only {
+            return Ok(match cache.get(&sensor_name) {
+                Some(value) => {
+                    let value = sensor.display_value(value);
+                    warp::reply::with_header(value.to_string(), CACHED_HEADER, "true")
+                        .into_response()
+                }
+                // The data collector hasn't got to it yet.
+                None => warp::reply::with_status(
+                    "NOT YET READ".to_string(),
+                    warp::http::StatusCode::SERVICE_UNAVAILABLE,
+                )
+                .into_response(),
+            });
         }
+
+        // The id is in the response, so a failure can be found in the logs.
+        let id = CorrelationId::next();
+        let response = id
+            .scope(async {
+                match sensor.read_value(ctx).await {
+                    Ok(value) => {
+                        cache.insert(&sensor_name, value.clone());
+                        let value = sensor.display_value(value);
+                        warp::reply::with_status(value.to_string(), warp::http::StatusCode::OK)
+                            .into_response()
+                    }
+                    Err(e) => {
+                        log(format_args!("could not read {}: {}", sensor_name, e));
+                        warp::reply::with_status(
+                            "INTERNAL_SERVER_ERROR".to_string(),
+                            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                        )
+                        .into_response()
+                    }
+                }
+            })
+            .await;
+        Ok(warp::reply::with_header(response, CORRELATION_HEADER, id.to_string()).into_response())
     } else {
-        Ok(warp::reply::with_status(
-            "NOT FOUND".to_string(),
-            warp::http::StatusCode::NOT_FOUND,
-        ))
+        Ok(
+            warp::reply::with_status("NOT FOUND".to_string(), warp::http::StatusCode::NOT_FOUND)
+                .into_response(),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SensorsQuery {
+    /// Comma separated sensor slugs. Without these, every sensor is listed instead.
+    slugs: Option<String>,
+}
+
+/// Every sensor, with its unit and last known value, without touching the bus. Sensors
+/// that haven't been read yet have a `null` value.
+fn sensor_listing(
+    sensors: &HashMap<String, SensorTypes<'_>>,
+    cache: &SensorCache,
+) -> BTreeMap<String, SensorListing> {
+    sensors
+        .iter()
+        .map(|(slug, sensor)| {
+            let listing = SensorListing {
+                unit: sensor.display_unit().map(str::to_string),
+                writable: sensor.is_writable(),
+                value: cache.get(slug).map(|value| sensor.display_value(value)),
+            };
+            (slug.clone(), listing)
+        })
+        .collect()
+}
+
+/// Read several sensors at once, as a JSON object of slug to value. Sensors that can't be
+/// read get an `{"error": ...}` in place of their value, rather than failing the request.
+/// Without any slugs, this lists every sensor instead.
+pub async fn sensors_get_handler(
+    query: SensorsQuery,
+    ctx: Arc<Mutex<Context>>,
+    sensors: HashMap<String, SensorTypes<'_>>,
+    cache: SensorCache,
+    read_throttle: Duration,
+    cached_reads_only: bool,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let slugs = match query.slugs {
+        Some(slugs) => slugs,
+        None => return Ok(warp::reply::json(&sensor_listing(&sensors, &cache)).into_response()),
+    };
+
+    let mut values = BTreeMap::new();
+    let mut live_sensors = Vec::new();
+    for slug in slugs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match sensors.get(slug) {
+            Some(sensor) if !sensor.is_readable() => {
+                values.insert(slug.to_owned(), SensorReading::write_only());
+            }
+            Some(sensor) => match cache.get_fresh(slug, read_throttle) {
+                Some(value) => {
+                    values.insert(slug.to_owned(), SensorReading::Value(value));
+                }
+                None if cached_reads_only => {
+                    let reading = match cache.get(slug) {
+                        Some(value) => SensorReading::Value(value),
+                        None => SensorReading::not_yet_read(),
+                    };
+                    values.insert(slug.to_owned(), reading);
+                }
+                None => live_sensors.push((slug, sensor)),
+            },
+            None => {
+                values.insert(slug.to_owned(), SensorReading::not_found());
+            }
+        }
+    }
+
+    // Only requests that touch the bus get an id, for finding their reads in the logs.
+    let id = (!live_sensors.is_empty()).then(CorrelationId::next);
+    if let Some(id) = id {
+        id.scope(async {
+            let registers = live_sensors
+                .iter()
+                .flat_map(|(_, sensor)| sensor.registers().iter().copied())
+                .collect();
+            let snapshot = RegisterSnapshot::read(ctx.clone(), registers)
+                .await
+                .map(RegisterSnapshot::into_context);
+            if let Err(e) = &snapshot {
+                log(format_args!("could not read the sensors' registers: {}", e));
+            }
+            for (slug, sensor) in live_sensors {
+                // Sensors that don't read holding registers can't be served from the snapshot.
+                let source = match (sensor.registers(), &snapshot) {
+                    ([], _) => ctx.clone(),
+                    (_, Ok(snapshot)) => snapshot.clone(),
+                    (_, Err(e)) => {
+                        values.insert(slug.to_owned(), SensorReading::from_error(e));
+                        continue;
+                    }
+                };
+                let value = match sensor.read_value(source).await {
+                    Ok(value) => {
+                        cache.insert(slug, value.clone());
+                        SensorReading::Value(value)
+                    }
+                    Err(e) => {
+                        log(format_args!("could not read {}: {}", slug, e));
+                        SensorReading::from_error(&*e)
+                    }
+                };
+                values.insert(slug.to_owned(), value);
+            }
+        })
+        .await;
+    }
+
+    // Cached and live values alike are in the sensors' base units until here.
+    for (slug, reading) in values.iter_mut() {
+        if let (SensorReading::Value(value), Some(sensor)) = (&*reading, sensors.get(slug)) {
+            *reading = SensorReading::Value(sensor.display_value(value.clone()));
+        }
     }
+    let response = warp::reply::json(&values).into_response();
+    Ok(match id {
+        Some(id) => {
+            warp::reply::with_header(response, CORRELATION_HEADER, id.to_string()).into_response()
+        }
+        None => response,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct WriteQuery {
+    /// Report the write that would be made rather than making it.
+    #[serde(default)]
+    dry_run: bool,
 }
 
 pub async fn sensor_post_handler(
     sensor_name: String,
+    query: WriteQuery,
     val: Bytes,
+    authorized: bool,
     ctx: Arc<Mutex<Context>>,
     sensors: HashMap<String, SensorTypes<'_>>,
-) -> Result<impl warp::Reply, warp::Rejection> {
+    cache: SensorCache,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if !authorized {
+        return Ok(unauthorized());
+    }
     if let Some(sensor) = sensors.get(&sensor_name) {
-        match sensor
-            .write(
-                ctx.clone(),
-                AtomicU16::new(std::str::from_utf8(&val).unwrap().parse::<u16>().unwrap()),
+        // Turn read-only sensors away before the value is parsed or the bus touched.
+        if !sensor.is_writable() {
+            return Ok(warp::reply::with_status(
+                "METHOD_NOT_ALLOWED".to_string(),
+                warp::http::StatusCode::METHOD_NOT_ALLOWED,
             )
-            .await
-        {
-            Ok(_) => Ok(warp::reply::reply()),
-            Err(_) => Err(warp::reject()),
+            .into_response());
+        }
+
+        let Some(value) = std::str::from_utf8(&val)
+            .ok()
+            .and_then(|v| v.parse::<u16>().ok())
+        else {
+            return Ok(warp::reply::with_status(
+                "BAD REQUEST".to_string(),
+                warp::http::StatusCode::BAD_REQUEST,
+            )
+            .into_response());
+        };
+        // A dry run is turned away for the same reasons as the write it stands in for.
+        if query.dry_run {
+            return match sensor.planned_write(value) {
+                Ok(write) => Ok(warp::reply::json(&PlannedWrite::from(&write)).into_response()),
+                Err(e) => write_error(e.as_ref()),
+            };
+        }
+
+        match sensor.write(ctx.clone(), AtomicU16::new(value)).await {
+            Ok(_) => {
+                cache.remove(&sensor_name);
+                Ok(warp::reply::reply().into_response())
+            }
+            Err(e) => write_error(e.as_ref()),
         }
     } else {
         Err(warp::reject())
     }
 }
 
-pub struct Server {
-    pub(crate) _join_handle: tokio::task::JoinHandle<()>,
+/// The response to a write the sensor refused, or that failed.
+fn write_error(e: &(dyn Error + 'static)) -> Result<warp::reply::Response, warp::Rejection> {
+    match e.downcast_ref::<SensorError>() {
+        Some(SensorError::RateLimited(retry_after)) => Ok(rate_limited(*retry_after)),
+        Some(SensorError::NotAllowed | SensorError::OutOfRange) => Ok(not_allowed()),
+        Some(SensorError::IsNotMut) => Ok(warp::reply::with_status(
+            "METHOD_NOT_ALLOWED".to_string(),
+            warp::http::StatusCode::METHOD_NOT_ALLOWED,
+        )
+        .into_response()),
+        // Binary sensors refuse anything but 0 and 1.
+        _ if e
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::Unsupported) =>
+        {
+            Ok(not_allowed())
+        }
+        _ => Err(warp::reject()),
+    }
 }
 
-pub async fn wait_for_healthcheck(address: Address) {
-    let deadline = Instant::now() + START_TIMEOUT;
+/// For a write too soon after the last, to be retried after `retry_after`.
+fn rate_limited(retry_after: Duration) -> warp::reply::Response {
+    // Retry-After is in whole seconds, so round up to not invite an early retry.
+    let retry_after = retry_after.as_secs() + (retry_after.subsec_nanos() > 0) as u64;
+    warp::reply::with_header(
+        warp::reply::with_status(
+            "TOO_MANY_REQUESTS".to_string(),
+            warp::http::StatusCode::TOO_MANY_REQUESTS,
+        ),
+        "retry-after",
+        retry_after.to_string(),
+    )
+    .into_response()
+}
 
-    while Instant::now() <= deadline {
-        if let Ok(res) = reqwest::get(origin_url(address) + "/api/healthcheck").await {
-            if res.status() == StatusCode::OK {
-                return;
-            }
+/// For a write of a value the sensor doesn't allow.
+fn not_allowed() -> warp::reply::Response {
+    warp::reply::with_status(
+        "UNPROCESSABLE_ENTITY".to_string(),
+        warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+    )
+    .into_response()
+}
+
+pub async fn sensor_adjust_handler(
+    sensor_name: String,
+    val: Bytes,
+    authorized: bool,
+    ctx: Arc<Mutex<Context>>,
+    sensors: HashMap<String, SensorTypes<'_>>,
+    cache: SensorCache,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if !authorized {
+        return Ok(unauthorized());
+    }
+    let Some(sensor) = sensors.get(&sensor_name) else {
+        return Ok(warp::reply::with_status(
+            "NOT FOUND".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        )
+        .into_response());
+    };
+    // Turn read-only sensors away before the delta is parsed or the bus touched.
+    if !sensor.is_writable() {
+        return Ok(warp::reply::with_status(
+            "METHOD_NOT_ALLOWED".to_string(),
+            warp::http::StatusCode::METHOD_NOT_ALLOWED,
+        )
+        .into_response());
+    }
+    let Some(delta) = std::str::from_utf8(&val)
+        .ok()
+        .and_then(|v| v.trim().parse::<i64>().ok())
+    else {
+        return Ok(warp::reply::with_status(
+            "BAD REQUEST".to_string(),
+            warp::http::StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    };
+
+    match sensor.adjust(ctx, delta).await {
+        Ok(value) => {
+            cache.remove(&sensor_name);
+            Ok(
+                warp::reply::with_status(value.to_string(), warp::http::StatusCode::OK)
+                    .into_response(),
+            )
         }
-        tokio::time::sleep(Duration::from_millis(50)).await;
+        // Anything the sensor didn't refuse itself failed on the way to the inverter.
+        Err(e) => write_error(e.as_ref()).or_else(|_| {
+            Ok(warp::reply::with_status(
+                "INTERNAL_SERVER_ERROR".to_string(),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response())
+        }),
     }
-    panic!("Server did not become available.");
 }
 
-impl Server {
-    pub async fn new(
-        ctx: Arc<Mutex<Context>>,
-        address: Address,
-        sensors: HashMap<String, SensorTypes<'static>>,
-    ) -> Result<Server, Box<dyn Error>> {
-        tokio::task::spawn(data_collector(sensors.clone(), ctx.clone()));
+/// Whether the request's `authorization` header holds the API token, if there is one.
+fn is_authorized(authorization: Option<String>, api_token: Option<String>) -> bool {
+    match api_token {
+        Some(token) => authorization == Some(format!("Bearer {}", token)),
+        None => true,
+    }
+}
 
-        let sensors_filter = warp::any().map(move || sensors.clone());
-        let modbus_client_ctx_filter = warp::any().map(move || ctx.clone());
+/// The credentials scrapes of `/metrics` must give, kept apart from the API token so
+/// Prometheus can be let in without also being able to write to the inverter, eg.
+/// ```toml
+/// [network.metrics_auth]
+/// username = "prometheus"
+/// password = "hunter2"
+/// ```
+/// or a `token` to require as a bearer token.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum MetricsAuth {
+    Basic { username: String, password: String },
+    Bearer { token: String },
+}
 
-        let unstable_api_read = warp::path!("api" / "unstable" / String)
-            .and(warp::get())
-            .and(modbus_client_ctx_filter.clone())
-            .and(sensors_filter.clone())
-            .and_then(sensor_get_handler);
+impl MetricsAuth {
+    /// Whether the request's `authorization` header holds the credentials.
+    fn allows(&self, authorization: Option<&str>) -> bool {
+        let expected = match self {
+            MetricsAuth::Basic { username, password } => format!(
+                "Basic {}",
+                BASE64_STANDARD.encode(format!("{}:{}", username, password))
+            ),
+            MetricsAuth::Bearer { token } => format!("Bearer {}", token),
+        };
+        authorization == Some(expected.as_str())
+    }
+}
 
-        let unstable_api_write = warp::path!("api" / "unstable" / String)
-            .and(warp::post())
-            .and(warp::body::bytes())
-            .and(modbus_client_ctx_filter.clone())
-            .and(sensors_filter.clone())
-            .and_then(sensor_post_handler);
+fn unauthorized() -> warp::reply::Response {
+    warp::reply::with_status(
+        "UNAUTHORIZED".to_string(),
+        warp::http::StatusCode::UNAUTHORIZED,
+    )
+    .into_response()
+}
 
-        let healthcheck_api_route = warp::path!("api" / "healthcheck")
-            .and(warp::get())
-            .and_then(healthcheck_handler);
+#[derive(Deserialize)]
+pub struct EnergyResetQuery {
+    period: EnergyPeriod,
+    /// Must be set, so a stray request can't wipe the day's counts.
+    #[serde(default)]
+    confirm: bool,
+}
 
-        let metrics = warp::path!("metrics").and_then(metrics_handler);
+/// Zero the inverter's energy counters for a period, eg. the daily ones at midnight. The
+/// counters are the sensors marked with `energy_counter` for the period in the config.
+/// None are reset while any is held back by its write limit. Not every firmware accepts
+/// writes to the counters, so the response lists any the inverter didn't reset, with a
+/// 502. Resets are refused unless the server has an API token, which must be given as a
+/// bearer token, and the request has `confirm=true`.
+pub async fn energy_reset_handler(
+    query: EnergyResetQuery,
+    authorization: Option<String>,
+    api_token: Option<String>,
+    ctx: Arc<Mutex<Context>>,
+    sensors: HashMap<String, SensorTypes<'_>>,
+    cache: SensorCache,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if api_token.is_none() {
+        return Ok(warp::reply::with_status(
+            "FORBIDDEN".to_string(),
+            warp::http::StatusCode::FORBIDDEN,
+        )
+        .into_response());
+    }
+    if !is_authorized(authorization, api_token) {
+        return Ok(unauthorized());
+    }
+    if !query.confirm {
+        return Ok(warp::reply::with_status(
+            "BAD REQUEST".to_string(),
+            warp::http::StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    }
 
-        let routes = healthcheck_api_route
-            .or(unstable_api_read)
-            .or(unstable_api_write)
-            .or(metrics);
+    let mut counters: Vec<(&String, &SensorTypes)> = sensors
+        .iter()
+        .filter(|(_, sensor)| sensor.energy_period() == Some(query.period))
+        .collect();
+    counters.sort_by_key(|(slug, _)| *slug);
+    if counters.is_empty() {
+        return Ok(warp::reply::with_status(
+            "CONFLICT".to_string(),
+            warp::http::StatusCode::CONFLICT,
+        )
+        .into_response());
+    }
+    if let Some(retry_after) = counters
+        .iter()
+        .filter_map(|(_, sensor)| sensor.write_retry_after())
+        .max()
+    {
+        return Ok(rate_limited(retry_after));
+    }
+
+    let mut reset = Vec::new();
+    let mut failed = Vec::new();
+    for (slug, sensor) in counters {
+        match sensor.reset(ctx.clone()).await {
+            Ok(()) => {
+                cache.remove(slug);
+                reset.push(slug.clone());
+            }
+            Err(e) => {
+                eprintln!("could not reset {}: {}", slug, e);
+                failed.push(slug.clone());
+            }
+        }
+    }
+    let status = match failed.is_empty() {
+        true => warp::http::StatusCode::OK,
+        false => warp::http::StatusCode::BAD_GATEWAY,
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&EnergyReset {
+            period: query.period,
+            reset,
+            failed,
+        }),
+        status,
+    )
+    .into_response())
+}
+
+/// Run a collection cycle now, returning once it's finished. If the server has an API
+/// token, it must be given as a bearer token.
+pub async fn collect_handler(
+    authorization: Option<String>,
+    api_token: Option<String>,
+    collect_requests: mpsc::Sender<CollectRequest>,
+    backpressure_alert: Option<Duration>,
+    queue_full: IntCounter,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if !is_authorized(authorization, api_token) {
+        return Ok(unauthorized());
+    }
+
+    let (done_tx, done_rx) = oneshot::channel();
+    let queued =
+        queue_collect_request(&collect_requests, done_tx, backpressure_alert, &queue_full).await;
+    if queued.is_err() || done_rx.await.is_err() {
+        return Ok(warp::reply::with_status(
+            "SERVICE_UNAVAILABLE".to_string(),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        )
+        .into_response());
+    }
+    Ok(warp::reply::with_status("OK".to_string(), warp::http::StatusCode::OK).into_response())
+}
+
+/// The inverter's schedule. With `cached_reads_only`, this is the schedule as last read or
+/// written through the API, and the bus isn't touched.
+pub async fn schedule_get_handler(
+    ctx: Arc<Mutex<Context>>,
+    cache: ScheduleCache,
+    cached_reads_only: bool,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if cached_reads_only {
+        return Ok(match cache.get() {
+            Some(slots) => {
+                warp::reply::with_header(warp::reply::json(&slots), CACHED_HEADER, "true")
+                    .into_response()
+            }
+            None => warp::reply::with_status(
+                "NOT YET READ".to_string(),
+                warp::http::StatusCode::SERVICE_UNAVAILABLE,
+            )
+            .into_response(),
+        });
+    }
+
+    match SCHEDULE.read_slots(ctx).await {
+        Ok(slots) => {
+            cache.insert(slots.clone());
+            Ok(warp::reply::json(&slots).into_response())
+        }
+        Err(_) => Ok(warp::reply::with_status(
+            "INTERNAL_SERVER_ERROR".to_string(),
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response()),
+    }
+}
+
+pub async fn schedule_post_handler(
+    slots: Vec<ScheduleSlot>,
+    authorization: Option<String>,
+    api_token: Option<String>,
+    ctx: Arc<Mutex<Context>>,
+    cache: ScheduleCache,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if !is_authorized(authorization, api_token) {
+        return Ok(unauthorized());
+    }
+
+    let result = SCHEDULE.write_slots(ctx, &slots).await;
+    match result {
+        Ok(written) => {
+            cache.insert(written);
+            Ok(
+                warp::reply::with_status("OK".to_string(), warp::http::StatusCode::OK)
+                    .into_response(),
+            )
+        }
+        Err(e) => {
+            let status = match e.downcast_ref::<io::Error>() {
+                Some(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                    warp::http::StatusCode::BAD_REQUEST
+                }
+                _ => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Ok(warp::reply::with_status(e.to_string(), status).into_response())
+        }
+    }
+}
+
+pub async fn sensor_history_handler(
+    sensor_name: String,
+    sensors: HashMap<String, SensorTypes<'_>>,
+    history: SensorHistory,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if !sensors.contains_key(&sensor_name) {
+        return Ok(warp::reply::with_status(
+            "NOT FOUND".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        )
+        .into_response());
+    }
+
+    let readings: Vec<HistoryEntry> = history
+        .get(&sensor_name)
+        .iter()
+        .map(HistoryEntry::from)
+        .collect();
+    Ok(warp::reply::json(&readings).into_response())
+}
+
+/// The extremes of a sensor's readings over the current aggregation window. Not found if
+/// the sensor hasn't been read in the window, or windows aren't enabled.
+async fn sensor_window_handler(
+    sensor_name: String,
+    windows: Option<SensorWindows>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match windows.and_then(|windows| windows.get(&sensor_name)) {
+        Some(stats) => Ok(warp::reply::json(&WindowSummary::from(&stats)).into_response()),
+        None => Ok(warp::reply::with_status(
+            "NOT FOUND".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        )
+        .into_response()),
+    }
+}
+
+/// The faults raised and cleared most recently, oldest first.
+async fn events_handler(events: FaultEvents) -> Result<impl warp::Reply, warp::Rejection> {
+    let entries: Vec<EventEntry> = events.recent().iter().map(EventEntry::from).collect();
+    Ok(warp::reply::json(&entries))
+}
+
+/// The active faults of each fault sensor as last read, or `null` for one that hasn't been
+/// read yet. This doesn't touch the bus.
+async fn faults_handler(
+    sensors: HashMap<String, SensorTypes<'_>>,
+    cache: SensorCache,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let faults: BTreeMap<String, Option<FaultReport>> = sensors
+        .iter()
+        .filter(|(_, sensor)| matches!(sensor, SensorTypes::Fault(_)))
+        .map(|(slug, _)| {
+            let report = cache.get(slug).as_ref().and_then(FaultReport::from_value);
+            (slug.clone(), report)
+        })
+        .collect();
+    Ok(warp::reply::json(&faults))
+}
+
+/// Settings for a `Server` beyond its Modbus connection, address and sensors.
+pub struct ServerOptions {
+    /// Where to publish the readings from each collection cycle. The sensors' gauges are
+    /// only set by a `PrometheusSink`, so `/metrics` needs one.
+    pub sinks: Vec<Arc<dyn OutputSink>>,
+    /// The minimum time between live reads of a sensor through the API. Requests within
+    /// this interval are served the last known value instead. Zero disables throttling.
+    pub read_throttle: Duration,
+    /// Serve reads through the API from the last known values only, never reading the
+    /// inverter, so a burst of requests can't hold up the data collector on an RTU bus.
+    pub cached_reads_only: bool,
+    /// Where to keep values accumulated by the exporter, like integrated energy totals, so
+    /// they survive a restart.
+    pub state_file: Option<PathBuf>,
+    /// How many recent readings of each sensor to keep for the history route. Zero disables
+    /// the history.
+    pub history_depth: usize,
+    /// Track the minimum and maximum of each sensor over windows of this length, eg. a day,
+    /// exported as `<slug>_min` and `<slug>_max`.
+    pub aggregation_window: Option<Duration>,
+    /// How often the data collector reads every sensor.
+    pub collect_interval: Duration,
+    /// How long a collection cycle may take before the sensors not yet read are skipped.
+    /// Reads already under way are finished first. Defaults to the collection interval, so
+    /// cycles never pile up.
+    pub cycle_timeout: Option<Duration>,
+    /// Put each polled cycle back by up to this long, chosen at random, so exporters
+    /// started together don't all poll a shared gateway at once. Off by default.
+    pub collect_jitter: Duration,
+    /// How many reads in a row must go unanswered before the link is reported down by the
+    /// health route. Must be at least one.
+    pub degraded_after: usize,
+    /// A bearer token required by routes that act on the inverter or the collector, rather
+    /// than just reading from them.
+    pub api_token: Option<String>,
+    /// Label every metric with the inverter's serial number, read once at startup, to tell
+    /// inverters apart when scraping several exporters into one Prometheus.
+    pub serial_label: bool,
+    /// Read the inverter's model code and firmware version once at startup, for the
+    /// version route.
+    pub inverter_version: bool,
+    /// Label every metric with the inverter's model code and firmware version too, as
+    /// `model` and `firmware`. Reads them even without `inverter_version`.
+    pub version_labels: bool,
+    /// Don't poll the sensors in the background. Instead, read them all whenever `/metrics`
+    /// is scraped, at most once per collection interval.
+    pub collect_on_scrape: bool,
+    /// Where the sensors' metrics are registered, and so what `/metrics` serves. Separate
+    /// registries let several servers, eg. one per inverter, run in the same process.
+    pub registry: Registry,
+    /// Where the sensor definitions came from, eg. a config file, for the version route.
+    pub sensor_map_source: String,
+    /// Connections for the data collector to read sensors over, one sensor on each at
+    /// once, eg. several Modbus TCP connections to the inverter. API requests take one
+    /// each too, so a slow read doesn't hold up a write. Without a pool, everything goes
+    /// one at a time over the server's connection, as an RTU bus needs.
+    pub read_pool: Option<ContextPool>,
+    /// How long HTTP clients have to send a request's headers before being disconnected.
+    pub http_header_timeout: Duration,
+    /// Keep HTTP connections open between requests. Scrapers reuse them, but turning it
+    /// off stops idle clients holding a connection each.
+    pub http_keep_alive: bool,
+    /// Log collect requests that wait longer than this for the data collector to make room
+    /// for them. Waits are counted in `collect_queue_full_total` either way.
+    pub backpressure_alert: Option<Duration>,
+    /// Serve only `/metrics` and the healthcheck, leaving out the API that reads and
+    /// writes the sensors, for deployments that are only ever scraped.
+    pub metrics_only: bool,
+    /// Credentials scrapes of `/metrics` must give. Scrapes are unauthenticated by default.
+    pub metrics_auth: Option<MetricsAuth>,
+    /// Labels added to every metric, eg. the site or region, to tell fleets of inverters
+    /// apart. The names must be valid Prometheus label names, and not ones the metrics
+    /// already have, eg. `sensor`.
+    pub metric_labels: Vec<(String, String)>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> ServerOptions {
+        ServerOptions {
+            sinks: vec![Arc::new(PrometheusSink)],
+            read_throttle: Duration::ZERO,
+            cached_reads_only: false,
+            state_file: None,
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            aggregation_window: None,
+            collect_interval: COLLECT_INTERVAL,
+            cycle_timeout: None,
+            collect_jitter: Duration::ZERO,
+            degraded_after: DEGRADED_AFTER,
+            api_token: None,
+            serial_label: false,
+            inverter_version: false,
+            version_labels: false,
+            collect_on_scrape: false,
+            registry: REGISTRY.clone(),
+            sensor_map_source: BUILTIN_SENSOR_MAP.to_string(),
+            read_pool: None,
+            http_header_timeout: HTTP_HEADER_TIMEOUT,
+            http_keep_alive: true,
+            backpressure_alert: None,
+            metrics_only: false,
+            metrics_auth: None,
+            metric_labels: Vec::new(),
+        }
+    }
+}
+
+pub struct Server {
+    pub(crate) _join_handle: tokio::task::JoinHandle<()>,
+    collector_handle: tokio::task::JoinHandle<()>,
+    sensors: HashMap<String, SensorTypes<'static>>,
+    state_file: Option<StateFile>,
+    connection_status: ConnectionStatus,
+}
+
+pub async fn wait_for_healthcheck(address: Address) {
+    let deadline = Instant::now() + START_TIMEOUT;
+
+    while Instant::now() <= deadline {
+        if let Ok(res) = reqwest::get(origin_url(address) + "/api/healthcheck").await {
+            if res.status() == StatusCode::OK {
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Server did not become available.");
+}
+
+/// The parts of `ServerOptions` the routes need.
+#[derive(Clone)]
+struct RouteSettings {
+    read_throttle: Duration,
+    cached_reads_only: bool,
+    api_token: Option<String>,
+    /// Labels added to every metric served from `/metrics`.
+    metric_labels: Vec<(String, String)>,
+    /// Set when sensors are read on scrape rather than by the data collector.
+    scrape_collector: Option<ScrapeCollector>,
+    registry: Registry,
+    sensor_map_source: String,
+    /// Empty unless read at startup.
+    inverter_version: InverterVersion,
+    connection_status: ConnectionStatus,
+    windows: Option<SensorWindows>,
+    events: FaultEvents,
+    /// Log collect requests that wait longer than this for room in the collector's queue.
+    backpressure_alert: Option<Duration>,
+    /// Counts collect requests that had to wait for room in the collector's queue.
+    collect_queue_full: IntCounter,
+    /// Serve only `/metrics` and the healthcheck.
+    metrics_only: bool,
+    metrics_auth: Option<MetricsAuth>,
+    /// Connections for requests to act on the inverter over, rather than sharing `ctx`.
+    pool: Option<ContextPool>,
+}
+
+impl Default for RouteSettings {
+    fn default() -> RouteSettings {
+        RouteSettings {
+            read_throttle: Duration::ZERO,
+            cached_reads_only: false,
+            api_token: None,
+            metric_labels: Vec::new(),
+            scrape_collector: None,
+            registry: REGISTRY.clone(),
+            sensor_map_source: BUILTIN_SENSOR_MAP.to_string(),
+            inverter_version: InverterVersion::default(),
+            connection_status: ConnectionStatus::default(),
+            windows: None,
+            events: FaultEvents::default(),
+            backpressure_alert: None,
+            collect_queue_full: CollectorMetrics::default().queue_full,
+            metrics_only: false,
+            metrics_auth: None,
+            pool: None,
+        }
+    }
+}
+
+fn routes(
+    ctx: Arc<Mutex<Context>>,
+    sensors: HashMap<String, SensorTypes<'static>>,
+    cache: SensorCache,
+    history: SensorHistory,
+    collect_requests: mpsc::Sender<CollectRequest>,
+    settings: RouteSettings,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let RouteSettings {
+        read_throttle,
+        cached_reads_only,
+        api_token,
+        metric_labels,
+        scrape_collector,
+        registry,
+        sensor_map_source,
+        inverter_version,
+        connection_status,
+        windows,
+        events,
+        backpressure_alert,
+        collect_queue_full,
+        metrics_only,
+        metrics_auth,
+        pool,
+    } = settings;
+    let sensors_filter = warp::any().map(move || sensors.clone());
+    // Each request gets a connection of its own from the pool, once it touches the bus.
+    let modbus_client_ctx_filter = warp::any().map(move || match &pool {
+        Some(pool) => pool.context(),
+        None => ctx.clone(),
+    });
+    let cache_filter = warp::any().map(move || cache.clone());
+    let api_token_filter = warp::any().map(move || api_token.clone());
+    // Whether the request gave the API token, for handlers with no room for both.
+    let authorized_filter = warp::header::optional::<String>("authorization")
+        .and(api_token_filter.clone())
+        .map(is_authorized);
+
+    let unstable_api_read = warp::path!("api" / "unstable" / String)
+        .and(warp::get())
+        .and(modbus_client_ctx_filter.clone())
+        .and(sensors_filter.clone())
+        .and(cache_filter.clone())
+        .and(warp::any().map(move || read_throttle))
+        .and(warp::any().map(move || cached_reads_only))
+        .and_then(sensor_get_handler);
+
+    let unstable_api_write = warp::path!("api" / "unstable" / String)
+        .and(warp::post())
+        .and(warp::query::<WriteQuery>())
+        .and(warp::body::content_length_limit(MAX_WRITE_BODY))
+        .and(warp::body::bytes())
+        .and(authorized_filter.clone())
+        .and(modbus_client_ctx_filter.clone())
+        .and(sensors_filter.clone())
+        .and(cache_filter.clone())
+        .and_then(sensor_post_handler);
+
+    let unstable_api_adjust = warp::path!("api" / "unstable" / String / "adjust")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_WRITE_BODY))
+        .and(warp::body::bytes())
+        .and(authorized_filter.clone())
+        .and(modbus_client_ctx_filter.clone())
+        .and(sensors_filter.clone())
+        .and(cache_filter.clone())
+        .and_then(sensor_adjust_handler);
+
+    let sensors_read = warp::path!("api" / "v1" / "sensors")
+        .and(warp::get())
+        .and(warp::query::<SensorsQuery>())
+        .and(modbus_client_ctx_filter.clone())
+        .and(sensors_filter.clone())
+        .and(cache_filter.clone())
+        .and(warp::any().map(move || read_throttle))
+        .and(warp::any().map(move || cached_reads_only))
+        .and_then(sensors_get_handler);
+
+    let history_route = warp::path!("api" / "v1" / "sensors" / String / "history")
+        .and(warp::get())
+        .and(sensors_filter.clone())
+        .and(warp::any().map(move || history.clone()))
+        .and_then(sensor_history_handler);
+
+    let window_route = warp::path!("api" / "v1" / "sensors" / String / "history" / "window")
+        .and(warp::get())
+        .and(warp::any().map(move || windows.clone()))
+        .and_then(sensor_window_handler);
+
+    let faults_route = warp::path!("api" / "v1" / "faults")
+        .and(warp::get())
+        .and(sensors_filter.clone())
+        .and(cache_filter.clone())
+        .and_then(faults_handler);
+
+    let events_route = warp::path!("api" / "v1" / "events")
+        .and(warp::get())
+        .and(warp::any().map(move || events.clone()))
+        .and_then(events_handler);
+
+    let schedule_cache = ScheduleCache::default();
+    let schedule_cache_filter = warp::any().map(move || schedule_cache.clone());
+    let schedule_read = warp::path!("api" / "v1" / "schedule")
+        .and(warp::get())
+        .and(modbus_client_ctx_filter.clone())
+        .and(schedule_cache_filter.clone())
+        .and(warp::any().map(move || cached_reads_only))
+        .and_then(schedule_get_handler);
+
+    let schedule_write = warp::path!("api" / "v1" / "schedule")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(api_token_filter.clone())
+        .and(modbus_client_ctx_filter.clone())
+        .and(schedule_cache_filter)
+        .and_then(schedule_post_handler);
+
+    let energy_reset = warp::path!("api" / "v1" / "energy" / "reset")
+        .and(warp::post())
+        .and(warp::query::<EnergyResetQuery>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(api_token_filter.clone())
+        .and(modbus_client_ctx_filter.clone())
+        .and(sensors_filter.clone())
+        .and(cache_filter.clone())
+        .and_then(energy_reset_handler);
+
+    let collect_route = warp::path!("api" / "v1" / "collect")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(api_token_filter)
+        .and(warp::any().map(move || collect_requests.clone()))
+        .and(warp::any().map(move || backpressure_alert))
+        .and(warp::any().map(move || collect_queue_full.clone()))
+        .and_then(collect_handler);
+
+    let version_route = warp::path!("api" / "v1" / "version")
+        .and(warp::get())
+        .and(sensors_filter.clone())
+        .and(warp::any().map(move || sensor_map_source.clone()))
+        .and(warp::any().map(move || inverter_version.clone()))
+        .and_then(version_handler);
+
+    let health_route = warp::path!("api" / "v1" / "health")
+        .and(warp::get())
+        .and(warp::any().map(move || connection_status.clone()))
+        .and_then(health_handler);
+
+    let healthcheck_api_route = warp::path!("api" / "healthcheck")
+        .and(warp::get())
+        .and_then(healthcheck_handler);
+
+    let metrics = warp::path!("metrics")
+        .and(warp::any().map(move || registry.clone()))
+        .and(warp::any().map(move || metric_labels.clone()))
+        .and(warp::any().map(move || scrape_collector.clone()))
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::any().map(move || metrics_auth.clone()))
+        .and_then(metrics_handler);
+
+    // Every route but the healthcheck and metrics reads from or acts on the inverter.
+    let api_enabled = warp::any()
+        .and_then(move || async move {
+            match metrics_only {
+                true => Err(warp::reject::not_found()),
+                false => Ok(()),
+            }
+        })
+        .untuple_one();
+    let api = unstable_api_read
+        .or(unstable_api_write)
+        .or(unstable_api_adjust)
+        .or(sensors_read)
+        .or(history_route)
+        .or(window_route)
+        .or(faults_route)
+        .or(events_route)
+        .or(schedule_read)
+        .or(schedule_write)
+        .or(energy_reset)
+        .or(collect_route)
+        .or(version_route)
+        .or(health_route);
+
+    healthcheck_api_route.or(api_enabled.and(api)).or(metrics)
+}
+
+impl Server {
+    pub async fn new(
+        ctx: Arc<Mutex<Context>>,
+        address: Address,
+        sensors: HashMap<String, SensorTypes<'static>>,
+    ) -> Result<Server, Box<dyn Error>> {
+        Server::new_with_options(ctx, address, sensors, ServerOptions::default()).await
+    }
+
+    pub async fn new_with_options(
+        ctx: Arc<Mutex<Context>>,
+        address: Address,
+        sensors: HashMap<String, SensorTypes<'static>>,
+        options: ServerOptions,
+    ) -> Result<Server, Box<dyn Error>> {
+        if let Some((name, _)) = options
+            .metric_labels
+            .iter()
+            .find(|(name, _)| !is_label_name(name))
+        {
+            return Err(invalid_input(format!("{} is not a valid label name", name)).into());
+        }
+        // A metric with the same label twice is rejected by Prometheus, failing the scrape.
+        let mut label_names: HashSet<&str> = RESERVED_LABELS.iter().copied().collect();
+        if options.serial_label {
+            label_names.insert("serial");
+        }
+        if options.version_labels {
+            label_names.extend(["model", "firmware"]);
+        }
+        if let Some((name, _)) = options
+            .metric_labels
+            .iter()
+            .find(|(name, _)| !label_names.insert(name))
+        {
+            return Err(invalid_input(format!("{} is already a label name", name)).into());
+        }
+        if options.degraded_after == 0 {
+            return Err(invalid_input("degraded_after must be at least 1".to_string()).into());
+        }
+        if options.collect_interval.is_zero() {
+            return Err(
+                invalid_input("collect_interval must be more than zero".to_string()).into(),
+            );
+        }
+        if options
+            .cycle_timeout
+            .is_some_and(|timeout| timeout > options.collect_interval)
+        {
+            return Err(invalid_input(
+                "cycle_timeout can't be longer than collect_interval".to_string(),
+            )
+            .into());
+        }
+        let cache = SensorCache::default();
+        let mut sinks = options.sinks;
+        sinks.push(Arc::new(cache.clone()));
+        let history = SensorHistory::new(options.history_depth);
+        sinks.push(Arc::new(history.clone()));
+        let windows = options
+            .aggregation_window
+            .map(|length| SensorWindows::new(length, options.registry.clone()));
+        if let Some(windows) = &windows {
+            sinks.push(Arc::new(windows.clone()));
+        }
+        let events = FaultEvents::new(
+            sensors
+                .iter()
+                .filter(|(_, sensor)| matches!(sensor, SensorTypes::Fault(_)))
+                .map(|(slug, _)| slug.clone()),
+        );
+        sinks.push(Arc::new(events.clone()));
+
+        let state_file = options.state_file.map(StateFile::new);
+        if let Some(file) = &state_file {
+            file.load()?.restore(&sensors);
+            sinks.push(Arc::new(StateSink::new(file.clone(), sensors.clone())));
+        }
+
+        let mut metric_labels = options.metric_labels;
+        if options.serial_label {
+            metric_labels.push(("serial".to_string(), read_serial(ctx.clone()).await));
+        }
+        let mut inverter_version = InverterVersion::default();
+        if options.inverter_version || options.version_labels {
+            inverter_version = InverterVersion::read(ctx.clone()).await;
+        }
+        if options.version_labels {
+            metric_labels.extend(inverter_version.labels());
+        }
+
+        let metrics = CollectorMetrics::new_in(&options.registry);
+
+        let connection_status = ConnectionStatus::degraded_after(options.degraded_after);
+        #[cfg(feature = "systemd")]
+        if let Some(notifier) =
+            crate::systemd::SystemdNotifier::from_env(connection_status.clone())?
+        {
+            sinks.push(Arc::new(notifier));
+        }
+        let readers = match options.read_pool.clone() {
+            Some(pool) => Readers::Pool(pool),
+            None => Readers::Shared(ctx.clone()),
+        };
+        let cycle_timeout = options.cycle_timeout.unwrap_or(options.collect_interval);
+        let (collect_tx, collect_rx) = mpsc::channel(8);
+        let mut scrape_collector = None;
+        let collector_handle;
+        if options.collect_on_scrape {
+            let collector = ScrapeCollector::new(
+                sensors.clone(),
+                readers,
+                sinks,
+                options.collect_interval,
+                cycle_timeout,
+                connection_status.clone(),
+                metrics.clone(),
+            );
+            collector_handle =
+                tokio::task::spawn(scrape_collect_requests(collector.clone(), collect_rx));
+            scrape_collector = Some(collector);
+        } else {
+            let (sensors, status) = (sensors.clone(), connection_status.clone());
+            let metrics = metrics.clone();
+            let schedule = CollectSchedule {
+                cycle_timeout,
+                jitter: options.collect_jitter,
+                ..CollectSchedule::new(options.collect_interval)
+            };
+            let collect_rx = Arc::new(Mutex::new(collect_rx));
+            let restarts = metrics.restarts.clone();
+            collector_handle = tokio::task::spawn(supervise(restarts, move || {
+                data_collector(
+                    sensors.clone(),
+                    readers.clone(),
+                    sinks.clone(),
+                    schedule,
+                    collect_rx.clone(),
+                    status.clone(),
+                    metrics.clone(),
+                )
+            }));
+        }
+
+        let routes = routes(
+            ctx,
+            sensors.clone(),
+            cache,
+            history,
+            collect_tx,
+            RouteSettings {
+                read_throttle: options.read_throttle,
+                cached_reads_only: options.cached_reads_only,
+                api_token: options.api_token,
+                metric_labels,
+                scrape_collector,
+                registry: options.registry,
+                sensor_map_source: options.sensor_map_source,
+                inverter_version,
+                connection_status: connection_status.clone(),
+                windows,
+                events,
+                backpressure_alert: options.backpressure_alert,
+                collect_queue_full: metrics.queue_full.clone(),
+                metrics_only: options.metrics_only,
+                metrics_auth: options.metrics_auth,
+                pool: options.read_pool,
+            },
+        );
+
+        // Served through hyper directly, as warp doesn't expose its connection settings.
+        let service = warp::service(routes);
+        let make_service = make_service_fn(move |_| {
+            let service = service.clone();
+            async move { Ok::<_, Infallible>(service) }
+        });
+        let http_server = hyper::Server::try_bind(&SocketAddr::from(address))?
+            .http1_header_read_timeout(options.http_header_timeout)
+            .http1_keepalive(options.http_keep_alive)
+            .serve(make_service);
 
         let server = Server {
-            _join_handle: tokio::spawn(async move { warp::serve(routes).run(address).await }),
+            _join_handle: tokio::spawn(async move {
+                if let Err(e) = http_server.await {
+                    eprintln!("http server failed: {}", e);
+                }
+            }),
+            collector_handle,
+            sensors,
+            state_file,
+            connection_status,
         };
         wait_for_healthcheck(address).await;
 
         Ok(server)
     }
+
+    /// Stop serving and collecting. The address can be bound again once this returns.
+    pub async fn shutdown(self) {
+        self.collector_handle.abort();
+        self._join_handle.abort();
+        let _ = self._join_handle.await;
+    }
+
+    /// The state of the link to the inverter, as seen by the data collector.
+    pub fn connection_status(&self) -> ConnectionStatus {
+        self.connection_status.clone()
+    }
+
+    /// Save the current state to the state file, if there is one, eg. on shutdown.
+    pub fn save_state(&self) -> io::Result<()> {
+        match &self.state_file {
+            Some(file) => file.save(&State::from_sensors(&self.sensors)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{modbus_context, ClientMock, Context};
+    use crate::sensor::{
+        BasicSensor, BinarySensor, FaultSensor, IntegratedEnergySensor, Sensor, SensorValue,
+        WriteFunction,
+    };
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::io::Read;
+    use tokio_modbus::prelude::{Request, Response};
+
+    #[derive(Default)]
+    struct RecordingSink {
+        readings: std::sync::Mutex<Vec<Reading>>,
+    }
+
+    #[async_trait]
+    impl OutputSink for RecordingSink {
+        async fn publish(&self, readings: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+            self.readings.lock().unwrap().extend_from_slice(readings);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_once_sensors_are_only_collected_once() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(610, 8000);
+        client.set_register(611, 1);
+        let read_counts = client.read_counts();
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "once_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new("Once Sensor", &[610], 1, false).read_once(),
+            )),
+        );
+        sensors.insert(
+            "polled_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Polled Sensor", &[611], 1, false))),
+        );
+
+        let collector = tokio::spawn(data_collector(
+            sensors,
+            Readers::Shared(ctx),
+            vec![],
+            CollectSchedule::new(COLLECT_INTERVAL),
+            Arc::new(Mutex::new(mpsc::channel(1).1)),
+            ConnectionStatus::default(),
+            CollectorMetrics::default(),
+        ));
+        // Enough time for the initial cycle plus three more.
+        tokio::time::sleep(COLLECT_INTERVAL * 3 + Duration::from_secs(1)).await;
+        collector.abort();
+
+        let read_counts = read_counts.lock().unwrap();
+        assert_eq!(read_counts.get(&610), Some(&1));
+        assert_eq!(read_counts.get(&611), Some(&4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_once_sensors_are_read_again_until_a_read_succeeds() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(612, 8000);
+        client.set_register(613, 1);
+        // The once sensor is read first, and its first read fails.
+        client.set_next_response(Err(io::Error::other("gateway unreachable")));
+        let read_counts = client.read_counts();
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "first_once_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new("First Once Sensor", &[612], 1, false).read_once(),
+            )),
+        );
+        sensors.insert(
+            "second_polled_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Second Polled Sensor",
+                &[613],
+                1,
+                false,
+            ))),
+        );
+
+        let collector = tokio::spawn(data_collector(
+            sensors,
+            Readers::Shared(ctx),
+            vec![],
+            CollectSchedule::new(COLLECT_INTERVAL),
+            Arc::new(Mutex::new(mpsc::channel(1).1)),
+            ConnectionStatus::default(),
+            CollectorMetrics::default(),
+        ));
+        // Enough time for the initial cycle plus three more.
+        tokio::time::sleep(COLLECT_INTERVAL * 3 + Duration::from_secs(1)).await;
+        collector.abort();
+
+        let read_counts = read_counts.lock().unwrap();
+        assert_eq!(read_counts.get(&612), Some(&2));
+        assert_eq!(read_counts.get(&613), Some(&4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn polled_cycles_are_jittered() {
+        #[derive(Default)]
+        struct CycleStarts(std::sync::Mutex<Vec<Instant>>);
+
+        #[async_trait]
+        impl OutputSink for CycleStarts {
+            async fn publish(&self, _: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+                self.0.lock().unwrap().push(Instant::now());
+                Ok(())
+            }
+        }
+
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(1140, 1);
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "jittered_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Jittered Sensor",
+                &[1140],
+                1,
+                false,
+            ))),
+        );
+        let starts = Arc::new(CycleStarts::default());
+        let jitter = Duration::from_secs(3);
+        let start = Instant::now();
+
+        let collector = tokio::spawn(data_collector(
+            sensors,
+            Readers::Shared(modbus_context(client)),
+            vec![starts.clone()],
+            CollectSchedule {
+                jitter,
+                ..CollectSchedule::new(COLLECT_INTERVAL)
+            },
+            Arc::new(Mutex::new(mpsc::channel(1).1)),
+            ConnectionStatus::default(),
+            CollectorMetrics::default(),
+        ));
+        tokio::time::sleep(COLLECT_INTERVAL * 10).await;
+        collector.abort();
+
+        // The startup cycle runs straight away, the rest up to the jitter after their tick.
+        let starts = starts.0.lock().unwrap();
+        assert_eq!(starts.len(), 10);
+        assert_eq!(starts[0], start);
+        let delays: Vec<Duration> = (1..starts.len())
+            .map(|cycle| starts[cycle] - (start + COLLECT_INTERVAL * cycle as u32))
+            .collect();
+        assert!(delays.iter().all(|&delay| delay < jitter), "{:?}", delays);
+        assert!(
+            delays.iter().any(|&delay| delay != delays[0]),
+            "{:?}",
+            delays
+        );
+    }
+
+    #[tokio::test]
+    async fn cycles_are_timed() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(1080, 3);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "timed_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Timed Sensor", &[1080], 1, false))),
+        );
+        let (collect_tx, collect_rx) = mpsc::channel(1);
+        let metrics = CollectorMetrics::default();
+
+        let collector = tokio::spawn(data_collector(
+            sensors,
+            Readers::Shared(ctx),
+            Vec::new(),
+            CollectSchedule::new(COLLECT_INTERVAL),
+            Arc::new(Mutex::new(collect_rx)),
+            ConnectionStatus::default(),
+            metrics.clone(),
+        ));
+        // Served once the startup cycle is over, with a cycle of its own.
+        let (done_tx, done_rx) = oneshot::channel();
+        collect_tx.send(done_tx).await.unwrap();
+        done_rx.await.unwrap();
+        collector.abort();
+
+        assert_eq!(metrics.cycle_duration.get_sample_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn collect_publishes_readings_to_sinks() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(600, 42);
+        client.set_register(601, 7);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "sink_sensor_a".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Sink Sensor A", &[600], 1, false))),
+        );
+        sensors.insert(
+            "sink_sensor_b".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Sink Sensor B", &[601], 1, false))),
+        );
+        let sink = Arc::new(RecordingSink::default());
+        let sinks: Vec<Arc<dyn OutputSink>> = vec![sink.clone()];
+
+        collect(
+            &sensors,
+            &Readers::Shared(ctx),
+            &sinks,
+            Instant::now() + COLLECT_INTERVAL,
+            &ConnectionStatus::default(),
+            &CollectorMetrics::default(),
+        )
+        .await;
+
+        let mut readings: Vec<(String, String)> = sink
+            .readings
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| (r.slug.clone(), r.value.to_string()))
+            .collect();
+        readings.sort();
+        assert_eq!(
+            readings,
+            vec![
+                ("sink_sensor_a".to_string(), "42".to_string()),
+                ("sink_sensor_b".to_string(), "7".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn gauges_are_set_by_the_prometheus_sink() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(602, 42);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let registry = Registry::new();
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "published_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new_in(
+                &registry,
+                "Published Sensor",
+                &[602],
+                1,
+                false,
+            ))),
+        );
+        let gauge = || registry.gather()[0].get_metric()[0].get_gauge().get_value();
+
+        for (sinks, expected) in [
+            (Vec::<Arc<dyn OutputSink>>::new(), 0.0),
+            (vec![Arc::new(PrometheusSink) as Arc<dyn OutputSink>], 42.0),
+        ] {
+            collect(
+                &sensors,
+                &Readers::Shared(ctx.clone()),
+                &sinks,
+                Instant::now() + COLLECT_INTERVAL,
+                &ConnectionStatus::default(),
+                &CollectorMetrics::default(),
+            )
+            .await;
+            assert_eq!(gauge(), expected);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sensors_are_read_in_parallel_over_a_pool() {
+        let delay = Duration::from_millis(100);
+        let registers: &[u16] = &[970, 971, 972, 973];
+        let slow_context = || {
+            let mut client = Box::<ClientMock>::default();
+            for register in registers {
+                client.set_register(*register, 1);
+            }
+            client.set_read_delay(delay);
+            modbus_context(client)
+        };
+
+        let mut sensors = HashMap::new();
+        for (i, name) in ["Pooled A", "Pooled B", "Pooled C", "Pooled D"]
+            .into_iter()
+            .enumerate()
+        {
+            sensors.insert(
+                crate::helpers::slug_name(name),
+                SensorTypes::Basic(BasicSensor(Sensor::new(name, &registers[i..=i], 1, false))),
+            );
+        }
+        let sink = Arc::new(RecordingSink::default());
+        let sinks: Vec<Arc<dyn OutputSink>> = vec![sink.clone()];
+
+        let pool = ContextPool::new((0..registers.len()).map(|_| slow_context()).collect());
+        let start = Instant::now();
+        collect(
+            &sensors,
+            &Readers::Pool(pool),
+            &sinks,
+            start + COLLECT_INTERVAL,
+            &ConnectionStatus::default(),
+            &CollectorMetrics::default(),
+        )
+        .await;
+        assert_eq!(start.elapsed(), delay);
+        assert_eq!(sink.readings.lock().unwrap().len(), registers.len());
+
+        // A single connection reads one sensor at a time.
+        let start = Instant::now();
+        collect(
+            &sensors,
+            &Readers::Shared(slow_context()),
+            &sinks,
+            start + COLLECT_INTERVAL,
+            &ConnectionStatus::default(),
+            &CollectorMetrics::default(),
+        )
+        .await;
+        assert_eq!(start.elapsed(), delay * registers.len() as u32);
+    }
+
+    #[tokio::test]
+    async fn sensors_are_read_in_priority_order() {
+        let mut client = Box::<ClientMock>::default();
+        for register in 950..954 {
+            client.set_register(register, 1);
+        }
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let mut sensors = HashMap::new();
+        for (slug, name, register, priority) in [
+            ("priority_low", "Priority Low", &[950], -1),
+            ("priority_default_b", "Priority Default B", &[951], 0),
+            ("priority_default_a", "Priority Default A", &[952], 0),
+            ("priority_high", "Priority High", &[953], 10),
+        ] {
+            sensors.insert(
+                slug.to_string(),
+                SensorTypes::Basic(BasicSensor(
+                    Sensor::new(name, register, 1, false).with_priority(priority),
+                )),
+            );
+        }
+        let sink = Arc::new(RecordingSink::default());
+        let sinks: Vec<Arc<dyn OutputSink>> = vec![sink.clone()];
+
+        collect(
+            &sensors,
+            &Readers::Shared(ctx),
+            &sinks,
+            Instant::now() + COLLECT_INTERVAL,
+            &ConnectionStatus::default(),
+            &CollectorMetrics::default(),
+        )
+        .await;
+
+        let order: Vec<String> = sink
+            .readings
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| r.slug.clone())
+            .collect();
+        assert_eq!(
+            order,
+            [
+                "priority_high",
+                "priority_default_a",
+                "priority_default_b",
+                "priority_low"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn throttled_reads_are_served_from_cache() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(620, 5);
+        let read_counts = client.read_counts();
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "throttled_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Throttled Sensor",
+                &[620],
+                1,
+                false,
+            ))),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                read_throttle: Duration::from_secs(60),
+                ..RouteSettings::default()
+            },
+        );
+
+        let res = warp::test::request()
+            .path("/api/unstable/throttled_sensor")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        assert_eq!(res.body(), "5");
+        assert!(res.headers().get(CACHED_HEADER).is_none());
+
+        for _ in 0..3 {
+            let res = warp::test::request()
+                .path("/api/unstable/throttled_sensor")
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), warp::http::StatusCode::OK);
+            assert_eq!(res.body(), "5");
+            assert_eq!(res.headers().get(CACHED_HEADER).unwrap(), "true");
+        }
+
+        assert_eq!(read_counts.lock().unwrap().get(&620), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn values_are_shown_in_their_display_unit() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(1170, 1500);
+        let registry = Registry::new();
+        let sensor = Sensor::new_in(&registry, "Display Power", &[1170], 1, false)
+            .with_unit("W")
+            .with_display_unit_in(&registry, "kW");
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "display_power".to_string(),
+            SensorTypes::Basic(BasicSensor(sensor)),
+        );
+        let cache = SensorCache::default();
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            cache.clone(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request()
+            .path("/api/unstable/display_power")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.body(), "1.5");
+        let res = warp::test::request()
+            .path("/api/v1/sensors")
+            .reply(&routes)
+            .await;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(res.body()).unwrap()["display_power"],
+            json!({"unit": "kW", "writable": false, "value": 1.5})
+        );
+
+        // Everything else keeps to the base unit.
+        assert_eq!(cache.get("display_power"), Some(SensorValue::Int(1500)));
+        let gauge = |name: &str| {
+            registry
+                .gather()
+                .into_iter()
+                .find(|family| family.get_name() == name)
+                .map(|family| family.get_metric()[0].get_gauge().get_value())
+                .unwrap()
+        };
+        assert_eq!(gauge("display_power"), 1500.0);
+        assert_eq!(gauge("display_power_kw"), 1.5);
+    }
+
+    #[tokio::test]
+    async fn cached_reads_never_touch_the_bus() {
+        let client = Box::<ClientMock>::default();
+        let read_counts = client.read_counts();
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "cached_only_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Cached Only Sensor",
+                &[1160],
+                1,
+                false,
+            ))),
+        );
+        sensors.insert(
+            "unread_cached_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Unread Cached Sensor",
+                &[1161],
+                1,
+                false,
+            ))),
+        );
+        let cache = SensorCache::default();
+        cache.insert("cached_only_sensor", SensorValue::Int(7));
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            cache,
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                cached_reads_only: true,
+                ..RouteSettings::default()
+            },
+        );
+
+        let res = warp::test::request()
+            .path("/api/unstable/cached_only_sensor")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.body(), "7");
+        assert_eq!(res.headers().get(CACHED_HEADER).unwrap(), "true");
+        let res = warp::test::request()
+            .path("/api/unstable/unread_cached_sensor")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::SERVICE_UNAVAILABLE);
+        let res = warp::test::request()
+            .path("/api/v1/sensors?slugs=cached_only_sensor,unread_cached_sensor")
+            .reply(&routes)
+            .await;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(res.body()).unwrap(),
+            json!({
+                "cached_only_sensor": 7,
+                "unread_cached_sensor": {"error": "not yet read"},
+            })
+        );
+
+        assert!(read_counts.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn state_is_restored_from_state_file() {
+        let path = std::env::temp_dir().join(format!("samsynk-state-{}.json", std::process::id()));
+        let mut state = State::default();
+        state
+            .energy_wh
+            .insert("restored_energy".to_string(), 1234.0);
+        StateFile::new(&path).save(&state).unwrap();
+
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(630, 0);
+        let sensor = IntegratedEnergySensor::new("Restored Energy", &[630], 1, false);
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "restored_energy".to_string(),
+            SensorTypes::IntegratedEnergy(sensor.clone()),
+        );
+        let options = ServerOptions {
+            state_file: Some(path.clone()),
+            ..ServerOptions::default()
+        };
+
+        let server = Server::new_with_options(
+            modbus_context(client),
+            ([127, 0, 0, 1], 8093),
+            sensors,
+            options,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(sensor.energy_wh(), 1234.0);
+        server.save_state().unwrap();
+        assert_eq!(StateFile::new(&path).load().unwrap(), state);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn slow_clients_are_disconnected() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let address = ([127, 0, 0, 1], 8094);
+        let options = ServerOptions {
+            http_header_timeout: Duration::from_millis(200),
+            ..ServerOptions::default()
+        };
+        let server = Server::new_with_options(
+            modbus_context(Box::<ClientMock>::default()),
+            address,
+            HashMap::new(),
+            options,
+        )
+        .await
+        .unwrap();
+
+        // Start a request, then never finish its headers.
+        let mut stream = tokio::net::TcpStream::connect(SocketAddr::from(address))
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET /api/healthcheck HTTP/1.1\r\nHost: localhost\r\n")
+            .await
+            .unwrap();
+        let start = Instant::now();
+        let mut response = Vec::new();
+        let closed =
+            tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+                .await
+                .expect("the connection should have been dropped");
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        // Dropped without being served, whether or not the close was clean.
+        if closed.is_ok() {
+            assert!(!String::from_utf8_lossy(&response).contains("200 OK"));
+        }
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn history_contains_recent_readings_in_order() {
+        let mut client = Box::<ClientMock>::default();
+        // Responses are served last-in first-out.
+        for val in (1..=5).rev() {
+            client.set_next_response(Ok(Response::ReadHoldingRegisters(vec![val])));
+        }
+        let ctx = modbus_context(client);
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "history_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("History Sensor", &[640], 1, false))),
+        );
+        let history = SensorHistory::new(3);
+        let sinks: Vec<Arc<dyn OutputSink>> = vec![Arc::new(history.clone())];
+        for _ in 0..5 {
+            collect(
+                &sensors,
+                &Readers::Shared(ctx.clone()),
+                &sinks,
+                Instant::now() + COLLECT_INTERVAL,
+                &ConnectionStatus::default(),
+                &CollectorMetrics::default(),
+            )
+            .await;
+        }
+
+        let routes = routes(
+            ctx,
+            sensors,
+            SensorCache::default(),
+            history,
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+        let res = warp::test::request()
+            .path("/api/v1/sensors/history_sensor/history")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        let values: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["value"].as_i64().unwrap())
+            .collect();
+        assert_eq!(values, vec![3, 4, 5]);
+
+        let res = warp::test::request()
+            .path("/api/v1/sensors/not_a_sensor/history")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn cleared_faults_are_reported_as_events() {
+        let mut client = Box::<ClientMock>::default();
+        // Responses are served last-in first-out: F2 is raised, then clears.
+        client.set_next_response(Ok(Response::ReadHoldingRegisters(vec![0, 0, 0, 0])));
+        client.set_next_response(Ok(Response::ReadHoldingRegisters(vec![0b10, 0, 0, 0])));
+        let ctx = modbus_context(client);
+
+        let registry = Registry::new();
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "event_faults".to_string(),
+            SensorTypes::Fault(FaultSensor::new_in(
+                &registry,
+                "Event Faults",
+                [1180, 1181, 1182, 1183],
+            )),
+        );
+        let events = FaultEvents::new(["event_faults".to_string()]);
+        let sinks: Vec<Arc<dyn OutputSink>> =
+            vec![Arc::new(PrometheusSink), Arc::new(events.clone())];
+        for _ in 0..2 {
+            collect(
+                &sensors,
+                &Readers::Shared(ctx.clone()),
+                &sinks,
+                Instant::now() + COLLECT_INTERVAL,
+                &ConnectionStatus::default(),
+                &CollectorMetrics::default(),
+            )
+            .await;
+        }
+        let gauge = registry.gather()[0].get_metric()[0].get_gauge().get_value();
+        assert_eq!(gauge, 0.0);
+
+        let routes = routes(
+            ctx,
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                events,
+                ..RouteSettings::default()
+            },
+        );
+        let res = warp::test::request()
+            .path("/api/v1/events")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        let events: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                (
+                    e["sensor"].as_str(),
+                    e["code"].as_str(),
+                    e["event"].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (Some("event_faults"), Some("F2"), Some("raised")),
+                (Some("event_faults"), Some("F2"), Some("cleared")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn faults_are_reported_from_the_last_reading() {
+        let registry = Registry::new();
+        let mut sensors = HashMap::new();
+        for (slug, name) in [
+            ("read_faults", "Read Faults"),
+            ("unread_faults", "Unread Faults"),
+        ] {
+            sensors.insert(
+                slug.to_string(),
+                SensorTypes::Fault(FaultSensor::new_in(
+                    &registry,
+                    name,
+                    [1198, 1199, 1200, 1201],
+                )),
+            );
+        }
+        let cache = SensorCache::default();
+        cache.insert("read_faults", SensorValue::Text("F1, F18".to_string()));
+        // Nothing is set, so a read reaching the mock would fail.
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            sensors,
+            cache,
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request()
+            .path("/api/v1/faults")
+            .reply(&routes)
+            .await;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(res.body()).unwrap(),
+            json!({"read_faults": {"codes": ["F1", "F18"]}, "unread_faults": null})
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_have_no_duplicate_families() {
+        let _sensor = Sensor::new("Clashing Metric", &[650], 1, false);
+        let clashing = prometheus::IntGauge::new("clashing_metric", "Clashing Metric").unwrap();
+        prometheus::register(Box::new(clashing)).unwrap();
+
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            HashMap::new(),
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+        let res = warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+
+        let body = std::str::from_utf8(res.body()).unwrap();
+        let mut headers: Vec<&str> = body.lines().filter(|l| l.starts_with('#')).collect();
+        let count = headers.len();
+        headers.sort();
+        headers.dedup();
+        assert_eq!(headers.len(), count);
+        assert!(headers.contains(&"# TYPE clashing_metric gauge"));
+    }
+
+    /// A transport that keeps its registers in memory, standing in for a custom one.
+    #[derive(Debug, Default)]
+    struct InMemoryTransport {
+        registers: HashMap<u16, u16>,
+    }
+
+    impl tokio_modbus::slave::SlaveContext for InMemoryTransport {
+        fn set_slave(&mut self, _slave: tokio_modbus::slave::Slave) {}
+    }
+
+    #[async_trait]
+    impl tokio_modbus::client::Client for InMemoryTransport {
+        async fn call(&mut self, request: Request<'_>) -> io::Result<Response> {
+            match request {
+                Request::ReadHoldingRegisters(start, count) => Ok(Response::ReadHoldingRegisters(
+                    (start..start + count)
+                        .map(|register| self.registers.get(&register).copied().unwrap_or(0))
+                        .collect(),
+                )),
+                Request::WriteSingleRegister(register, value) => {
+                    self.registers.insert(register, value);
+                    Ok(Response::WriteSingleRegister(register, value))
+                }
+                _ => Err(io::Error::new(io::ErrorKind::Unsupported, "unsupported")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_run_over_a_custom_transport() {
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "custom_transport_setting".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new_mut(
+                "Custom Transport Setting",
+                &[860],
+                1,
+                false,
+            ))),
+        );
+        let routes = routes(
+            crate::sensor::shared_context(InMemoryTransport::default()),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/unstable/custom_transport_setting")
+            .body("42")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+
+        let res = warp::test::request()
+            .path("/api/unstable/custom_transport_setting")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.body(), "42");
+    }
+
+    /// One of several Modbus TCP connections to the same inverter, which is slow to answer
+    /// reads.
+    #[derive(Debug)]
+    struct SlowTcpConnection {
+        registers: Arc<std::sync::Mutex<HashMap<u16, u16>>>,
+        read_delay: Duration,
+    }
+
+    impl tokio_modbus::slave::SlaveContext for SlowTcpConnection {
+        fn set_slave(&mut self, _slave: tokio_modbus::slave::Slave) {}
+    }
+
+    #[async_trait]
+    impl tokio_modbus::client::Client for SlowTcpConnection {
+        async fn call(&mut self, request: Request<'_>) -> io::Result<Response> {
+            match request {
+                Request::ReadHoldingRegisters(start, count) => {
+                    tokio::time::sleep(self.read_delay).await;
+                    let registers = self.registers.lock().unwrap();
+                    Ok(Response::ReadHoldingRegisters(
+                        (start..start + count)
+                            .map(|register| registers.get(&register).copied().unwrap_or(0))
+                            .collect(),
+                    ))
+                }
+                Request::WriteSingleRegister(register, value) => {
+                    self.registers.lock().unwrap().insert(register, value);
+                    Ok(Response::WriteSingleRegister(register, value))
+                }
+                _ => Err(io::Error::new(io::ErrorKind::Unsupported, "unsupported")),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_reads_dont_hold_up_writes_over_a_pool() {
+        let registers = Arc::new(std::sync::Mutex::new(HashMap::from([(1205, 7)])));
+        let read_delay = Duration::from_secs(5);
+        let connections: Vec<_> = (0..2)
+            .map(|_| {
+                crate::sensor::shared_context(SlowTcpConnection {
+                    registers: registers.clone(),
+                    read_delay,
+                })
+            })
+            .collect();
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "pooled_reading".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Pooled Reading",
+                &[1205],
+                1,
+                false,
+            ))),
+        );
+        sensors.insert(
+            "pooled_setting".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new_mut(
+                "Pooled Setting",
+                &[1206],
+                1,
+                false,
+            ))),
+        );
+
+        // How long a write started a second into a slow read takes to be made.
+        let write_during_read = |pool: Option<ContextPool>| {
+            let routes = routes(
+                connections[0].clone(),
+                sensors.clone(),
+                SensorCache::default(),
+                SensorHistory::new(0),
+                mpsc::channel(1).0,
+                RouteSettings {
+                    pool,
+                    ..RouteSettings::default()
+                },
+            );
+            async move {
+                let read = warp::test::request()
+                    .path("/api/unstable/pooled_reading")
+                    .reply(&routes);
+                let write = async {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    let start = Instant::now();
+                    let res = warp::test::request()
+                        .method("POST")
+                        .path("/api/unstable/pooled_setting")
+                        .body("3")
+                        .reply(&routes)
+                        .await;
+                    assert_eq!(res.status(), warp::http::StatusCode::OK);
+                    start.elapsed()
+                };
+                let (read, write_time) = tokio::join!(read, write);
+                assert_eq!(read.body(), "7");
+                write_time
+            }
+        };
+
+        let pool = ContextPool::new(connections.clone());
+        assert_eq!(write_during_read(Some(pool)).await, Duration::ZERO);
+        assert_eq!(registers.lock().unwrap()[&1206], 3);
+        // Over the one connection, the write waits for the read to finish.
+        assert_eq!(write_during_read(None).await, Duration::from_secs(4));
+    }
+
+    #[tokio::test]
+    async fn version_reports_the_crate_version() {
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "versioned_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Versioned Sensor",
+                &[880],
+                1,
+                false,
+            ))),
+        );
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                sensor_map_source: "/etc/samsynk.toml".to_string(),
+                ..RouteSettings::default()
+            },
+        );
+
+        let res = warp::test::request()
+            .path("/api/v1/version")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["crate_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["sensor_map_source"], "/etc/samsynk.toml");
+        assert_eq!(body["sensor_count"], 1);
+        assert_eq!(body["model"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn inverter_version_is_read_at_startup() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(MODEL_REGISTER, 5);
+        client.set_register(FIRMWARE.register, 0x0312);
+        let version = InverterVersion::read(modbus_context(client)).await;
+        assert_eq!(
+            version,
+            InverterVersion {
+                model: Some("5".to_string()),
+                firmware: Some("3.18".to_string()),
+            }
+        );
+        let unread = InverterVersion::read(modbus_context(Box::<ClientMock>::default())).await;
+        assert_eq!(unread, InverterVersion::default());
+
+        let _sensor = Sensor::new("Version Labelled", &[1186], 1, false);
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            HashMap::new(),
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                metric_labels: version.labels().to_vec(),
+                inverter_version: version,
+                ..RouteSettings::default()
+            },
+        );
+        let res = warp::test::request()
+            .path("/api/v1/version")
+            .reply(&routes)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(
+            (&body["model"], &body["firmware"]),
+            (&json!("5"), &json!("3.18"))
+        );
+
+        let res = warp::test::request().path("/metrics").reply(&routes).await;
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert!(body.contains("version_labelled{model=\"5\",firmware=\"3.18\"} 0"));
+    }
+
+    #[tokio::test]
+    async fn health_reports_the_connection_status() {
+        let connection_status = ConnectionStatus::default();
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            HashMap::new(),
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                connection_status: connection_status.clone(),
+                ..RouteSettings::default()
+            },
+        );
+
+        let res = warp::test::request()
+            .path("/api/v1/health")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body, json!({"connected": false, "last_success": null}));
+
+        connection_status.mark_success();
+        let res = warp::test::request()
+            .path("/api/v1/health")
+            .reply(&routes)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["connected"], true);
+        assert!(body["last_success"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn metrics_are_gzipped_when_accepted() {
+        let _sensor = Sensor::new("Gzipped Metric", &[850], 1, false);
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            HashMap::new(),
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request()
+            .path("/metrics")
+            .header("accept-encoding", "deflate, gzip;q=0.8")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        assert_eq!(res.headers()["content-encoding"], "gzip");
+        let mut body = String::new();
+        flate2::read::GzDecoder::new(&res.body()[..])
+            .read_to_string(&mut body)
+            .unwrap();
+        assert!(body.contains("# TYPE gzipped_metric gauge"));
+
+        let res = warp::test::request()
+            .path("/metrics")
+            .header("accept-encoding", "gzip;q=0")
+            .reply(&routes)
+            .await;
+        assert!(!res.headers().contains_key("content-encoding"));
+        assert!(std::str::from_utf8(res.body())
+            .unwrap()
+            .contains("# TYPE gzipped_metric gauge"));
+    }
+
+    #[tokio::test]
+    async fn metrics_scrapes_must_authenticate_when_enabled() {
+        let scrape = |auth: MetricsAuth| {
+            let routes = routes(
+                modbus_context(Box::<ClientMock>::default()),
+                HashMap::new(),
+                SensorCache::default(),
+                SensorHistory::new(0),
+                mpsc::channel(1).0,
+                RouteSettings {
+                    metrics_auth: Some(auth),
+                    ..RouteSettings::default()
+                },
+            );
+            move |authorization: Option<&str>| {
+                let mut request = warp::test::request().path("/metrics");
+                if let Some(authorization) = authorization {
+                    request = request.header("authorization", authorization);
+                }
+                let routes = routes.clone();
+                async move { request.reply(&routes).await.status() }
+            }
+        };
+
+        let basic = scrape(MetricsAuth::Basic {
+            username: "prometheus".to_string(),
+            password: "hunter2".to_string(),
+        });
+        // "prometheus:hunter2"
+        let credentials = "Basic cHJvbWV0aGV1czpodW50ZXIy";
+        assert_eq!(basic(Some(credentials)).await, warp::http::StatusCode::OK);
+        assert_eq!(basic(None).await, warp::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            basic(Some("Basic cHJvbWV0aGV1czp3cm9uZw==")).await,
+            warp::http::StatusCode::UNAUTHORIZED
+        );
+
+        let bearer = scrape(MetricsAuth::Bearer {
+            token: "scrape-token".to_string(),
+        });
+        assert_eq!(
+            bearer(Some("Bearer scrape-token")).await,
+            warp::http::StatusCode::OK
+        );
+        assert_eq!(bearer(None).await, warp::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            bearer(Some(credentials)).await,
+            warp::http::StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_as_encoded() {
+        let _sensor = Sensor::new("Plain Metric", &[920], 1, false);
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            HashMap::new(),
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "text/plain; charset=utf-8");
+        assert!(!res.body().is_empty());
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert!(body.contains("# TYPE plain_metric gauge"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_failures_are_counted_per_sensor() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(670, 1);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "working_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Working Sensor", &[670], 1, false))),
+        );
+        // Nothing is served at 671, so every read of this sensor fails.
+        sensors.insert(
+            "failing_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Failing Sensor", &[671], 1, false))),
+        );
+
+        let metrics = CollectorMetrics::default();
+        let collector = tokio::spawn(data_collector(
+            sensors,
+            Readers::Shared(ctx),
+            vec![],
+            CollectSchedule::new(COLLECT_INTERVAL),
+            Arc::new(Mutex::new(mpsc::channel(1).1)),
+            ConnectionStatus::default(),
+            metrics.clone(),
+        ));
+        tokio::time::sleep(COLLECT_INTERVAL * 2 + Duration::from_secs(1)).await;
+        collector.abort();
+
+        let failures = |slug| metrics.read_failures.with_label_values(&[slug]).get();
+        assert_eq!(failures("failing_sensor"), 3);
+        assert_eq!(failures("working_sensor"), 0);
+    }
+
+    /// Panics on its first publish, as a collector hitting a bug would.
+    #[derive(Default)]
+    struct PanicOnceSink {
+        panicked: std::sync::atomic::AtomicBool,
+        publishes: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl OutputSink for PanicOnceSink {
+        async fn publish(&self, _: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+            use std::sync::atomic::Ordering;
+            if !self.panicked.swap(true, Ordering::Relaxed) {
+                panic!("injected collector panic");
+            }
+            self.publishes.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collector_is_restarted_after_a_panic() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(940, 1);
+        let ctx = Arc::new(Mutex::new(Context { client }));
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "supervised_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Supervised Sensor",
+                &[940],
+                1,
+                false,
+            ))),
+        );
+        let sink = Arc::new(PanicOnceSink::default());
+        let collect_requests = Arc::new(Mutex::new(mpsc::channel(1).1));
+        let restarts = IntCounter::new("restarts_total", "restarts").unwrap();
+
+        let sinks: Vec<Arc<dyn OutputSink>> = vec![sink.clone()];
+        let supervisor = tokio::spawn(supervise(restarts.clone(), move || {
+            data_collector(
+                sensors.clone(),
+                Readers::Shared(ctx.clone()),
+                sinks.clone(),
+                CollectSchedule::new(COLLECT_INTERVAL),
+                collect_requests.clone(),
+                ConnectionStatus::default(),
+                CollectorMetrics::default(),
+            )
+        }));
+        // The first cycle panics, and the restarted collector runs its own first cycle
+        // once the backoff is up.
+        tokio::time::sleep(COLLECTOR_BACKOFF + Duration::from_millis(100)).await;
+        assert_eq!(restarts.get(), 1);
+        assert_eq!(sink.publishes.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        tokio::time::sleep(COLLECT_INTERVAL).await;
+        supervisor.abort();
+        assert_eq!(sink.publishes.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn adjust_writes_relative_to_current_value() {
+        let mut client = Box::<ClientMock>::default();
+        // 10.0 A, with a factor of 10.
+        client.set_register(680, 100);
+        client.set_next_request(Ok(Request::WriteSingleRegister(680, 150)));
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "adjustable_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new_mut(
+                "Adjustable Sensor",
+                &[680],
+                10,
+                false,
+            ))),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/unstable/adjustable_sensor/adjust")
+            .body("5")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        assert_eq!(res.body(), "15");
+    }
+
+    #[tokio::test]
+    async fn refused_adjustments_are_client_errors() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(681, 100);
+        client.set_register(682, 100);
+        // No write is queued, so one reaching the mock would panic.
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "overflowing_setting".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new_mut(
+                "Overflowing Setting",
+                &[681],
+                10,
+                false,
+            ))),
+        );
+        sensors.insert(
+            "read_only_setting".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Read Only Setting",
+                &[682],
+                10,
+                false,
+            ))),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+        let adjust = |slug: &str, body: String| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/api/unstable/{}/adjust", slug))
+                .body(body)
+                .reply(&routes)
+        };
+
+        assert_eq!(
+            adjust("overflowing_setting", i64::MAX.to_string())
+                .await
+                .status(),
+            warp::http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            adjust("overflowing_setting", "10000".to_string())
+                .await
+                .status(),
+            warp::http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            adjust("read_only_setting", "5".to_string()).await.status(),
+            warp::http::StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collect_skips_sensors_after_deadline() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_read_delay(Duration::from_secs(4));
+        let mut sensors = HashMap::new();
+        for (slug, name, register) in [
+            ("slow_sensor_a", "Slow Sensor A", &[700]),
+            ("slow_sensor_b", "Slow Sensor B", &[701]),
+            ("slow_sensor_c", "Slow Sensor C", &[702]),
+            ("slow_sensor_d", "Slow Sensor D", &[703]),
+        ] {
+            client.set_register(register[0], 1);
+            sensors.insert(
+                slug.to_string(),
+                SensorTypes::Basic(BasicSensor(Sensor::new(name, register, 1, false))),
+            );
+        }
+        let ctx = Arc::new(Mutex::new(Context { client }));
+        let sink = Arc::new(RecordingSink::default());
+        let sinks: Vec<Arc<dyn OutputSink>> = vec![sink.clone()];
+
+        let metrics = CollectorMetrics::default();
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(10);
+        collect(
+            &sensors,
+            &Readers::Shared(ctx),
+            &sinks,
+            deadline,
+            &ConnectionStatus::default(),
+            &metrics,
+        )
+        .await;
+
+        // Two reads fit before the deadline. The third starts before it, so is finished
+        // rather than cut off mid-frame, and the fourth never starts.
+        assert_eq!(Instant::now(), start + Duration::from_secs(12));
+        assert_eq!(sink.readings.lock().unwrap().len(), 3);
+        let skipped: u64 = sensors
+            .keys()
+            .map(|slug| metrics.skipped_reads.with_label_values(&[slug]).get())
+            .sum();
+        assert_eq!(skipped, 1);
+    }
+
+    #[tokio::test]
+    async fn multiple_sensors_are_read_in_one_request() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(710, 3);
+        client.set_register(711, 4);
+        let read_counts = client.read_counts();
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "batch_sensor_a".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Batch Sensor A", &[710], 1, false))),
+        );
+        sensors.insert(
+            "batch_sensor_b".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Batch Sensor B", &[711], 1, false))),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request()
+            .path("/api/v1/sensors?slugs=batch_sensor_a,not_a_sensor,batch_sensor_b")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "batch_sensor_a": 3,
+                "batch_sensor_b": 4,
+                "not_a_sensor": {"error": "not found"},
+            })
+        );
+        // Both sensors' registers are fetched in a single read.
+        let read_counts = read_counts.lock().unwrap();
+        assert_eq!(read_counts.get(&710), Some(&1));
+        assert_eq!(read_counts.get(&711), None);
+    }
+
+    #[tokio::test]
+    async fn failed_reads_are_logged_with_the_request_id() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(1202, 5);
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "traced_sensor_a".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Traced Sensor A",
+                &[1202],
+                1,
+                false,
+            ))),
+        );
+        sensors.insert(
+            "traced_sensor_b".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Traced Sensor B",
+                &[1203],
+                1,
+                false,
+            ))),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        crate::correlation::captured::take();
+        let res = warp::test::request()
+            .path("/api/v1/sensors?slugs=traced_sensor_a,traced_sensor_b")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        let id = res.headers()[CORRELATION_HEADER].to_str().unwrap();
+        let logged = crate::correlation::captured::take();
+        // The refused register, then the sensor that needed it.
+        let query_log = logged
+            .iter()
+            .find(|line| line.contains("could not read register 1203"))
+            .unwrap();
+        let request_log = logged
+            .iter()
+            .find(|line| line.contains("could not read traced_sensor_b"))
+            .unwrap();
+        assert!(query_log.starts_with(&format!("[{}] ", id)));
+        assert!(request_log.starts_with(&format!("[{}] ", id)));
+
+        // A request that doesn't touch the bus doesn't get one.
+        let res = warp::test::request()
+            .path("/api/v1/sensors")
+            .reply(&routes)
+            .await;
+        assert!(!res.headers().contains_key(CORRELATION_HEADER));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collect_route_runs_a_cycle_immediately() {
+        let mut client = Box::<ClientMock>::default();
+        // Responses are served last-in first-out.
+        client.set_next_response(Ok(Response::ReadHoldingRegisters(vec![2])));
+        client.set_next_response(Ok(Response::ReadHoldingRegisters(vec![1])));
+        let ctx = modbus_context(client);
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "on_demand_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "On Demand Sensor",
+                &[730],
+                1,
+                false,
+            ))),
+        );
+        let sink = Arc::new(RecordingSink::default());
+        let (collect_tx, collect_rx) = mpsc::channel(1);
+        let collector = tokio::spawn(data_collector(
+            sensors.clone(),
+            Readers::Shared(ctx.clone()),
+            vec![sink.clone()],
+            CollectSchedule::new(COLLECT_INTERVAL),
+            Arc::new(Mutex::new(collect_rx)),
+            ConnectionStatus::default(),
+            CollectorMetrics::default(),
+        ));
+        let routes = routes(
+            ctx,
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            collect_tx,
+            RouteSettings {
+                api_token: Some("secret".to_string()),
+                ..RouteSettings::default()
+            },
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let start = Instant::now();
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/v1/collect")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::UNAUTHORIZED);
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/v1/collect")
+            .header("authorization", "Bearer secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        assert!(start.elapsed() < COLLECT_INTERVAL);
+        collector.abort();
+
+        let values: Vec<String> = sink
+            .readings
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| r.value.to_string())
+            .collect();
+        assert_eq!(values, vec!["1", "2"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn full_collect_queue_is_counted() {
+        let (collect_tx, mut collect_rx) = mpsc::channel(1);
+        let queue_full = IntCounter::new("queue_full_total", "queue full").unwrap();
+        queue_collect_request(&collect_tx, oneshot::channel().0, None, &queue_full)
+            .await
+            .unwrap();
+        assert_eq!(queue_full.get(), 0);
+
+        // The collector hasn't taken the first request, so the second waits for it to.
+        let counter = queue_full.clone();
+        let waiting = tokio::spawn(async move {
+            let alert_after = Some(Duration::from_secs(1));
+            queue_collect_request(&collect_tx, oneshot::channel().0, alert_after, &counter).await
+        });
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(queue_full.get(), 1);
+        assert!(!waiting.is_finished());
+
+        collect_rx.recv().await.unwrap();
+        waiting.await.unwrap().unwrap();
+        assert!(collect_rx.recv().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn metrics_are_labelled_with_serial() {
+        let mut client = Box::<ClientMock>::default();
+        for (register, val) in (3..=7).zip([0x0102, 0x0304, 0x0506, 0x0708, 0x0900]) {
+            client.set_register(register, val);
+        }
+        let serial = read_serial(Arc::new(Mutex::new(Context { client }))).await;
+        assert_eq!(serial, "1234567890");
+        let placeholder = read_serial(Arc::new(Mutex::new(Context {
+            client: Box::<ClientMock>::default(),
+        })))
+        .await;
+        assert_eq!(placeholder, UNKNOWN_IDENTITY);
+
+        let _sensor = Sensor::new("Serial Labelled", &[740], 1, false);
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            HashMap::new(),
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                metric_labels: vec![("serial".to_string(), serial)],
+                ..RouteSettings::default()
+            },
+        );
+        let res = warp::test::request().path("/metrics").reply(&routes).await;
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert!(body.contains("serial_labelled{serial=\"1234567890\"} 0"));
+        assert!(body
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .all(|l| l.contains("serial=\"1234567890\"")));
+    }
+
+    #[tokio::test]
+    async fn metrics_carry_configured_labels() {
+        let _sensor = Sensor::new("Site Labelled", &[1130], 1, false);
+        let address = ([127, 0, 0, 1], 8095);
+        let options = |name: &str| ServerOptions {
+            metric_labels: vec![
+                ("region".to_string(), "north".to_string()),
+                (name.to_string(), "home".to_string()),
+            ],
+            ..ServerOptions::default()
+        };
+
+        let server = Server::new_with_options(
+            modbus_context(Box::<ClientMock>::default()),
+            address,
+            HashMap::new(),
+            options("site name"),
+        )
+        .await;
+        assert_eq!(
+            server.err().unwrap().to_string(),
+            "site name is not a valid label name"
+        );
+        for name in ["sensor", "region"] {
+            let server = Server::new_with_options(
+                modbus_context(Box::<ClientMock>::default()),
+                address,
+                HashMap::new(),
+                options(name),
+            )
+            .await;
+            assert_eq!(
+                server.err().unwrap().to_string(),
+                format!("{} is already a label name", name)
+            );
+        }
+
+        let _server = Server::new_with_options(
+            modbus_context(Box::<ClientMock>::default()),
+            address,
+            HashMap::new(),
+            options("site"),
+        )
+        .await
+        .unwrap();
+        let body = reqwest::get(origin_url(address) + "/metrics")
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(body.contains("site_labelled{region=\"north\",site=\"home\"} 0"));
+    }
+
+    #[tokio::test]
+    async fn collector_counters_are_served_from_a_custom_registry() {
+        let address = ([127, 0, 0, 1], 8096);
+        let options = ServerOptions {
+            registry: Registry::new(),
+            ..ServerOptions::default()
+        };
+        let _server = Server::new_with_options(
+            modbus_context(Box::<ClientMock>::default()),
+            address,
+            HashMap::new(),
+            options,
+        )
+        .await
+        .unwrap();
+        let body = reqwest::get(origin_url(address) + "/metrics")
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(body.contains("collector_restarts_total"));
+    }
+
+    #[tokio::test]
+    async fn servers_count_their_own_sensors() {
+        let all_sensors: [(&str, &str, &[u16]); 2] = [
+            ("counted_sensor_a", "Counted Sensor A", &[1210]),
+            ("counted_sensor_b", "Counted Sensor B", &[1211]),
+        ];
+        let mut servers = Vec::new();
+        for (port, count) in [(8097, 1), (8098, 2)] {
+            let registry = Registry::new();
+            let sensors: HashMap<_, _> = all_sensors[..count]
+                .iter()
+                .map(|(slug, name, registers)| {
+                    let sensor = Sensor::new_in(&registry, name, registers, 1, false);
+                    (slug.to_string(), SensorTypes::Basic(BasicSensor(sensor)))
+                })
+                .collect();
+            let options = ServerOptions {
+                registry,
+                ..ServerOptions::default()
+            };
+            let address = ([127, 0, 0, 1], port);
+            let server = Server::new_with_options(
+                modbus_context(Box::<ClientMock>::default()),
+                address,
+                sensors,
+                options,
+            )
+            .await
+            .unwrap();
+            servers.push((server, address, count));
+        }
+
+        for (_, address, count) in &servers {
+            let body = reqwest::get(origin_url(*address) + "/metrics")
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+            assert!(body.contains(&format!("samsynk_sensors_total {}\n", count)));
+        }
+    }
+
+    #[tokio::test]
+    async fn schedule_post_writes_encoded_registers() {
+        #[rustfmt::skip]
+        let current: Vec<u16> = vec![
+            0, 100, 200, 300, 400, 500,
+            0, 0, 0, 0, 0, 0,
+            49, 49, 49, 49, 49, 49,
+            0, 0, 0, 0, 0, 0,
+            2, 2, 0, 0, 0, 0,
+        ];
+        #[rustfmt::skip]
+        let expected: Vec<u16> = vec![
+            0, 530, 800, 1600, 2130, 2300,
+            5000, 5000, 4000, 5000, 5000, 5000,
+            49, 49, 49, 49, 49, 49,
+            100, 30, 30, 20, 50, 100,
+            3, 2, 0, 0, 0, 1,
+        ];
+        let mut client = Box::<ClientMock>::default();
+        // Responses are served last-in first-out: the read back, then the initial read.
+        client.set_next_response(Ok(Response::ReadHoldingRegisters(expected.clone())));
+        client.set_next_response(Ok(Response::ReadHoldingRegisters(current)));
+        client.set_next_request(Ok(Request::WriteMultipleRegisters(
+            250,
+            std::borrow::Cow::Owned(expected),
+        )));
+        let routes = routes(
+            modbus_context(client),
+            HashMap::new(),
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/v1/schedule")
+            .json(&json!([
+                {"start": "00:00", "power": 5000, "soc": 100, "grid_charge": true},
+                {"start": "05:30", "power": 5000, "soc": 30, "grid_charge": false},
+                {"start": "08:00", "power": 4000, "soc": 30, "grid_charge": false},
+                {"start": "16:00", "power": 5000, "soc": 20, "grid_charge": false},
+                {"start": "21:30", "power": 5000, "soc": 50, "grid_charge": false},
+                {"start": "23:00", "power": 5000, "soc": 100, "grid_charge": true},
+            ]))
+            .reply(&routes)
+            .await;
+
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+    }
+
+    /// A transport that fails the test if anything reads from it.
+    #[derive(Debug)]
+    struct UnreadableTransport;
+
+    impl tokio_modbus::slave::SlaveContext for UnreadableTransport {
+        fn set_slave(&mut self, _slave: tokio_modbus::slave::Slave) {}
+    }
+
+    #[async_trait]
+    impl tokio_modbus::client::Client for UnreadableTransport {
+        async fn call(&mut self, request: Request<'_>) -> io::Result<Response> {
+            panic!("{:?} reached the bus", request)
+        }
+    }
+
+    #[tokio::test]
+    async fn cached_schedule_reads_never_touch_the_bus() {
+        let settings = || RouteSettings {
+            cached_reads_only: true,
+            ..RouteSettings::default()
+        };
+        let unread_routes = routes(
+            crate::sensor::shared_context(UnreadableTransport),
+            HashMap::new(),
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            settings(),
+        );
+        let res = warp::test::request()
+            .path("/api/v1/schedule")
+            .reply(&unread_routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.body(), "NOT YET READ");
+
+        // A schedule written through the API is served from then on.
+        #[rustfmt::skip]
+        let block: Vec<u16> = vec![
+            0, 100, 200, 300, 400, 500,
+            5000, 5000, 5000, 5000, 5000, 5000,
+            49, 49, 49, 49, 49, 49,
+            100, 30, 30, 30, 30, 30,
+            0, 0, 0, 0, 0, 0,
+        ];
+        let mut client = Box::<ClientMock>::default();
+        // Responses are served last-in first-out: the read back, then the initial read.
+        client.set_next_response(Ok(Response::ReadHoldingRegisters(block.clone())));
+        client.set_next_response(Ok(Response::ReadHoldingRegisters(block.clone())));
+        client.set_next_request(Ok(Request::WriteMultipleRegisters(
+            250,
+            std::borrow::Cow::Owned(block),
+        )));
+        let read_counts = client.read_counts();
+        let routes = routes(
+            modbus_context(client),
+            HashMap::new(),
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            settings(),
+        );
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/v1/schedule")
+            .json(&json!([
+                {"start": "00:00", "power": 5000, "soc": 100, "grid_charge": false},
+                {"start": "01:00", "power": 5000, "soc": 30, "grid_charge": false},
+                {"start": "02:00", "power": 5000, "soc": 30, "grid_charge": false},
+                {"start": "03:00", "power": 5000, "soc": 30, "grid_charge": false},
+                {"start": "04:00", "power": 5000, "soc": 30, "grid_charge": false},
+                {"start": "05:00", "power": 5000, "soc": 30, "grid_charge": false},
+            ]))
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        let reads = read_counts.lock().unwrap().clone();
+
+        let res = warp::test::request()
+            .path("/api/v1/schedule")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.headers().get(CACHED_HEADER).unwrap(), "true");
+        let slots = serde_json::from_slice::<serde_json::Value>(res.body()).unwrap();
+        assert_eq!(
+            slots[1],
+            json!({"start": "01:00", "power": 5000, "soc": 30, "grid_charge": false})
+        );
+        assert_eq!(*read_counts.lock().unwrap(), reads);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collect_on_scrape_only_reads_when_scraped() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(750, 12);
+        let read_counts = client.read_counts();
+        let ctx = modbus_context(client);
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "scrape_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Scrape Sensor", &[750], 1, false))),
+        );
+        let collector = ScrapeCollector::new(
+            sensors.clone(),
+            Readers::Shared(ctx.clone()),
+            vec![],
+            COLLECT_INTERVAL,
+            COLLECT_INTERVAL,
+            ConnectionStatus::default(),
+            CollectorMetrics::default(),
+        );
+        let routes = routes(
+            ctx,
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                scrape_collector: Some(collector),
+                ..RouteSettings::default()
+            },
+        );
+
+        tokio::time::sleep(COLLECT_INTERVAL * 3).await;
+        assert_eq!(read_counts.lock().unwrap().get(&750), None);
+
+        let res = warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        assert_eq!(read_counts.lock().unwrap().get(&750), Some(&1));
+
+        // A second scrape straight after is served from the first one's cycle.
+        warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(read_counts.lock().unwrap().get(&750), Some(&1));
+
+        tokio::time::sleep(COLLECT_INTERVAL).await;
+        warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(read_counts.lock().unwrap().get(&750), Some(&2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collect_on_scrape_reads_read_once_sensors_until_a_read_succeeds() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(612, 8000);
+        client.set_next_response(Err(io::Error::other("gateway unreachable")));
+        let read_counts = client.read_counts();
+        let ctx = modbus_context(client);
+
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "scrape_once_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new("Scrape Once Sensor", &[612], 1, false).read_once(),
+            )),
+        );
+        let collector = ScrapeCollector::new(
+            sensors,
+            Readers::Shared(ctx),
+            vec![],
+            COLLECT_INTERVAL,
+            COLLECT_INTERVAL,
+            ConnectionStatus::default(),
+            CollectorMetrics::default(),
+        );
+
+        // Each scrape gets a clone of the collector, which shares what it has read.
+        for _ in 0..4 {
+            collector.clone().collect(true).await;
+        }
+        assert_eq!(read_counts.lock().unwrap().get(&612), Some(&2));
+    }
+
+    #[tokio::test]
+    async fn writes_to_read_only_sensors_are_rejected() {
+        // No write is queued, so a write reaching the mock would panic.
+        let client = Box::<ClientMock>::default();
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "read_only_sensor".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Read Only Sensor",
+                &[790],
+                1,
+                false,
+            ))),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/unstable/read_only_sensor")
+            .body("1")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(res.status(), warp::http::StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn writes_need_the_api_token() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_register(800, 10);
+        // Only the writes made with the token are queued, last first; any other would panic
+        // the mock.
+        client.set_next_request(Ok(Request::WriteSingleRegister(800, 15)));
+        client.set_next_request(Ok(Request::WriteSingleRegister(800, 20)));
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "guarded_setting".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new_mut(
+                "Guarded Setting",
+                &[800],
+                1,
+                false,
+            ))),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                api_token: Some("secret".to_string()),
+                ..RouteSettings::default()
+            },
+        );
+        let post = |path: &'static str, body: &'static str, authorization: Option<&str>| {
+            let mut request = warp::test::request().method("POST").path(path).body(body);
+            if let Some(authorization) = authorization {
+                request = request.header("authorization", authorization);
+            }
+            request.reply(&routes)
+        };
+
+        for authorization in [None, Some("Bearer wrong")] {
+            let res = post("/api/unstable/guarded_setting", "20", authorization).await;
+            assert_eq!(res.status(), warp::http::StatusCode::UNAUTHORIZED);
+            let res = post("/api/unstable/guarded_setting/adjust", "5", authorization).await;
+            assert_eq!(res.status(), warp::http::StatusCode::UNAUTHORIZED);
+        }
+
+        let res = post("/api/unstable/guarded_setting", "20", Some("Bearer secret")).await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        let res = post(
+            "/api/unstable/guarded_setting/adjust",
+            "5",
+            Some("Bearer secret"),
+        )
+        .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        assert_eq!(res.body(), "15");
+    }
+
+    #[tokio::test]
+    async fn reads_of_write_only_sensors_are_refused() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_request(Ok(Request::WriteSingleRegister(1105, 1)));
+        let read_counts = client.read_counts();
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "restart_command".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new_mut("Restart Command", &[1105], 1, false).write_only(),
+            )),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request()
+            .path("/api/unstable/restart_command")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()["allow"], "POST");
+        let res = warp::test::request()
+            .path("/api/v1/sensors?slugs=restart_command")
+            .reply(&routes)
+            .await;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(res.body()).unwrap(),
+            json!({"restart_command": {"error": "write only"}})
+        );
+        assert!(read_counts.lock().unwrap().is_empty());
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/unstable/restart_command")
+            .body("1")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn dry_run_writes_report_the_write_without_making_it() {
+        // No write is queued, so one reaching the mock would panic.
+        let client = Box::<ClientMock>::default();
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "dry_run_setting".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new_mut("Dry Run Setting", &[1090], 1, false)
+                    .with_write_fn(WriteFunction::MultipleRegisters)
+                    .with_write_interval(Duration::from_secs(60)),
+            )),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        // Dry runs don't count towards the write limit either.
+        for _ in 0..2 {
+            let res = warp::test::request()
+                .method("POST")
+                .path("/api/unstable/dry_run_setting?dry_run=true")
+                .body("450")
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), warp::http::StatusCode::OK);
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(res.body()).unwrap(),
+                json!({"function_code": 16, "register": 1090, "value": 450})
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_runs_are_refused_like_the_write() {
+        // No write is queued, so one reaching the mock would panic.
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "dry_run_switch".to_string(),
+            SensorTypes::Binary(BinarySensor(Sensor::new_mut(
+                "Dry Run Switch",
+                &[1191],
+                1,
+                false,
+            ))),
+        );
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        for path in [
+            "/api/unstable/dry_run_switch",
+            "/api/unstable/dry_run_switch?dry_run=true",
+        ] {
+            let write = |value: &'static str| {
+                warp::test::request()
+                    .method("POST")
+                    .path(path)
+                    .body(value)
+                    .reply(&routes)
+            };
+            assert_eq!(
+                write("2").await.status(),
+                warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+                "{}",
+                path
+            );
+            assert_eq!(
+                write("on").await.status(),
+                warp::http::StatusCode::BAD_REQUEST,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rapid_writes_are_rate_limited() {
+        let mut client = Box::<ClientMock>::default();
+        // One write is queued for each sensor, so a second reaching the mock would panic.
+        client.set_register(1195, 1);
+        client.set_next_request(Ok(Request::WriteSingleRegister(1195, 2)));
+        client.set_next_request(Ok(Request::WriteSingleRegister(830, 1)));
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "flash_setting".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new_mut("Flash Setting", &[830], 1, false)
+                    .with_write_interval(Duration::from_secs(60)),
+            )),
+        );
+        sensors.insert(
+            "adjusted_flash_setting".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new_mut("Adjusted Flash Setting", &[1195], 1, false)
+                    .with_write_interval(Duration::from_secs(60)),
+            )),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let write = || {
+            warp::test::request()
+                .method("POST")
+                .path("/api/unstable/flash_setting")
+                .body("1")
+                .reply(&routes)
+        };
+        assert_eq!(write().await.status(), warp::http::StatusCode::OK);
+
+        let res = write().await;
+        assert_eq!(res.status(), warp::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["retry-after"], "60");
+
+        // Adjustments are writes too, so count against the same limit.
+        let adjust = |slug: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/api/unstable/{}/adjust", slug))
+                .body("1")
+                .reply(&routes)
+        };
+        let res = adjust("flash_setting").await;
+        assert_eq!(res.status(), warp::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["retry-after"], "60");
+
+        assert_eq!(
+            adjust("adjusted_flash_setting").await.status(),
+            warp::http::StatusCode::OK
+        );
+        let res = adjust("adjusted_flash_setting").await;
+        assert_eq!(res.status(), warp::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["retry-after"], "60");
+    }
+
+    #[tokio::test]
+    async fn writes_outside_the_allowed_values_are_refused() {
+        let mut client = Box::<ClientMock>::default();
+        // Only the allowed write is queued, so another reaching the mock would panic.
+        client.set_next_request(Ok(Request::WriteSingleRegister(1187, 1)));
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "control_mode".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new_mut("Control Mode", &[1187], 1, false).with_allowed_values(&[0, 1, 2]),
+            )),
+        );
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let write = |path: &'static str, value: &'static str| {
+            warp::test::request()
+                .method("POST")
+                .path(path)
+                .body(value)
+                .reply(&routes)
+        };
+        assert_eq!(
+            write("/api/unstable/control_mode", "1").await.status(),
+            warp::http::StatusCode::OK
+        );
+        assert_eq!(
+            write("/api/unstable/control_mode", "3").await.status(),
+            warp::http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            write("/api/unstable/control_mode?dry_run=true", "3")
+                .await
+                .status(),
+            warp::http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_writes_are_refused() {
+        // No write is queued, so one reaching the mock would panic.
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "bounded_setting".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new_mut(
+                "Bounded Setting",
+                &[960],
+                1,
+                false,
+            ))),
+        );
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        for path in [
+            "/api/unstable/bounded_setting",
+            "/api/unstable/bounded_setting/adjust",
+        ] {
+            let res = warp::test::request()
+                .method("POST")
+                .path(path)
+                .body("1".repeat(MAX_WRITE_BODY as usize + 1))
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), warp::http::StatusCode::PAYLOAD_TOO_LARGE);
+        }
+    }
+
+    #[tokio::test]
+    async fn energy_counters_are_reset() {
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "day_reset_energy".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new_mut("Day Reset Energy", &[1000], 10, false)
+                    .energy_counter(EnergyPeriod::Day)
+                    .with_write_interval(Duration::from_secs(60)),
+            )),
+        );
+        sensors.insert(
+            "day_reset_import".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new_mut("Day Reset Import", &[1001, 1002], 10, false)
+                    .energy_counter(EnergyPeriod::Day),
+            )),
+        );
+        sensors.insert(
+            "month_reset_energy".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new(
+                "Month Reset Energy",
+                &[1003],
+                10,
+                false,
+            ))),
+        );
+        let mut client = Box::<ClientMock>::default();
+        // Writes are checked last-in first-out, and the counters are reset in slug order.
+        client.set_next_request(Ok(Request::WriteMultipleRegisters(1001, vec![0, 0].into())));
+        client.set_next_request(Ok(Request::WriteSingleRegister(1000, 0)));
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                api_token: Some("secret".to_string()),
+                ..RouteSettings::default()
+            },
+        );
+
+        let reset = |period: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!(
+                    "/api/v1/energy/reset?period={}&confirm=true",
+                    period
+                ))
+                .header("authorization", "Bearer secret")
+                .reply(&routes)
+        };
+        let res = reset("day").await;
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(res.body()).unwrap(),
+            json!({
+                "period": "day",
+                "reset": ["day_reset_energy", "day_reset_import"],
+                "failed": [],
+            })
+        );
+        // None are reset again until every counter's write limit allows it.
+        let res = reset("day").await;
+        assert_eq!(res.status(), warp::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["retry-after"], "60");
+        // The monthly sensor isn't marked as a counter, and there's no such period as a week.
+        assert_eq!(
+            reset("month").await.status(),
+            warp::http::StatusCode::CONFLICT
+        );
+        assert_eq!(
+            reset("week").await.status(),
+            warp::http::StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn energy_resets_need_a_token_and_confirmation() {
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "day_guarded_energy".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new_mut("Day Guarded Energy", &[1208], 10, false)
+                    .energy_counter(EnergyPeriod::Day),
+            )),
+        );
+        // Nothing is queued, so a write reaching the mock would fail the test.
+        let reset = |api_token: Option<&str>, path: &'static str| {
+            let routes = routes(
+                modbus_context(Box::<ClientMock>::default()),
+                sensors.clone(),
+                SensorCache::default(),
+                SensorHistory::new(0),
+                mpsc::channel(1).0,
+                RouteSettings {
+                    api_token: api_token.map(str::to_string),
+                    ..RouteSettings::default()
+                },
+            );
+            async move {
+                warp::test::request()
+                    .method("POST")
+                    .path(path)
+                    .header("authorization", "Bearer secret")
+                    .reply(&routes)
+                    .await
+                    .status()
+            }
+        };
+
+        assert_eq!(
+            reset(None, "/api/v1/energy/reset?period=day&confirm=true").await,
+            warp::http::StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            reset(Some("secret"), "/api/v1/energy/reset?period=day").await,
+            warp::http::StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            reset(
+                Some("secret"),
+                "/api/v1/energy/reset?period=day&confirm=false"
+            )
+            .await,
+            warp::http::StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_energy_resets_are_reported() {
+        let mut sensors = HashMap::new();
+        for (slug, name, register) in [
+            ("year_refused_energy", "Year Refused Energy", &[1196]),
+            ("year_accepted_energy", "Year Accepted Energy", &[1197]),
+        ] {
+            sensors.insert(
+                slug.to_string(),
+                SensorTypes::Basic(BasicSensor(
+                    Sensor::new_mut(name, register, 10, false).energy_counter(EnergyPeriod::Year),
+                )),
+            );
+        }
+        let mut client = Box::<ClientMock>::default();
+        // Writes are checked last-in first-out, and the counters are reset in slug order.
+        // The mock refuses a write that doesn't match the one queued.
+        client.set_next_request(Err(io::Error::other("refused")));
+        client.set_next_request(Ok(Request::WriteSingleRegister(1197, 0)));
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                api_token: Some("secret".to_string()),
+                ..RouteSettings::default()
+            },
+        );
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/v1/energy/reset?period=year&confirm=true")
+            .header("authorization", "Bearer secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(res.body()).unwrap(),
+            json!({
+                "period": "year",
+                "reset": ["year_accepted_energy"],
+                "failed": ["year_refused_energy"],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn energy_resets_need_writable_counters() {
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "month_read_only_energy".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new("Month Read Only Energy", &[1194], 10, false)
+                    .energy_counter(EnergyPeriod::Month),
+            )),
+        );
+        // The registers are neither in order nor all consecutive.
+        sensors.insert(
+            "month_split_energy".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new_mut("Month Split Energy", &[1199, 1198, 1201], 10, false)
+                    .energy_counter(EnergyPeriod::Month),
+            )),
+        );
+        let mut client = Box::<ClientMock>::default();
+        // Writes are checked last-in first-out. None is queued for the read-only counter,
+        // so a write to it would panic.
+        client.set_next_request(Ok(Request::WriteMultipleRegisters(1201, vec![0].into())));
+        client.set_next_request(Ok(Request::WriteMultipleRegisters(1198, vec![0, 0].into())));
+        let routes = routes(
+            modbus_context(client),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                api_token: Some("secret".to_string()),
+                ..RouteSettings::default()
+            },
+        );
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/v1/energy/reset?period=month&confirm=true")
+            .header("authorization", "Bearer secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), warp::http::StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(res.body()).unwrap(),
+            json!({
+                "period": "month",
+                "reset": ["month_split_energy"],
+                "failed": ["month_read_only_energy"],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_only_leaves_out_the_api() {
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "hidden_setting".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new_mut(
+                "Hidden Setting",
+                &[1030],
+                1,
+                false,
+            ))),
+        );
+        // Nothing is queued, so a read or write reaching the mock would fail the test.
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            sensors,
+            SensorCache::default(),
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings {
+                metrics_only: true,
+                ..RouteSettings::default()
+            },
+        );
+
+        for (method, path) in [
+            ("GET", "/api/unstable/hidden_setting"),
+            ("POST", "/api/unstable/hidden_setting"),
+            ("GET", "/api/v1/sensors"),
+            ("POST", "/api/v1/collect"),
+        ] {
+            let res = warp::test::request()
+                .method(method)
+                .path(path)
+                .body("1")
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), warp::http::StatusCode::NOT_FOUND, "{}", path);
+        }
+        for path in ["/metrics", "/api/healthcheck"] {
+            let res = warp::test::request().path(path).reply(&routes).await;
+            assert_eq!(res.status(), warp::http::StatusCode::OK, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn sensor_listing_has_units_and_cached_values() {
+        let mut sensors = HashMap::new();
+        sensors.insert(
+            "listed_power".to_string(),
+            SensorTypes::Basic(BasicSensor(
+                Sensor::new("Listed Power", &[800], 1, false).with_unit("W"),
+            )),
+        );
+        sensors.insert(
+            "listed_unread".to_string(),
+            SensorTypes::Basic(BasicSensor(Sensor::new("Listed Unread", &[801], 1, false))),
+        );
+        let cache = SensorCache::default();
+        cache.insert("listed_power", SensorValue::Int(1500));
+        let routes = routes(
+            modbus_context(Box::<ClientMock>::default()),
+            sensors,
+            cache,
+            SensorHistory::new(0),
+            mpsc::channel(1).0,
+            RouteSettings::default(),
+        );
+
+        let res = warp::test::request()
+            .path("/api/v1/sensors")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(res.status(), warp::http::StatusCode::OK);
+        let listing: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(
+            listing,
+            json!({
+                "listed_power": {"unit": "W", "writable": false, "value": 1500},
+                "listed_unread": {"unit": null, "writable": false, "value": null},
+            })
+        );
+    }
 }
@@ -0,0 +1,118 @@
+use crate::sensor::{MetricUpdate, SensorValue};
+use async_trait::async_trait;
+use serde_json::json;
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single sensor value, as read during a collection cycle.
+#[derive(Clone, Debug)]
+pub struct Reading {
+    pub slug: String,
+    pub value: SensorValue,
+    pub timestamp: SystemTime,
+    /// Sets the sensor's gauges to the value, once the cycle is published.
+    pub metrics: MetricUpdate,
+}
+
+impl Reading {
+    /// Seconds since the Unix epoch.
+    pub fn unix_timestamp(&self) -> u64 {
+        self.timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// Somewhere to send the readings from each collection cycle, once every sensor has been read.
+#[async_trait]
+pub trait OutputSink: Send + Sync {
+    async fn publish(&self, readings: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// Set each sensor's gauges in the registry it was built with to the cycle's readings,
+/// so `/metrics` serves whole cycles rather than one half read.
+pub struct PrometheusSink;
+
+#[async_trait]
+impl OutputSink for PrometheusSink {
+    async fn publish(&self, readings: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for reading in readings.iter() {
+            reading.metrics.apply();
+        }
+        Ok(())
+    }
+}
+
+/// Write each reading as a line of JSON, eg.
+/// `{"sensor":"battery_soc","timestamp":1700000000,"value":54}`
+pub struct JsonLinesSink<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesSink<W> {
+    pub fn new(writer: W) -> JsonLinesSink<W> {
+        JsonLinesSink {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl JsonLinesSink<io::Stdout> {
+    pub fn stdout() -> JsonLinesSink<io::Stdout> {
+        JsonLinesSink::new(io::stdout())
+    }
+}
+
+#[async_trait]
+impl<W: Write + Send> OutputSink for JsonLinesSink<W> {
+    async fn publish(&self, readings: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut writer = self.writer.lock().unwrap();
+        for reading in readings.iter() {
+            let line = json!({
+                "sensor": reading.slug,
+                "value": reading.value,
+                "timestamp": reading.unix_timestamp(),
+            });
+            writeln!(writer, "{}", line)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn json_lines_sink_publish() {
+        let sink = JsonLinesSink::new(Vec::new());
+        let readings = vec![
+            Reading {
+                slug: "battery_soc".to_string(),
+                value: SensorValue::Int(54),
+                timestamp: UNIX_EPOCH + Duration::from_secs(1700000000),
+                metrics: MetricUpdate::default(),
+            },
+            Reading {
+                slug: "sunsynk_fault_codes".to_string(),
+                value: SensorValue::Text("F1, F8".to_string()),
+                timestamp: UNIX_EPOCH + Duration::from_secs(1700000001),
+                metrics: MetricUpdate::default(),
+            },
+        ];
+
+        sink.publish(&readings).await.unwrap();
+
+        let output = String::from_utf8(sink.writer.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "{\"sensor\":\"battery_soc\",\"timestamp\":1700000000,\"value\":54}\n\
+             {\"sensor\":\"sunsynk_fault_codes\",\"timestamp\":1700000001,\"value\":\"F1, F8\"}\n"
+        );
+    }
+}
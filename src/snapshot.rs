@@ -0,0 +1,139 @@
+use crate::correlation::log;
+use crate::helpers::group_consecutive;
+use crate::modbus_error::ModbusError;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_modbus::client::{Client, Context, Reader};
+use tokio_modbus::prelude::{Request, Response, Slave, SlaveContext};
+
+/// Holding register values read ahead of time, so several sensors can be decoded from a
+/// handful of batched reads rather than a read each.
+#[derive(Debug, Default)]
+pub struct RegisterSnapshot {
+    registers: HashMap<u16, u16>,
+}
+
+impl RegisterSnapshot {
+    /// Read `registers` in as few requests as possible. If the inverter refuses a run of
+    /// registers, its registers are read one at a time instead, so an unreadable register
+    /// only fails the sensors that need it rather than its neighbours too. Any other error
+    /// means the bus isn't answering, so it's returned rather than retried.
+    pub async fn read(
+        ctx: Arc<Mutex<dyn Reader>>,
+        mut registers: Vec<u16>,
+    ) -> Result<RegisterSnapshot, Error> {
+        let mut snapshot = RegisterSnapshot::default();
+        registers.sort();
+        registers.dedup();
+        if registers.is_empty() {
+            return Ok(snapshot);
+        }
+
+        let mut ctx = ctx.lock().await;
+        for (start, len) in group_consecutive(registers) {
+            match ctx.read_holding_registers(start, len).await {
+                Ok(values) => snapshot.registers.extend((start..).zip(values)),
+                Err(e) if is_exception(&e) && len > 1 => {
+                    log(format_args!(
+                        "could not read registers {} to {}, reading them one at a time: {}",
+                        start,
+                        start + len - 1,
+                        e
+                    ));
+                    for register in start..start + len {
+                        match ctx.read_holding_registers(register, 1).await {
+                            Ok(values) => {
+                                snapshot.registers.insert(register, values[0]);
+                            }
+                            Err(e) if is_exception(&e) => {
+                                log(format_args!("could not read register {}: {}", register, e))
+                            }
+                            Err(e) => return Err(e),
+                        }
+                    }
+                }
+                Err(e) if is_exception(&e) => {
+                    log(format_args!("could not read register {}: {}", start, e))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// A context that serves reads from the snapshot, to pass to `SensorRead::read_value`.
+    pub fn into_context(self) -> Arc<Mutex<Context>> {
+        Arc::new(Mutex::new(Context::from(Box::new(self) as Box<dyn Client>)))
+    }
+}
+
+/// Whether the inverter answered with a Modbus exception, ie. refused the read.
+fn is_exception(e: &Error) -> bool {
+    matches!(ModbusError::from(e), ModbusError::Exception(_))
+}
+
+#[async_trait]
+impl Client for RegisterSnapshot {
+    async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
+        match request {
+            Request::ReadHoldingRegisters(addr, cnt) => (addr..addr + cnt)
+                .map(|reg| self.registers.get(&reg).copied())
+                .collect::<Option<Vec<u16>>>()
+                .map(Response::ReadHoldingRegisters)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, "register was not read")),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                "only holding register reads can be served from a snapshot",
+            )),
+        }
+    }
+}
+
+impl SlaveContext for RegisterSnapshot {
+    fn set_slave(&mut self, _slave: Slave) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{modbus_context, ClientMock};
+
+    #[tokio::test]
+    async fn unreadable_register_only_loses_itself() {
+        let mut client = Box::<ClientMock>::default();
+        // 1021 isn't set, so the mock refuses any read that includes it.
+        for register in [1020, 1022, 1023] {
+            client.set_register(register, register * 2);
+        }
+        let snapshot = RegisterSnapshot::read(modbus_context(client), vec![1020, 1021, 1022, 1023])
+            .await
+            .unwrap();
+
+        let read: Vec<(u16, u16)> = (1020..1024)
+            .filter_map(|reg| Some((reg, *snapshot.registers.get(&reg)?)))
+            .collect();
+        assert_eq!(read, [(1020, 2040), (1022, 2044), (1023, 2046)]);
+    }
+
+    #[tokio::test]
+    async fn transport_errors_are_not_retried_register_by_register() {
+        let mut client = Box::<ClientMock>::default();
+        for register in 1192..1195 {
+            client.set_register(register, register);
+        }
+        client.set_next_response(Err(Error::new(
+            ErrorKind::BrokenPipe,
+            "serial port disconnected",
+        )));
+        let read_counts = client.read_counts();
+
+        let e = RegisterSnapshot::read(modbus_context(client), vec![1192, 1193, 1194])
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::BrokenPipe);
+        assert_eq!(read_counts.lock().unwrap().get(&1193), None);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::sensor::SensorTypes;
+use crate::sink::{OutputSink, Reading};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Values accumulated by the exporter itself, which would otherwise reset (and show up as
+/// counter resets in Prometheus) whenever it restarts.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    /// Totals of integrated energy sensors in Wh, by slug.
+    #[serde(default)]
+    pub energy_wh: HashMap<String, f64>,
+}
+
+impl State {
+    pub fn from_sensors(sensors: &HashMap<String, SensorTypes<'_>>) -> State {
+        let mut state = State::default();
+        for (slug, sensor) in sensors.iter() {
+            if let SensorTypes::IntegratedEnergy(s) = sensor {
+                state.energy_wh.insert(slug.clone(), s.energy_wh());
+            }
+        }
+        state
+    }
+
+    /// Carry the saved values over to the given sensors. Values for sensors that no longer
+    /// exist are ignored.
+    pub fn restore(&self, sensors: &HashMap<String, SensorTypes<'_>>) {
+        for (slug, energy_wh) in self.energy_wh.iter() {
+            if let Some(SensorTypes::IntegratedEnergy(s)) = sensors.get(slug) {
+                s.set_energy_wh(*energy_wh);
+            }
+        }
+    }
+}
+
+/// A JSON file holding the exporter's `State`.
+#[derive(Clone, Debug)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>) -> StateFile {
+        StateFile { path: path.into() }
+    }
+
+    /// Load the saved state, or an empty one if nothing has been saved yet.
+    pub fn load(&self) -> io::Result<State> {
+        match fs::read(&self.path) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write to a temporary file and rename it into place, so a crash mid-write can't
+    /// leave a truncated state file behind.
+    pub fn save(&self, state: &State) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(state)?)?;
+        fs::rename(tmp_path, &self.path)
+    }
+}
+
+/// Saves the state of the given sensors after every collection cycle.
+pub struct StateSink {
+    file: StateFile,
+    sensors: HashMap<String, SensorTypes<'static>>,
+}
+
+impl StateSink {
+    pub fn new(file: StateFile, sensors: HashMap<String, SensorTypes<'static>>) -> StateSink {
+        StateSink { file, sensors }
+    }
+}
+
+#[async_trait]
+impl OutputSink for StateSink {
+    async fn publish(&self, _readings: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.file.save(&State::from_sensors(&self.sensors))?;
+        Ok(())
+    }
+}
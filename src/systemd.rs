@@ -0,0 +1,102 @@
+//! Readiness and watchdog notifications for running as a systemd service, sent as
+//! datagrams to the socket systemd passes in `$NOTIFY_SOCKET`.
+
+use crate::connection::ConnectionStatus;
+use crate::sink::{OutputSink, Reading};
+use async_trait::async_trait;
+use std::error::Error;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Tells systemd the exporter is ready once the first collection cycle has finished, and
+/// pings the watchdog after every cycle that reached the inverter. With `WatchdogSec=` set
+/// on the unit, systemd restarts the exporter if the collector hangs or loses the inverter
+/// for longer than that.
+pub struct SystemdNotifier {
+    socket: UnixDatagram,
+    status: ConnectionStatus,
+    ready: AtomicBool,
+}
+
+impl SystemdNotifier {
+    /// A notifier for the socket in `$NOTIFY_SOCKET`, or `None` if it isn't set, ie. the
+    /// exporter isn't running under systemd.
+    pub fn from_env(status: ConnectionStatus) -> io::Result<Option<SystemdNotifier>> {
+        match std::env::var("NOTIFY_SOCKET") {
+            Ok(path) => SystemdNotifier::new(&path, status).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// A notifier for the socket at `path`. Paths starting with `@` are in the abstract
+    /// namespace.
+    pub fn new(path: &str, status: ConnectionStatus) -> io::Result<SystemdNotifier> {
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(path)?,
+        };
+        let socket = UnixDatagram::unbound()?;
+        socket.connect_addr(&addr)?;
+        Ok(SystemdNotifier {
+            socket,
+            status,
+            ready: AtomicBool::new(false),
+        })
+    }
+
+    fn notify(&self, state: &str) -> io::Result<()> {
+        self.socket.send(state.as_bytes()).map(|_| ())
+    }
+}
+
+#[async_trait]
+impl OutputSink for SystemdNotifier {
+    async fn publish(&self, _readings: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.ready.swap(true, Ordering::Relaxed) {
+            self.notify("READY=1")?;
+        }
+        if self.status.is_healthy() {
+            self.notify("WATCHDOG=1")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(socket: &UnixDatagram) -> Vec<String> {
+        let mut messages = Vec::new();
+        let mut buf = [0; 64];
+        while let Ok(len) = socket.recv(&mut buf) {
+            messages.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn ready_is_sent_after_the_first_cycle() {
+        let path = std::env::temp_dir().join(format!("samsynk-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        systemd.set_nonblocking(true).unwrap();
+
+        let status = ConnectionStatus::default();
+        let notifier = SystemdNotifier::new(path.to_str().unwrap(), status.clone()).unwrap();
+        assert!(received(&systemd).is_empty());
+
+        // The inverter didn't answer, so the watchdog isn't pinged, but the exporter is up.
+        notifier.publish(&[]).await.unwrap();
+        assert_eq!(received(&systemd), ["READY=1"]);
+
+        status.mark_success();
+        notifier.publish(&[]).await.unwrap();
+        notifier.publish(&[]).await.unwrap();
+        assert_eq!(received(&systemd), ["WATCHDOG=1", "WATCHDOG=1"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+use crate::bms::PACK_LEN;
+use crate::helpers::slug_name;
+use crate::sensor_config::SensorDefinition;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// Something in a set of sensor definitions that is probably a mistake, eg. a typo in a
+/// register number. The exporter still runs with them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SensorWarning {
+    /// Several sensors share a slug, so all but one would be silently dropped.
+    DuplicateSlug(String),
+    /// Two sensors read the same register. Compound sensors are left out, as they're
+    /// made to combine other sensors' registers.
+    RegisterOverlap {
+        register: u16,
+        first: String,
+        second: String,
+    },
+    /// A negative factor, where `signed` was probably meant.
+    NegativeFactor(String),
+    /// A binary sensor with a factor or offset, which would turn its 0 or 1 into
+    /// something else.
+    ScaledBinary(String),
+}
+
+impl fmt::Display for SensorWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SensorWarning::DuplicateSlug(slug) => {
+                write!(f, "more than one sensor has the slug {}", slug)
+            }
+            SensorWarning::RegisterOverlap {
+                register,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} and {} both read register {}",
+                first, second, register
+            ),
+            SensorWarning::NegativeFactor(slug) => {
+                write!(f, "{} has a negative factor, should it be signed?", slug)
+            }
+            SensorWarning::ScaledBinary(slug) => {
+                write!(f, "{} is binary but has a factor or offset", slug)
+            }
+        }
+    }
+}
+
+/// The registers a sensor reads as its own, rather than combining other sensors' values.
+fn own_registers(definition: &SensorDefinition) -> Vec<u16> {
+    match definition {
+        SensorDefinition::Basic(d)
+        | SensorDefinition::Binary(d)
+        | SensorDefinition::Temperature(d) => d.registers.clone(),
+        SensorDefinition::Fault { registers, .. } => registers.to_vec(),
+        SensorDefinition::Phase { registers, .. }
+        | SensorDefinition::Text { registers, .. }
+        | SensorDefinition::Directional { registers, .. } => registers.clone(),
+        SensorDefinition::Bitfield { register, .. } => vec![*register],
+        SensorDefinition::Ratio {
+            numerator,
+            denominator,
+            ..
+        } => vec![*numerator, *denominator],
+        // The pack count register, then each pack's registers.
+        SensorDefinition::Bms {
+            start_register,
+            max_packs,
+            ..
+        } => (*start_register..start_register + 1 + (max_packs * PACK_LEN) as u16).collect(),
+        SensorDefinition::Compound { .. } | SensorDefinition::EnergyShare { .. } => Vec::new(),
+    }
+}
+
+fn factor_warnings(definition: &SensorDefinition, slug: &str) -> Vec<SensorWarning> {
+    let factors = match definition {
+        // A rational scale stands in for the factor, so the factor goes unused.
+        SensorDefinition::Basic(d) | SensorDefinition::Temperature(d) if d.scale.is_some() => {
+            Vec::new()
+        }
+        SensorDefinition::Basic(d) | SensorDefinition::Temperature(d) => vec![d.factor],
+        SensorDefinition::Binary(d) if d.factor != 1 || d.offset != 0 || d.scale.is_some() => {
+            return vec![SensorWarning::ScaledBinary(slug.to_owned())];
+        }
+        SensorDefinition::Phase { factor, .. } | SensorDefinition::Directional { factor, .. } => {
+            vec![*factor]
+        }
+        SensorDefinition::Binary(_)
+        | SensorDefinition::Compound { .. }
+        | SensorDefinition::Fault { .. }
+        | SensorDefinition::Bms { .. }
+        | SensorDefinition::Text { .. }
+        | SensorDefinition::Ratio { .. }
+        | SensorDefinition::Bitfield { .. }
+        | SensorDefinition::EnergyShare { .. } => Vec::new(),
+    };
+
+    factors
+        .into_iter()
+        .filter(|factor| *factor < 0)
+        .map(|_| SensorWarning::NegativeFactor(slug.to_owned()))
+        .collect()
+}
+
+/// Check a set of sensor definitions for likely mistakes: duplicate slugs, registers read
+/// by more than one sensor, and negative factors. Warnings come in the order of the
+/// definitions, followed by any duplicate slugs.
+pub fn validate_sensor_set(definitions: &[SensorDefinition]) -> Vec<SensorWarning> {
+    let mut warnings = Vec::new();
+    let mut slug_counts: BTreeMap<String, usize> = BTreeMap::new();
+    let mut owners: HashMap<u16, String> = HashMap::new();
+    let mut overlapping: HashSet<(String, String)> = HashSet::new();
+
+    for definition in definitions.iter() {
+        let slug = slug_name(definition.name());
+        *slug_counts.entry(slug.clone()).or_default() += 1;
+        warnings.extend(factor_warnings(definition, &slug));
+
+        for register in own_registers(definition) {
+            match owners.get(&register) {
+                // Only the first shared register of each pair is reported.
+                Some(first) if *first != slug => {
+                    if overlapping.insert((first.clone(), slug.clone())) {
+                        warnings.push(SensorWarning::RegisterOverlap {
+                            register,
+                            first: first.clone(),
+                            second: slug.clone(),
+                        });
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    owners.insert(register, slug.clone());
+                }
+            }
+        }
+    }
+
+    warnings.extend(
+        slug_counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(slug, _)| SensorWarning::DuplicateSlug(slug)),
+    );
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor_config::{builtin_definitions, RegisterSensorDefinition};
+
+    fn basic(name: &str, registers: &[u16], factor: i64) -> SensorDefinition {
+        SensorDefinition::Basic(RegisterSensorDefinition {
+            name: name.to_string(),
+            registers: registers.to_vec(),
+            factor,
+            scale: None,
+            signed: false,
+            sign_bits: None,
+            offset: 0,
+            zero_epsilon: 0,
+            unit: None,
+            display_unit: None,
+            ffff_unavailable: false,
+            writable: false,
+            write_only: false,
+            read_once: false,
+            priority: 0,
+            emit_raw: false,
+            write_interval_secs: None,
+            allowed_values: None,
+            energy_counter: None,
+        })
+    }
+
+    #[test]
+    fn builtin_sensors_are_valid() {
+        assert_eq!(validate_sensor_set(&builtin_definitions()), []);
+    }
+
+    #[test]
+    fn bms_blocks_end_at_their_last_pack() {
+        let bms = SensorDefinition::Bms {
+            name: "Battery BMS".to_string(),
+            start_register: 400,
+            max_packs: 2,
+        };
+        let last = 400 + 2 * PACK_LEN as u16;
+        assert_eq!(own_registers(&bms).last(), Some(&last));
+
+        let next = basic("After BMS", &[last + 1], 1);
+        assert_eq!(validate_sensor_set(&[bms.clone(), next]), []);
+        let overlapping = basic("Last Pack", &[last], 1);
+        assert_eq!(validate_sensor_set(&[bms, overlapping]).len(), 1);
+    }
+
+    #[test]
+    fn flags_duplicate_slugs_and_overlapping_registers() {
+        let definitions = [
+            basic("Battery Voltage", &[183], 100),
+            basic("battery voltage", &[184], 100),
+            basic("Grid Power", &[169, 170], 1),
+            basic("Load Power", &[170], 1),
+            SensorDefinition::Compound {
+                name: "Total Power".to_string(),
+                registers: vec![169, 170],
+                sensors: Vec::new(),
+                factors: vec![1, -1],
+                no_negative: false,
+                absolute: false,
+                zero_epsilon: 0,
+            },
+        ];
+
+        assert_eq!(
+            validate_sensor_set(&definitions),
+            [
+                SensorWarning::RegisterOverlap {
+                    register: 170,
+                    first: "grid_power".to_string(),
+                    second: "load_power".to_string(),
+                },
+                SensorWarning::DuplicateSlug("battery_voltage".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use crate::helpers::invalid_input;
+use crate::sensor::SensorTypes;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tokio::time::{interval, Duration};
+use tokio_modbus::prelude::Reader;
+
+/// How often `--watch` reads its sensor, unless given an `--interval`.
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The arguments of `--watch <slug> [--count <reads>] [--interval <secs>]`, which prints
+/// one sensor's values rather than serving them all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchArgs {
+    pub slug: String,
+    /// Stop after this many reads, rather than running until interrupted.
+    pub count: Option<usize>,
+    pub interval: Duration,
+}
+
+impl WatchArgs {
+    /// Take the watch arguments out of `args`, leaving the rest for the config. `None` if
+    /// there's no `--watch`.
+    pub fn take(args: Vec<String>) -> io::Result<(Option<WatchArgs>, Vec<String>)> {
+        let mut slug = None;
+        let mut count = None;
+        let mut interval = WATCH_INTERVAL;
+        let mut rest = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| invalid_input(format!("{} needs a value", arg)))
+            };
+            match arg.as_str() {
+                "--watch" => slug = Some(value()?),
+                "--count" => {
+                    let reads = value()?;
+                    count = Some(reads.parse().map_err(|_| {
+                        invalid_input(format!("--count needs a number, got {}", reads))
+                    })?);
+                }
+                "--interval" => {
+                    let secs = value()?;
+                    interval = Duration::from_secs(secs.parse().map_err(|_| {
+                        invalid_input(format!(
+                            "--interval needs a number of seconds, got {}",
+                            secs
+                        ))
+                    })?);
+                }
+                _ => rest.push(arg),
+            }
+        }
+
+        let watch = slug.map(|slug| WatchArgs {
+            slug,
+            count,
+            interval,
+        });
+        Ok((watch, rest))
+    }
+}
+
+/// Read `sensor` every `interval`, writing each value to `out` after the time it was read,
+/// in seconds since the Unix epoch, eg. `1700000000 54`. Failed reads are logged and count
+/// towards `count`, if there is one.
+pub async fn watch_sensor(
+    sensor: &SensorTypes<'_>,
+    ctx: Arc<Mutex<dyn Reader>>,
+    interval_length: Duration,
+    count: Option<usize>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut ticks = interval(interval_length);
+    let mut reads = 0;
+    while count != Some(reads) {
+        ticks.tick().await;
+        let result = sensor.collect_value(ctx.clone()).await;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match result {
+            Ok((value, _)) => writeln!(out, "{} {}", timestamp, value)?,
+            Err(e) => eprintln!("{} could not read sensor: {}", timestamp, e),
+        }
+        out.flush()?;
+        reads += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{modbus_context, ClientMock};
+    use crate::sensor::{BasicSensor, Sensor};
+    use tokio_modbus::prelude::Response::ReadHoldingRegisters;
+
+    #[test]
+    fn watch_args_are_taken_from_the_rest() {
+        let args = [
+            "--watch",
+            "battery_soc",
+            "--set",
+            "serial.slave=2",
+            "--count",
+            "3",
+        ];
+        let (watch, rest) = WatchArgs::take(args.map(String::from).to_vec()).unwrap();
+        assert_eq!(
+            watch,
+            Some(WatchArgs {
+                slug: "battery_soc".to_string(),
+                count: Some(3),
+                interval: WATCH_INTERVAL,
+            })
+        );
+        assert_eq!(rest, ["--set", "serial.slave=2"]);
+
+        assert!(WatchArgs::take(vec!["--watch".to_string()]).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn watch_prints_each_read() {
+        let mut client = Box::<ClientMock>::default();
+        // Responses are served last-in first-out.
+        for value in [250, 200] {
+            client.set_next_response(Ok(ReadHoldingRegisters(vec![value])));
+        }
+        let sensor = SensorTypes::Basic(BasicSensor(Sensor::new(
+            "Watched Power",
+            &[1010],
+            10,
+            false,
+        )));
+
+        let mut out = Vec::new();
+        let start = tokio::time::Instant::now();
+        watch_sensor(
+            &sensor,
+            modbus_context(client),
+            WATCH_INTERVAL,
+            Some(2),
+            &mut out,
+        )
+        .await
+        .unwrap();
+        // The first read is straight away, the second an interval later.
+        assert_eq!(start.elapsed(), WATCH_INTERVAL);
+
+        let output = String::from_utf8(out).unwrap();
+        let values: Vec<&str> = output
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(values, ["20", "25"]);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::sensor::SensorValue;
+use crate::sink::{OutputSink, Reading};
+use async_trait::async_trait;
+use prometheus::{Gauge, Registry};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The extremes of one sensor's readings since the start of the current window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowStats {
+    pub start: SystemTime,
+    pub min: f64,
+    pub max: f64,
+    pub last: f64,
+}
+
+struct SensorWindow {
+    stats: WindowStats,
+    min_gauge: Gauge,
+    max_gauge: Gauge,
+}
+
+/// The minimum and maximum of each numeric sensor over a fixed window, eg. a day, for
+/// charting daily peaks without a time series database. Windows start on multiples of
+/// their length since the Unix epoch, so daily windows run from midnight UTC.
+///
+/// The extremes are exported as `<slug>_min` and `<slug>_max` gauges, registered the
+/// first time each sensor is read.
+#[derive(Clone)]
+pub struct SensorWindows {
+    length: Duration,
+    registry: Registry,
+    windows: Arc<Mutex<HashMap<String, SensorWindow>>>,
+}
+
+impl SensorWindows {
+    pub fn new(length: Duration, registry: Registry) -> SensorWindows {
+        assert!(!length.is_zero(), "an aggregation window needs a length");
+        SensorWindows {
+            length,
+            registry,
+            windows: Arc::default(),
+        }
+    }
+
+    /// The stats of `slug`'s current window, if it has been read since it started.
+    pub fn get(&self, slug: &str) -> Option<WindowStats> {
+        let windows = self.windows.lock().unwrap();
+        let window = windows.get(slug)?;
+        (window.stats.start == self.window_start(SystemTime::now())).then(|| window.stats.clone())
+    }
+
+    /// The start of the window `time` falls in.
+    fn window_start(&self, time: SystemTime) -> SystemTime {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let length = self.length.as_secs().max(1);
+        UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs() / length * length)
+    }
+
+    fn gauge(&self, slug: &str, suffix: &str, help: &str) -> Gauge {
+        let gauge = Gauge::new(format!("{}_{}", slug, suffix), help).unwrap();
+        // Already registered if another server shares the registry, in which case that
+        // server's gauge is the one exported.
+        let _ = self.registry.register(Box::new(gauge.clone()));
+        gauge
+    }
+}
+
+fn numeric(value: &SensorValue) -> Option<f64> {
+    match value {
+        SensorValue::Int(v) => Some(*v as f64),
+        SensorValue::Float(v) => Some(*v),
+        SensorValue::Text(_) | SensorValue::Unavailable => None,
+    }
+}
+
+#[async_trait]
+impl OutputSink for SensorWindows {
+    async fn publish(&self, readings: &[Reading]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut windows = self.windows.lock().unwrap();
+        for reading in readings.iter() {
+            let Some(value) = numeric(&reading.value) else {
+                continue;
+            };
+            let start = self.window_start(reading.timestamp);
+            let fresh = WindowStats {
+                start,
+                min: value,
+                max: value,
+                last: value,
+            };
+
+            let window = windows
+                .entry(reading.slug.clone())
+                .or_insert_with(|| SensorWindow {
+                    stats: fresh.clone(),
+                    min_gauge: self.gauge(&reading.slug, "min", "Minimum over the window"),
+                    max_gauge: self.gauge(&reading.slug, "max", "Maximum over the window"),
+                });
+            if window.stats.start != start {
+                window.stats = fresh;
+            }
+            let stats = &mut window.stats;
+            stats.min = stats.min.min(value);
+            stats.max = stats.max.max(value);
+            stats.last = value;
+            window.min_gauge.set(stats.min);
+            window.max_gauge.set(stats.max);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::MetricUpdate;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn reading(slug: &str, value: SensorValue, timestamp: SystemTime) -> Reading {
+        Reading {
+            slug: slug.to_string(),
+            value,
+            timestamp,
+            metrics: MetricUpdate::default(),
+        }
+    }
+
+    fn gauge(registry: &Registry, name: &str) -> f64 {
+        registry
+            .gather()
+            .iter()
+            .find(|family| family.get_name() == name)
+            .map(|family| family.get_metric()[0].get_gauge().get_value())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn gauges_follow_the_window_extremes() {
+        let registry = Registry::new();
+        let windows = SensorWindows::new(DAY, registry.clone());
+        let day = UNIX_EPOCH + DAY * 10;
+
+        for (offset, value) in [(60, 5), (120, 12), (180, -3), (240, 4)] {
+            let time = day + Duration::from_secs(offset);
+            let readings = [
+                reading("window_power", SensorValue::Int(value), time),
+                reading("window_text", SensorValue::Text("on".to_string()), time),
+            ];
+            windows.publish(&readings).await.unwrap();
+        }
+        assert_eq!(gauge(&registry, "window_power_max"), 12.0);
+        assert_eq!(gauge(&registry, "window_power_min"), -3.0);
+        assert!(registry
+            .gather()
+            .iter()
+            .all(|family| !family.get_name().starts_with("window_text")));
+
+        // The next day starts a new window.
+        let next_day = day + DAY + Duration::from_secs(60);
+        let readings = [reading("window_power", SensorValue::Float(7.5), next_day)];
+        windows.publish(&readings).await.unwrap();
+        assert_eq!(gauge(&registry, "window_power_max"), 7.5);
+        assert_eq!(gauge(&registry, "window_power_min"), 7.5);
+        // The window is long gone by now.
+        assert_eq!(windows.get("window_power"), None);
+
+        let now = SystemTime::now();
+        windows
+            .publish(&[reading("window_power", SensorValue::Int(1), now)])
+            .await
+            .unwrap();
+        let stats = windows.get("window_power").unwrap();
+        assert_eq!((stats.min, stats.max, stats.last), (1.0, 1.0, 1.0));
+        assert!(stats.start <= now);
+    }
+}
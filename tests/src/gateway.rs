@@ -0,0 +1,26 @@
+use crate::setup::gateway::GatewayServer;
+use samsynk::connection::UnitContext;
+use samsynk::sensor::{BasicSensor, Sensor, SensorRead, SensorValue};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_modbus::client::{tcp, Client, Context};
+use tokio_modbus::prelude::*;
+
+#[tokio::test]
+async fn units_behind_a_gateway_get_their_own_requests() {
+    let gateway = GatewayServer::start(HashMap::from([(1, 100), (2, 200)])).await;
+    let transport = tcp::connect(gateway.address).await.unwrap();
+    let shared = Arc::new(Mutex::new(transport));
+    let unit = |id| {
+        let unit = UnitContext::new(shared.clone(), Slave(id));
+        Arc::new(Mutex::new(Context::from(Box::new(unit) as Box<dyn Client>)))
+    };
+    let sensor = BasicSensor(Sensor::new("Gateway Unit Power", &[1204], 1, false));
+
+    // Both reads go out over the one connection at once, each with its own unit id.
+    let (first, second) = tokio::join!(sensor.read_value(unit(1)), sensor.read_value(unit(2)));
+    assert_eq!(first.unwrap(), SensorValue::Int(100));
+    assert_eq!(second.unwrap(), SensorValue::Int(200));
+    gateway.stop();
+}
@@ -0,0 +1,43 @@
+use crate::setup::modbus::{socat_available, ModbusServer};
+use crate::setup::setup::{TestContext, TestState};
+use std::path::Path;
+
+#[tokio::test]
+async fn check_servers_can_be_set_up_twice() {
+    // A different port to the shared servers, which may be running alongside.
+    let addr = ([127, 0, 0, 1], 8083);
+    for _ in 0..2 {
+        let state = TestState::start(addr).await;
+        state.teardown().await;
+    }
+}
+
+#[tokio::test]
+async fn check_stopping_modbus_server_removes_serial_ports() {
+    if !socat_available() {
+        eprintln!("skipped: socat is not installed");
+        return;
+    }
+
+    let server = ModbusServer::start().await;
+    let port = server.client_port().to_string();
+    assert!(Path::new(&port).exists());
+
+    server.stop().await;
+    assert!(!Path::new(&port).exists());
+}
+
+#[tokio::test]
+async fn check_api_without_serial_ports() {
+    let addr = ([127, 0, 0, 1], 8084);
+    let state = TestState::start_in_memory(addr).await;
+    let mut tctx = TestContext::new(addr);
+    tctx.set_sensor_state("grid_frequency".to_string(), vec![5000])
+        .await
+        .unwrap();
+
+    let ret = tctx.http_get("/api/unstable/grid_frequency").await.unwrap();
+    assert_eq!(ret.status(), reqwest::StatusCode::OK);
+    assert_eq!(ret.text().await.unwrap(), "50");
+    state.teardown().await;
+}
@@ -1,2 +1,5 @@
 mod api;
+mod gateway;
+mod harness;
+mod sensors;
 mod setup;
@@ -0,0 +1,19 @@
+use prometheus::Registry;
+use samsynk::sensor::register_sensors_except;
+
+#[test]
+fn check_disabled_sensors_are_not_registered() {
+    let registry = Registry::new();
+    let sensors = register_sensors_except(&registry, &["pv2_power"]);
+
+    assert!(!sensors.contains_key("pv2_power"));
+    assert!(sensors.contains_key("pv1_power"));
+
+    let metric_names: Vec<String> = registry
+        .gather()
+        .iter()
+        .map(|family| family.get_name().to_string())
+        .collect();
+    assert!(!metric_names.contains(&"pv2_power".to_string()));
+    assert!(metric_names.contains(&"pv1_power".to_string()));
+}
@@ -0,0 +1,70 @@
+use futures::future;
+use std::collections::HashMap;
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_modbus::prelude::*;
+use tokio_modbus::server::tcp::{accept_tcp_connection, Server};
+
+/// A mock Modbus TCP gateway, routing each request by its unit id to one of several units.
+/// Each unit answers every holding register read with its own value.
+pub struct GatewayServer {
+    pub address: SocketAddr,
+    _join_handle: tokio::task::JoinHandle<Result<(), Error>>,
+}
+
+impl GatewayServer {
+    pub async fn start(units: HashMap<u8, u16>) -> GatewayServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let units = Arc::new(units);
+        let server = Server::new(listener);
+        let join_handle = tokio::spawn(async move {
+            let on_connected = |stream, socket_addr| {
+                let units = units.clone();
+                async move {
+                    accept_tcp_connection(stream, socket_addr, move |_| {
+                        Ok(Some(GatewayService {
+                            units: units.clone(),
+                        }))
+                    })
+                }
+            };
+            server
+                .serve(&on_connected, |e| eprintln!("mock gateway failed: {}", e))
+                .await
+        });
+
+        GatewayServer {
+            address,
+            _join_handle: join_handle,
+        }
+    }
+
+    pub fn stop(self) {
+        self._join_handle.abort();
+    }
+}
+
+struct GatewayService {
+    units: Arc<HashMap<u8, u16>>,
+}
+
+impl tokio_modbus::server::Service for GatewayService {
+    type Request = SlaveRequest<'static>;
+    type Response = Option<Response>;
+    type Error = Error;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        // A unit that isn't there doesn't answer, as on the RS-485 side of a real gateway.
+        let response = match (req.request, self.units.get(&req.slave)) {
+            (Request::ReadHoldingRegisters(_, cnt), Some(&value)) => {
+                Some(Response::ReadHoldingRegisters(vec![value; cnt as usize]))
+            }
+            _ => None,
+        };
+        future::ready(Ok(response))
+    }
+}
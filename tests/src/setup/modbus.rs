@@ -1,39 +1,64 @@
+use async_trait::async_trait;
 use futures::future;
 use itertools::Itertools;
 use samsynk::sensor::{register_sensors, SensorTypes};
 use std::collections::HashMap;
 use std::io::Error;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
 use time::Duration;
 use tokio::{process, time};
 use tokio_modbus;
 use tokio_modbus::prelude::*;
 
-pub const DEFAULT_TEST_PORT_NAMES: &str = concat!(
-    env!("CARGO_TARGET_TMPDIR"),
-    "/ttyUSB0;",
-    env!("CARGO_TARGET_TMPDIR"),
-    "/ttyUSB1"
-);
-
 pub static MOCK_VALUES: Mutex<Option<HashMap<u16, u16>>> = Mutex::new(None);
 
-pub fn get_test_port_names() -> (&'static str, &'static str) {
-    std::option_env!("TEST_PORT_NAMES")
-        .unwrap_or(DEFAULT_TEST_PORT_NAMES)
-        .split(';')
-        .collect_tuple()
-        .expect("Expected 2 ports, found a different number.")
+/// Counts the port pairs made by this process, so each gets its own link paths.
+static PORT_PAIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// The `TEST_PORT_NAMES` ports if set, otherwise a pair of links unique to this process
+/// and call, so concurrent or repeated runs can't clash on a link left behind.
+pub fn get_test_port_names() -> (String, String) {
+    if let Some(names) = std::option_env!("TEST_PORT_NAMES") {
+        return names
+            .split(';')
+            .map(String::from)
+            .collect_tuple()
+            .expect("Expected 2 ports, found a different number.");
+    }
+
+    let pair = PORT_PAIRS.fetch_add(1, Ordering::Relaxed);
+    let link = |n| {
+        format!(
+            "{}/ttyUSB{}-{}-{}",
+            env!("CARGO_TARGET_TMPDIR"),
+            n,
+            std::process::id(),
+            pair
+        )
+    };
+    (link(0), link(1))
+}
+
+/// Whether socat, which the mock serial ports need, is installed.
+pub fn socat_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        std::process::Command::new("socat")
+            .arg("-V")
+            .output()
+            .is_ok()
+    })
 }
 
 pub struct SerialInterface {
-    _process: process::Child,
-    pub port_a: &'static str,
-    pub port_b: &'static str,
+    process: process::Child,
+    pub port_a: String,
+    pub port_b: String,
 }
 
 impl SerialInterface {
-    pub async fn new(port_a: &'static str, port_b: &'static str) -> Self {
+    pub async fn new(port_a: String, port_b: String) -> Self {
         let args = [
             format!("pty,rawer,echo=0,link={}", port_a),
             format!("pty,rawer,echo=0,link={}", port_b),
@@ -45,11 +70,49 @@ impl SerialInterface {
             .expect("unable to spawn socat process: Is socat installed?");
 
         Self {
-            _process: process,
+            process,
             port_a,
             port_b,
         }
     }
+
+    /// Kill socat and remove its links, which it doesn't get the chance to do itself.
+    pub async fn stop(mut self) {
+        let _ = self.process.kill().await;
+        for port in [&self.port_a, &self.port_b] {
+            let _ = std::fs::remove_file(port);
+        }
+    }
+}
+
+/// Answer a request from `MOCK_VALUES`, as the mock inverter.
+fn mock_response(request: Request<'_>) -> Response {
+    match request {
+        Request::ReadHoldingRegisters(addr, cnt) => {
+            // Registers no test has set read as zero, as the data collector reads them all.
+            let mock_values = MOCK_VALUES.lock().unwrap();
+            Response::ReadHoldingRegisters(
+                (addr..addr + cnt)
+                    .map(|reg| {
+                        mock_values
+                            .as_ref()
+                            .and_then(|values| values.get(&reg).copied())
+                            .unwrap_or(0)
+                    })
+                    .collect(),
+            )
+        }
+        Request::WriteSingleRegister(addr, val) => {
+            let mut mock_values = MOCK_VALUES.lock().unwrap();
+            if let Some(values) = mock_values.as_mut() {
+                values.insert(addr, val);
+                Response::WriteSingleRegister(addr, val)
+            } else {
+                panic!();
+            }
+        }
+        _ => unimplemented!(),
+    }
 }
 
 #[derive(Default)]
@@ -62,59 +125,62 @@ impl tokio_modbus::server::Service for ModbusService {
     type Future = future::Ready<Result<Self::Response, Self::Error>>;
 
     fn call(&self, req: Self::Request) -> Self::Future {
-        match req.request {
-            Request::ReadHoldingRegisters(addr, cnt) => {
-                let mut mock_values = MOCK_VALUES.lock().unwrap();
-                if let Some(values) = mock_values.as_mut() {
-                    let out = values.get(&addr).unwrap().clone();
-                    future::ready(Ok(Some(Response::ReadHoldingRegisters(vec![out]))))
-                } else {
-                    future::ready(Ok(Some(Response::ReadHoldingRegisters(vec![
-                        0u16;
-                        cnt as usize
-                    ]))))
-                }
-            }
-            Request::WriteSingleRegister(addr, val) => {
-                let mut mock_values = MOCK_VALUES.lock().unwrap();
-                if let Some(values) = mock_values.as_mut() {
-                    values.insert(addr, val);
-                    future::ready(Ok(Some(Response::WriteSingleRegister(addr, val))))
-                } else {
-                    panic!();
-                }
-            }
-            _ => unimplemented!(),
-        }
+        future::ready(Ok(Some(mock_response(req.request))))
+    }
+}
+
+/// The mock inverter as a transport of its own, for when there's no socat to connect to
+/// it over a serial port.
+#[derive(Debug, Default)]
+pub struct InMemoryTransport;
+
+impl SlaveContext for InMemoryTransport {
+    fn set_slave(&mut self, _slave: Slave) {}
+}
+
+#[async_trait]
+impl Client for InMemoryTransport {
+    async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
+        Ok(mock_response(request))
     }
 }
 
 pub struct ModbusServer {
     pub(crate) _join_handle: tokio::task::JoinHandle<Result<(), Error>>,
-    pub(crate) _serial_interface: tokio::task::JoinHandle<SerialInterface>,
+    pub(crate) serial_interface: SerialInterface,
     pub sensors: HashMap<String, SensorTypes<'static>>,
 }
 
 impl ModbusServer {
     pub async fn start() -> ModbusServer {
-        let port_names = get_test_port_names();
+        let (port_a, port_b) = get_test_port_names();
 
-        let serial_interface = SerialInterface::new(port_names.0, port_names.1);
-        let serial_handler = tokio::spawn(async move { serial_interface.await });
+        let serial_interface = SerialInterface::new(port_a.clone(), port_b).await;
         // Wait a little bit for the serial interface to start up.
         time::sleep(Duration::from_millis(50)).await;
         let service = ModbusService::default();
 
         // Baud rate must be 0 here. We skip setting the baud rate so it can be set via ioctl.
         // See: https://docs.rs/serialport/latest/serialport/struct.TTYPort.html
-        let server = tokio_modbus::server::rtu::Server::new_from_path(port_names.0, 0)
+        let server = tokio_modbus::server::rtu::Server::new_from_path(port_a, 0)
             .unwrap()
             .serve_forever(service);
 
         ModbusServer {
-            _serial_interface: serial_handler,
+            serial_interface,
             _join_handle: tokio::spawn(async move { server.await }),
             sensors: register_sensors(),
         }
     }
+
+    /// The port for clients to connect to.
+    pub fn client_port(&self) -> &str {
+        &self.serial_interface.port_b
+    }
+
+    pub async fn stop(self) {
+        self._join_handle.abort();
+        let _ = self._join_handle.await;
+        self.serial_interface.stop().await;
+    }
 }
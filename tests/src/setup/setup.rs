@@ -1,10 +1,10 @@
-use crate::setup::modbus::{get_test_port_names, ModbusServer, MOCK_VALUES};
+use crate::setup::modbus::{socat_available, InMemoryTransport, ModbusServer, MOCK_VALUES};
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 use reqwest;
 use reqwest::Response;
-use samsynk::sensor::register_sensors;
 use samsynk::sensor::SensorTypes;
+use samsynk::sensor::{register_sensors, shared_context};
 use samsynk::server::{origin_url, Server};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -20,8 +20,51 @@ lazy_static! {
 }
 
 pub struct TestState {
-    _http_server: Server,
-    _modbus_server: ModbusServer,
+    http_server: Server,
+    /// Not set when the mock inverter is served from memory.
+    modbus_server: Option<ModbusServer>,
+}
+
+impl TestState {
+    /// Start the mock inverter, and an HTTP server on `addr` reading from it. The mock is
+    /// reached over a pair of serial ports, or directly if socat isn't installed.
+    pub async fn start(addr: ([u8; 4], u16)) -> TestState {
+        if !socat_available() {
+            eprintln!("socat is not installed, serving the mock inverter from memory");
+            return TestState::start_in_memory(addr).await;
+        }
+
+        let modbus_server = ModbusServer::start().await;
+        let builder = tokio_serial::new(modbus_server.client_port(), 0);
+
+        let client_serial = tokio_serial::SerialStream::open(&builder)
+            .expect("Could not open a serial connection.");
+
+        let ctx = Arc::new(Mutex::new(rtu::attach(client_serial)));
+        let sensors = register_sensors();
+        TestState {
+            modbus_server: Some(modbus_server),
+            http_server: Server::new(ctx.clone(), addr, sensors).await.unwrap(),
+        }
+    }
+
+    /// Start an HTTP server on `addr` reading from the mock inverter without a serial
+    /// link, to test the HTTP layer alone.
+    pub async fn start_in_memory(addr: ([u8; 4], u16)) -> TestState {
+        let ctx = shared_context(InMemoryTransport);
+        TestState {
+            modbus_server: None,
+            http_server: Server::new(ctx, addr, register_sensors()).await.unwrap(),
+        }
+    }
+
+    /// Stop both servers, and the socat process behind the mock serial ports.
+    pub async fn teardown(self) {
+        self.http_server.shutdown().await;
+        if let Some(modbus_server) = self.modbus_server {
+            modbus_server.stop().await;
+        }
+    }
 }
 
 pub(crate) struct TestContext {
@@ -67,7 +110,10 @@ impl TestContext {
         let sensor_registers = match sensor_type {
             SensorTypes::Basic(s) => s.registers,
             SensorTypes::Binary(s) => s.registers,
+            SensorTypes::ByteSlice(s) => s.registers,
             SensorTypes::Compound(s) => s.registers,
+            SensorTypes::Delta(s) => s.registers,
+            SensorTypes::IntegratedEnergy(s) => s.registers,
             SensorTypes::Temperature(s) => s.registers,
             _ => panic!("Could not find sensor type."),
         };
@@ -93,19 +139,7 @@ impl AsyncTestContext for TestContext {
         let mut server_state = SERVER_STATE.lock().await;
         match *server_state {
             None => {
-                let modbus_server = ModbusServer::start().await;
-                let modbus_addr = get_test_port_names().1.to_string();
-                let builder = tokio_serial::new(modbus_addr, 0);
-
-                let client_serial = tokio_serial::SerialStream::open(&builder)
-                    .expect("Could not open a serial connection.");
-
-                let ctx = Arc::new(Mutex::new(rtu::attach(client_serial)));
-                let sensors = register_sensors();
-                *server_state = Some(TestState {
-                    _modbus_server: modbus_server,
-                    _http_server: Server::new(ctx.clone(), addr, sensors).await.unwrap(),
-                });
+                *server_state = Some(TestState::start(addr).await);
                 TestContext::new(addr)
             }
             Some(_) => TestContext::new(addr),